@@ -0,0 +1,123 @@
+//! Parsing a `SET` request from the read buffer, with `Frame::check` followed
+//! by `Frame::parse` as the connection used to, and with the incremental
+//! `Parser`.
+//!
+//! `complete` parses a request that is entirely buffered. `chunked` receives
+//! it in reads of 4KB and tries to parse it after each of them: `check` scans
+//! the whole buffered request again every time, while `Parser` only looks at
+//! the new bytes. Values of 32KB and more are not copied by `Parser`.
+//!
+//! Run with `cargo bench --bench frame`.
+
+use bytes::{Buf, BytesMut};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use mini_redis::frame::{self, Frame, Parser};
+use std::io::Cursor;
+
+/// Sizes of the value set by the request.
+const VALUE_SIZES: &[usize] = &[16, 1024, 100 * 1024];
+
+/// Bytes received by each read in the `chunked` benchmarks.
+const READ_SIZE: usize = 4096;
+
+fn complete(c: &mut Criterion) {
+    let mut group = c.benchmark_group("complete");
+
+    for &size in VALUE_SIZES {
+        let request = request(size);
+        group.throughput(Throughput::Bytes(request.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("check_parse", size), &request, |b, r| {
+            b.iter_batched_ref(
+                || BytesMut::from(&r[..]),
+                |buf| check_parse(buf).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+
+        group.bench_with_input(BenchmarkId::new("parser", size), &request, |b, r| {
+            b.iter_batched_ref(
+                || BytesMut::from(&r[..]),
+                |buf| Parser::new().parse(buf).unwrap().unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+fn chunked(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chunked");
+
+    for &size in VALUE_SIZES {
+        let request = request(size);
+        group.throughput(Throughput::Bytes(request.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("check_parse", size), &request, |b, r| {
+            b.iter(|| {
+                let mut buf = BytesMut::with_capacity(READ_SIZE);
+
+                for chunk in r.chunks(READ_SIZE) {
+                    buf.extend_from_slice(chunk);
+
+                    if let Some(frame) = check_parse(&mut buf) {
+                        return frame;
+                    }
+                }
+
+                unreachable!()
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("parser", size), &request, |b, r| {
+            b.iter(|| {
+                let mut buf = BytesMut::with_capacity(READ_SIZE);
+                let mut parser = Parser::new();
+
+                for chunk in r.chunks(READ_SIZE) {
+                    buf.extend_from_slice(chunk);
+
+                    if let Some(frame) = parser.parse(&mut buf).unwrap() {
+                        return frame;
+                    }
+                }
+
+                unreachable!()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Parses a frame the way the connection did before `Parser`: check that the
+/// frame is complete, then parse it from the start and discard its bytes.
+fn check_parse(buf: &mut BytesMut) -> Option<Frame> {
+    let mut cursor = Cursor::new(&buf[..]);
+
+    match Frame::check(&mut cursor) {
+        Ok(()) => {}
+        Err(frame::Error::Incomplete) => return None,
+        Err(err) => panic!("{}", err),
+    }
+
+    let len = cursor.position() as usize;
+    cursor.set_position(0);
+
+    let frame = Frame::parse(&mut cursor).unwrap();
+    buf.advance(len);
+
+    Some(frame)
+}
+
+/// Encodes `SET key value`, with a value of `size` bytes.
+fn request(size: usize) -> Vec<u8> {
+    let mut request = format!("*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n${}\r\n", size).into_bytes();
+    request.resize(request.len() + size, b'x');
+    request.extend_from_slice(b"\r\n");
+    request
+}
+
+criterion_group!(benches, complete, chunked);
+criterion_main!(benches);
@@ -0,0 +1,122 @@
+//! Throughput of pipelined `GET` requests, and number of writes the server
+//! needs to answer them.
+//!
+//! The server executes every request already buffered on a connection before
+//! writing the responses, so a pipeline of `n` requests is answered with a
+//! single write system call instead of `n`. Tokio sockets write with `send`,
+//! which Linux does not count in `/proc/self/io`. Instead, the TCP segments
+//! sent are read from `/proc/net/snmp` and printed per pipeline before the
+//! benchmark runs: with `TCP_NODELAY`, every write of a small response is a
+//! segment of its own. The count includes the segment used by the client to
+//! send the pipeline and acknowledgements, and covers the whole network
+//! namespace, so other traffic adds noise.
+//!
+//! Run with `cargo bench --bench pipeline`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mini_redis::server;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+/// Number of requests sent at once.
+const DEPTHS: &[usize] = &[1, 16, 128, 1024];
+
+/// Pipelines sent to count the TCP segments.
+const SEGMENT_SAMPLES: usize = 100;
+
+fn pipeline(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let addr = rt.block_on(start_server());
+    let mut stream = rt.block_on(connect(addr));
+
+    for &depth in DEPTHS {
+        let request = request(depth);
+        let reply_len = depth * b"$5\r\nworld\r\n".len();
+
+        if let Some(before) = tcp_out_segments() {
+            rt.block_on(async {
+                for _ in 0..SEGMENT_SAMPLES {
+                    send(&mut stream, &request, reply_len).await;
+                }
+            });
+
+            let segments = tcp_out_segments().unwrap() - before;
+            println!(
+                "pipeline/{}: {:.2} TCP segments per pipeline",
+                depth,
+                segments as f64 / SEGMENT_SAMPLES as f64
+            );
+        }
+    }
+
+    let mut group = c.benchmark_group("pipeline");
+
+    for &depth in DEPTHS {
+        let request = request(depth);
+        let reply_len = depth * b"$5\r\nworld\r\n".len();
+
+        group.throughput(Throughput::Elements(depth as u64));
+        group.bench_function(BenchmarkId::from_parameter(depth), |b| {
+            b.iter(|| rt.block_on(send(&mut stream, &request, reply_len)))
+        });
+    }
+
+    group.finish();
+}
+
+/// Writes `request` and waits for `reply_len` bytes of responses.
+async fn send(stream: &mut TcpStream, request: &[u8], reply_len: usize) {
+    let mut reply = vec![0; reply_len];
+
+    stream.write_all(request).await.unwrap();
+    stream.read_exact(&mut reply).await.unwrap();
+}
+
+/// Encodes `depth` `GET hello` requests.
+fn request(depth: usize) -> Vec<u8> {
+    b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n".repeat(depth)
+}
+
+/// Returns the number of TCP segments sent so far, if the platform reports
+/// it.
+fn tcp_out_segments() -> Option<u64> {
+    let snmp = std::fs::read_to_string("/proc/net/snmp").ok()?;
+
+    // A line of field names is followed by a line of values.
+    let mut tcp = snmp.lines().filter(|line| line.starts_with("Tcp:"));
+    let names = tcp.next()?.split_whitespace();
+    let values = tcp.next()?.split_whitespace();
+
+    names
+        .zip(values)
+        .find(|(name, _)| *name == "OutSegs")
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+async fn connect(addr: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.set_nodelay(true).unwrap();
+
+    send(
+        &mut stream,
+        b"*3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n",
+        b"+OK\r\n".len(),
+    )
+    .await;
+
+    stream
+}
+
+async fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(server::run(listener, std::future::pending::<()>()));
+
+    addr
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
@@ -0,0 +1,103 @@
+//! Throughput of concurrent connections, with the keyspace in a single shard
+//! and split across the default number of shards.
+//!
+//! Each connection repeatedly sets and reads its own keys. With a single
+//! shard every command waits for the same lock, with many shards connections
+//! mostly touch different shards and proceed in parallel.
+//!
+//! Run with `cargo bench --bench shards`.
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mini_redis::{clients::Client, server};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+
+/// Number of connections sending commands concurrently.
+const CONNECTIONS: &[usize] = &[1, 4, 16, 64];
+
+/// Keys written and read by each connection per iteration.
+const KEYS_PER_CONNECTION: usize = 16;
+
+fn shards(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("shards");
+
+    // `0` is the default number of shards.
+    for (name, shards) in [("single", 1), ("default", 0)] {
+        let addr = rt.block_on(start_server(shards));
+
+        for &connections in CONNECTIONS {
+            let commands = connections * KEYS_PER_CONNECTION * 2;
+            group.throughput(Throughput::Elements(commands as u64));
+
+            group.bench_with_input(
+                BenchmarkId::new(name, connections),
+                &connections,
+                |b, &n| b.to_async(&rt).iter_custom(|iters| run(addr, n, iters)),
+            );
+        }
+    }
+
+    group.finish();
+}
+
+/// Opens `connections` connections, then measures how long they take to run
+/// `iters` iterations of their commands concurrently.
+async fn run(addr: SocketAddr, connections: usize, iters: u64) -> Duration {
+    let mut clients = Vec::with_capacity(connections);
+
+    for _ in 0..connections {
+        clients.push(Client::connect(addr).await.unwrap());
+    }
+
+    let start = Instant::now();
+
+    let tasks: Vec<_> = clients
+        .into_iter()
+        .enumerate()
+        .map(|(id, mut client)| {
+            tokio::spawn(async move {
+                for _ in 0..iters {
+                    for i in 0..KEYS_PER_CONNECTION {
+                        let key = format!("key:{}:{}", id, i);
+                        client
+                            .set(&key, Bytes::from_static(b"value"))
+                            .await
+                            .unwrap();
+                        client.get(&key).await.unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    start.elapsed()
+}
+
+async fn start_server(shards: usize) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let config = server::Config {
+        shards,
+        ..Default::default()
+    };
+
+    tokio::spawn(server::run_with_config(
+        listener,
+        config,
+        std::future::pending::<()>(),
+    ));
+
+    addr
+}
+
+criterion_group!(benches, shards);
+criterion_main!(benches);
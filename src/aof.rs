@@ -0,0 +1,350 @@
+//! Append-only file persistence
+//!
+//! Every write is logged to the append-only file (AOF) as the equivalent Redis
+//! command, encoded as a regular protocol frame. On startup, the file is
+//! replayed through `Command::from_frame` to rebuild the dataset.
+//!
+//! Expirations are always logged as absolute UNIX times (`SET ... PXAT` and
+//! `PEXPIREAT`) so that replaying the file later does not extend the time to
+//! live of the keys.
+//!
+//! Writes are first appended to an in-memory buffer while holding the `Db`
+//! lock, which guarantees the file has the same order as the changes to the
+//! dataset. The buffer is written to the file before the reply is sent to the
+//! client. When the data is flushed to disk depends on the `AppendFsync`
+//! policy.
+
+use crate::cmd::{Del, Set};
+use crate::frame::{self, Frame, Limits};
+use crate::snapshot::{to_unix_millis, Record};
+use crate::{Command, Db, Shutdown};
+
+use bytes::{Bytes, BytesMut};
+use std::fmt;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio::time;
+use tracing::{error, warn};
+
+/// When the append-only file is flushed to disk.
+///
+/// Same as the Redis `appendfsync` configuration directive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// Flush after every write, before replying to the client. Slowest, but
+    /// no acknowledged write is lost.
+    Always,
+
+    /// Flush once per second. At most one second of writes is lost on crash.
+    #[default]
+    EverySec,
+
+    /// Let the operating system decide when to flush.
+    No,
+}
+
+/// Append-only file state, stored in the `Db` state.
+#[derive(Debug)]
+pub(crate) struct Log {
+    /// Path of the append-only file.
+    path: PathBuf,
+
+    /// Flush policy.
+    fsync: AppendFsync,
+
+    /// The file is shared with the tasks writing the buffer. Tokio's mutex is
+    /// used as the lock is held while writing to the file.
+    file: Arc<Mutex<LogFile>>,
+
+    /// Writes that are not yet written to the file.
+    buf: BytesMut,
+
+    /// Writes made since the start of a rewrite. `None` when no rewrite is in
+    /// progress.
+    rewrite_buf: Option<BytesMut>,
+}
+
+#[derive(Debug)]
+pub(crate) struct LogFile {
+    file: File,
+
+    /// `true` if data was written since the last flush to disk.
+    unsynced: bool,
+}
+
+impl Log {
+    /// Opens the append-only file at `path`, creating it if needed.
+    pub(crate) async fn open(path: PathBuf, fsync: AppendFsync) -> io::Result<Log> {
+        let file = LogFile::open(&path).await?;
+
+        Ok(Log {
+            path,
+            fsync,
+            file: Arc::new(Mutex::new(file)),
+            buf: BytesMut::new(),
+            rewrite_buf: None,
+        })
+    }
+
+    /// Appends a write to the buffer.
+    pub(crate) fn feed(&mut self, frame: &Frame) {
+        frame.encode(&mut self.buf);
+
+        if let Some(rewrite_buf) = &mut self.rewrite_buf {
+            frame.encode(rewrite_buf);
+        }
+    }
+
+    pub(crate) fn file(&self) -> Arc<Mutex<LogFile>> {
+        self.file.clone()
+    }
+
+    pub(crate) fn fsync(&self) -> AppendFsync {
+        self.fsync
+    }
+
+    /// Takes the writes that are not yet written to the file.
+    pub(crate) fn take_buf(&mut self) -> BytesMut {
+        self.buf.split()
+    }
+
+    /// Starts a rewrite. Returns the path of the file, or `None` if a rewrite
+    /// is already in progress.
+    pub(crate) fn start_rewrite(&mut self) -> Option<PathBuf> {
+        if self.rewrite_buf.is_some() {
+            return None;
+        }
+
+        self.rewrite_buf = Some(BytesMut::new());
+        Some(self.path.clone())
+    }
+
+    /// Completes a rewrite. Returns the writes made since the rewrite
+    /// started.
+    ///
+    /// Writes that are not yet written to the current file are discarded. They
+    /// are either part of the rewritten dataset or of the returned writes.
+    pub(crate) fn finish_rewrite(&mut self) -> BytesMut {
+        self.buf.clear();
+        self.rewrite_buf.take().unwrap_or_default()
+    }
+
+    /// Aborts a failed rewrite.
+    pub(crate) fn abort_rewrite(&mut self) {
+        self.rewrite_buf = None;
+    }
+}
+
+impl LogFile {
+    async fn open(path: &Path) -> io::Result<LogFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        Ok(LogFile {
+            file,
+            unsynced: false,
+        })
+    }
+
+    /// Writes `buf` to the file, flushing it to disk if required by `fsync`.
+    pub(crate) async fn write(&mut self, buf: &[u8], fsync: AppendFsync) -> io::Result<()> {
+        if !buf.is_empty() {
+            self.file.write_all(buf).await?;
+            // `tokio::fs::File` writes in the background, `flush` waits for
+            // the write to complete.
+            self.file.flush().await?;
+            self.unsynced = true;
+        }
+
+        if fsync == AppendFsync::Always {
+            self.sync().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes written data to disk.
+    pub(crate) async fn sync(&mut self) -> io::Result<()> {
+        if self.unsynced {
+            self.file.sync_data().await?;
+            self.unsynced = false;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the file with the rewritten file at `tmp`, after appending
+    /// `buf` to it.
+    pub(crate) async fn replace(&mut self, tmp: &Path, path: &Path, buf: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new().append(true).open(tmp).await?;
+        file.write_all(buf).await?;
+        file.sync_all().await?;
+
+        tokio::fs::rename(tmp, path).await?;
+        *self = LogFile::open(path).await?;
+
+        Ok(())
+    }
+}
+
+impl FromStr for AppendFsync {
+    type Err = crate::Error;
+
+    fn from_str(src: &str) -> crate::Result<AppendFsync> {
+        match &src.to_lowercase()[..] {
+            "always" => Ok(AppendFsync::Always),
+            "everysec" => Ok(AppendFsync::EverySec),
+            "no" => Ok(AppendFsync::No),
+            _ => Err(format!("invalid appendfsync policy `{}`", src).into()),
+        }
+    }
+}
+
+impl fmt::Display for AppendFsync {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppendFsync::Always => "always".fmt(fmt),
+            AppendFsync::EverySec => "everysec".fmt(fmt),
+            AppendFsync::No => "no".fmt(fmt),
+        }
+    }
+}
+
+/// Returns the frame logged for a `SET`.
+pub(crate) fn set_frame(key: &str, value: Bytes, expires_at: Option<SystemTime>) -> Frame {
+    let mut frame = Set::new(key, value, None).into_frame();
+
+    if let Some(when) = expires_at {
+        frame.push_bulk(Bytes::from("pxat".as_bytes()));
+        frame.push_int(to_unix_millis(when));
+    }
+
+    frame
+}
+
+/// Returns the frame logged for a `DEL`.
+pub(crate) fn del_frame(key: &str) -> Frame {
+    Del::new(vec![key.to_string()]).into_frame()
+}
+
+/// Returns the frame logged for an `EXPIRE`.
+pub(crate) fn pexpireat_frame(key: &str, when: SystemTime) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from("pexpireat".as_bytes()));
+    frame.push_bulk(Bytes::from(key.to_string()));
+    frame.push_int(to_unix_millis(when));
+    frame
+}
+
+/// Writes the `records` of a rewrite to `tmp`.
+pub(crate) async fn write_rewrite(tmp: &Path, records: Vec<Record>) -> io::Result<()> {
+    let mut buf = BytesMut::new();
+
+    for record in records {
+        set_frame(&record.key, record.value, record.expires_at).encode(&mut buf);
+    }
+
+    tokio::fs::write(tmp, buf).await
+}
+
+/// Returns the path of the temporary file used when rewriting `path`.
+pub(crate) fn rewrite_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".rewrite-{}", std::process::id()));
+    PathBuf::from(tmp)
+}
+
+/// Replays the append-only file at `path` into `db`. A missing file is an
+/// empty file.
+///
+/// A last record that is only partially written, for example because the
+/// server crashed while writing it, is discarded and the file is truncated
+/// to the last complete record. Any other error stops the replay.
+pub(crate) async fn replay(path: &Path, db: &Db) -> crate::Result<()> {
+    let src = match tokio::fs::read(path).await {
+        Ok(src) => src,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut cursor = Cursor::new(&src[..]);
+
+    while (cursor.position() as usize) < src.len() {
+        let start = cursor.position();
+
+        // The file was written by this server, possibly under less strict
+        // limits than the current ones.
+        match Frame::check_with_limits(&mut cursor, &Limits::trusted()) {
+            Ok(()) => {}
+            Err(frame::Error::Incomplete) => {
+                warn!(
+                    offset = start,
+                    "append-only file ends with a truncated record, discarding it"
+                );
+
+                let file = OpenOptions::new().write(true).open(path).await?;
+                file.set_len(start).await?;
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        cursor.set_position(start);
+        let frame = Frame::parse(&mut cursor)?;
+
+        apply(Command::from_frame(frame)?, db)?;
+    }
+
+    Ok(())
+}
+
+/// Applies a command read from the append-only file. Also used by replicas
+/// to apply the writes streamed by their primary.
+pub(crate) fn apply(cmd: Command, db: &Db) -> crate::Result<()> {
+    match cmd {
+        Command::Set(cmd) => db.set(cmd.key().to_string(), cmd.value().clone(), cmd.expire())?,
+        Command::Del(cmd) => {
+            for key in cmd.keys() {
+                db.del(key);
+            }
+        }
+        Command::Expire(cmd) => {
+            db.expire(cmd.key(), cmd.expire())?;
+        }
+        cmd => {
+            return Err(format!(
+                "unexpected command `{}` in append-only file",
+                cmd.get_name()
+            )
+            .into())
+        }
+    }
+
+    Ok(())
+}
+
+/// Flushes the append-only file to disk once per second, for the `everysec`
+/// policy. Runs until `shutdown` is received.
+pub(crate) async fn run_fsync(db: Db, mut shutdown: Shutdown) {
+    let mut interval = time::interval(Duration::from_secs(1));
+
+    while !shutdown.is_shutdown() {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.recv() => break,
+        }
+
+        if let Err(err) = db.sync_aof().await {
+            error!(cause = %err, "failed to flush the append-only file");
+        }
+    }
+}
@@ -2,6 +2,7 @@ use mini_redis::{clients::Client, DEFAULT_PORT};
 
 use bytes::Bytes;
 use clap::{Parser, Subcommand};
+use std::collections::{BTreeMap, HashSet};
 use std::num::ParseIntError;
 use std::str;
 use std::time::Duration;
@@ -15,7 +16,19 @@ use std::time::Duration;
 )]
 struct Cli {
     #[clap(subcommand)]
-    command: Command,
+    command: Option<Command>,
+
+    /// Sample the keyspace and list the keys with the biggest values, by type
+    #[arg(long, conflicts_with = "memkeys")]
+    bigkeys: bool,
+
+    /// Sample the keyspace and list the keys using the most memory, by type
+    #[arg(long)]
+    memkeys: bool,
+
+    /// Number of random keys sampled by `--bigkeys` and `--memkeys`
+    #[arg(long, default_value_t = 1000)]
+    samples: usize,
 
     #[arg(id = "hostname", long, default_value = "127.0.0.1")]
     host: String,
@@ -85,8 +98,19 @@ async fn main() -> mini_redis::Result<()> {
     // Establish a connection
     let mut client = Client::connect(&addr).await?;
 
+    let command = match (cli.command, cli.bigkeys || cli.memkeys) {
+        (Some(command), false) => command,
+        (None, true) => return scan_keys(&mut client, cli.samples, cli.memkeys).await,
+        (Some(_), true) => {
+            return Err("`--bigkeys` and `--memkeys` cannot be used with a command".into())
+        }
+        (None, false) => {
+            return Err("a command, `--bigkeys` or `--memkeys` must be provided".into())
+        }
+    };
+
     // Process the requested command
-    match cli.command {
+    match command {
         Command::Ping { msg } => {
             let value = client.ping(msg).await?;
             if let Ok(string) = str::from_utf8(&value) {
@@ -145,6 +169,83 @@ async fn main() -> mini_redis::Result<()> {
     Ok(())
 }
 
+/// Number of keys listed for each type by `--bigkeys` and `--memkeys`.
+const TOP_KEYS: usize = 10;
+
+/// Samples up to `samples` random keys and prints the biggest keys of each
+/// type. Keys are measured by the length of their value, or by their memory
+/// usage if `memory` is set.
+async fn scan_keys(client: &mut Client, samples: usize, memory: bool) -> mini_redis::Result<()> {
+    let unit = if memory { "bytes of memory" } else { "bytes" };
+
+    println!(
+        "# Sampling {} random keys to find the biggest keys ({})",
+        samples, unit
+    );
+
+    // Keys are sampled with replacement, so the same key may come up again.
+    let mut seen = HashSet::new();
+
+    // Sizes of the sampled keys, by type.
+    let mut by_type: BTreeMap<String, Vec<(u64, String)>> = BTreeMap::new();
+
+    for _ in 0..samples {
+        let key = match client.random_key().await? {
+            Some(key) => key,
+            None => break,
+        };
+
+        if !seen.insert(key.clone()) {
+            continue;
+        }
+
+        // The key may be removed between the commands.
+        let size = if memory {
+            match client.memory_usage(&key).await? {
+                Some(size) => size,
+                None => continue,
+            }
+        } else {
+            client.strlen(&key).await?
+        };
+
+        let key_type = client.key_type(&key).await?;
+        if key_type == "none" {
+            continue;
+        }
+
+        by_type.entry(key_type).or_default().push((size, key));
+    }
+
+    println!();
+    println!("-------- summary -------");
+    println!();
+    println!("Sampled {} distinct keys", seen.len());
+
+    for (key_type, mut keys) in by_type {
+        keys.sort_by(|a, b| b.cmp(a));
+
+        let total: u64 = keys.iter().map(|(size, _)| size).sum();
+
+        println!();
+        println!(
+            "{} {}s with {} {} (avg size {:.2})",
+            keys.len(),
+            key_type,
+            total,
+            unit,
+            total as f64 / keys.len() as f64
+        );
+        println!("Biggest {}s:", key_type);
+
+        for (size, key) in keys.iter().take(TOP_KEYS) {
+            println!("  {:>12} {}  \"{}\"", size, unit, key);
+        }
+    }
+
+    Ok(())
+}
+
 fn duration_from_ms_str(src: &str) -> Result<Duration, ParseIntError> {
     let ms = src.parse::<u64>()?;
     Ok(Duration::from_millis(ms))
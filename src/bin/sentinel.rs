@@ -0,0 +1,64 @@
+//! mini-redis sentinel.
+//!
+//! Monitors a primary and its replicas, and promotes a replica when the
+//! primary is down. See `mini_redis::sentinel` for details.
+//!
+//! The `clap` crate is used for parsing arguments.
+
+use mini_redis::sentinel::{self, Monitor};
+use mini_redis::server;
+
+use clap::Parser;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::signal;
+
+#[tokio::main]
+pub async fn main() -> mini_redis::Result<()> {
+    tracing_subscriber::fmt::try_init()?;
+
+    let cli = Cli::parse();
+    let port = cli.port.unwrap_or(sentinel::DEFAULT_PORT);
+
+    let config = server::Config {
+        sentinel: Some(sentinel::Config {
+            monitor: cli.monitor,
+            down_after: Duration::from_millis(cli.down_after_milliseconds),
+            sentinels: cli.sentinel,
+        }),
+        ..Default::default()
+    };
+
+    // Bind a TCP listener
+    let listener = TcpListener::bind(&format!("127.0.0.1:{}", port)).await?;
+
+    server::run_with_config(listener, config, signal::ctrl_c()).await;
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "mini-redis-sentinel",
+    version,
+    author,
+    about = "Automatic failover for mini-redis"
+)]
+struct Cli {
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// The primary to monitor: `<name> <host> <port> <quorum>`, e.g.
+    /// `--monitor "mymaster 127.0.0.1 6379 2"`
+    #[arg(long)]
+    monitor: Monitor,
+
+    /// Consider the primary down when it did not reply for this long
+    #[arg(long, default_value_t = 30000)]
+    down_after_milliseconds: u64,
+
+    /// Address of another sentinel monitoring the primary, `host:port`. May
+    /// be repeated
+    #[arg(long)]
+    sentinel: Vec<String>,
+}
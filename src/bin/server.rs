@@ -6,10 +6,20 @@
 //!
 //! The `clap` crate is used for parsing arguments.
 
-use mini_redis::{server, DEFAULT_PORT};
+use mini_redis::frame::Limits;
+#[cfg(feature = "tls")]
+use mini_redis::tls::{self, AuthClients, TlsListener};
+use mini_redis::{server, AppendFsync, KeyspaceEvents, MaxmemoryPolicy, SaveRule, DEFAULT_PORT};
 
 use clap::Parser;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
 use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::signal;
 
 #[cfg(feature = "otel")]
@@ -35,19 +45,197 @@ pub async fn main() -> mini_redis::Result<()> {
     let cli = Cli::parse();
     let port = cli.port.unwrap_or(DEFAULT_PORT);
 
-    // Bind a TCP listener
-    let listener = TcpListener::bind(&format!("127.0.0.1:{}", port)).await?;
+    let config = server::Config {
+        notify_keyspace_events: cli.notify_keyspace_events.unwrap_or_default(),
+        dbfilename: Some(cli.dbfilename),
+        save: cli.save,
+        appendfilename: cli.appendonly.then_some(cli.appendfilename),
+        appendfsync: cli.appendfsync,
+        import_rdb: cli.import_rdb,
+        cluster_enabled: cli.cluster_enabled,
+        cluster_config_file: cli.cluster_config_file,
+        sentinel: None,
+        maxmemory: cli.maxmemory,
+        maxmemory_policy: cli.maxmemory_policy,
+        shards: cli.shards,
+        proto_limits: Limits {
+            max_bulk_len: cli.proto_max_bulk_len,
+            max_multibulk_len: cli.proto_max_multibulk_len,
+            max_depth: cli.proto_max_nesting_depth,
+            max_line_len: cli.proto_max_line_len,
+        },
+    };
 
-    server::run(listener, signal::ctrl_c()).await;
+    // Bind a TCP listener, unless disabled with port 0
+    let mut listeners = server::Listeners::default();
+
+    if port != 0 {
+        listeners.tcp = Some(TcpListener::bind(&format!("127.0.0.1:{}", port)).await?);
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = &cli.unixsocket {
+        listeners.unix = Some(bind_unix(path, cli.unixsocketperm)?);
+    }
+
+    #[cfg(feature = "tls")]
+    if let (Some(tls_port), Some(cert_file), Some(key_file)) =
+        (cli.tls_port, &cli.tls_cert_file, &cli.tls_key_file)
+    {
+        let tls_config = tls::server_config(
+            cert_file,
+            key_file,
+            cli.tls_ca_cert_file.as_deref(),
+            cli.tls_auth_clients,
+        )?;
+        let listener = TcpListener::bind(&format!("127.0.0.1:{}", tls_port)).await?;
+        listeners.tls = Some(TlsListener::new(listener, tls_config));
+    }
+
+    server::run_with_config(listeners, config, signal::ctrl_c()).await;
+
+    // Same as Redis, the socket file is removed on shutdown.
+    #[cfg(unix)]
+    if let Some(path) = &cli.unixsocket {
+        let _ = std::fs::remove_file(path);
+    }
 
     Ok(())
 }
 
+/// Binds a Unix domain socket at `path`, replacing the file left by a previous
+/// run, and sets its permissions to `perm` if given.
+#[cfg(unix)]
+fn bind_unix(path: &Path, perm: Option<u32>) -> mini_redis::Result<UnixListener> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    if let Some(perm) = perm {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(perm))?;
+    }
+
+    Ok(listener)
+}
+
+/// Parses file permissions written in octal, e.g. `700`.
+#[cfg(unix)]
+fn parse_perm(src: &str) -> Result<u32, String> {
+    u32::from_str_radix(src, 8).map_err(|_| format!("invalid permissions `{}`", src))
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "mini-redis-server", version, author, about = "A Redis server")]
 struct Cli {
+    /// TCP port to listen on, 0 to only listen on the Unix socket
     #[arg(long)]
     port: Option<u16>,
+
+    /// Path of a Unix domain socket to listen on, in addition to TCP
+    #[cfg(unix)]
+    #[arg(long)]
+    unixsocket: Option<PathBuf>,
+
+    /// Permissions of the Unix socket file, in octal (e.g. `700`)
+    #[cfg(unix)]
+    #[arg(long, requires = "unixsocket", value_parser = parse_perm)]
+    unixsocketperm: Option<u32>,
+
+    /// Keyspace events to publish, using the Redis flag syntax (e.g. `KEA`)
+    #[arg(long)]
+    notify_keyspace_events: Option<KeyspaceEvents>,
+
+    /// Path of the snapshot file, loaded at startup
+    #[arg(long, default_value = "dump.rdb")]
+    dbfilename: PathBuf,
+
+    /// Save the dataset after `<seconds> <changes>`, e.g. `--save "900 1"`.
+    /// May be repeated
+    #[arg(long)]
+    save: Vec<SaveRule>,
+
+    /// Log every write to the append-only file
+    #[arg(long)]
+    appendonly: bool,
+
+    /// Path of the append-only file, replayed at startup
+    #[arg(long, default_value = "appendonly.aof")]
+    appendfilename: PathBuf,
+
+    /// When to flush the append-only file to disk: always, everysec or no
+    #[arg(long, default_value_t = AppendFsync::EverySec)]
+    appendfsync: AppendFsync,
+
+    /// Import the string keys of a Redis RDB file at startup
+    #[arg(long)]
+    import_rdb: Option<PathBuf>,
+
+    /// Run in cluster mode
+    #[arg(long)]
+    cluster_enabled: bool,
+
+    /// Static cluster configuration, in the `CLUSTER NODES` format
+    #[arg(long, requires = "cluster_enabled")]
+    cluster_config_file: Option<PathBuf>,
+
+    /// Memory limit of the dataset in bytes, 0 for no limit
+    #[arg(long, default_value_t = 0)]
+    maxmemory: usize,
+
+    /// Keys evicted when the memory limit is reached: noeviction, allkeys-lru,
+    /// allkeys-lfu, allkeys-random, volatile-lru or volatile-ttl
+    #[arg(long, default_value_t = MaxmemoryPolicy::NoEviction)]
+    maxmemory_policy: MaxmemoryPolicy,
+
+    /// Number of independently locked keyspace shards (0 uses the default)
+    #[arg(long, default_value_t = 0)]
+    shards: usize,
+
+    /// Maximum length of a bulk string sent by a client, in bytes
+    #[arg(long, default_value_t = Limits::default().max_bulk_len)]
+    proto_max_bulk_len: usize,
+
+    /// Maximum number of elements of an array sent by a client
+    #[arg(long, default_value_t = Limits::default().max_multibulk_len)]
+    proto_max_multibulk_len: usize,
+
+    /// Maximum nesting depth of the arrays sent by a client
+    #[arg(long, default_value_t = Limits::default().max_depth)]
+    proto_max_nesting_depth: usize,
+
+    /// Maximum length of a line sent by a client, such as the header of an
+    /// array or a bulk string, in bytes
+    #[arg(long, default_value_t = Limits::default().max_line_len)]
+    proto_max_line_len: usize,
+
+    /// TCP port to accept TLS connections on
+    #[cfg(feature = "tls")]
+    #[arg(long, requires_all = ["tls_cert_file", "tls_key_file"])]
+    tls_port: Option<u16>,
+
+    /// Certificate chain presented by the server, in PEM format
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    tls_cert_file: Option<PathBuf>,
+
+    /// Private key of the server certificate, in PEM format
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    tls_key_file: Option<PathBuf>,
+
+    /// Certificate authorities client certificates are verified against, in
+    /// PEM format
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    tls_ca_cert_file: Option<PathBuf>,
+
+    /// Whether TLS clients must present a certificate: yes, no or optional
+    #[cfg(feature = "tls")]
+    #[arg(long, default_value_t = AuthClients::Yes)]
+    tls_auth_clients: AuthClients,
 }
 
 #[cfg(not(feature = "otel"))]
@@ -2,7 +2,12 @@
 //!
 //! Provides a blocking connect and methods for issuing the supported commands.
 
+#[cfg(feature = "tls")]
+use crate::tls;
+
 use bytes::Bytes;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::ToSocketAddrs;
 use tokio::runtime::Runtime;
@@ -80,6 +85,41 @@ impl BlockingClient {
         Ok(BlockingClient { inner, rt })
     }
 
+    /// Establish a TLS connection with the Redis server located at `addr`.
+    ///
+    /// Same as `Client::connect_tls`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use mini_redis::{clients::BlockingClient, tls};
+    /// use std::path::Path;
+    ///
+    /// fn main() {
+    ///     let config = tls::client_config(Path::new("ca.crt"), None).unwrap();
+    ///
+    ///     let client = match BlockingClient::connect_tls("localhost:6380", "localhost", config) {
+    ///         Ok(client) => client,
+    ///         Err(_) => panic!("failed to establish connection"),
+    ///     };
+    /// # drop(client);
+    /// }
+    /// ```
+    #[cfg(feature = "tls")]
+    pub fn connect_tls<T: ToSocketAddrs>(
+        addr: T,
+        domain: &str,
+        config: Arc<tls::ClientConfig>,
+    ) -> crate::Result<BlockingClient> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let inner = rt.block_on(crate::clients::Client::connect_tls(addr, domain, config))?;
+
+        Ok(BlockingClient { inner, rt })
+    }
+
     /// Get the value of key.
     ///
     /// If the key does not exist the special value `None` is returned.
@@ -235,6 +275,14 @@ impl BlockingSubscriber {
         self.rt.block_on(self.inner.next_message())
     }
 
+    /// Ping the server while in the subscribed state.
+    ///
+    /// Returns PONG if no argument is provided, otherwise returns a copy of
+    /// the argument.
+    pub fn ping(&mut self, msg: Option<Bytes>) -> crate::Result<Bytes> {
+        self.rt.block_on(self.inner.ping(msg))
+    }
+
     /// Convert the subscriber into an `Iterator` yielding new messages published
     /// on subscribed channels.
     pub fn into_iter(self) -> impl Iterator<Item = crate::Result<Message>> {
@@ -2,27 +2,41 @@
 //!
 //! Provides an async connect and methods for issuing the supported commands.
 
-use crate::cmd::{Get, Ping, Publish, Set, Subscribe, Unsubscribe};
-use crate::{Connection, Frame};
+use crate::cmd::{
+    Asking, Cluster, Del, Dump, Expire, Get, Info, Memory, Ping, Psync, Publish, RandomKey,
+    ReplicaOf, Restore, SPublish, SSubscribe, SUnsubscribe, Sentinel, Set, Strlen, Subscribe, Type,
+    Unsubscribe, Wait,
+};
+#[cfg(feature = "tls")]
+use crate::tls;
+use crate::{Connection, Frame, Transport};
 
 use async_stream::try_stream;
 use bytes::Bytes;
+use std::collections::VecDeque;
 use std::io::{Error, ErrorKind};
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
 use std::time::Duration;
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::net::{TcpStream, ToSocketAddrs};
 use tokio_stream::Stream;
 use tracing::{debug, instrument};
 
 /// Established connection with a Redis server.
 ///
-/// Backed by a single `TcpStream`, `Client` provides basic network client
-/// functionality (no pooling, retrying, ...). Connections are established using
-/// the [`connect`](fn@connect) function.
+/// Backed by a single `TcpStream`, or any other transport, `Client` provides
+/// basic network client functionality (no pooling, retrying, ...). Connections
+/// are established using the [`connect`](fn@connect) function, or
+/// [`from_stream`](Client::from_stream) for other transports.
 ///
 /// Requests are issued using the various methods of `Client`.
 pub struct Client {
-    /// The TCP connection decorated with the redis protocol encoder / decoder
-    /// implemented using a buffered `TcpStream`.
+    /// The connection decorated with the redis protocol encoder / decoder
+    /// implemented using a buffered stream.
     ///
     /// When `Listener` receives an inbound connection, the `TcpStream` is
     /// passed to `Connection::new`, which initializes the associated buffers.
@@ -42,9 +56,18 @@ pub struct Subscriber {
 
     /// The set of channels to which the `Subscriber` is currently subscribed.
     subscribed_channels: Vec<String>,
+
+    /// The set of shard channels to which the `Subscriber` is currently
+    /// subscribed.
+    subscribed_shard_channels: Vec<String>,
+
+    /// Messages received while waiting for the reply to a command, such as
+    /// `ping`. They are returned by `next_message` before reading from the
+    /// socket again.
+    pending_messages: VecDeque<Message>,
 }
 
-/// A message received on a subscribed channel.
+/// A message received on a subscribed channel or shard channel.
 #[derive(Debug, Clone)]
 pub struct Message {
     pub channel: String,
@@ -80,11 +103,171 @@ impl Client {
         // bubbled up to the caller of `mini_redis` connect.
         let socket = TcpStream::connect(addr).await?;
 
+        Ok(Client::from_stream(socket))
+    }
+
+    /// Establish a connection with the Redis server listening on the Unix
+    /// domain socket at `path`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::Client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let client = match Client::connect_unix("/tmp/mini-redis.sock").await {
+    ///         Ok(client) => client,
+    ///         Err(_) => panic!("failed to establish connection"),
+    ///     };
+    /// # drop(client);
+    /// }
+    /// ```
+    #[cfg(unix)]
+    pub async fn connect_unix(path: impl AsRef<Path>) -> crate::Result<Client> {
+        let socket = UnixStream::connect(path).await?;
+
+        Ok(Client::from_stream(socket))
+    }
+
+    /// Establish a TLS connection with the Redis server located at `addr`.
+    ///
+    /// The server certificate must be valid for `domain`. `config` holds the
+    /// trusted certificate authorities and, when the server authenticates
+    /// clients, the client certificate, see `tls::client_config`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use mini_redis::{clients::Client, tls};
+    /// use std::path::Path;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let config = tls::client_config(Path::new("ca.crt"), None).unwrap();
+    ///
+    ///     let client = match Client::connect_tls("localhost:6380", "localhost", config).await {
+    ///         Ok(client) => client,
+    ///         Err(_) => panic!("failed to establish connection"),
+    ///     };
+    /// # drop(client);
+    /// }
+    /// ```
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls<T: ToSocketAddrs>(
+        addr: T,
+        domain: &str,
+        config: Arc<tls::ClientConfig>,
+    ) -> crate::Result<Client> {
+        let stream = tls::connect(addr, domain, config).await?;
+
+        Ok(Client::from_stream(stream))
+    }
+
+    /// Use an already established `stream` to communicate with a Redis server.
+    ///
+    /// `stream` may be any transport, for example a `tokio::io::duplex` pipe
+    /// connected to a server started with `server::run_with_incoming`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mini_redis::clients::Client;
+    /// use mini_redis::server;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+    ///     let incoming = tokio_stream::once(server_stream);
+    ///     tokio::spawn(server::run_with_incoming(
+    ///         incoming,
+    ///         Default::default(),
+    ///         std::future::pending::<()>(),
+    ///     ));
+    ///
+    ///     let mut client = Client::from_stream(client_stream);
+    ///     client.set("foo", "bar".into()).await.unwrap();
+    /// }
+    /// ```
+    pub fn from_stream(stream: impl Transport) -> Client {
         // Initialize the connection state. This allocates read/write buffers to
-        // perform redis protocol frame parsing.
-        let connection = Connection::new(socket);
+        // perform redis protocol frame parsing. The stream is boxed, so that
+        // `Client` does not depend on the transport.
+        let connection = Connection::new(Box::new(stream) as Box<dyn Transport>);
+
+        Client { connection }
+    }
+
+    /// Establish a connection with the current primary named `name`, as
+    /// reported by the first reachable sentinel of `sentinels`.
+    ///
+    /// The primary may change when a sentinel fails over, see `sentinel`. The
+    /// connection is only established once the server confirms that it is a
+    /// primary.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::Client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let sentinels = ["localhost:26379", "localhost:26380"];
+    ///     let mut client = Client::connect_via_sentinel(sentinels, "mymaster")
+    ///         .await
+    ///         .unwrap();
+    ///
+    ///     client.set("foo", "bar".into()).await.unwrap();
+    /// }
+    /// ```
+    pub async fn connect_via_sentinel<T: ToSocketAddrs>(
+        sentinels: impl IntoIterator<Item = T>,
+        name: &str,
+    ) -> crate::Result<Client> {
+        for sentinel in sentinels {
+            match Client::connect_to_primary(sentinel, name).await {
+                Ok(client) => return Ok(client),
+                Err(err) => debug!(cause = %err, "failed to resolve primary"),
+            }
+        }
+
+        Err(format!("no sentinel could resolve the primary `{}`", name).into())
+    }
 
-        Ok(Client { connection })
+    /// Asks the sentinel at `sentinel` for the address of the primary `name`
+    /// and connects to it.
+    async fn connect_to_primary<T: ToSocketAddrs>(
+        sentinel: T,
+        name: &str,
+    ) -> crate::Result<Client> {
+        let mut client = Client::connect(sentinel).await?;
+
+        let frame = Sentinel::GetMasterAddrByName(name.to_string()).into_frame();
+
+        let (host, port) = match client.execute(&frame).await? {
+            Frame::Array(addr) => match &addr[..] {
+                [Frame::Bulk(host), Frame::Bulk(port)] => (
+                    String::from_utf8(host.to_vec())?,
+                    std::str::from_utf8(port)?.parse::<u16>()?,
+                ),
+                _ => return Err(Frame::Array(addr).to_error()),
+            },
+            Frame::Null => return Err(format!("unknown primary `{}`", name).into()),
+            frame => return Err(frame.to_error()),
+        };
+
+        let mut primary = Client::connect((host.as_str(), port)).await?;
+
+        // The sentinel may not know about a failover yet.
+        if !primary
+            .info(Some("replication"))
+            .await?
+            .contains("role:master")
+        {
+            return Err(format!("{}:{} is not a primary", host, port).into());
+        }
+
+        Ok(primary)
     }
 
     /// Ping to the server.
@@ -270,6 +453,381 @@ impl Client {
         }
     }
 
+    /// Removes the given `keys`.
+    ///
+    /// Returns the number of keys that were removed. Keys that do not exist
+    /// are ignored.
+    ///
+    /// # Examples
+    ///
+    /// Demonstrates basic usage.
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::Client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = Client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     client.set("foo", "bar".into()).await.unwrap();
+    ///
+    ///     let removed = client.del(&["foo".into(), "baz".into()]).await.unwrap();
+    ///     assert_eq!(removed, 1);
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn del(&mut self, keys: &[String]) -> crate::Result<u64> {
+        let frame = Del::new(keys.to_vec()).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(removed) => Ok(removed),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Set `key` to expire after `expiration`.
+    ///
+    /// Any previous expiration associated with the key is replaced. Returns
+    /// `true` if the expiration was set, `false` if the key does not exist.
+    ///
+    /// # Examples
+    ///
+    /// Demonstrates basic usage.
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::Client;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = Client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     client.set("foo", "bar".into()).await.unwrap();
+    ///
+    ///     let set = client.expire("foo", Duration::from_secs(10)).await.unwrap();
+    ///     assert!(set);
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn expire(&mut self, key: &str, expiration: Duration) -> crate::Result<bool> {
+        let frame = Expire::new(key, expiration).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(set) => Ok(set == 1),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Serializes the value stored at `key`.
+    ///
+    /// Returns `None` if the key does not exist. The serialized value can be
+    /// passed to `restore`.
+    ///
+    /// # Examples
+    ///
+    /// Demonstrates basic usage.
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::Client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = Client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     client.set("foo", "bar".into()).await.unwrap();
+    ///
+    ///     let payload = client.dump("foo").await.unwrap().unwrap();
+    ///     client.restore("copy", None, payload, false).await.unwrap();
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn dump(&mut self, key: &str) -> crate::Result<Option<Bytes>> {
+        let frame = Dump::new(key).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Creates `key` from a value serialized with `dump`.
+    ///
+    /// If `ttl` is `Some`, the key expires after the specified duration. An
+    /// existing key is only overwritten if `replace` is `true`, otherwise a
+    /// `BUSYKEY` error is returned.
+    #[instrument(skip(self, payload))]
+    pub async fn restore(
+        &mut self,
+        key: &str,
+        ttl: Option<Duration>,
+        payload: Bytes,
+        replace: bool,
+    ) -> crate::Result<()> {
+        let frame = Restore::new(key, ttl, payload, replace).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Makes the server a replica of the primary at `host:port`.
+    ///
+    /// The server discards its dataset, loads the dataset of the primary and
+    /// then applies every write made on the primary. Writes sent directly to a
+    /// replica are rejected with a `READONLY` error.
+    ///
+    /// # Examples
+    ///
+    /// Demonstrates basic usage.
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::Client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = Client::connect("localhost:6380").await.unwrap();
+    ///
+    ///     client.replicaof("localhost", 6379).await.unwrap();
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn replicaof(&mut self, host: &str, port: u16) -> crate::Result<()> {
+        self.replicaof_cmd(ReplicaOf::new(Some((host.to_string(), port))))
+            .await
+    }
+
+    /// Stops replicating and turns the server into a primary. The dataset is
+    /// kept.
+    #[instrument(skip(self))]
+    pub async fn replicaof_no_one(&mut self) -> crate::Result<()> {
+        self.replicaof_cmd(ReplicaOf::new(None)).await
+    }
+
+    async fn replicaof_cmd(&mut self, cmd: ReplicaOf) -> crate::Result<()> {
+        let frame = cmd.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Waits until the writes made so far are applied by `numreplicas`
+    /// replicas, or until `timeout` elapses. `None` waits forever.
+    ///
+    /// Returns the number of replicas that applied the writes.
+    ///
+    /// # Examples
+    ///
+    /// Demonstrates basic usage.
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::Client;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = Client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     client.set("foo", "bar".into()).await.unwrap();
+    ///
+    ///     let replicas = client.wait(1, Some(Duration::from_secs(1))).await.unwrap();
+    ///     println!("written to {} replicas", replicas);
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn wait(
+        &mut self,
+        numreplicas: u64,
+        timeout: Option<Duration>,
+    ) -> crate::Result<u64> {
+        let frame = Wait::new(numreplicas, timeout).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(replicas) => Ok(replicas),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns information about the server, as `field:value` lines.
+    ///
+    /// Only the `replication` section is supported. All sections are returned
+    /// when `section` is `None`.
+    ///
+    /// # Examples
+    ///
+    /// Demonstrates basic usage.
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::Client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = Client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     let info = client.info(Some("replication")).await.unwrap();
+    ///     assert!(info.contains("role:master"));
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn info(&mut self, section: Option<&str>) -> crate::Result<String> {
+        let frame = Info::new(section).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(info) => Ok(String::from_utf8(info.to_vec())?),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns a random key, or `None` if the dataset is empty.
+    #[instrument(skip(self))]
+    pub async fn random_key(&mut self) -> crate::Result<Option<String>> {
+        let frame = RandomKey::new().into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(key) => Ok(Some(String::from_utf8(key.to_vec())?)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the type of the value stored at `key`, `none` if the key does
+    /// not exist.
+    #[instrument(skip(self))]
+    pub async fn key_type(&mut self, key: &str) -> crate::Result<String> {
+        let frame = Type::new(key).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(name) => Ok(name),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the length of the value stored at `key`, 0 if the key does not
+    /// exist.
+    #[instrument(skip(self))]
+    pub async fn strlen(&mut self, key: &str) -> crate::Result<u64> {
+        let frame = Strlen::new(key).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(len) => Ok(len),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the estimated number of bytes used by `key` and its value, or
+    /// `None` if the key does not exist.
+    ///
+    /// # Examples
+    ///
+    /// Demonstrates basic usage.
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::Client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = Client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     client.set("foo", "bar".into()).await.unwrap();
+    ///
+    ///     let usage = client.memory_usage("foo").await.unwrap();
+    ///     println!("foo uses {:?} bytes", usage);
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn memory_usage(&mut self, key: &str) -> crate::Result<Option<u64>> {
+        match self.memory_cmd(Memory::usage(key)).await? {
+            Frame::Integer(size) => Ok(Some(size)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the aggregated memory usage of the server, as name and value
+    /// pairs, such as `dataset.bytes` or `keys.count`.
+    #[instrument(skip(self))]
+    pub async fn memory_stats(&mut self) -> crate::Result<Vec<(String, u64)>> {
+        let fields = match self.memory_cmd(Memory::Stats).await? {
+            Frame::Array(fields) => fields,
+            frame => return Err(frame.to_error()),
+        };
+
+        let mut stats = vec![];
+
+        for pair in fields.chunks(2) {
+            match pair {
+                [Frame::Bulk(name), Frame::Integer(value)] => {
+                    stats.push((String::from_utf8(name.to_vec())?, *value));
+                }
+                _ => return Err("protocol error; invalid MEMORY STATS reply".into()),
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Returns a human readable report of the memory issues detected by the
+    /// server.
+    #[instrument(skip(self))]
+    pub async fn memory_doctor(&mut self) -> crate::Result<String> {
+        match self.memory_cmd(Memory::Doctor).await? {
+            Frame::Bulk(report) => Ok(String::from_utf8(report.to_vec())?),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    async fn memory_cmd(&mut self, cmd: Memory) -> crate::Result<Frame> {
+        let frame = cmd.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+        self.read_response().await
+    }
+
     /// Posts `message` to the given `channel`.
     ///
     /// Returns the number of subscribers currently listening on the channel.
@@ -308,6 +866,43 @@ impl Client {
         }
     }
 
+    /// Posts `message` to the given shard `channel`.
+    ///
+    /// Shard channels use a namespace separate from regular channels, the
+    /// message is only received by clients subscribed with `ssubscribe`.
+    ///
+    /// Returns the number of subscribers currently listening on the shard
+    /// channel.
+    ///
+    /// # Examples
+    ///
+    /// Demonstrates basic usage.
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::Client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = Client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     let val = client.spublish("foo", "bar".into()).await.unwrap();
+    ///     println!("Got = {:?}", val);
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn spublish(&mut self, channel: &str, message: Bytes) -> crate::Result<u64> {
+        let frame = SPublish::new(channel, message).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(response) => Ok(response),
+            frame => Err(frame.to_error()),
+        }
+    }
+
     /// Subscribes the client to the specified channels.
     ///
     /// Once a client issues a subscribe command, it may no longer issue any
@@ -326,6 +921,25 @@ impl Client {
         Ok(Subscriber {
             client: self,
             subscribed_channels: channels,
+            subscribed_shard_channels: vec![],
+            pending_messages: VecDeque::new(),
+        })
+    }
+
+    /// Subscribes the client to the specified shard channels.
+    ///
+    /// Same as `subscribe`, but for the shard channel namespace. Messages
+    /// published with `spublish` are received through the returned
+    /// `Subscriber`.
+    #[instrument(skip(self))]
+    pub async fn ssubscribe(mut self, channels: Vec<String>) -> crate::Result<Subscriber> {
+        self.ssubscribe_cmd(&channels).await?;
+
+        Ok(Subscriber {
+            client: self,
+            subscribed_channels: vec![],
+            subscribed_shard_channels: channels,
+            pending_messages: VecDeque::new(),
         })
     }
 
@@ -334,6 +948,25 @@ impl Client {
         // Convert the `Subscribe` command into a frame
         let frame = Subscribe::new(channels.to_vec()).into_frame();
 
+        self.subscribe_frame(frame, "subscribe", channels).await
+    }
+
+    /// The core `SSUBSCRIBE` logic, used by misc ssubscribe fns
+    async fn ssubscribe_cmd(&mut self, channels: &[String]) -> crate::Result<()> {
+        let frame = SSubscribe::new(channels.to_vec()).into_frame();
+
+        self.subscribe_frame(frame, "ssubscribe", channels).await
+    }
+
+    /// Sends a subscribe command `frame` and waits for the server to confirm
+    /// each of the `channels`. `kind` is the name of the command, which the
+    /// server uses as the first element of each confirmation.
+    async fn subscribe_frame(
+        &mut self,
+        frame: Frame,
+        kind: &str,
+        channels: &[String],
+    ) -> crate::Result<()> {
         debug!(request = ?frame);
 
         // Write the frame to the socket
@@ -351,14 +984,13 @@ impl Client {
                     // The server responds with an array frame in the form of:
                     //
                     // ```
-                    // [ "subscribe", channel, num-subscribed ]
+                    // [ kind, channel, num-subscribed ]
                     // ```
                     //
                     // where channel is the name of the channel and
                     // num-subscribed is the number of channels that the client
                     // is currently subscribed to.
-                    [subscribe, schannel, ..]
-                        if *subscribe == "subscribe" && *schannel == channel => {}
+                    [subscribe, schannel, ..] if *subscribe == kind && *schannel == channel => {}
                     _ => return Err(response.to_error()),
                 },
                 frame => return Err(frame.to_error()),
@@ -368,6 +1000,99 @@ impl Client {
         Ok(())
     }
 
+    /// Starts replicating the server, resuming the replication stream `replid`
+    /// at `offset`. Returns the `FULLRESYNC` or `CONTINUE` reply.
+    ///
+    /// Used by replicas. The connection then carries the replication stream,
+    /// see `into_connection`.
+    pub(crate) async fn psync(&mut self, replid: &str, offset: i64) -> crate::Result<String> {
+        let frame = Psync::new(replid, offset).into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) => Ok(response),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the cluster topology known by the server, in the `CLUSTER
+    /// NODES` format.
+    ///
+    /// Used by `CLUSTER MEET` to learn the nodes of another cluster.
+    pub(crate) async fn cluster_nodes(&mut self) -> crate::Result<String> {
+        let frame = Cluster::Nodes.into_frame();
+
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(value) => Ok(String::from_utf8(value.to_vec())?),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the ranges of slots served by each node of the cluster, as
+    /// `(start, end, "host:port")`.
+    pub(crate) async fn cluster_slots(&mut self) -> crate::Result<Vec<(u16, u16, String)>> {
+        let frame = Cluster::Slots.into_frame();
+
+        let ranges = match self.execute(&frame).await? {
+            Frame::Array(ranges) => ranges,
+            frame => return Err(frame.to_error()),
+        };
+
+        ranges
+            .iter()
+            .map(|range| match range {
+                Frame::Array(fields) => match &fields[..] {
+                    [Frame::Integer(start), Frame::Integer(end), Frame::Array(node), ..] => {
+                        match &node[..] {
+                            [Frame::Bulk(host), Frame::Integer(port), ..] => Ok((
+                                *start as u16,
+                                *end as u16,
+                                format!("{}:{}", String::from_utf8_lossy(host), port),
+                            )),
+                            _ => Err(range.to_error()),
+                        }
+                    }
+                    _ => Err(range.to_error()),
+                },
+                _ => Err(range.to_error()),
+            })
+            .collect()
+    }
+
+    /// Allows the next command to access a slot the server is importing.
+    ///
+    /// Used by `MIGRATE` and `ClusterClient` to follow `-ASK` redirections.
+    pub(crate) async fn asking(&mut self) -> crate::Result<()> {
+        match self.execute(&Asking.into_frame()).await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Sends a request frame and returns the response.
+    ///
+    /// Used by `ClusterClient`, which must inspect error replies to follow
+    /// redirections.
+    pub(crate) async fn execute(&mut self, frame: &Frame) -> crate::Result<Frame> {
+        debug!(request = ?frame);
+
+        self.connection.write_frame(frame).await?;
+
+        self.read_response().await
+    }
+
+    /// Returns the underlying connection.
+    pub(crate) fn into_connection(self) -> Connection {
+        self.connection
+    }
+
     /// Reads a response frame from the socket.
     ///
     /// If an `Error` frame is received, it is converted to `Err`.
@@ -398,30 +1123,98 @@ impl Subscriber {
         &self.subscribed_channels
     }
 
+    /// Returns the set of shard channels currently subscribed to.
+    pub fn get_subscribed_shard(&self) -> &[String] {
+        &self.subscribed_shard_channels
+    }
+
     /// Receive the next message published on a subscribed channel, waiting if
     /// necessary.
     ///
     /// `None` indicates the subscription has been terminated.
     pub async fn next_message(&mut self) -> crate::Result<Option<Message>> {
+        // Messages buffered while waiting for a command reply are delivered
+        // first, preserving the order in which they were published.
+        if let Some(message) = self.pending_messages.pop_front() {
+            return Ok(Some(message));
+        }
+
         match self.client.connection.read_frame().await? {
             Some(mframe) => {
                 debug!(?mframe);
 
-                match mframe {
-                    Frame::Array(ref frame) => match frame.as_slice() {
-                        [message, channel, content] if *message == "message" => Ok(Some(Message {
-                            channel: channel.to_string(),
-                            content: Bytes::from(content.to_string()),
-                        })),
-                        _ => Err(mframe.to_error()),
-                    },
-                    frame => Err(frame.to_error()),
+                match parse_message(&mframe) {
+                    Some(message) => Ok(Some(message)),
+                    None => Err(mframe.to_error()),
                 }
             }
             None => Ok(None),
         }
     }
 
+    /// Ping the server while in the subscribed state.
+    ///
+    /// Returns PONG if no argument is provided, otherwise returns a copy of
+    /// the argument. This can be used to check that a subscription connection
+    /// is still alive.
+    ///
+    /// Messages published while waiting for the reply are not lost, they are
+    /// returned by subsequent calls to `next_message`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::Client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let client = Client::connect("localhost:6379").await.unwrap();
+    ///     let mut subscriber = client.subscribe(vec!["foo".into()]).await.unwrap();
+    ///
+    ///     let pong = subscriber.ping(None).await.unwrap();
+    ///     assert_eq!(b"PONG", &pong[..]);
+    /// }
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn ping(&mut self, msg: Option<Bytes>) -> crate::Result<Bytes> {
+        let echo = msg.is_some();
+        let frame = Ping::new(msg).into_frame();
+        debug!(request = ?frame);
+        self.client.connection.write_frame(&frame).await?;
+
+        loop {
+            let response = self.client.read_response().await?;
+
+            // Published messages may arrive before the reply, buffer them.
+            if let Some(message) = parse_message(&response) {
+                self.pending_messages.push_back(message);
+                continue;
+            }
+
+            // In the subscribed state the server replies with an array frame
+            // in the form of:
+            //
+            // ```
+            // [ "pong", message ]
+            // ```
+            //
+            // where message is empty if no message was sent.
+            return match response {
+                Frame::Array(ref frame) => match frame.as_slice() {
+                    [pong, Frame::Bulk(value)] if *pong == "pong" => {
+                        if echo {
+                            Ok(value.clone())
+                        } else {
+                            Ok(Bytes::from_static(b"PONG"))
+                        }
+                    }
+                    _ => Err(response.to_error()),
+                },
+                frame => Err(frame.to_error()),
+            };
+        }
+    }
+
     /// Convert the subscriber into a `Stream` yielding new messages published
     /// on subscribed channels.
     ///
@@ -460,49 +1253,113 @@ impl Subscriber {
     pub async fn unsubscribe(&mut self, channels: &[String]) -> crate::Result<()> {
         let frame = Unsubscribe::new(channels).into_frame();
 
-        debug!(request = ?frame);
+        unsubscribe_frame(
+            &mut self.client,
+            frame,
+            "unsubscribe",
+            channels,
+            &mut self.subscribed_channels,
+        )
+        .await
+    }
 
-        // Write the frame to the socket
-        self.client.connection.write_frame(&frame).await?;
+    /// Subscribe to a list of new shard channels
+    #[instrument(skip(self))]
+    pub async fn ssubscribe(&mut self, channels: &[String]) -> crate::Result<()> {
+        self.client.ssubscribe_cmd(channels).await?;
 
-        // if the input channel list is empty, server acknowledges as unsubscribing
-        // from all subscribed channels, so we assert that the unsubscribe list received
-        // matches the client subscribed one
-        let num = if channels.is_empty() {
-            self.subscribed_channels.len()
-        } else {
-            channels.len()
-        };
+        self.subscribed_shard_channels
+            .extend(channels.iter().map(Clone::clone));
 
-        // Read the response
-        for _ in 0..num {
-            let response = self.client.read_response().await?;
+        Ok(())
+    }
 
-            match response {
-                Frame::Array(ref frame) => match frame.as_slice() {
-                    [unsubscribe, channel, ..] if *unsubscribe == "unsubscribe" => {
-                        let len = self.subscribed_channels.len();
+    /// Unsubscribe to a list of shard channels
+    #[instrument(skip(self))]
+    pub async fn sunsubscribe(&mut self, channels: &[String]) -> crate::Result<()> {
+        let frame = SUnsubscribe::new(channels).into_frame();
 
-                        if len == 0 {
-                            // There must be at least one channel
-                            return Err(response.to_error());
-                        }
+        unsubscribe_frame(
+            &mut self.client,
+            frame,
+            "sunsubscribe",
+            channels,
+            &mut self.subscribed_shard_channels,
+        )
+        .await
+    }
+}
 
-                        // unsubscribed channel should exist in the subscribed list at this point
-                        self.subscribed_channels.retain(|c| *channel != &c[..]);
+/// Sends an unsubscribe command `frame` and waits for the server to confirm
+/// each removed channel, updating `subscribed` accordingly. `kind` is the name
+/// of the command, which the server uses as the first element of each
+/// confirmation.
+async fn unsubscribe_frame(
+    client: &mut Client,
+    frame: Frame,
+    kind: &str,
+    channels: &[String],
+    subscribed: &mut Vec<String>,
+) -> crate::Result<()> {
+    debug!(request = ?frame);
 
-                        // Only a single channel should be removed from the
-                        // list of subscribed channels.
-                        if self.subscribed_channels.len() != len - 1 {
-                            return Err(response.to_error());
-                        }
+    // Write the frame to the socket
+    client.connection.write_frame(&frame).await?;
+
+    // if the input channel list is empty, server acknowledges as unsubscribing
+    // from all subscribed channels, so we assert that the unsubscribe list received
+    // matches the client subscribed one
+    let num = if channels.is_empty() {
+        subscribed.len()
+    } else {
+        channels.len()
+    };
+
+    // Read the response
+    for _ in 0..num {
+        let response = client.read_response().await?;
+
+        match response {
+            Frame::Array(ref frame) => match frame.as_slice() {
+                [unsubscribe, channel, ..] if *unsubscribe == kind => {
+                    let len = subscribed.len();
+
+                    if len == 0 {
+                        // There must be at least one channel
+                        return Err(response.to_error());
                     }
-                    _ => return Err(response.to_error()),
-                },
-                frame => return Err(frame.to_error()),
-            };
-        }
 
-        Ok(())
+                    // unsubscribed channel should exist in the subscribed list at this point
+                    subscribed.retain(|c| *channel != &c[..]);
+
+                    // Only a single channel should be removed from the
+                    // list of subscribed channels.
+                    if subscribed.len() != len - 1 {
+                        return Err(response.to_error());
+                    }
+                }
+                _ => return Err(response.to_error()),
+            },
+            frame => return Err(frame.to_error()),
+        };
+    }
+
+    Ok(())
+}
+
+/// Converts a `message` or `smessage` frame received in the subscribed state
+/// into a `Message`. Returns `None` if the frame is not a published message.
+fn parse_message(frame: &Frame) -> Option<Message> {
+    match frame {
+        Frame::Array(frame) => match frame.as_slice() {
+            [message, channel, content] if *message == "message" || *message == "smessage" => {
+                Some(Message {
+                    channel: channel.to_string(),
+                    content: Bytes::from(content.to_string()),
+                })
+            }
+            _ => None,
+        },
+        _ => None,
     }
 }
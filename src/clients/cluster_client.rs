@@ -0,0 +1,266 @@
+use crate::clients::Client;
+use crate::cluster::{key_slot, SLOTS};
+use crate::cmd::{Del, Expire, Get, SPublish, Set};
+use crate::Frame;
+
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::time::Duration;
+use tracing::{debug, instrument};
+
+/// Maximum number of redirections followed for a single command.
+const MAX_REDIRECTIONS: usize = 16;
+
+/// Client for a Redis cluster.
+///
+/// The cluster splits the keyspace into hash slots served by different nodes,
+/// see `cluster`. `ClusterClient` fetches the slot table with `CLUSTER SLOTS`
+/// and sends each command to the node serving its keys, using one `Client`
+/// per node. Connections are established on first use.
+///
+/// When the topology changes, nodes answer with redirections. `-MOVED` means
+/// that the slot is now served by another node: the command is sent again to
+/// that node and the slot table is fetched again. `-ASK` means that the slot
+/// is being migrated and the key is on the target node: the command is sent
+/// to the target node, preceded by `ASKING`, without updating the slot table.
+///
+/// Commands on several keys are split into one command per slot.
+pub struct ClusterClient {
+    /// Address of the node serving each slot, `None` if no node serves it.
+    slots: Vec<Option<String>>,
+
+    /// Connections to the nodes, by address.
+    nodes: HashMap<String, Client>,
+}
+
+/// How a node replied to a command.
+enum Reply {
+    Frame(Frame),
+    Moved(String),
+    Ask(String),
+}
+
+impl ClusterClient {
+    /// Connects to the cluster through the node at `addr`, which must be a
+    /// `host:port` string, and fetches the slot table.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use mini_redis::clients::ClusterClient;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = ClusterClient::connect("localhost:7000").await.unwrap();
+    ///
+    ///     client.set("foo", "bar".into()).await.unwrap();
+    /// }
+    /// ```
+    pub async fn connect(addr: &str) -> crate::Result<ClusterClient> {
+        let mut client = ClusterClient {
+            slots: vec![None; SLOTS as usize],
+            nodes: HashMap::new(),
+        };
+
+        client.refresh(addr).await?;
+
+        Ok(client)
+    }
+
+    /// Get the value of key.
+    ///
+    /// If the key does not exist the special value `None` is returned.
+    #[instrument(skip(self))]
+    pub async fn get(&mut self, key: &str) -> crate::Result<Option<Bytes>> {
+        match self.execute(key, Get::new(key).into_frame()).await? {
+            Frame::Simple(value) => Ok(Some(value.into())),
+            Frame::Bulk(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Set `key` to hold the given `value`.
+    #[instrument(skip(self))]
+    pub async fn set(&mut self, key: &str, value: Bytes) -> crate::Result<()> {
+        self.set_cmd(Set::new(key, value, None)).await
+    }
+
+    /// Set `key` to hold the given `value`. The value expires after
+    /// `expiration`.
+    #[instrument(skip(self))]
+    pub async fn set_expires(
+        &mut self,
+        key: &str,
+        value: Bytes,
+        expiration: Duration,
+    ) -> crate::Result<()> {
+        self.set_cmd(Set::new(key, value, Some(expiration))).await
+    }
+
+    async fn set_cmd(&mut self, cmd: Set) -> crate::Result<()> {
+        let key = cmd.key().to_string();
+
+        match self.execute(&key, cmd.into_frame()).await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Removes the specified keys, which may be served by different nodes.
+    ///
+    /// Returns the number of keys that were removed.
+    #[instrument(skip(self))]
+    pub async fn del(&mut self, keys: &[String]) -> crate::Result<u64> {
+        // Keys in different slots cannot be removed by the same command.
+        let mut by_slot: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+
+        for key in keys {
+            by_slot
+                .entry(key_slot(key.as_bytes()))
+                .or_default()
+                .push(key.clone());
+        }
+
+        let mut removed = 0;
+
+        for keys in by_slot.into_values() {
+            let key = keys[0].clone();
+
+            match self.execute(&key, Del::new(keys).into_frame()).await? {
+                Frame::Integer(n) => removed += n,
+                frame => return Err(frame.to_error()),
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Set `key` to expire after `expiration`.
+    ///
+    /// Returns `true` if the expiration was set, `false` if the key does not
+    /// exist.
+    #[instrument(skip(self))]
+    pub async fn expire(&mut self, key: &str, expiration: Duration) -> crate::Result<bool> {
+        match self
+            .execute(key, Expire::new(key, expiration).into_frame())
+            .await?
+        {
+            Frame::Integer(set) => Ok(set == 1),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Posts `message` to the shard `channel`, on the node serving the slot of
+    /// the channel.
+    ///
+    /// Returns the number of subscribers of the channel on that node.
+    #[instrument(skip(self))]
+    pub async fn spublish(&mut self, channel: &str, message: Bytes) -> crate::Result<u64> {
+        match self
+            .execute(channel, SPublish::new(channel, message).into_frame())
+            .await?
+        {
+            Frame::Integer(response) => Ok(response),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Sends `frame`, a command on `key`, to the node serving `key` and
+    /// follows the redirections.
+    async fn execute(&mut self, key: &str, frame: Frame) -> crate::Result<Frame> {
+        let slot = key_slot(key.as_bytes());
+
+        let mut addr = match &self.slots[slot as usize] {
+            Some(addr) => addr.clone(),
+            None => return Err(format!("slot {} is not served", slot).into()),
+        };
+        let mut asking = false;
+
+        for _ in 0..MAX_REDIRECTIONS {
+            match self.send(&addr, &frame, asking).await? {
+                Reply::Frame(frame) => return Ok(frame),
+                Reply::Moved(target) => {
+                    debug!(slot, %target, "moved");
+
+                    // The topology changed, fetch the slot table again from
+                    // the node that knows about the change.
+                    self.slots[slot as usize] = Some(target.clone());
+                    self.refresh(&target).await?;
+
+                    addr = target;
+                    asking = false;
+                }
+                Reply::Ask(target) => {
+                    debug!(slot, %target, "ask");
+
+                    addr = target;
+                    asking = true;
+                }
+            }
+        }
+
+        Err("too many cluster redirections".into())
+    }
+
+    /// Sends `frame` to the node at `addr`, preceded by `ASKING` if `asking`
+    /// is set.
+    async fn send(&mut self, addr: &str, frame: &Frame, asking: bool) -> crate::Result<Reply> {
+        let client = self.node(addr).await?;
+
+        let res = async {
+            if asking {
+                client.asking().await?;
+            }
+
+            client.execute(frame).await
+        }
+        .await;
+
+        match res {
+            Ok(frame) => Ok(Reply::Frame(frame)),
+            Err(err) => {
+                // The connection is established again for the next command.
+                if err.downcast_ref::<io::Error>().is_some() {
+                    self.nodes.remove(addr);
+                    return Err(err);
+                }
+
+                let msg = err.to_string();
+                let args: Vec<&str> = msg.split(' ').collect();
+
+                match &args[..] {
+                    ["MOVED", _, target] => Ok(Reply::Moved(target.to_string())),
+                    ["ASK", _, target] => Ok(Reply::Ask(target.to_string())),
+                    _ => Err(err),
+                }
+            }
+        }
+    }
+
+    /// Replaces the slot table with the one of the node at `addr`.
+    async fn refresh(&mut self, addr: &str) -> crate::Result<()> {
+        let ranges = self.node(addr).await?.cluster_slots().await?;
+
+        self.slots = vec![None; SLOTS as usize];
+
+        for (start, end, node) in ranges {
+            for slot in start..=end.min(SLOTS - 1) {
+                self.slots[slot as usize] = Some(node.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the connection to the node at `addr`, connecting if needed.
+    async fn node(&mut self, addr: &str) -> crate::Result<&mut Client> {
+        if !self.nodes.contains_key(addr) {
+            let client = Client::connect(addr).await?;
+            self.nodes.insert(addr.to_string(), client);
+        }
+
+        Ok(self.nodes.get_mut(addr).unwrap())
+    }
+}
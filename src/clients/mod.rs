@@ -6,3 +6,6 @@ pub use blocking_client::BlockingClient;
 
 mod buffered_client;
 pub use buffered_client::BufferedClient;
+
+mod cluster_client;
+pub use cluster_client::ClusterClient;
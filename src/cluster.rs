@@ -0,0 +1,539 @@
+//! Cluster mode
+//!
+//! Same as Redis Cluster, the keyspace is split into 16384 hash slots. The
+//! slot of a key is the CRC16 of the key modulo 16384. When the key contains a
+//! non-empty `{hashtag}`, only the hashtag is hashed, which allows multi-key
+//! commands on related keys.
+//!
+//! Every node knows which node serves each slot. Commands on keys served by
+//! another node are answered with a `-MOVED slot host:port` redirection. While
+//! a slot is migrated, keys that are not found on the source node are
+//! redirected to the target node with `-ASK slot host:port`. The target node
+//! only accepts them after an `ASKING` command.
+//!
+//! There is no cluster bus: the topology is read from a static configuration
+//! file when the server starts, and is changed with `CLUSTER ADDSLOTS`,
+//! `CLUSTER SETSLOT` and `CLUSTER MEET`, which fetches the topology of another
+//! node once.
+//!
+//! # Configuration file
+//!
+//! The configuration file uses the format of the `CLUSTER NODES` output, so
+//! the output of a running node can be used as configuration. Every line
+//! describes a node:
+//!
+//! ```text
+//! <id> <host:port[@cport]> <flags> <master> <ping-sent> <pong-recv> <config-epoch> <link-state> <slot> ...
+//! ```
+//!
+//! Exactly one node must have the `myself` flag. Slots are either single slots
+//! or `start-end` ranges. Empty lines and lines starting with `#` are ignored.
+
+use crate::{replication, Frame};
+
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Number of hash slots.
+pub const SLOTS: u16 = 16384;
+
+/// Returns the hash slot of `key`.
+///
+/// # Examples
+///
+/// ```
+/// use mini_redis::cluster::key_slot;
+///
+/// assert_eq!(12182, key_slot(b"foo"));
+/// assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"{user1000}.followers"));
+/// ```
+pub fn key_slot(key: &[u8]) -> u16 {
+    crc16(hashtag(key)) % SLOTS
+}
+
+/// Returns the part of `key` that is hashed: the content of the first `{...}`
+/// if it is not empty, the whole key otherwise.
+fn hashtag(key: &[u8]) -> &[u8] {
+    if let Some(start) = key.iter().position(|b| *b == b'{') {
+        if let Some(len) = key[start + 1..].iter().position(|b| *b == b'}') {
+            if len > 0 {
+                return &key[start + 1..start + 1 + len];
+            }
+        }
+    }
+
+    key
+}
+
+/// CRC16 as used by Redis Cluster (XMODEM: polynomial 0x1021, initial value 0).
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// A node of the cluster.
+#[derive(Debug, Clone)]
+pub(crate) struct Node {
+    pub(crate) id: String,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+}
+
+/// Cluster topology as known by this node, stored in the `Db` state.
+#[derive(Debug)]
+pub(crate) struct ClusterState {
+    /// Known nodes. `myself` is always the first one.
+    nodes: Vec<Node>,
+
+    /// Index in `nodes` of the node serving each slot.
+    slots: Vec<Option<usize>>,
+
+    /// Slots migrated from this node, with the index of the target node.
+    migrating: HashMap<u16, usize>,
+
+    /// Slots imported by this node, with the index of the source node.
+    importing: HashMap<u16, usize>,
+}
+
+/// A node parsed from the `CLUSTER NODES` format.
+#[derive(Debug)]
+struct NodeLine {
+    node: Node,
+    myself: bool,
+    slots: Vec<u16>,
+    migrating: Vec<(u16, String)>,
+    importing: Vec<(u16, String)>,
+}
+
+impl ClusterState {
+    /// Creates a cluster containing only this node, listening on
+    /// `host:port`, without any slot.
+    pub(crate) fn new(host: String, port: u16) -> ClusterState {
+        ClusterState {
+            nodes: vec![Node {
+                id: replication::random_id(),
+                host,
+                port,
+            }],
+            slots: vec![None; SLOTS as usize],
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+        }
+    }
+
+    /// Reads the configuration file at `path`.
+    pub(crate) fn load(path: &Path) -> crate::Result<ClusterState> {
+        let src = std::fs::read_to_string(path)?;
+        let lines = parse_nodes(&src)?;
+
+        let myself = match lines.iter().filter(|line| line.myself).count() {
+            1 => lines.iter().find(|line| line.myself).unwrap(),
+            _ => return Err("exactly one node must have the `myself` flag".into()),
+        };
+
+        let mut cluster = ClusterState {
+            nodes: vec![myself.node.clone()],
+            slots: vec![None; SLOTS as usize],
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+        };
+
+        for line in lines.iter().filter(|line| !line.myself) {
+            cluster.nodes.push(line.node.clone());
+        }
+
+        for line in &lines {
+            let index = cluster.index(&line.node.id).unwrap();
+
+            for slot in &line.slots {
+                if cluster.slots[*slot as usize].replace(index).is_some() {
+                    return Err(format!("slot {} is assigned to several nodes", slot).into());
+                }
+            }
+        }
+
+        for (slot, id) in &myself.migrating {
+            let index = cluster.known(id)?;
+            cluster.migrating.insert(*slot, index);
+        }
+
+        for (slot, id) in &myself.importing {
+            let index = cluster.known(id)?;
+            cluster.importing.insert(*slot, index);
+        }
+
+        Ok(cluster)
+    }
+
+    /// ID of this node.
+    pub(crate) fn myid(&self) -> &str {
+        &self.nodes[0].id
+    }
+
+    /// Returns the redirection for a command on `keys`, or `None` if the
+    /// command can be executed by this node. `exists` returns `true` if a key
+    /// is stored on this node.
+    ///
+    /// `asking` is set when the previous command of the connection was
+    /// `ASKING`.
+    pub(crate) fn redirect(
+        &self,
+        keys: &[&str],
+        asking: bool,
+        exists: impl Fn(&str) -> bool,
+    ) -> Option<Frame> {
+        let slot = key_slot(keys.first()?.as_bytes());
+
+        if keys.iter().any(|key| key_slot(key.as_bytes()) != slot) {
+            return Some(Frame::Error(
+                "CROSSSLOT Keys in request don't hash to the same slot".to_string(),
+            ));
+        }
+
+        match self.slots[slot as usize] {
+            None => Some(Frame::Error("CLUSTERDOWN Hash slot not served".to_string())),
+            Some(0) => {
+                // Keys that were already migrated are served by the target.
+                let target = *self.migrating.get(&slot)?;
+
+                if keys.iter().all(|key| exists(key)) {
+                    return None;
+                }
+
+                Some(self.redirection("ASK", slot, target))
+            }
+            Some(owner) => {
+                if asking && self.importing.contains_key(&slot) {
+                    return None;
+                }
+
+                Some(self.redirection("MOVED", slot, owner))
+            }
+        }
+    }
+
+    /// Assigns `slots` to this node.
+    pub(crate) fn add_slots(&mut self, slots: &[u16]) -> Result<(), String> {
+        if let Some(slot) = slots
+            .iter()
+            .find(|slot| self.slots[**slot as usize].is_some())
+        {
+            return Err(format!("ERR Slot {} is already busy", slot));
+        }
+
+        for slot in slots {
+            self.slots[*slot as usize] = Some(0);
+        }
+
+        Ok(())
+    }
+
+    /// Marks `slot` as migrated to the node `id`.
+    pub(crate) fn set_migrating(&mut self, slot: u16, id: &str) -> Result<(), String> {
+        if self.slots[slot as usize] != Some(0) {
+            return Err(format!("ERR I'm not the owner of hash slot {}", slot));
+        }
+
+        let index = self.known(id)?;
+        self.migrating.insert(slot, index);
+        Ok(())
+    }
+
+    /// Marks `slot` as imported from the node `id`.
+    pub(crate) fn set_importing(&mut self, slot: u16, id: &str) -> Result<(), String> {
+        if self.slots[slot as usize] == Some(0) {
+            return Err(format!("ERR I'm already the owner of hash slot {}", slot));
+        }
+
+        let index = self.known(id)?;
+        self.importing.insert(slot, index);
+        Ok(())
+    }
+
+    /// Assigns `slot` to the node `id`, ending any migration of the slot.
+    pub(crate) fn set_node(&mut self, slot: u16, id: &str) -> Result<(), String> {
+        let index = self.known(id)?;
+
+        self.slots[slot as usize] = Some(index);
+        self.set_stable(slot);
+        Ok(())
+    }
+
+    /// Ends any migration of `slot`.
+    pub(crate) fn set_stable(&mut self, slot: u16) {
+        self.migrating.remove(&slot);
+        self.importing.remove(&slot);
+    }
+
+    /// Adds the nodes listed in `nodes`, the `CLUSTER NODES` output of
+    /// another node. Slots they serve are assigned to them, unless served by
+    /// this node.
+    pub(crate) fn meet(&mut self, nodes: &str) -> crate::Result<()> {
+        let lines = parse_nodes(nodes)?;
+
+        for line in lines {
+            if line.node.id == self.myid() {
+                continue;
+            }
+
+            let index = match self.index(&line.node.id) {
+                Some(index) => {
+                    self.nodes[index] = line.node;
+                    index
+                }
+                None => {
+                    self.nodes.push(line.node);
+                    self.nodes.len() - 1
+                }
+            };
+
+            for slot in line.slots {
+                if self.slots[slot as usize] != Some(0) {
+                    self.slots[slot as usize] = Some(index);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `CLUSTER SLOTS` reply.
+    pub(crate) fn slots_frame(&self) -> Frame {
+        let mut frame = Frame::array();
+
+        for (start, end, index) in self.ranges() {
+            let node = &self.nodes[index];
+
+            let mut range = Frame::array();
+            range.push_int(start as u64);
+            range.push_int(end as u64);
+            range.push(node_frame(node));
+
+            frame.push(range);
+        }
+
+        frame
+    }
+
+    /// Returns the `CLUSTER SHARDS` reply. Every node serving slots is a
+    /// shard, as replicas are not part of the cluster.
+    pub(crate) fn shards_frame(&self) -> Frame {
+        let ranges = self.ranges();
+        let mut frame = Frame::array();
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            let owned: Vec<_> = ranges.iter().filter(|range| range.2 == index).collect();
+
+            if index != 0 && owned.is_empty() {
+                continue;
+            }
+
+            let mut slots = Frame::array();
+
+            for (start, end, _) in owned {
+                slots.push_int(*start as u64);
+                slots.push_int(*end as u64);
+            }
+
+            let mut details = Frame::array();
+            details.push_bulk(Bytes::from("id"));
+            details.push_bulk(Bytes::from(node.id.clone()));
+            details.push_bulk(Bytes::from("port"));
+            details.push_int(node.port as u64);
+            details.push_bulk(Bytes::from("ip"));
+            details.push_bulk(Bytes::from(node.host.clone()));
+            details.push_bulk(Bytes::from("endpoint"));
+            details.push_bulk(Bytes::from(node.host.clone()));
+            details.push_bulk(Bytes::from("role"));
+            details.push_bulk(Bytes::from("master"));
+            details.push_bulk(Bytes::from("replication-offset"));
+            details.push_int(0);
+            details.push_bulk(Bytes::from("health"));
+            details.push_bulk(Bytes::from("online"));
+
+            let mut nodes = Frame::array();
+            nodes.push(details);
+
+            let mut shard = Frame::array();
+            shard.push_bulk(Bytes::from("slots"));
+            shard.push(slots);
+            shard.push_bulk(Bytes::from("nodes"));
+            shard.push(nodes);
+
+            frame.push(shard);
+        }
+
+        frame
+    }
+
+    /// Returns the `CLUSTER NODES` reply, which is also the format of the
+    /// configuration file.
+    pub(crate) fn nodes(&self) -> String {
+        let ranges = self.ranges();
+        let mut out = String::new();
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            let flags = if index == 0 {
+                "myself,master"
+            } else {
+                "master"
+            };
+
+            let _ = write!(
+                out,
+                "{} {}:{}@{} {} - 0 0 0 connected",
+                node.id,
+                node.host,
+                node.port,
+                node.port as u32 + 10000,
+                flags
+            );
+
+            for (start, end, _) in ranges.iter().filter(|range| range.2 == index) {
+                if start == end {
+                    let _ = write!(out, " {}", start);
+                } else {
+                    let _ = write!(out, " {}-{}", start, end);
+                }
+            }
+
+            if index == 0 {
+                for (slot, target) in &self.migrating {
+                    let _ = write!(out, " [{}->-{}]", slot, self.nodes[*target].id);
+                }
+
+                for (slot, source) in &self.importing {
+                    let _ = write!(out, " [{}-<-{}]", slot, self.nodes[*source].id);
+                }
+            }
+
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// Returns the ranges of consecutive slots served by the same node, as
+    /// `(start, end, node index)`.
+    fn ranges(&self) -> Vec<(u16, u16, usize)> {
+        let mut ranges: Vec<(u16, u16, usize)> = vec![];
+
+        for (slot, owner) in self.slots.iter().enumerate() {
+            let owner = match owner {
+                Some(owner) => *owner,
+                None => continue,
+            };
+
+            match ranges.last_mut() {
+                Some((_, end, index)) if *end as usize + 1 == slot && *index == owner => {
+                    *end = slot as u16;
+                }
+                _ => ranges.push((slot as u16, slot as u16, owner)),
+            }
+        }
+
+        ranges
+    }
+
+    fn redirection(&self, kind: &str, slot: u16, index: usize) -> Frame {
+        let node = &self.nodes[index];
+        Frame::Error(format!("{} {} {}:{}", kind, slot, node.host, node.port))
+    }
+
+    fn index(&self, id: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.id == id)
+    }
+
+    fn known(&self, id: &str) -> Result<usize, String> {
+        self.index(id)
+            .ok_or_else(|| format!("ERR I don't know about node {}", id))
+    }
+}
+
+/// Returns the `[host, port, id]` frame describing `node`.
+fn node_frame(node: &Node) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from(node.host.clone()));
+    frame.push_int(node.port as u64);
+    frame.push_bulk(Bytes::from(node.id.clone()));
+    frame
+}
+
+/// Parses nodes in the `CLUSTER NODES` format.
+fn parse_nodes(src: &str) -> crate::Result<Vec<NodeLine>> {
+    let mut lines = vec![];
+
+    for line in src.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        if fields.len() < 8 {
+            return Err(format!("invalid cluster node `{}`", line).into());
+        }
+
+        // The cluster bus port is ignored.
+        let addr = fields[1].split('@').next().unwrap();
+        let (host, port) = match addr.rsplit_once(':') {
+            Some((host, port)) => (host.to_string(), port.parse()?),
+            None => return Err(format!("invalid node address `{}`", addr).into()),
+        };
+
+        let mut node = NodeLine {
+            node: Node {
+                id: fields[0].to_string(),
+                host,
+                port,
+            },
+            myself: fields[2].split(',').any(|flag| flag == "myself"),
+            slots: vec![],
+            migrating: vec![],
+            importing: vec![],
+        };
+
+        for slot in &fields[8..] {
+            if let Some(migration) = slot.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                if let Some((slot, id)) = migration.split_once("->-") {
+                    node.migrating.push((parse_slot(slot)?, id.to_string()));
+                } else if let Some((slot, id)) = migration.split_once("-<-") {
+                    node.importing.push((parse_slot(slot)?, id.to_string()));
+                } else {
+                    return Err(format!("invalid slot migration `{}`", slot).into());
+                }
+            } else if let Some((start, end)) = slot.split_once('-') {
+                node.slots.extend(parse_slot(start)?..=parse_slot(end)?);
+            } else {
+                node.slots.push(parse_slot(slot)?);
+            }
+        }
+
+        lines.push(node);
+    }
+
+    Ok(lines)
+}
+
+/// Parses a slot number, which must be lower than `SLOTS`.
+pub(crate) fn parse_slot(src: &str) -> crate::Result<u16> {
+    match src.parse::<u16>() {
+        Ok(slot) if slot < SLOTS => Ok(slot),
+        _ => Err(format!("invalid slot `{}`", src).into()),
+    }
+}
@@ -0,0 +1,52 @@
+use crate::{Connection, Frame, Parse};
+
+use bytes::Bytes;
+
+use tracing::{debug, instrument};
+
+/// Allow the next command to access a slot this node is importing.
+///
+/// Sent by clients following an `-ASK` redirection. The connection handler
+/// keeps track of the flag, which only applies to the next command.
+#[derive(Debug, Default)]
+pub struct Asking;
+
+impl Asking {
+    /// Parse an `Asking` instance from a received frame.
+    ///
+    /// The `ASKING` string has already been consumed and the command takes no
+    /// arguments.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// ASKING
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<Asking> {
+        Ok(Asking)
+    }
+
+    /// Apply the `Asking` command.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, dst))]
+    pub(crate) async fn apply(self, dst: &mut Connection) -> crate::Result<()> {
+        let response = Frame::Simple("OK".to_string());
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the cluster client when following an `-ASK`
+    /// redirection.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("asking".as_bytes()));
+        frame
+    }
+}
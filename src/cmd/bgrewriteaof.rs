@@ -0,0 +1,46 @@
+use crate::{Connection, Db, Frame, Parse};
+
+use tracing::{debug, instrument};
+
+/// Rewrite the append-only file in the background.
+///
+/// The new file only contains the writes needed to rebuild the current
+/// dataset. Writes made while rewriting are appended to the new file before
+/// it replaces the current one.
+#[derive(Debug, Default)]
+pub struct BgRewriteAof;
+
+impl BgRewriteAof {
+    /// Parse a `BgRewriteAof` instance from a received frame.
+    ///
+    /// The `BGREWRITEAOF` string has already been consumed and the command
+    /// takes no arguments.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing a single entry.
+    ///
+    /// ```text
+    /// BGREWRITEAOF
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<BgRewriteAof> {
+        Ok(BgRewriteAof)
+    }
+
+    /// Apply the `BgRewriteAof` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match db.bgrewriteaof() {
+            Ok(()) => Frame::Simple("Background append only file rewriting started".to_string()),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        };
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use crate::clients::Client;
+use crate::cluster::{self, key_slot};
+use crate::cmd::{Parse, ParseError};
+use crate::{Connection, Db, Frame};
+
+use bytes::Bytes;
+use std::convert::TryFrom;
+use tracing::{debug, instrument};
+
+/// Inspect and change the cluster topology.
+///
+/// Only available when the server runs in cluster mode, see `cluster`.
+#[derive(Debug)]
+pub enum Cluster {
+    /// `CLUSTER SLOTS`: the nodes serving each range of slots.
+    Slots,
+
+    /// `CLUSTER SHARDS`: the slots and nodes of every shard.
+    Shards,
+
+    /// `CLUSTER NODES`: the topology, in the configuration file format.
+    Nodes,
+
+    /// `CLUSTER MYID`: the ID of this node.
+    MyId,
+
+    /// `CLUSTER KEYSLOT key`: the hash slot of a key.
+    KeySlot(String),
+
+    /// `CLUSTER ADDSLOTS slot [slot ...]`: assign slots to this node.
+    AddSlots(Vec<u16>),
+
+    /// `CLUSTER SETSLOT slot IMPORTING|MIGRATING|NODE id` or
+    /// `CLUSTER SETSLOT slot STABLE`: change the state of a slot.
+    SetSlot(u16, SetSlot),
+
+    /// `CLUSTER MEET host port`: add the nodes known by another node.
+    Meet(String, u16),
+}
+
+/// State of a slot set by `CLUSTER SETSLOT`.
+#[derive(Debug)]
+pub enum SetSlot {
+    Importing(String),
+    Migrating(String),
+    Node(String),
+    Stable,
+}
+
+impl Cluster {
+    /// Parse a `Cluster` instance from a received frame.
+    ///
+    /// The `CLUSTER` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// CLUSTER SLOTS|SHARDS|NODES|MYID
+    /// CLUSTER KEYSLOT key
+    /// CLUSTER ADDSLOTS slot [slot ...]
+    /// CLUSTER SETSLOT slot IMPORTING|MIGRATING|NODE node-id
+    /// CLUSTER SETSLOT slot STABLE
+    /// CLUSTER MEET host port
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Cluster> {
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "slots" => Ok(Cluster::Slots),
+            "shards" => Ok(Cluster::Shards),
+            "nodes" => Ok(Cluster::Nodes),
+            "myid" => Ok(Cluster::MyId),
+            "keyslot" => Ok(Cluster::KeySlot(parse.next_string()?)),
+            "addslots" => {
+                let mut slots = vec![cluster::parse_slot(&parse.next_string()?)?];
+
+                loop {
+                    match parse.next_string() {
+                        Ok(slot) => slots.push(cluster::parse_slot(&slot)?),
+                        Err(ParseError::EndOfStream) => break,
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+
+                Ok(Cluster::AddSlots(slots))
+            }
+            "setslot" => {
+                let slot = cluster::parse_slot(&parse.next_string()?)?;
+
+                let state = match &parse.next_string()?.to_lowercase()[..] {
+                    "importing" => SetSlot::Importing(parse.next_string()?),
+                    "migrating" => SetSlot::Migrating(parse.next_string()?),
+                    "node" => SetSlot::Node(parse.next_string()?),
+                    "stable" => SetSlot::Stable,
+                    state => return Err(format!("invalid slot state `{}`", state).into()),
+                };
+
+                Ok(Cluster::SetSlot(slot, state))
+            }
+            "meet" => {
+                let host = parse.next_string()?;
+                let port = u16::try_from(parse.next_int()?).map_err(|_| "invalid port")?;
+
+                Ok(Cluster::Meet(host, port))
+            }
+            _ => Err(format!("unsupported `CLUSTER` subcommand `{}`", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Cluster` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match self {
+            // The topology of the other node is fetched before taking the
+            // `Db` lock.
+            Cluster::Meet(host, port) => match fetch_nodes(&host, port).await {
+                Ok(nodes) => db.with_cluster(|cluster| match cluster.meet(&nodes) {
+                    Ok(()) => Frame::Simple("OK".to_string()),
+                    Err(err) => Frame::Error(format!("ERR {}", err)),
+                }),
+                Err(err) => Some(Frame::Error(format!(
+                    "ERR failed to meet {}:{}: {}",
+                    host, port, err
+                ))),
+            },
+            cmd => db.with_cluster(|cluster| cmd.apply_cluster(cluster)),
+        };
+
+        let response = response.unwrap_or_else(|| {
+            Frame::Error("ERR This instance has cluster support disabled".to_string())
+        });
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Applies the subcommands that only use the cluster state.
+    fn apply_cluster(self, cluster: &mut cluster::ClusterState) -> Frame {
+        let ok = |res: Result<(), String>| match res {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(err) => Frame::Error(err),
+        };
+
+        match self {
+            Cluster::Slots => cluster.slots_frame(),
+            Cluster::Shards => cluster.shards_frame(),
+            Cluster::Nodes => Frame::Bulk(Bytes::from(cluster.nodes())),
+            Cluster::MyId => Frame::Bulk(Bytes::from(cluster.myid().to_string())),
+            Cluster::KeySlot(key) => Frame::Integer(key_slot(key.as_bytes()) as u64),
+            Cluster::AddSlots(slots) => ok(cluster.add_slots(&slots)),
+            Cluster::SetSlot(slot, SetSlot::Importing(id)) => ok(cluster.set_importing(slot, &id)),
+            Cluster::SetSlot(slot, SetSlot::Migrating(id)) => ok(cluster.set_migrating(slot, &id)),
+            Cluster::SetSlot(slot, SetSlot::Node(id)) => ok(cluster.set_node(slot, &id)),
+            Cluster::SetSlot(slot, SetSlot::Stable) => {
+                cluster.set_stable(slot);
+                ok(Ok(()))
+            }
+            Cluster::Meet(..) => unreachable!(),
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Cluster` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("cluster".as_bytes()));
+        match self {
+            Cluster::Slots => frame.push_bulk(Bytes::from("slots".as_bytes())),
+            Cluster::Shards => frame.push_bulk(Bytes::from("shards".as_bytes())),
+            Cluster::Nodes => frame.push_bulk(Bytes::from("nodes".as_bytes())),
+            Cluster::MyId => frame.push_bulk(Bytes::from("myid".as_bytes())),
+            Cluster::KeySlot(key) => {
+                frame.push_bulk(Bytes::from("keyslot".as_bytes()));
+                frame.push_bulk(Bytes::from(key.into_bytes()));
+            }
+            Cluster::AddSlots(slots) => {
+                frame.push_bulk(Bytes::from("addslots".as_bytes()));
+                for slot in slots {
+                    frame.push_bulk(Bytes::from(slot.to_string()));
+                }
+            }
+            Cluster::SetSlot(slot, state) => {
+                frame.push_bulk(Bytes::from("setslot".as_bytes()));
+                frame.push_bulk(Bytes::from(slot.to_string()));
+
+                let (state, id) = match state {
+                    SetSlot::Importing(id) => ("importing", Some(id)),
+                    SetSlot::Migrating(id) => ("migrating", Some(id)),
+                    SetSlot::Node(id) => ("node", Some(id)),
+                    SetSlot::Stable => ("stable", None),
+                };
+
+                frame.push_bulk(Bytes::from(state.as_bytes()));
+                if let Some(id) = id {
+                    frame.push_bulk(Bytes::from(id.into_bytes()));
+                }
+            }
+            Cluster::Meet(host, port) => {
+                frame.push_bulk(Bytes::from("meet".as_bytes()));
+                frame.push_bulk(Bytes::from(host.into_bytes()));
+                frame.push_int(port as u64);
+            }
+        }
+        frame
+    }
+}
+
+/// Fetches the `CLUSTER NODES` output of the node at `host:port`.
+async fn fetch_nodes(host: &str, port: u16) -> crate::Result<String> {
+    let mut client = Client::connect((host, port)).await?;
+    client.cluster_nodes().await
+}
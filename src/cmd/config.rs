@@ -0,0 +1,162 @@
+use crate::db::glob_match;
+use crate::eviction;
+use crate::{Connection, Db, Frame, KeyspaceEvents, MaxmemoryPolicy, Parse, SaveRule};
+
+use bytes::Bytes;
+use std::path::PathBuf;
+use tracing::{debug, instrument};
+
+/// Read or update the server configuration at runtime.
+///
+/// Only a subset of the Redis configuration parameters is supported, see
+/// `PARAMETERS`.
+///
+/// # Subcommands
+///
+/// * GET `pattern` -- Replies with the name and value of every parameter
+///   matching the glob-style `pattern`.
+/// * SET `parameter` `value` -- Updates `parameter`.
+#[derive(Debug)]
+pub enum Config {
+    /// `CONFIG GET pattern`
+    Get(String),
+
+    /// `CONFIG SET parameter value`
+    Set(String, String),
+}
+
+/// Configuration parameters supported by `CONFIG GET` and `CONFIG SET`.
+const PARAMETERS: &[&str] = &[
+    "dbfilename",
+    "maxmemory",
+    "maxmemory-policy",
+    "notify-keyspace-events",
+    "save",
+];
+
+impl Config {
+    /// Parse a `Config` instance from a received frame.
+    ///
+    /// The `CONFIG` string has already been consumed.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Config` value on success. If the frame is malformed or the
+    /// subcommand is not supported, `Err` is returned.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing three or four entries.
+    ///
+    /// ```text
+    /// CONFIG GET pattern
+    /// CONFIG SET parameter value
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Config> {
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "get" => Ok(Config::Get(parse.next_string()?.to_lowercase())),
+            "set" => {
+                let parameter = parse.next_string()?.to_lowercase();
+                let value = parse.next_string()?;
+                Ok(Config::Set(parameter, value))
+            }
+            _ => Err(format!("unsupported CONFIG subcommand `{}`", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Config` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match self {
+            Config::Get(pattern) => {
+                let mut response = Frame::array();
+
+                for parameter in PARAMETERS {
+                    if glob_match(pattern.as_bytes(), parameter.as_bytes()) {
+                        response.push_bulk(Bytes::from_static(parameter.as_bytes()));
+                        response.push_bulk(Bytes::from(get_parameter(db, parameter)));
+                    }
+                }
+
+                response
+            }
+            Config::Set(parameter, value) => match set_parameter(db, &parameter, &value) {
+                Ok(()) => Frame::Simple("OK".to_string()),
+                Err(msg) => Frame::Error(msg),
+            },
+        };
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+}
+
+/// Returns the current value of a parameter listed in `PARAMETERS`.
+fn get_parameter(db: &Db, parameter: &str) -> String {
+    match parameter {
+        "dbfilename" => db
+            .dbfilename()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default(),
+        "maxmemory" => db.maxmemory().to_string(),
+        "maxmemory-policy" => db.maxmemory_policy().to_string(),
+        "notify-keyspace-events" => db.notify_keyspace_events().to_string(),
+        "save" => SaveRule::format_list(&db.save_rules()),
+        _ => unreachable!("parameter `{}` missing from `get_parameter`", parameter),
+    }
+}
+
+/// Updates a parameter. On failure, the message for the error reply is
+/// returned.
+fn set_parameter(db: &Db, parameter: &str, value: &str) -> Result<(), String> {
+    let invalid = || {
+        format!(
+            "ERR Invalid argument '{}' for CONFIG SET '{}'",
+            value, parameter
+        )
+    };
+
+    match parameter {
+        // The empty string disables persistence.
+        "dbfilename" => {
+            db.set_dbfilename(Some(PathBuf::from(value)).filter(|_| !value.is_empty()));
+            Ok(())
+        }
+        // Like Redis, the size may use a unit, e.g. `100mb`.
+        "maxmemory" => {
+            let maxmemory = eviction::parse_memory(value).map_err(|_| invalid())?;
+
+            db.set_maxmemory(maxmemory);
+            Ok(())
+        }
+        "maxmemory-policy" => {
+            let policy = value.parse::<MaxmemoryPolicy>().map_err(|_| invalid())?;
+
+            db.set_maxmemory_policy(policy);
+            Ok(())
+        }
+        "notify-keyspace-events" => {
+            let events = value.parse::<KeyspaceEvents>().map_err(|_| invalid())?;
+
+            db.set_notify_keyspace_events(events);
+            Ok(())
+        }
+        "save" => {
+            let rules = SaveRule::parse_list(value).map_err(|_| invalid())?;
+
+            db.set_save_rules(rules);
+            Ok(())
+        }
+        _ => Err(format!(
+            "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+            parameter
+        )),
+    }
+}
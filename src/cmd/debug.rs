@@ -0,0 +1,58 @@
+use crate::{Connection, Db, Frame, Parse};
+
+use tracing::{debug, instrument};
+
+/// Internal commands used to test the server.
+///
+/// # Subcommands
+///
+/// * SET-ACTIVE-EXPIRE `0|1` -- Disables or enables the background deletion
+///   of expired keys. While disabled, expired keys are only deleted when
+///   accessed.
+#[derive(Debug)]
+pub enum Debug {
+    /// `DEBUG SET-ACTIVE-EXPIRE 0|1`
+    SetActiveExpire(bool),
+}
+
+impl Debug {
+    /// Parse a `Debug` instance from a received frame.
+    ///
+    /// The `DEBUG` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// DEBUG SET-ACTIVE-EXPIRE 0|1
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Debug> {
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "set-active-expire" => match parse.next_int()? {
+                0 => Ok(Debug::SetActiveExpire(false)),
+                1 => Ok(Debug::SetActiveExpire(true)),
+                _ => Err("SET-ACTIVE-EXPIRE expects 0 or 1".into()),
+            },
+            _ => Err(format!("unsupported DEBUG subcommand `{}`", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Debug` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        match self {
+            Debug::SetActiveExpire(enabled) => db.set_active_expire(enabled),
+        }
+
+        let response = Frame::Simple("OK".to_string());
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use crate::cmd::set::until;
+use crate::{Connection, Db, Frame, Parse};
+
+use bytes::Bytes;
+use std::time::Duration;
+use tracing::{debug, instrument};
+
+/// Set a timeout on `key`. After the timeout has expired, the key is
+/// automatically deleted.
+///
+/// Any previous timeout associated with the key is replaced. Replies with `1`
+/// if the timeout was set and `0` if the key does not exist.
+///
+/// The `EXPIRE` (seconds) and `PEXPIRE` (milliseconds) forms are parsed into
+/// this command, as well as the `EXPIREAT` and `PEXPIREAT` forms which take an
+/// absolute UNIX time. Absolute times are converted to the remaining time to
+/// live when parsing.
+#[derive(Debug)]
+pub struct Expire {
+    /// the lookup key
+    key: String,
+
+    /// Time after which the key expires
+    expire: Duration,
+}
+
+impl Expire {
+    /// Create a new `Expire` command which expires `key` after `expire`.
+    pub fn new(key: impl ToString, expire: Duration) -> Expire {
+        Expire {
+            key: key.to_string(),
+            expire,
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the expire
+    pub fn expire(&self) -> Duration {
+        self.expire
+    }
+
+    /// Parse an `EXPIRE` instance from a received frame. The timeout is in
+    /// seconds.
+    ///
+    /// The `EXPIRE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing three entries.
+    ///
+    /// ```text
+    /// EXPIRE key seconds
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Expire> {
+        let key = parse.next_string()?;
+        let secs = parse.next_int()?;
+
+        Ok(Expire {
+            key,
+            expire: Duration::from_secs(secs),
+        })
+    }
+
+    /// Parse a `PEXPIRE` instance from a received frame. The timeout is in
+    /// milliseconds.
+    ///
+    /// The `PEXPIRE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing three entries.
+    ///
+    /// ```text
+    /// PEXPIRE key milliseconds
+    /// ```
+    pub(crate) fn parse_frames_millis(parse: &mut Parse) -> crate::Result<Expire> {
+        let key = parse.next_string()?;
+        let ms = parse.next_int()?;
+
+        Ok(Expire {
+            key,
+            expire: Duration::from_millis(ms),
+        })
+    }
+
+    /// Parse an `EXPIREAT` instance from a received frame. The expiration is
+    /// a UNIX time in seconds.
+    ///
+    /// The `EXPIREAT` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing three entries.
+    ///
+    /// ```text
+    /// EXPIREAT key timestamp
+    /// ```
+    pub(crate) fn parse_frames_at(parse: &mut Parse) -> crate::Result<Expire> {
+        let key = parse.next_string()?;
+        let secs = parse.next_int()?;
+
+        Ok(Expire {
+            key,
+            expire: until(Duration::from_secs(secs)),
+        })
+    }
+
+    /// Parse a `PEXPIREAT` instance from a received frame. The expiration is
+    /// a UNIX time in milliseconds.
+    ///
+    /// The `PEXPIREAT` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing three entries.
+    ///
+    /// ```text
+    /// PEXPIREAT key timestamp
+    /// ```
+    pub(crate) fn parse_frames_millis_at(parse: &mut Parse) -> crate::Result<Expire> {
+        let key = parse.next_string()?;
+        let ms = parse.next_int()?;
+
+        Ok(Expire {
+            key,
+            expire: until(Duration::from_millis(ms)),
+        })
+    }
+
+    /// Apply the `Expire` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match db.expire(&self.key, self.expire) {
+            Ok(set) => {
+                // Log the write before acknowledging it.
+                db.flush_aof().await?;
+                Frame::Integer(set as u64)
+            }
+            Err(_) => Frame::Error("ERR invalid expire time in 'expire' command".to_string()),
+        };
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding an `Expire` command to send
+    /// to the server. As with `Set`, the millisecond form is used for greater
+    /// precision.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("pexpire".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame.push_int(self.expire.as_millis() as u64);
+        frame
+    }
+}
@@ -0,0 +1,81 @@
+use crate::cmd::{Parse, ParseError};
+use crate::{Connection, Db, Frame};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Returns information about the server, as `field:value` lines grouped in
+/// sections.
+///
+/// Only the `stats` and `replication` sections are supported. The
+/// `replication` section is used by sentinels to discover the replicas of a
+/// primary. Unknown sections are empty.
+#[derive(Debug, Default)]
+pub struct Info {
+    /// The section to return, all sections when `None`.
+    section: Option<String>,
+}
+
+impl Info {
+    /// Create a new `Info` command returning `section`, or all sections.
+    pub fn new(section: Option<&str>) -> Info {
+        Info {
+            section: section.map(str::to_string),
+        }
+    }
+
+    /// Parse an `Info` instance from a received frame.
+    ///
+    /// The `INFO` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// INFO [section]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Info> {
+        match parse.next_string() {
+            Ok(section) => Ok(Info::new(Some(&section))),
+            Err(ParseError::EndOfStream) => Ok(Info::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Apply the `Info` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let section = self.section.map(|section| section.to_lowercase());
+
+        let info = match section.as_deref() {
+            None | Some("all") | Some("default") | Some("everything") => {
+                format!("{}\r\n{}", db.stats_info(), db.replication_info())
+            }
+            Some("stats") => db.stats_info(),
+            Some("replication") => db.replication_info(),
+            Some(_) => String::new(),
+        };
+
+        let response = Frame::Bulk(Bytes::from(info));
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding an `Info` command to send to
+    /// the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("info".as_bytes()));
+        if let Some(section) = self.section {
+            frame.push_bulk(Bytes::from(section.into_bytes()));
+        }
+        frame
+    }
+}
@@ -0,0 +1,42 @@
+use crate::snapshot::to_unix_millis;
+use crate::{Connection, Db, Frame, Parse};
+
+use tracing::{debug, instrument};
+
+/// Returns the UNIX time, in seconds, of the last successful snapshot save.
+///
+/// Before the first save, the time at which the server started is returned.
+#[derive(Debug, Default)]
+pub struct LastSave;
+
+impl LastSave {
+    /// Parse a `LastSave` instance from a received frame.
+    ///
+    /// The `LASTSAVE` string has already been consumed and the command takes
+    /// no arguments.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing a single entry.
+    ///
+    /// ```text
+    /// LASTSAVE
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<LastSave> {
+        Ok(LastSave)
+    }
+
+    /// Apply the `LastSave` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = Frame::Integer(to_unix_millis(db.last_save()) / 1000);
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+}
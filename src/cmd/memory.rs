@@ -0,0 +1,230 @@
+use crate::cmd::{Parse, ParseError};
+use crate::eviction::{MaxmemoryPolicy, MemoryStats};
+use crate::{Connection, Db, Frame};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Reports the memory used by the server.
+///
+/// The sizes are estimates, see `eviction`.
+///
+/// # Subcommands
+///
+/// * USAGE `key` [SAMPLES `count`] -- Replies with the number of bytes used
+///   by the key, its value and the tracking of its expiration, or nil if the
+///   key does not exist. Values are never nested, so `SAMPLES` is accepted
+///   for compatibility but ignored.
+/// * STATS -- Replies with the aggregated memory usage, as name and value
+///   pairs.
+/// * DOCTOR -- Replies with a report of the memory issues detected.
+#[derive(Debug)]
+pub enum Memory {
+    /// `MEMORY USAGE key`
+    Usage(String),
+
+    /// `MEMORY STATS`
+    Stats,
+
+    /// `MEMORY DOCTOR`
+    Doctor,
+}
+
+/// The dataset is close to the memory limit when it uses more than this
+/// percentage of it.
+const HIGH_USAGE_PERCENT: usize = 90;
+
+impl Memory {
+    /// Create a new `Memory` command reporting the memory used by `key`.
+    pub fn usage(key: impl ToString) -> Memory {
+        Memory::Usage(key.to_string())
+    }
+
+    /// Parse a `Memory` instance from a received frame.
+    ///
+    /// The `MEMORY` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// MEMORY USAGE key [SAMPLES count]
+    /// MEMORY STATS
+    /// MEMORY DOCTOR
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Memory> {
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "usage" => {
+                let key = parse.next_string()?;
+
+                match parse.next_string() {
+                    Ok(option) if option.eq_ignore_ascii_case("samples") => {
+                        parse.next_int()?;
+                    }
+                    Ok(option) => return Err(format!("unsupported option `{}`", option).into()),
+                    Err(ParseError::EndOfStream) => {}
+                    Err(err) => return Err(err.into()),
+                }
+
+                Ok(Memory::Usage(key))
+            }
+            "stats" => Ok(Memory::Stats),
+            "doctor" => Ok(Memory::Doctor),
+            _ => Err(format!("unsupported MEMORY subcommand `{}`", subcommand).into()),
+        }
+    }
+
+    /// Returns the key accessed by the command, if any.
+    pub(crate) fn key(&self) -> Option<&str> {
+        match self {
+            Memory::Usage(key) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Apply the `Memory` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match self {
+            Memory::Usage(key) => match db.memory_usage(&key) {
+                Some(size) => Frame::Integer(size as u64),
+                None => Frame::Null,
+            },
+            Memory::Stats => {
+                let stats = db.memory_stats();
+                let mut response = Frame::array();
+
+                for (name, value) in stats_fields(&stats) {
+                    response.push_bulk(Bytes::from_static(name.as_bytes()));
+                    response.push_int(value as u64);
+                }
+
+                response
+            }
+            Memory::Doctor => Frame::Bulk(Bytes::from(doctor(&db.memory_stats()))),
+        };
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Memory` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("memory".as_bytes()));
+
+        match self {
+            Memory::Usage(key) => {
+                frame.push_bulk(Bytes::from("usage".as_bytes()));
+                frame.push_bulk(Bytes::from(key.into_bytes()));
+            }
+            Memory::Stats => frame.push_bulk(Bytes::from("stats".as_bytes())),
+            Memory::Doctor => frame.push_bulk(Bytes::from("doctor".as_bytes())),
+        }
+
+        frame
+    }
+}
+
+/// Returns the fields of the `MEMORY STATS` reply. The names follow Redis when
+/// it reports the same information.
+fn stats_fields(stats: &MemoryStats) -> Vec<(&'static str, usize)> {
+    vec![
+        (
+            "total.allocated",
+            stats.dataset + stats.pubsub + stats.replication_backlog,
+        ),
+        ("dataset.bytes", stats.dataset),
+        ("keys.count", stats.keys),
+        (
+            "keys.bytes-per-key",
+            stats.dataset.checked_div(stats.keys).unwrap_or(0),
+        ),
+        ("expires.count", stats.expires),
+        ("pubsub.channels", stats.pubsub_channels),
+        ("pubsub.bytes", stats.pubsub),
+        ("replication.backlog", stats.replication_backlog),
+        ("maxmemory", stats.maxmemory),
+        ("evicted.keys", stats.evicted_keys as usize),
+    ]
+}
+
+/// Returns a human readable report of the memory issues detected.
+fn doctor(stats: &MemoryStats) -> String {
+    if stats.keys == 0 && stats.pubsub_channels == 0 {
+        return "The dataset is empty, there is nothing to report.\n".to_string();
+    }
+
+    let mut issues = vec![];
+
+    if stats.maxmemory == 0 {
+        issues.push(
+            "No memory limit: `maxmemory` is not set, the dataset may grow until the process \
+             runs out of memory."
+                .to_string(),
+        );
+    } else if stats.dataset * 100 >= stats.maxmemory * HIGH_USAGE_PERCENT {
+        let percent = stats.dataset * 100 / stats.maxmemory;
+
+        match stats.maxmemory_policy {
+            MaxmemoryPolicy::NoEviction => issues.push(format!(
+                "High memory usage: the dataset uses {}% of `maxmemory` and keys are never \
+                 evicted, writes fail with OOM errors once the limit is reached. Consider \
+                 raising `maxmemory` or setting an eviction policy.",
+                percent
+            )),
+            MaxmemoryPolicy::VolatileLru | MaxmemoryPolicy::VolatileTtl
+                if stats.expires < stats.keys =>
+            {
+                issues.push(format!(
+                    "High memory usage: the dataset uses {}% of `maxmemory` and only the {} \
+                     keys with an expiration can be evicted, the other {} keys are kept \
+                     until writes fail with OOM errors.",
+                    percent,
+                    stats.expires,
+                    stats.keys - stats.expires
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    if stats.evicted_keys > 0 {
+        issues.push(format!(
+            "Evictions: {} keys were evicted to stay under `maxmemory`. This is expected when \
+             the dataset is a cache, otherwise raise `maxmemory`.",
+            stats.evicted_keys
+        ));
+    }
+
+    if stats.pubsub > stats.dataset {
+        issues.push(format!(
+            "Pub/sub: the {} channels and patterns with subscribers use more memory than the \
+             dataset, {} bytes. Each channel buffers up to 1024 messages.",
+            stats.pubsub_channels, stats.pubsub
+        ));
+    }
+
+    if issues.is_empty() {
+        return "No memory issues detected.\n".to_string();
+    }
+
+    let mut report = "Memory issues detected:\n".to_string();
+
+    for issue in issues {
+        report.push_str("\n * ");
+        report.push_str(&issue);
+        report.push('\n');
+    }
+
+    report
+}
@@ -0,0 +1,247 @@
+use crate::clients::Client;
+use crate::cmd::{Parse, ParseError};
+use crate::{rdb, Connection, Db, Frame};
+
+use bytes::Bytes;
+use std::convert::TryFrom;
+use std::io;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+use tracing::{debug, instrument};
+
+/// Transfer keys to another mini-redis (or Redis) instance.
+///
+/// The server connects to the target instance and restores every key with
+/// `RESTORE`, using the payload returned by `DUMP` and the remaining time to
+/// live of the key. Once all keys were restored, they are deleted from the
+/// local instance, unless they were modified during the transfer: those are
+/// kept and reported in an error. If the transfer fails, the local keys are
+/// left untouched.
+///
+/// # Options
+///
+/// * COPY -- Do not remove the keys from the local instance.
+/// * REPLACE -- Replace existing keys on the target instance.
+/// * KEYS `key [key ...]` -- Transfer multiple keys. The `key` argument must
+///   be the empty string.
+#[derive(Debug)]
+pub struct Migrate {
+    /// Host of the target instance
+    host: String,
+
+    /// Port of the target instance
+    port: u16,
+
+    /// Keys to transfer
+    keys: Vec<String>,
+
+    /// Database of the target instance. Only `0` is supported.
+    db: u64,
+
+    /// Maximum duration of the transfer
+    timeout: Duration,
+
+    /// Keep the local keys
+    copy: bool,
+
+    /// Replace existing keys on the target instance
+    replace: bool,
+}
+
+impl Migrate {
+    /// Parse a `Migrate` instance from a received frame.
+    ///
+    /// The `MIGRATE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE] [KEYS key [key ...]]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Migrate> {
+        use ParseError::EndOfStream;
+
+        let host = parse.next_string()?;
+        let port = u16::try_from(parse.next_int()?).map_err(|_| "invalid port")?;
+        let key = parse.next_string()?;
+        let db = parse.next_int()?;
+
+        // Same as Redis, a timeout of zero is a one second timeout.
+        let timeout = match parse.next_int()? {
+            0 => Duration::from_secs(1),
+            ms => Duration::from_millis(ms),
+        };
+
+        let mut keys = vec![];
+        let mut copy = false;
+        let mut replace = false;
+
+        loop {
+            match parse.next_string() {
+                Ok(s) if s.to_uppercase() == "COPY" => copy = true,
+                Ok(s) if s.to_uppercase() == "REPLACE" => replace = true,
+                Ok(s) if s.to_uppercase() == "KEYS" => {
+                    if !key.is_empty() {
+                        return Err("`MIGRATE` KEYS requires an empty key argument".into());
+                    }
+
+                    // All the remaining arguments are keys.
+                    loop {
+                        match parse.next_string() {
+                            Ok(key) => keys.push(key),
+                            Err(EndOfStream) => break,
+                            Err(err) => return Err(err.into()),
+                        }
+                    }
+                }
+                Ok(s) => return Err(format!("unsupported `MIGRATE` option `{}`", s).into()),
+                Err(EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        if !key.is_empty() {
+            keys.push(key);
+        }
+
+        Ok(Migrate {
+            host,
+            port,
+            keys,
+            db,
+            timeout,
+            copy,
+            replace,
+        })
+    }
+
+    /// Apply the `Migrate` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = self.migrate(db).await?;
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Transfers the keys and returns the reply to send to the client.
+    async fn migrate(self, db: &Db) -> crate::Result<Frame> {
+        if self.db != 0 {
+            return Ok(Frame::Error(
+                "ERR mini-redis only supports database 0".to_string(),
+            ));
+        }
+
+        // Keys that do not exist are ignored.
+        let entries: Vec<Dumped> = self
+            .keys
+            .iter()
+            .filter_map(|key| {
+                let (value, expires_at) = db.get_with_expiration(key)?;
+                Some(Dumped {
+                    key: key.clone(),
+                    payload: rdb::dump(&value),
+                    value,
+                    expires_at,
+                })
+            })
+            .collect();
+
+        if entries.is_empty() {
+            return Ok(Frame::Simple("NOKEY".to_string()));
+        }
+
+        // In cluster mode, the target node is importing the slot of the keys,
+        // which it only accepts after `ASKING`.
+        let asking = db.cluster_enabled();
+        let transfer = transfer(&self.host, self.port, &entries, self.replace, asking);
+
+        match time::timeout(self.timeout, transfer).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) if !err.is::<io::Error>() => {
+                return Ok(Frame::Error(format!(
+                    "ERR Target instance replied with error: {}",
+                    err
+                )))
+            }
+            // Connection errors and timeouts.
+            _ => {
+                return Ok(Frame::Error(format!(
+                    "IOERR error or timeout migrating to target instance {}:{}",
+                    self.host, self.port
+                )))
+            }
+        }
+
+        if self.copy {
+            return Ok(Frame::Simple("OK".to_string()));
+        }
+
+        // A key written while it was being transferred is kept, the new value
+        // would be lost otherwise.
+        let changed: Vec<&str> = entries
+            .iter()
+            .filter(|entry| !db.del_if_unchanged(&entry.key, &entry.value, entry.expires_at))
+            .map(|entry| &entry.key[..])
+            .collect();
+
+        // Log the deletes before acknowledging them.
+        db.flush_aof().await?;
+
+        if !changed.is_empty() {
+            return Ok(Frame::Error(format!(
+                "ERR keys modified during the migration were not deleted: {}",
+                changed.join(" ")
+            )));
+        }
+
+        Ok(Frame::Simple("OK".to_string()))
+    }
+}
+
+/// A key read from the local instance, to transfer.
+struct Dumped {
+    key: String,
+
+    /// Value of the key, to check that it did not change during the transfer
+    value: Bytes,
+
+    /// Value of the key serialized by `DUMP`
+    payload: Bytes,
+
+    /// When the key expires, if it does
+    expires_at: Option<Instant>,
+}
+
+/// Restores `entries` on the target instance.
+async fn transfer(
+    host: &str,
+    port: u16,
+    entries: &[Dumped],
+    replace: bool,
+    asking: bool,
+) -> crate::Result<()> {
+    let mut client = Client::connect((host, port)).await?;
+
+    for entry in entries {
+        if asking {
+            client.asking().await?;
+        }
+
+        // The remaining time to live is computed when the key is sent.
+        let ttl = entry
+            .expires_at
+            .map(|when| when.saturating_duration_since(Instant::now()));
+
+        client
+            .restore(&entry.key, ttl, entry.payload.clone(), replace)
+            .await?;
+    }
+
+    Ok(())
+}
@@ -1,34 +1,149 @@
+mod asking;
+pub use asking::Asking;
+
+mod bgrewriteaof;
+pub use bgrewriteaof::BgRewriteAof;
+
+mod cluster;
+pub use cluster::{Cluster, SetSlot};
+
+mod config;
+pub use config::Config;
+
+mod debug;
+pub use debug::Debug;
+
+mod del;
+pub use del::Del;
+
+mod dump;
+pub use dump::Dump;
+
+mod expire;
+pub use expire::Expire;
+
 mod get;
 pub use get::Get;
 
+mod info;
+pub use info::Info;
+
+mod key_type;
+pub use key_type::Type;
+
+mod lastsave;
+pub use lastsave::LastSave;
+
+mod memory;
+pub use memory::Memory;
+
+mod migrate;
+pub use migrate::Migrate;
+
+mod psync;
+pub use psync::Psync;
+
 mod publish;
-pub use publish::Publish;
+pub use publish::{Publish, SPublish};
+
+mod randomkey;
+pub use randomkey::RandomKey;
+
+mod replconf;
+pub use replconf::ReplConf;
+
+mod replicaof;
+pub use replicaof::ReplicaOf;
+
+mod restore;
+pub use restore::Restore;
+
+mod save;
+pub use save::{BgSave, Save};
+
+mod sentinel;
+pub use sentinel::Sentinel;
 
 mod set;
 pub use set::Set;
 
+mod strlen;
+pub use strlen::Strlen;
+
 mod subscribe;
-pub use subscribe::{Subscribe, Unsubscribe};
+pub use subscribe::{PSubscribe, PUnsubscribe, SSubscribe, SUnsubscribe, Subscribe, Unsubscribe};
 
 mod ping;
 pub use ping::Ping;
 
+mod quit;
+pub use quit::Quit;
+
+mod reset;
+pub use reset::Reset;
+
+mod wait;
+pub use wait::Wait;
+
 mod unknown;
 pub use unknown::Unknown;
 
 use crate::{Connection, Db, Frame, Parse, ParseError, Shutdown};
 
+/// 执行命令之后连接的去向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Flow {
+    /// 在当前状态下继续处理命令
+    Continue,
+
+    /// 离开订阅状态，回到普通的命令循环（`RESET`）
+    Exit,
+
+    /// 关闭连接（`QUIT`）。回复已经写入，连接的写入端已经关闭
+    Quit,
+}
+
 /// 支持的 Redis 命令枚举
 ///
 /// 对 `Command` 调用的方法会委托给具体的命令实现
 #[derive(Debug)]
 pub enum Command {
+    Asking(Asking),
+    BgRewriteAof(BgRewriteAof),
+    BgSave(BgSave),
+    Cluster(Cluster),
+    Config(Config),
+    Debug(Debug),
+    Del(Del),
+    Dump(Dump),
+    Expire(Expire),
     Get(Get),
+    Info(Info),
+    LastSave(LastSave),
+    Memory(Memory),
+    Migrate(Migrate),
+    Psync(Psync),
     Publish(Publish),
+    RandomKey(RandomKey),
+    ReplConf(ReplConf),
+    ReplicaOf(ReplicaOf),
+    Restore(Restore),
+    SPublish(SPublish),
+    Save(Save),
+    Sentinel(Sentinel),
     Set(Set),
+    Strlen(Strlen),
     Subscribe(Subscribe),
     Unsubscribe(Unsubscribe),
+    PSubscribe(PSubscribe),
+    PUnsubscribe(PUnsubscribe),
+    SSubscribe(SSubscribe),
+    SUnsubscribe(SUnsubscribe),
     Ping(Ping),
+    Quit(Quit),
+    Reset(Reset),
+    Type(Type),
+    Wait(Wait),
     Unknown(Unknown),
 }
 
@@ -52,12 +167,45 @@ impl Command {
 
         // 匹配命令名，将其余的解析委托给具体的命令
         let command = match &command_name[..] {
+            "asking" => Command::Asking(Asking::parse_frames(&mut parse)?),
+            "bgrewriteaof" => Command::BgRewriteAof(BgRewriteAof::parse_frames(&mut parse)?),
+            "bgsave" => Command::BgSave(BgSave::parse_frames(&mut parse)?),
+            "cluster" => Command::Cluster(Cluster::parse_frames(&mut parse)?),
+            "config" => Command::Config(Config::parse_frames(&mut parse)?),
+            "debug" => Command::Debug(Debug::parse_frames(&mut parse)?),
+            "del" => Command::Del(Del::parse_frames(&mut parse)?),
+            "dump" => Command::Dump(Dump::parse_frames(&mut parse)?),
+            "expire" => Command::Expire(Expire::parse_frames(&mut parse)?),
+            "pexpire" => Command::Expire(Expire::parse_frames_millis(&mut parse)?),
+            "expireat" => Command::Expire(Expire::parse_frames_at(&mut parse)?),
+            "pexpireat" => Command::Expire(Expire::parse_frames_millis_at(&mut parse)?),
             "get" => Command::Get(Get::parse_frames(&mut parse)?),
+            "info" => Command::Info(Info::parse_frames(&mut parse)?),
+            "lastsave" => Command::LastSave(LastSave::parse_frames(&mut parse)?),
+            "memory" => Command::Memory(Memory::parse_frames(&mut parse)?),
+            "migrate" => Command::Migrate(Migrate::parse_frames(&mut parse)?),
+            "psync" => Command::Psync(Psync::parse_frames(&mut parse)?),
             "publish" => Command::Publish(Publish::parse_frames(&mut parse)?),
+            "spublish" => Command::SPublish(SPublish::parse_frames(&mut parse)?),
+            "randomkey" => Command::RandomKey(RandomKey::parse_frames(&mut parse)?),
+            "replconf" => Command::ReplConf(ReplConf::parse_frames(&mut parse)?),
+            "replicaof" | "slaveof" => Command::ReplicaOf(ReplicaOf::parse_frames(&mut parse)?),
+            "restore" => Command::Restore(Restore::parse_frames(&mut parse)?),
+            "save" => Command::Save(Save::parse_frames(&mut parse)?),
+            "sentinel" => Command::Sentinel(Sentinel::parse_frames(&mut parse)?),
             "set" => Command::Set(Set::parse_frames(&mut parse)?),
+            "strlen" => Command::Strlen(Strlen::parse_frames(&mut parse)?),
             "subscribe" => Command::Subscribe(Subscribe::parse_frames(&mut parse)?),
             "unsubscribe" => Command::Unsubscribe(Unsubscribe::parse_frames(&mut parse)?),
+            "psubscribe" => Command::PSubscribe(PSubscribe::parse_frames(&mut parse)?),
+            "punsubscribe" => Command::PUnsubscribe(PUnsubscribe::parse_frames(&mut parse)?),
+            "ssubscribe" => Command::SSubscribe(SSubscribe::parse_frames(&mut parse)?),
+            "sunsubscribe" => Command::SUnsubscribe(SUnsubscribe::parse_frames(&mut parse)?),
             "ping" => Command::Ping(Ping::parse_frames(&mut parse)?),
+            "quit" => Command::Quit(Quit::parse_frames(&mut parse)?),
+            "reset" => Command::Reset(Reset::parse_frames(&mut parse)?),
+            "type" => Command::Type(Type::parse_frames(&mut parse)?),
+            "wait" => Command::Wait(Wait::parse_frames(&mut parse)?),
             _ => {
                 // 无法识别命令，返回一个 Unknown 命令。
                 //
@@ -77,36 +225,156 @@ impl Command {
 
     /// 将命令应用到指定的 `Db` 实例
     ///
-    /// 响应写入到 `dst`。这由服务器调用来执行接收到的命令
+    /// 响应写入到 `dst`。这由服务器调用来执行接收到的命令。返回 `Flow::Quit`
+    /// 时，调用者不再从连接读取请求
     pub(crate) async fn apply(
         self,
         db: &Db,
         dst: &mut Connection,
         shutdown: &mut Shutdown,
-    ) -> crate::Result<()> {
+    ) -> crate::Result<Flow> {
         use Command::*;
 
+        // 副本只接受来自主节点的写入。
+        if self.is_write() && db.is_replica() {
+            let response =
+                Frame::Error("READONLY You can't write against a read only replica.".to_string());
+            dst.write_frame(&response).await?;
+            return Ok(Flow::Continue);
+        }
+
         match self {
+            Asking(cmd) => cmd.apply(dst).await,
+            BgRewriteAof(cmd) => cmd.apply(db, dst).await,
+            BgSave(cmd) => cmd.apply(db, dst).await,
+            Cluster(cmd) => cmd.apply(db, dst).await,
+            Config(cmd) => cmd.apply(db, dst).await,
+            Debug(cmd) => cmd.apply(db, dst).await,
+            Del(cmd) => cmd.apply(db, dst).await,
+            Dump(cmd) => cmd.apply(db, dst).await,
+            Expire(cmd) => cmd.apply(db, dst).await,
             Get(cmd) => cmd.apply(db, dst).await,
+            Info(cmd) => cmd.apply(db, dst).await,
+            LastSave(cmd) => cmd.apply(db, dst).await,
+            Memory(cmd) => cmd.apply(db, dst).await,
+            Migrate(cmd) => cmd.apply(db, dst).await,
+            Psync(cmd) => cmd.apply(db, dst, shutdown).await,
             Publish(cmd) => cmd.apply(db, dst).await,
+            SPublish(cmd) => cmd.apply(db, dst).await,
+            RandomKey(cmd) => cmd.apply(db, dst).await,
+            ReplConf(cmd) => cmd.apply(dst).await,
+            ReplicaOf(cmd) => cmd.apply(db, dst).await,
+            Restore(cmd) => cmd.apply(db, dst).await,
+            Save(cmd) => cmd.apply(db, dst).await,
+            Sentinel(cmd) => cmd.apply(db, dst).await,
             Set(cmd) => cmd.apply(db, dst).await,
-            Subscribe(cmd) => cmd.apply(db, dst, shutdown).await,
+            Strlen(cmd) => cmd.apply(db, dst).await,
+            // 订阅状态以 `RESET` 结束，或者以关闭连接的 `QUIT` 结束。
+            Subscribe(cmd) => return cmd.apply(db, dst, shutdown).await,
+            PSubscribe(cmd) => return cmd.apply(db, dst, shutdown).await,
+            SSubscribe(cmd) => return cmd.apply(db, dst, shutdown).await,
             Ping(cmd) => cmd.apply(dst).await,
+            Quit(cmd) => {
+                cmd.apply(dst).await?;
+                return Ok(Flow::Quit);
+            }
+            Reset(cmd) => cmd.apply(dst).await,
+            Type(cmd) => cmd.apply(db, dst).await,
+            Wait(cmd) => cmd.apply(db, dst).await,
             Unknown(cmd) => cmd.apply(dst).await,
             // `Unsubscribe` 不能在此上下文中应用。它只能从 `Subscribe` 命令的上下文中接收。
             Unsubscribe(_) => Err("`Unsubscribe` is unsupported in this context".into()),
+            PUnsubscribe(_) => Err("`PUnsubscribe` is unsupported in this context".into()),
+            SUnsubscribe(_) => Err("`SUnsubscribe` is unsupported in this context".into()),
+        }?;
+
+        Ok(Flow::Continue)
+    }
+
+    /// 如果命令会修改数据集，返回 `true`
+    pub(crate) fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Del(_)
+                | Command::Expire(_)
+                | Command::Migrate(_)
+                | Command::Restore(_)
+                | Command::Set(_)
+        )
+    }
+
+    /// 如果命令在写入回复之前可能等待较长时间，返回 `true`，例如等待网络、
+    /// 副本或者同步的磁盘 I/O
+    pub(crate) fn blocks(&self) -> bool {
+        matches!(
+            self,
+            Command::Cluster(Cluster::Meet(..))
+                | Command::Migrate(_)
+                | Command::Psync(_)
+                | Command::Save(_)
+                | Command::Wait(_)
+        )
+    }
+
+    /// 返回命令访问的键，在集群模式下用于将命令路由到负责的节点
+    ///
+    /// 分片频道与键一样映射到哈希槽
+    pub(crate) fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Del(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            Command::Dump(cmd) => vec![cmd.key()],
+            Command::Expire(cmd) => vec![cmd.key()],
+            Command::Get(cmd) => vec![cmd.key()],
+            Command::Memory(cmd) => cmd.key().into_iter().collect(),
+            Command::Restore(cmd) => vec![cmd.key()],
+            Command::Set(cmd) => vec![cmd.key()],
+            Command::Strlen(cmd) => vec![cmd.key()],
+            Command::Type(cmd) => vec![cmd.key()],
+            Command::SPublish(cmd) => vec![cmd.channel()],
+            Command::SSubscribe(cmd) => cmd.channels().iter().map(String::as_str).collect(),
+            _ => vec![],
         }
     }
 
     /// 返回命令名称
     pub(crate) fn get_name(&self) -> &str {
         match self {
+            Command::Asking(_) => "asking",
+            Command::BgRewriteAof(_) => "bgrewriteaof",
+            Command::BgSave(_) => "bgsave",
+            Command::Cluster(_) => "cluster",
+            Command::Config(_) => "config",
+            Command::Debug(_) => "debug",
+            Command::Del(_) => "del",
+            Command::Dump(_) => "dump",
+            Command::Expire(_) => "expire",
             Command::Get(_) => "get",
+            Command::Info(_) => "info",
+            Command::LastSave(_) => "lastsave",
+            Command::Memory(_) => "memory",
+            Command::Migrate(_) => "migrate",
+            Command::Psync(_) => "psync",
             Command::Publish(_) => "pub",
+            Command::SPublish(_) => "spublish",
+            Command::RandomKey(_) => "randomkey",
+            Command::ReplConf(_) => "replconf",
+            Command::ReplicaOf(_) => "replicaof",
+            Command::Restore(_) => "restore",
+            Command::Save(_) => "save",
+            Command::Sentinel(_) => "sentinel",
             Command::Set(_) => "set",
+            Command::Strlen(_) => "strlen",
             Command::Subscribe(_) => "subscribe",
             Command::Unsubscribe(_) => "unsubscribe",
+            Command::PSubscribe(_) => "psubscribe",
+            Command::PUnsubscribe(_) => "punsubscribe",
+            Command::SSubscribe(_) => "ssubscribe",
+            Command::SUnsubscribe(_) => "sunsubscribe",
             Command::Ping(_) => "ping",
+            Command::Quit(_) => "quit",
+            Command::Reset(_) => "reset",
+            Command::Type(_) => "type",
+            Command::Wait(_) => "wait",
             Command::Unknown(cmd) => cmd.get_name(),
         }
     }
@@ -66,6 +66,11 @@ impl Ping {
         Ok(())
     }
 
+    /// Consumes the command, returning the optional message.
+    pub(crate) fn into_msg(self) -> Option<Bytes> {
+        self.msg
+    }
+
     /// Converts the command into an equivalent `Frame`.
     ///
     /// This is called by the client when encoding a `Ping` command to send
@@ -0,0 +1,135 @@
+use crate::cmd::ReplConf;
+use crate::replication::Resync;
+use crate::{snapshot, Command, Connection, Db, Frame, Parse, Shutdown};
+
+use bytes::Bytes;
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info, instrument};
+
+/// Start replicating the server.
+///
+/// Sent by replicas to their primary. `replid` and `offset` identify the
+/// position in the replication stream the replica already received, `?` and
+/// `-1` when the replica has no data yet.
+///
+/// The primary replies `+FULLRESYNC replid offset` followed by a snapshot of
+/// the dataset, or `+CONTINUE replid` followed by the writes the replica
+/// missed. Afterwards, every write is streamed to the replica until the
+/// connection is closed. Meanwhile, the replica reports the offset it applied
+/// with `REPLCONF ACK`.
+#[derive(Debug)]
+pub struct Psync {
+    /// Replication ID of the stream received by the replica
+    replid: String,
+
+    /// Offset of the stream received by the replica
+    offset: i64,
+}
+
+impl Psync {
+    /// Create a new `Psync` command resuming the stream `replid` at `offset`.
+    pub(crate) fn new(replid: impl ToString, offset: i64) -> Psync {
+        Psync {
+            replid: replid.to_string(),
+            offset,
+        }
+    }
+
+    /// Parse a `Psync` instance from a received frame.
+    ///
+    /// The `PSYNC` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing three entries.
+    ///
+    /// ```text
+    /// PSYNC replid offset
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Psync> {
+        let replid = parse.next_string()?;
+        let offset = parse.next_string()?.parse()?;
+
+        Ok(Psync { replid, offset })
+    }
+
+    /// Apply the `Psync` command to the specified `Db` instance.
+    ///
+    /// The connection is used to stream the writes to the replica until it is
+    /// closed or the server shuts down.
+    #[instrument(skip(self, db, dst, shutdown))]
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        dst: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<()> {
+        // The receiver is created under the same lock as the synchronization
+        // data, so no write is missed or sent twice.
+        let (sync, mut stream, replica) = db.psync(&self.replid, self.offset);
+
+        match sync {
+            Resync::Full {
+                replid,
+                offset,
+                records,
+            } => {
+                info!(keys = records.len(), "full resynchronization of replica");
+
+                let response = Frame::Simple(format!("FULLRESYNC {} {}", replid, offset));
+                dst.write_frame(&response).await?;
+                dst.write_frame(&Frame::Bulk(snapshot::encode(&records)))
+                    .await?;
+            }
+            Resync::Partial { replid, missed } => {
+                info!(
+                    missed = missed.len(),
+                    "partial resynchronization of replica"
+                );
+
+                let response = Frame::Simple(format!("CONTINUE {}", replid));
+                dst.write_frame(&response).await?;
+                dst.write_bytes(&missed).await?;
+            }
+        }
+
+        loop {
+            select! {
+                res = stream.recv() => match res {
+                    Ok(data) => dst.write_bytes(&data).await?,
+                    // The replica reconnects and resumes from the backlog.
+                    Err(RecvError::Lagged(_)) => {
+                        return Err("replica is too slow to receive the replication stream".into())
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                res = dst.read_frame() => match res? {
+                    Some(frame) => match Command::from_frame(frame)? {
+                        Command::ReplConf(ReplConf::Ack(offset)) => replica.ack(offset),
+                        Command::ReplConf(cmd) => match cmd.announced_addr() {
+                            Some((host, port)) => replica.set_addr(host, port),
+                            None => debug!(?cmd, "ignoring command sent by replica"),
+                        },
+                        cmd => debug!(?cmd, "ignoring command sent by replica"),
+                    },
+                    // The replica disconnected.
+                    None => return Ok(()),
+                },
+                _ = shutdown.recv() => return Ok(()),
+            }
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Psync` command to send to
+    /// the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("psync".as_bytes()));
+        frame.push_bulk(Bytes::from(self.replid.into_bytes()));
+        frame.push_bulk(Bytes::from(self.offset.to_string()));
+        frame
+    }
+}
@@ -98,3 +98,77 @@ impl Publish {
         frame
     }
 }
+
+/// Posts a message to the given shard channel.
+///
+/// Shard channels use a namespace separate from regular channels. The message
+/// is only delivered to clients subscribed with `SSUBSCRIBE`, and is not
+/// matched against patterns.
+#[derive(Debug)]
+pub struct SPublish {
+    /// Name of the shard channel on which the message should be published.
+    channel: String,
+
+    /// The message to publish.
+    message: Bytes,
+}
+
+impl SPublish {
+    /// Create a new `SPublish` command which sends `message` on the shard
+    /// `channel`.
+    pub(crate) fn new(channel: impl ToString, message: Bytes) -> SPublish {
+        SPublish {
+            channel: channel.to_string(),
+            message,
+        }
+    }
+
+    /// Get the shard channel
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// Parse a `SPublish` instance from a received frame.
+    ///
+    /// The `SPUBLISH` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing three entries.
+    ///
+    /// ```text
+    /// SPUBLISH shardchannel message
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<SPublish> {
+        let channel = parse.next_string()?;
+        let message = parse.next_bytes()?;
+
+        Ok(SPublish { channel, message })
+    }
+
+    /// Apply the `SPublish` command to the specified `Db` instance.
+    ///
+    /// The number of shard channel subscribers is written to `dst`. As with
+    /// `Publish`, it should only be used as a "hint".
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let num_subscribers = db.spublish(&self.channel, self.message);
+
+        let response = Frame::Integer(num_subscribers as u64);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `SPublish` command to
+    /// send to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("spublish".as_bytes()));
+        frame.push_bulk(Bytes::from(self.channel.into_bytes()));
+        frame.push_bulk(self.message);
+
+        frame
+    }
+}
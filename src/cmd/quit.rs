@@ -0,0 +1,50 @@
+use crate::{Connection, Frame, Parse};
+
+use tracing::{debug, instrument};
+
+/// Ask the server to close the connection.
+///
+/// The connection is closed as soon as all pending replies have been written
+/// to the client.
+#[derive(Debug, Default)]
+pub struct Quit;
+
+impl Quit {
+    /// Create a new `Quit` command.
+    pub fn new() -> Quit {
+        Quit
+    }
+
+    /// Parse a `Quit` instance from a received frame.
+    ///
+    /// The `QUIT` string has already been consumed and the command takes no
+    /// arguments.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing a single entry.
+    ///
+    /// ```text
+    /// QUIT
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<Quit> {
+        Ok(Quit)
+    }
+
+    /// Apply the `Quit` command.
+    ///
+    /// `OK` is written to `dst`, after which the write half of the connection
+    /// is shut down. The caller is responsible for no longer reading requests
+    /// from the connection.
+    #[instrument(skip(self, dst))]
+    pub(crate) async fn apply(self, dst: &mut Connection) -> crate::Result<()> {
+        let response = Frame::Simple("OK".to_string());
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+        dst.shutdown().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use crate::cmd::{Parse, ParseError};
+use crate::{Connection, Frame};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Exchange replication information between a primary and its replicas.
+///
+/// Replicas report the offset of the replication stream they applied with
+/// `REPLCONF ACK offset`, once per second and whenever the primary asks for
+/// it with `REPLCONF GETACK *`. Replicas announce their address with the
+/// `listening-port` and `ip-address` options. Other options are accepted and
+/// ignored.
+#[derive(Debug)]
+pub enum ReplConf {
+    /// The replica applied the replication stream up to the offset.
+    Ack(u64),
+
+    /// The primary asks the replica to report its offset.
+    GetAck,
+
+    /// Other options, as `(option, value)` pairs.
+    Options(Vec<(String, String)>),
+}
+
+impl ReplConf {
+    /// Parse a `ReplConf` instance from a received frame.
+    ///
+    /// The `REPLCONF` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// REPLCONF ACK offset
+    /// REPLCONF GETACK *
+    /// REPLCONF option value [option value ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ReplConf> {
+        let option = parse.next_string()?.to_lowercase();
+
+        match &option[..] {
+            "ack" => Ok(ReplConf::Ack(parse.next_int()?)),
+            "getack" => {
+                parse.next_string()?;
+                Ok(ReplConf::GetAck)
+            }
+            _ => {
+                let mut options = vec![(option, parse.next_string()?)];
+
+                loop {
+                    match parse.next_string() {
+                        Ok(option) => options.push((option.to_lowercase(), parse.next_string()?)),
+                        Err(ParseError::EndOfStream) => return Ok(ReplConf::Options(options)),
+                        Err(err) => return Err(err.into()),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Returns `true` for `REPLCONF GETACK`.
+    pub(crate) fn is_getack(&self) -> bool {
+        matches!(self, ReplConf::GetAck)
+    }
+
+    /// Returns the address announced with the `ip-address` and
+    /// `listening-port` options.
+    pub(crate) fn announced_addr(&self) -> Option<(String, u16)> {
+        let options = match self {
+            ReplConf::Options(options) => options,
+            _ => return None,
+        };
+
+        let option = |name: &str| {
+            options
+                .iter()
+                .find(|(option, _)| option == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        let port = option("listening-port")?.parse().ok()?;
+        let host = option("ip-address")?;
+
+        Some((host.to_string(), port))
+    }
+
+    /// Apply the `ReplConf` command.
+    ///
+    /// Acknowledgements are only meaningful on the replication link, see
+    /// `Psync`, and are not replied to.
+    #[instrument(skip(self, dst))]
+    pub(crate) async fn apply(self, dst: &mut Connection) -> crate::Result<()> {
+        if let ReplConf::Options(_) = self {
+            let response = Frame::Simple("OK".to_string());
+            debug!(?response);
+            dst.write_frame(&response).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("replconf".as_bytes()));
+        match self {
+            ReplConf::Ack(offset) => {
+                frame.push_bulk(Bytes::from("ACK".as_bytes()));
+                frame.push_bulk(Bytes::from(offset.to_string()));
+            }
+            ReplConf::GetAck => {
+                frame.push_bulk(Bytes::from("GETACK".as_bytes()));
+                frame.push_bulk(Bytes::from("*".as_bytes()));
+            }
+            ReplConf::Options(options) => {
+                for (option, value) in options {
+                    frame.push_bulk(Bytes::from(option.into_bytes()));
+                    frame.push_bulk(Bytes::from(value.into_bytes()));
+                }
+            }
+        }
+        frame
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{Connection, Db, Frame, Parse};
+
+use bytes::Bytes;
+use std::convert::TryFrom;
+use tracing::{debug, instrument};
+
+/// Make the server a replica of another server, or promote it to a primary.
+///
+/// `REPLICAOF host port` discards the dataset and replicates the given
+/// primary. Replicas reject writes from clients. `REPLICAOF NO ONE` stops the
+/// replication and keeps the dataset.
+#[derive(Debug)]
+pub struct ReplicaOf {
+    /// Host and port of the primary, `None` for `NO ONE`
+    primary: Option<(String, u16)>,
+}
+
+impl ReplicaOf {
+    /// Create a new `ReplicaOf` command. `None` promotes the server to a
+    /// primary.
+    pub fn new(primary: Option<(String, u16)>) -> ReplicaOf {
+        ReplicaOf { primary }
+    }
+
+    /// Parse a `ReplicaOf` instance from a received frame.
+    ///
+    /// The `REPLICAOF` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing three entries.
+    ///
+    /// ```text
+    /// REPLICAOF host port
+    /// REPLICAOF NO ONE
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ReplicaOf> {
+        let host = parse.next_string()?;
+        let port = parse.next_string()?;
+
+        if host.to_uppercase() == "NO" && port.to_uppercase() == "ONE" {
+            return Ok(ReplicaOf { primary: None });
+        }
+
+        let port = port
+            .parse::<u64>()
+            .ok()
+            .and_then(|port| u16::try_from(port).ok())
+            .ok_or("invalid port")?;
+
+        Ok(ReplicaOf {
+            primary: Some((host, port)),
+        })
+    }
+
+    /// Apply the `ReplicaOf` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        db.replicaof(self.primary);
+
+        let response = Frame::Simple("OK".to_string());
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `ReplicaOf` command to
+    /// send to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("replicaof".as_bytes()));
+        match self.primary {
+            Some((host, port)) => {
+                frame.push_bulk(Bytes::from(host.into_bytes()));
+                frame.push_bulk(Bytes::from(port.to_string()));
+            }
+            None => {
+                frame.push_bulk(Bytes::from("no".as_bytes()));
+                frame.push_bulk(Bytes::from("one".as_bytes()));
+            }
+        }
+        frame
+    }
+}
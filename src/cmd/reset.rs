@@ -0,0 +1,50 @@
+use crate::{Connection, Frame, Parse};
+
+use tracing::{debug, instrument};
+
+/// Reset the connection's server-side context.
+///
+/// A connection in the subscribed state is unsubscribed from all channels and
+/// patterns and returns to the normal state. mini-redis does not keep any
+/// other per-connection state, so outside of the subscribed state `RESET` only
+/// acknowledges the request.
+#[derive(Debug, Default)]
+pub struct Reset;
+
+impl Reset {
+    /// Create a new `Reset` command.
+    pub fn new() -> Reset {
+        Reset
+    }
+
+    /// Parse a `Reset` instance from a received frame.
+    ///
+    /// The `RESET` string has already been consumed and the command takes no
+    /// arguments.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing a single entry.
+    ///
+    /// ```text
+    /// RESET
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<Reset> {
+        Ok(Reset)
+    }
+
+    /// Apply the `Reset` command.
+    ///
+    /// Any subscriptions must already have been dropped by the caller. The
+    /// `RESET` acknowledgement is written to `dst`.
+    #[instrument(skip(self, dst))]
+    pub(crate) async fn apply(self, dst: &mut Connection) -> crate::Result<()> {
+        let response = Frame::Simple("RESET".to_string());
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+}
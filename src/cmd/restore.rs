@@ -0,0 +1,185 @@
+use crate::cmd::set::until;
+use crate::cmd::{Parse, ParseError};
+use crate::db::WriteError;
+use crate::rdb;
+use crate::{Connection, Db, Frame};
+
+use bytes::Bytes;
+use std::time::Duration;
+use tracing::{debug, instrument};
+
+/// Create a key from a value serialized with `DUMP`.
+///
+/// # Options
+///
+/// * REPLACE -- Overwrite the key if it already exists. Without this option,
+///   restoring an existing key fails with a `BUSYKEY` error.
+/// * ABSTTL -- `ttl` is the UNIX time, in milliseconds, at which the key
+///   expires instead of a time to live.
+#[derive(Debug)]
+pub struct Restore {
+    /// Name of the key to create
+    key: String,
+
+    /// Time to live in milliseconds, or absolute UNIX time with `absttl`. `0`
+    /// means the key does not expire.
+    ttl: u64,
+
+    /// Value serialized by `DUMP`
+    payload: Bytes,
+
+    /// Overwrite an existing key
+    replace: bool,
+
+    /// `ttl` is an absolute UNIX time
+    absttl: bool,
+}
+
+impl Restore {
+    /// Create a new `Restore` command which creates `key` from `payload`.
+    ///
+    /// If `ttl` is `Some`, the key expires after the specified duration,
+    /// rounded up to the millisecond. A time to live under a millisecond is
+    /// sent as `1`, since `0` means that the key does not expire.
+    pub fn new(
+        key: impl ToString,
+        ttl: Option<Duration>,
+        payload: Bytes,
+        replace: bool,
+    ) -> Restore {
+        Restore {
+            key: key.to_string(),
+            ttl: ttl
+                .map(|ttl| (ttl.as_nanos().div_ceil(1_000_000) as u64).max(1))
+                .unwrap_or(0),
+            payload,
+            replace,
+            absttl: false,
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Parse a `Restore` instance from a received frame.
+    ///
+    /// The `RESTORE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing at least four entries.
+    ///
+    /// ```text
+    /// RESTORE key ttl serialized-value [REPLACE] [ABSTTL]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Restore> {
+        use ParseError::EndOfStream;
+
+        let key = parse.next_string()?;
+        let ttl = parse.next_int()?;
+        let payload = parse.next_bytes()?;
+
+        let mut replace = false;
+        let mut absttl = false;
+
+        loop {
+            match parse.next_string() {
+                Ok(s) if s.to_uppercase() == "REPLACE" => replace = true,
+                Ok(s) if s.to_uppercase() == "ABSTTL" => absttl = true,
+                Ok(s) => return Err(format!("unsupported `RESTORE` option `{}`", s).into()),
+                Err(EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(Restore {
+            key,
+            ttl,
+            payload,
+            replace,
+            absttl,
+        })
+    }
+
+    /// Apply the `Restore` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match self.restore(db) {
+            Ok(()) => {
+                // Log the write before acknowledging it.
+                db.flush_aof().await?;
+                Frame::Simple("OK".to_string())
+            }
+            Err(response) => response,
+        };
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Restores the key. The error is the reply to send to the client.
+    fn restore(self, db: &Db) -> Result<(), Frame> {
+        let value = match rdb::restore(&self.payload) {
+            Ok(value) => value,
+            Err(err) => return Err(Frame::Error(format!("ERR {}", err))),
+        };
+
+        let expire = match (self.ttl, self.absttl) {
+            (0, _) => None,
+            (ms, true) => Some(until(Duration::from_millis(ms))),
+            (ms, false) => Some(Duration::from_millis(ms)),
+        };
+
+        let busy = || Frame::Error("BUSYKEY Target key name already exists.".to_string());
+
+        // A key that is already expired is not created, but an existing key is
+        // still replaced.
+        if expire == Some(Duration::ZERO) {
+            if !self.replace && db.get(&self.key).is_some() {
+                return Err(busy());
+            }
+
+            db.del(&self.key);
+            return Ok(());
+        }
+
+        match db.restore(self.key, value, expire, self.replace) {
+            Ok(true) => {}
+            Ok(false) => return Err(busy()),
+            Err(WriteError::InvalidExpireTime) => {
+                return Err(Frame::Error(
+                    "ERR invalid expire time in 'restore' command".to_string(),
+                ))
+            }
+            Err(err) => return Err(Frame::Error(err.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Restore` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("restore".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame.push_int(self.ttl);
+        frame.push_bulk(self.payload);
+        if self.replace {
+            frame.push_bulk(Bytes::from("replace".as_bytes()));
+        }
+        if self.absttl {
+            frame.push_bulk(Bytes::from("absttl".as_bytes()));
+        }
+        frame
+    }
+}
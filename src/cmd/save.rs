@@ -0,0 +1,87 @@
+use crate::{Connection, Db, Frame, Parse};
+
+use tracing::{debug, instrument};
+
+/// Save the dataset to the snapshot file.
+///
+/// Replies once the snapshot has been written. The snapshot is written on the
+/// blocking thread pool, other clients are still served while saving.
+#[derive(Debug, Default)]
+pub struct Save;
+
+/// Save the dataset to the snapshot file in the background.
+///
+/// Replies as soon as the save started. `LASTSAVE` can be used to check when
+/// the save completed.
+#[derive(Debug, Default)]
+pub struct BgSave;
+
+impl Save {
+    /// Parse a `Save` instance from a received frame.
+    ///
+    /// The `SAVE` string has already been consumed and the command takes no
+    /// arguments.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing a single entry.
+    ///
+    /// ```text
+    /// SAVE
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<Save> {
+        Ok(Save)
+    }
+
+    /// Apply the `Save` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match db.save().await {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        };
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+}
+
+impl BgSave {
+    /// Parse a `BgSave` instance from a received frame.
+    ///
+    /// The `BGSAVE` string has already been consumed and the command takes no
+    /// arguments.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing a single entry.
+    ///
+    /// ```text
+    /// BGSAVE
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<BgSave> {
+        Ok(BgSave)
+    }
+
+    /// Apply the `BgSave` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = match db.bgsave() {
+            Ok(()) => Frame::Simple("Background saving started".to_string()),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        };
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use crate::cmd::Parse;
+use crate::{Connection, Db, Frame};
+
+use bytes::Bytes;
+use std::convert::TryFrom;
+use tracing::{debug, instrument};
+
+/// Query a sentinel, or ask it whether the primary is down.
+///
+/// Only available when the server runs as a sentinel, see `sentinel`.
+#[derive(Debug)]
+pub enum Sentinel {
+    /// `SENTINEL get-master-addr-by-name name`: the address of the primary.
+    GetMasterAddrByName(String),
+
+    /// `SENTINEL master name`: the state of the primary.
+    Master(String),
+
+    /// `SENTINEL is-master-down-by-addr host port epoch runid`: whether the
+    /// sentinel considers the primary down. Unless `runid` is `*`, the
+    /// sentinel `runid` also asks for a vote in `epoch`.
+    IsMasterDownByAddr {
+        host: String,
+        port: u16,
+        epoch: u64,
+        runid: String,
+    },
+}
+
+impl Sentinel {
+    /// Parse a `Sentinel` instance from a received frame.
+    ///
+    /// The `SENTINEL` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// ```text
+    /// SENTINEL GET-MASTER-ADDR-BY-NAME name
+    /// SENTINEL MASTER name
+    /// SENTINEL IS-MASTER-DOWN-BY-ADDR host port epoch runid
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Sentinel> {
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "get-master-addr-by-name" => Ok(Sentinel::GetMasterAddrByName(parse.next_string()?)),
+            "master" => Ok(Sentinel::Master(parse.next_string()?)),
+            "is-master-down-by-addr" => {
+                let host = parse.next_string()?;
+                let port = u16::try_from(parse.next_int()?).map_err(|_| "invalid port")?;
+                let epoch = parse.next_int()?;
+                let runid = parse.next_string()?;
+
+                Ok(Sentinel::IsMasterDownByAddr {
+                    host,
+                    port,
+                    epoch,
+                    runid,
+                })
+            }
+            _ => Err(format!("unsupported `SENTINEL` subcommand `{}`", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Sentinel` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = db.with_sentinel(|sentinel| match self {
+            Sentinel::GetMasterAddrByName(name) => match sentinel.primary_addr(&name) {
+                Some((host, port)) => {
+                    let mut frame = Frame::array();
+                    frame.push_bulk(Bytes::from(host.clone()));
+                    frame.push_bulk(Bytes::from(port.to_string()));
+                    frame
+                }
+                None => Frame::Null,
+            },
+            Sentinel::Master(name) => sentinel
+                .master_frame(&name)
+                .unwrap_or_else(|| Frame::Error("ERR No such master with that name".to_string())),
+            Sentinel::IsMasterDownByAddr {
+                host,
+                port,
+                epoch,
+                runid,
+            } => sentinel.is_master_down(&host, port, epoch, &runid),
+        });
+
+        let response = response.unwrap_or_else(|| {
+            Frame::Error("ERR This instance is not running in sentinel mode".to_string())
+        });
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by sentinels and clients when encoding a `Sentinel`
+    /// command to send to a sentinel.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("sentinel".as_bytes()));
+        match self {
+            Sentinel::GetMasterAddrByName(name) => {
+                frame.push_bulk(Bytes::from("get-master-addr-by-name".as_bytes()));
+                frame.push_bulk(Bytes::from(name.into_bytes()));
+            }
+            Sentinel::Master(name) => {
+                frame.push_bulk(Bytes::from("master".as_bytes()));
+                frame.push_bulk(Bytes::from(name.into_bytes()));
+            }
+            Sentinel::IsMasterDownByAddr {
+                host,
+                port,
+                epoch,
+                runid,
+            } => {
+                frame.push_bulk(Bytes::from("is-master-down-by-addr".as_bytes()));
+                frame.push_bulk(Bytes::from(host.into_bytes()));
+                frame.push_bulk(Bytes::from(port.to_string()));
+                frame.push_bulk(Bytes::from(epoch.to_string()));
+                frame.push_bulk(Bytes::from(runid.into_bytes()));
+            }
+        }
+        frame
+    }
+}
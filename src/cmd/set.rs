@@ -1,8 +1,9 @@
 use crate::cmd::{Parse, ParseError};
+use crate::db::WriteError;
 use crate::{Connection, Db, Frame};
 
 use bytes::Bytes;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::{debug, instrument};
 
 /// Set `key` to hold the string `value`.
@@ -17,6 +18,10 @@ use tracing::{debug, instrument};
 ///
 /// * EX `seconds` -- Set the specified expire time, in seconds.
 /// * PX `milliseconds` -- Set the specified expire time, in milliseconds.
+/// * EXAT `timestamp` -- Set the specified UNIX time at which the key expires,
+///   in seconds.
+/// * PXAT `timestamp` -- Set the specified UNIX time at which the key expires,
+///   in milliseconds.
 #[derive(Debug)]
 pub struct Set {
     /// the lookup key
@@ -75,7 +80,7 @@ impl Set {
     /// Expects an array frame containing at least 3 entries.
     ///
     /// ```text
-    /// SET key value [EX seconds|PX milliseconds]
+    /// SET key value [EX seconds|PX milliseconds|EXAT timestamp|PXAT timestamp]
     /// ```
     pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Set> {
         use ParseError::EndOfStream;
@@ -104,6 +109,16 @@ impl Set {
                 let ms = parse.next_int()?;
                 expire = Some(Duration::from_millis(ms));
             }
+            Ok(s) if s.to_uppercase() == "EXAT" => {
+                // An absolute expiration is specified in seconds.
+                let secs = parse.next_int()?;
+                expire = Some(until(Duration::from_secs(secs)));
+            }
+            Ok(s) if s.to_uppercase() == "PXAT" => {
+                // An absolute expiration is specified in milliseconds.
+                let ms = parse.next_int()?;
+                expire = Some(until(Duration::from_millis(ms)));
+            }
             // Currently, mini-redis does not support any of the other SET
             // options. An error here results in the connection being
             // terminated. Other connections will continue to operate normally.
@@ -125,11 +140,21 @@ impl Set {
     /// 响应被写入 `dst`。这是由服务器调用以执行接收到的命令
     #[instrument(skip(self, db, dst))]
     pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
-        // Set the value in the shared database state.
-        db.set(self.key, self.value, self.expire);
-
-        // Create a success response and write it to `dst`.
-        let response = Frame::Simple("OK".to_string());
+        // Set the value in the shared database state. The write is rejected
+        // if it exceeds the memory limit and no key can be evicted.
+        let response = match db.set(self.key, self.value, self.expire) {
+            Ok(()) => {
+                // Log the write before acknowledging it.
+                db.flush_aof().await?;
+
+                // Create a success response and write it to `dst`.
+                Frame::Simple("OK".to_string())
+            }
+            Err(WriteError::InvalidExpireTime) => {
+                Frame::Error("ERR invalid expire time in 'set' command".to_string())
+            }
+            Err(err) => Frame::Error(err.to_string()),
+        };
         debug!(?response);
         dst.write_frame(&response).await?;
 
@@ -158,3 +183,13 @@ impl Set {
         frame
     }
 }
+
+/// Returns the time remaining until the UNIX time `since_epoch`. Times in the
+/// past expire immediately. Times the clock can not represent saturate to
+/// `Duration::MAX`, which `Db` rejects as an invalid expire time.
+pub(crate) fn until(since_epoch: Duration) -> Duration {
+    match UNIX_EPOCH.checked_add(since_epoch) {
+        Some(when) => when.duration_since(SystemTime::now()).unwrap_or_default(),
+        None => Duration::MAX,
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{Connection, Db, Frame, Parse};
+
+use bytes::Bytes;
+use tracing::{debug, instrument};
+
+/// Returns the length of the string value stored at key, or 0 if the key does
+/// not exist.
+#[derive(Debug)]
+pub struct Strlen {
+    /// Name of the key
+    key: String,
+}
+
+impl Strlen {
+    /// Create a new `Strlen` command returning the length of the value of
+    /// `key`.
+    pub fn new(key: impl ToString) -> Strlen {
+        Strlen {
+            key: key.to_string(),
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Parse a `Strlen` instance from a received frame.
+    ///
+    /// The `STRLEN` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing two entries.
+    ///
+    /// ```text
+    /// STRLEN key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Strlen> {
+        let key = parse.next_string()?;
+
+        Ok(Strlen { key })
+    }
+
+    /// Apply the `Strlen` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let len = db.get(&self.key).map_or(0, |value| value.len());
+        let response = Frame::Integer(len as u64);
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Strlen` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("strlen".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame
+    }
+}
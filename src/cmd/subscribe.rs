@@ -1,4 +1,4 @@
-use crate::cmd::{Parse, ParseError, Unknown};
+use crate::cmd::{Flow, Parse, ParseError, Unknown};
 use crate::{Command, Connection, Db, Frame, Shutdown};
 
 use bytes::Bytes;
@@ -26,12 +26,80 @@ pub struct Unsubscribe {
     channels: Vec<String>,
 }
 
+/// Subscribes the client to one or more glob-style patterns.
+///
+/// Messages published on any channel whose name matches one of the patterns
+/// are delivered to the client as `pmessage` frames. Supported glob syntax is
+/// `*`, `?`, `[...]` and `\` escaping.
+#[derive(Debug)]
+pub struct PSubscribe {
+    patterns: Vec<String>,
+}
+
+/// Unsubscribes the client from one or more patterns.
+///
+/// When no patterns are specified, the client is unsubscribed from all the
+/// previously subscribed patterns.
+#[derive(Clone, Debug)]
+pub struct PUnsubscribe {
+    patterns: Vec<String>,
+}
+
+/// Subscribes the client to one or more shard channels.
+///
+/// Shard channels use a namespace separate from regular channels: messages
+/// sent with `SPUBLISH` are only delivered to `SSUBSCRIBE` subscribers and are
+/// never matched against patterns. Messages are delivered as `smessage`
+/// frames.
+#[derive(Debug)]
+pub struct SSubscribe {
+    channels: Vec<String>,
+}
+
+/// Unsubscribes the client from one or more shard channels.
+///
+/// When no channels are specified, the client is unsubscribed from all the
+/// previously subscribed shard channels.
+#[derive(Clone, Debug)]
+pub struct SUnsubscribe {
+    channels: Vec<String>,
+}
+
 /// Stream of messages. The stream receives messages from the
 /// `broadcast::Receiver`. We use `stream!` to create a `Stream` that consumes
 /// messages. Because `stream!` values cannot be named, we box the stream using
 /// a trait object.
 type Messages = Pin<Box<dyn Stream<Item = Bytes> + Send>>;
 
+/// Stream of messages received through a pattern subscription. Each message is
+/// paired with the name of the channel it was published on.
+type PatternMessages = Pin<Box<dyn Stream<Item = (String, Bytes)> + Send>>;
+
+/// The active subscriptions of a client in the subscribed state.
+///
+/// Redis reports the number of subscriptions as the sum of channel and pattern
+/// subscriptions, so both are tracked together. Shard channel subscriptions
+/// are counted separately.
+#[derive(Default)]
+struct Subscriptions {
+    /// Channel subscriptions, keyed by channel name.
+    channels: StreamMap<String, Messages>,
+
+    /// Pattern subscriptions, keyed by pattern.
+    patterns: StreamMap<String, PatternMessages>,
+
+    /// Shard channel subscriptions, keyed by channel name.
+    shard_channels: StreamMap<String, Messages>,
+}
+
+/// Subscriptions requested by the client that have not been applied yet.
+#[derive(Default)]
+struct PendingSubscriptions {
+    channels: Vec<String>,
+    patterns: Vec<String>,
+    shard_channels: Vec<String>,
+}
+
 impl Subscribe {
     /// Creates a new `Subscribe` command to listen on the specified channels.
     pub(crate) fn new(channels: Vec<String>) -> Subscribe {
@@ -98,59 +166,17 @@ impl Subscribe {
     ///
     /// [here]: https://redis.io/topics/pubsub
     pub(crate) async fn apply(
-        mut self,
+        self,
         db: &Db,
         dst: &mut Connection,
         shutdown: &mut Shutdown,
-    ) -> crate::Result<()> {
-        // Each individual channel subscription is handled using a
-        // `sync::broadcast` channel. Messages are then fanned out to all
-        // clients currently subscribed to the channels.
-        //
-        // An individual client may subscribe to multiple channels and may
-        // dynamically add and remove channels from its subscription set. To
-        // handle this, a `StreamMap` is used to track active subscriptions. The
-        // `StreamMap` merges messages from individual broadcast channels as
-        // they are received.
-        let mut subscriptions = StreamMap::new();
+    ) -> crate::Result<Flow> {
+        let pending = PendingSubscriptions {
+            channels: self.channels,
+            ..PendingSubscriptions::default()
+        };
 
-        loop {
-            // `self.channels` is used to track additional channels to subscribe
-            // to. When new `SUBSCRIBE` commands are received during the
-            // execution of `apply`, the new channels are pushed onto this vec.
-            for channel_name in self.channels.drain(..) {
-                subscribe_to_channel(channel_name, &mut subscriptions, db, dst).await?;
-            }
-
-            // Wait for one of the following to happen:
-            //
-            // - Receive a message from one of the subscribed channels.
-            // - Receive a subscribe or unsubscribe command from the client.
-            // - A server shutdown signal.
-            select! {
-                // Receive messages from subscribed channels
-                Some((channel_name, msg)) = subscriptions.next() => {
-                    dst.write_frame(&make_message_frame(channel_name, msg)).await?;
-                }
-                res = dst.read_frame() => {
-                    let frame = match res? {
-                        Some(frame) => frame,
-                        // This happens if the remote client has disconnected.
-                        None => return Ok(())
-                    };
-
-                    handle_command(
-                        frame,
-                        &mut self.channels,
-                        &mut subscriptions,
-                        dst,
-                    ).await?;
-                }
-                _ = shutdown.recv() => {
-                    return Ok(());
-                }
-            };
-        }
+        run_subscribed(pending, db, dst, shutdown).await
     }
 
     /// Converts the command into an equivalent `Frame`.
@@ -167,28 +193,112 @@ impl Subscribe {
     }
 }
 
+/// The subscribed state of a connection.
+///
+/// Entered through `SUBSCRIBE`, `PSUBSCRIBE` or `SSUBSCRIBE` with the initial
+/// list of channels, patterns or shard channels to subscribe to. Additional
+/// subscribe and unsubscribe commands may be received from the client and the
+/// list of subscriptions is updated accordingly.
+///
+/// Returns once the client disconnects, issues `RESET` or `QUIT`, or the server
+/// shuts down. `Flow::Quit` is returned when the connection must not be read
+/// from anymore, `Flow::Exit` otherwise.
+///
+/// [here]: https://redis.io/topics/pubsub
+async fn run_subscribed(
+    mut pending: PendingSubscriptions,
+    db: &Db,
+    dst: &mut Connection,
+    shutdown: &mut Shutdown,
+) -> crate::Result<Flow> {
+    // Each individual channel subscription is handled using a
+    // `sync::broadcast` channel. Messages are then fanned out to all
+    // clients currently subscribed to the channels.
+    //
+    // An individual client may subscribe to multiple channels and may
+    // dynamically add and remove channels from its subscription set. To
+    // handle this, a `StreamMap` is used to track active subscriptions. The
+    // `StreamMap` merges messages from individual broadcast channels as
+    // they are received.
+    let mut subscriptions = Subscriptions::default();
+
+    loop {
+        // `pending` is used to track additional subscriptions. When new
+        // subscribe commands are received while in the subscribed state, the
+        // new names are pushed onto its vecs.
+        for channel_name in pending.channels.drain(..) {
+            subscribe_to_channel(channel_name, &mut subscriptions, db, dst).await?;
+        }
+
+        for pattern in pending.patterns.drain(..) {
+            subscribe_to_pattern(pattern, &mut subscriptions, db, dst).await?;
+        }
+
+        for channel_name in pending.shard_channels.drain(..) {
+            subscribe_to_shard_channel(channel_name, &mut subscriptions, db, dst).await?;
+        }
+
+        // Wait for one of the following to happen:
+        //
+        // - Receive a message from one of the subscribed channels or patterns.
+        // - Receive a command from the client.
+        // - A server shutdown signal.
+        select! {
+            // Receive messages from subscribed channels
+            Some((channel_name, msg)) = subscriptions.channels.next() => {
+                dst.write_frame(&make_message_frame(channel_name, msg)).await?;
+            }
+            // Receive messages from subscribed patterns
+            Some((pattern, (channel_name, msg))) = subscriptions.patterns.next() => {
+                dst.write_frame(&make_pmessage_frame(pattern, channel_name, msg)).await?;
+            }
+            // Receive messages from subscribed shard channels
+            Some((channel_name, msg)) = subscriptions.shard_channels.next() => {
+                dst.write_frame(&make_smessage_frame(channel_name, msg)).await?;
+            }
+            res = dst.read_frame() => {
+                let frame = match res? {
+                    Some(frame) => frame,
+                    // This happens if the remote client has disconnected.
+                    None => return Ok(Flow::Quit)
+                };
+
+                let flow = handle_command(
+                    frame,
+                    &mut pending,
+                    &mut subscriptions,
+                    dst,
+                ).await?;
+
+                if flow != Flow::Continue {
+                    return Ok(flow);
+                }
+            }
+            _ = shutdown.recv() => {
+                return Ok(Flow::Exit);
+            }
+        };
+    }
+}
+
+impl Subscriptions {
+    /// Total number of channel and pattern subscriptions.
+    fn len(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+}
+
 async fn subscribe_to_channel(
     channel_name: String,
-    subscriptions: &mut StreamMap<String, Messages>,
+    subscriptions: &mut Subscriptions,
     db: &Db,
     dst: &mut Connection,
 ) -> crate::Result<()> {
-    let mut rx = db.subscribe(channel_name.clone());
-
     // Subscribe to the channel.
-    let rx = Box::pin(async_stream::stream! {
-        loop {
-            match rx.recv().await {
-                Ok(msg) => yield msg,
-                // If we lagged in consuming messages, just resume.
-                Err(broadcast::error::RecvError::Lagged(_)) => {}
-                Err(_) => break,
-            }
-        }
-    });
+    let rx = into_stream(db.subscribe(channel_name.clone()));
 
     // Track subscription in this client's subscription set.
-    subscriptions.insert(channel_name.clone(), rx);
+    subscriptions.channels.insert(channel_name.clone(), rx);
 
     // Respond with the successful subscription
     let response = make_subscribe_frame(channel_name, subscriptions.len());
@@ -197,26 +307,83 @@ async fn subscribe_to_channel(
     Ok(())
 }
 
-/// Handle a command received while inside `Subscribe::apply`. Only subscribe
-/// and unsubscribe commands are permitted in this context.
+async fn subscribe_to_pattern(
+    pattern: String,
+    subscriptions: &mut Subscriptions,
+    db: &Db,
+    dst: &mut Connection,
+) -> crate::Result<()> {
+    let rx = into_stream(db.psubscribe(pattern.clone()));
+
+    subscriptions.patterns.insert(pattern.clone(), rx);
+
+    let response = make_psubscribe_frame(pattern, subscriptions.len());
+    dst.write_frame(&response).await?;
+
+    Ok(())
+}
+
+async fn subscribe_to_shard_channel(
+    channel_name: String,
+    subscriptions: &mut Subscriptions,
+    db: &Db,
+    dst: &mut Connection,
+) -> crate::Result<()> {
+    let rx = into_stream(db.ssubscribe(channel_name.clone()));
+
+    subscriptions
+        .shard_channels
+        .insert(channel_name.clone(), rx);
+
+    // Unlike the other subscribe replies, the count only includes shard
+    // channels.
+    let response = make_ssubscribe_frame(channel_name, subscriptions.shard_channels.len());
+    dst.write_frame(&response).await?;
+
+    Ok(())
+}
+
+/// Converts a `broadcast::Receiver` into a `Stream` of the received messages.
+fn into_stream<T>(mut rx: broadcast::Receiver<T>) -> Pin<Box<dyn Stream<Item = T> + Send>>
+where
+    T: Clone + Send + 'static,
+{
+    Box::pin(async_stream::stream! {
+        loop {
+            match rx.recv().await {
+                Ok(msg) => yield msg,
+                // If we lagged in consuming messages, just resume.
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(_) => break,
+            }
+        }
+    })
+}
+
+/// Handle a command received while in the subscribed state. Only the
+/// subscription management commands, `PING`, `RESET` and `QUIT` are permitted
+/// in this context.
 ///
-/// Any new subscriptions are appended to `subscribe_to` instead of modifying
+/// Any new subscriptions are appended to `pending` instead of modifying
 /// `subscriptions`.
 async fn handle_command(
     frame: Frame,
-    subscribe_to: &mut Vec<String>,
-    subscriptions: &mut StreamMap<String, Messages>,
+    pending: &mut PendingSubscriptions,
+    subscriptions: &mut Subscriptions,
     dst: &mut Connection,
-) -> crate::Result<()> {
+) -> crate::Result<Flow> {
     // A command has been received from the client.
-    //
-    // Only `SUBSCRIBE` and `UNSUBSCRIBE` commands are permitted
-    // in this context.
     match Command::from_frame(frame)? {
         Command::Subscribe(subscribe) => {
-            // The `apply` method will subscribe to the channels we add to this
-            // vector.
-            subscribe_to.extend(subscribe.channels.into_iter());
+            // The subscriber loop will subscribe to the channels we add to
+            // this vector.
+            pending.channels.extend(subscribe.channels);
+        }
+        Command::PSubscribe(psubscribe) => {
+            pending.patterns.extend(psubscribe.patterns);
+        }
+        Command::SSubscribe(ssubscribe) => {
+            pending.shard_channels.extend(ssubscribe.channels);
         }
         Command::Unsubscribe(mut unsubscribe) => {
             // If no channels are specified, this requests unsubscribing from
@@ -225,24 +392,78 @@ async fn handle_command(
             // to.
             if unsubscribe.channels.is_empty() {
                 unsubscribe.channels = subscriptions
+                    .channels
                     .keys()
                     .map(|channel_name| channel_name.to_string())
                     .collect();
             }
 
             for channel_name in unsubscribe.channels {
-                subscriptions.remove(&channel_name);
+                subscriptions.channels.remove(&channel_name);
 
                 let response = make_unsubscribe_frame(channel_name, subscriptions.len());
                 dst.write_frame(&response).await?;
             }
         }
+        Command::PUnsubscribe(mut punsubscribe) => {
+            // Same as `UNSUBSCRIBE`, an empty list means all patterns.
+            if punsubscribe.patterns.is_empty() {
+                punsubscribe.patterns = subscriptions
+                    .patterns
+                    .keys()
+                    .map(|pattern| pattern.to_string())
+                    .collect();
+            }
+
+            for pattern in punsubscribe.patterns {
+                subscriptions.patterns.remove(&pattern);
+
+                let response = make_punsubscribe_frame(pattern, subscriptions.len());
+                dst.write_frame(&response).await?;
+            }
+        }
+        Command::SUnsubscribe(mut sunsubscribe) => {
+            // Same as `UNSUBSCRIBE`, an empty list means all shard channels.
+            if sunsubscribe.channels.is_empty() {
+                sunsubscribe.channels = subscriptions
+                    .shard_channels
+                    .keys()
+                    .map(|channel_name| channel_name.to_string())
+                    .collect();
+            }
+
+            for channel_name in sunsubscribe.channels {
+                subscriptions.shard_channels.remove(&channel_name);
+
+                let response =
+                    make_sunsubscribe_frame(channel_name, subscriptions.shard_channels.len());
+                dst.write_frame(&response).await?;
+            }
+        }
+        Command::Ping(ping) => {
+            // In the subscribed state, `PING` replies with a `pong` array
+            // instead of a simple string so that it cannot be confused with a
+            // published message.
+            let response = make_pong_frame(ping.into_msg());
+            dst.write_frame(&response).await?;
+        }
+        Command::Reset(reset) => {
+            // Dropping the streams unsubscribes from all channels and patterns
+            // without notifying the client, as `RESET` only replies once.
+            *subscriptions = Subscriptions::default();
+            reset.apply(dst).await?;
+            return Ok(Flow::Exit);
+        }
+        Command::Quit(quit) => {
+            quit.apply(dst).await?;
+            return Ok(Flow::Quit);
+        }
         command => {
             let cmd = Unknown::new(command.get_name());
             cmd.apply(dst).await?;
         }
     }
-    Ok(())
+    Ok(Flow::Continue)
 }
 
 /// Creates the response to a subscribe request.
@@ -268,6 +489,52 @@ fn make_unsubscribe_frame(channel_name: String, num_subs: usize) -> Frame {
     response
 }
 
+/// Creates the response to a psubscribe request.
+fn make_psubscribe_frame(pattern: String, num_subs: usize) -> Frame {
+    let mut response = Frame::array();
+    response.push_bulk(Bytes::from_static(b"psubscribe"));
+    response.push_bulk(Bytes::from(pattern));
+    response.push_int(num_subs as u64);
+    response
+}
+
+/// Creates the response to a punsubscribe request.
+fn make_punsubscribe_frame(pattern: String, num_subs: usize) -> Frame {
+    let mut response = Frame::array();
+    response.push_bulk(Bytes::from_static(b"punsubscribe"));
+    response.push_bulk(Bytes::from(pattern));
+    response.push_int(num_subs as u64);
+    response
+}
+
+/// Creates the response to a ssubscribe request.
+fn make_ssubscribe_frame(channel_name: String, num_subs: usize) -> Frame {
+    let mut response = Frame::array();
+    response.push_bulk(Bytes::from_static(b"ssubscribe"));
+    response.push_bulk(Bytes::from(channel_name));
+    response.push_int(num_subs as u64);
+    response
+}
+
+/// Creates the response to a sunsubscribe request.
+fn make_sunsubscribe_frame(channel_name: String, num_subs: usize) -> Frame {
+    let mut response = Frame::array();
+    response.push_bulk(Bytes::from_static(b"sunsubscribe"));
+    response.push_bulk(Bytes::from(channel_name));
+    response.push_int(num_subs as u64);
+    response
+}
+
+/// Creates the response to a ping received in the subscribed state. The
+/// second element is the ping message, or an empty bulk string if none was
+/// given.
+fn make_pong_frame(msg: Option<Bytes>) -> Frame {
+    let mut response = Frame::array();
+    response.push_bulk(Bytes::from_static(b"pong"));
+    response.push_bulk(msg.unwrap_or_default());
+    response
+}
+
 /// Creates a message informing the client about a new message on a channel that
 /// the client subscribes to.
 fn make_message_frame(channel_name: String, msg: Bytes) -> Frame {
@@ -278,6 +545,27 @@ fn make_message_frame(channel_name: String, msg: Bytes) -> Frame {
     response
 }
 
+/// Creates a message informing the client about a new message on a shard
+/// channel that the client subscribes to.
+fn make_smessage_frame(channel_name: String, msg: Bytes) -> Frame {
+    let mut response = Frame::array();
+    response.push_bulk(Bytes::from_static(b"smessage"));
+    response.push_bulk(Bytes::from(channel_name));
+    response.push_bulk(msg);
+    response
+}
+
+/// Creates a message informing the client about a new message on a channel
+/// matching a pattern that the client subscribes to.
+fn make_pmessage_frame(pattern: String, channel_name: String, msg: Bytes) -> Frame {
+    let mut response = Frame::array();
+    response.push_bulk(Bytes::from_static(b"pmessage"));
+    response.push_bulk(Bytes::from(pattern));
+    response.push_bulk(Bytes::from(channel_name));
+    response.push_bulk(msg);
+    response
+}
+
 impl Unsubscribe {
     /// Create a new `Unsubscribe` command with the given `channels`.
     pub(crate) fn new(channels: &[String]) -> Unsubscribe {
@@ -347,3 +635,180 @@ impl Unsubscribe {
         frame
     }
 }
+
+impl PSubscribe {
+    /// Parse a `PSubscribe` instance from a received frame.
+    ///
+    /// The `PSUBSCRIBE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing two or more entries.
+    ///
+    /// ```text
+    /// PSUBSCRIBE pattern [pattern ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<PSubscribe> {
+        use ParseError::EndOfStream;
+
+        // At least one pattern is required.
+        let mut patterns = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(s) => patterns.push(s),
+                Err(EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(PSubscribe { patterns })
+    }
+
+    /// Apply the `PSubscribe` command to the specified `Db` instance.
+    ///
+    /// Enters the subscribed state, see `Subscribe::apply`.
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        dst: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<Flow> {
+        let pending = PendingSubscriptions {
+            patterns: self.patterns,
+            ..PendingSubscriptions::default()
+        };
+
+        run_subscribed(pending, db, dst, shutdown).await
+    }
+}
+
+impl PUnsubscribe {
+    /// Parse a `PUnsubscribe` instance from a received frame.
+    ///
+    /// The `PUNSUBSCRIBE` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing at least one entry.
+    ///
+    /// ```text
+    /// PUNSUBSCRIBE [pattern [pattern ...]]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<PUnsubscribe, ParseError> {
+        use ParseError::EndOfStream;
+
+        let mut patterns = vec![];
+
+        loop {
+            match parse.next_string() {
+                Ok(s) => patterns.push(s),
+                Err(EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(PUnsubscribe { patterns })
+    }
+}
+
+impl SSubscribe {
+    /// Creates a new `SSubscribe` command to listen on the specified shard
+    /// channels.
+    pub(crate) fn new(channels: Vec<String>) -> SSubscribe {
+        SSubscribe { channels }
+    }
+
+    /// Get the shard channels
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// Parse a `SSubscribe` instance from a received frame.
+    ///
+    /// The `SSUBSCRIBE` string has already been consumed. The arguments are
+    /// the same as for `SUBSCRIBE`.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing two or more entries.
+    ///
+    /// ```text
+    /// SSUBSCRIBE shardchannel [shardchannel ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<SSubscribe> {
+        let Subscribe { channels } = Subscribe::parse_frames(parse)?;
+        Ok(SSubscribe { channels })
+    }
+
+    /// Apply the `SSubscribe` command to the specified `Db` instance.
+    ///
+    /// Enters the subscribed state, see `Subscribe::apply`.
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        dst: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<Flow> {
+        let pending = PendingSubscriptions {
+            shard_channels: self.channels,
+            ..PendingSubscriptions::default()
+        };
+
+        run_subscribed(pending, db, dst, shutdown).await
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `SSubscribe` command to
+    /// send to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("ssubscribe".as_bytes()));
+        for channel in self.channels {
+            frame.push_bulk(Bytes::from(channel.into_bytes()));
+        }
+        frame
+    }
+}
+
+impl SUnsubscribe {
+    /// Create a new `SUnsubscribe` command with the given `channels`.
+    pub(crate) fn new(channels: &[String]) -> SUnsubscribe {
+        SUnsubscribe {
+            channels: channels.to_vec(),
+        }
+    }
+
+    /// Parse a `SUnsubscribe` instance from a received frame.
+    ///
+    /// The `SUNSUBSCRIBE` string has already been consumed. The arguments are
+    /// the same as for `UNSUBSCRIBE`.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing at least one entry.
+    ///
+    /// ```text
+    /// SUNSUBSCRIBE [shardchannel [shardchannel ...]]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<SUnsubscribe, ParseError> {
+        let Unsubscribe { channels } = Unsubscribe::parse_frames(parse)?;
+        Ok(SUnsubscribe { channels })
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `SUnsubscribe` command to
+    /// send to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("sunsubscribe".as_bytes()));
+
+        for channel in self.channels {
+            frame.push_bulk(Bytes::from(channel.into_bytes()));
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{Connection, Db, Frame, Parse};
+
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+use tracing::{debug, instrument};
+
+/// Block until the writes made so far are applied by `numreplicas` replicas.
+///
+/// Returns the number of replicas that acknowledged the current replication
+/// offset, either once `numreplicas` is reached or when `timeout`, in
+/// milliseconds, elapses. A timeout of `0` blocks forever.
+#[derive(Debug)]
+pub struct Wait {
+    /// Number of replicas to wait for
+    numreplicas: u64,
+
+    /// Maximum time to wait, `None` to wait forever
+    timeout: Option<Duration>,
+}
+
+impl Wait {
+    /// Create a new `Wait` command.
+    pub fn new(numreplicas: u64, timeout: Option<Duration>) -> Wait {
+        Wait {
+            numreplicas,
+            timeout,
+        }
+    }
+
+    /// Parse a `Wait` instance from a received frame.
+    ///
+    /// The `WAIT` string has already been consumed.
+    ///
+    /// # Format
+    ///
+    /// Expects an array frame containing three entries.
+    ///
+    /// ```text
+    /// WAIT numreplicas timeout
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Wait> {
+        let numreplicas = parse.next_int()?;
+
+        let timeout = match parse.next_int()? {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        };
+
+        Ok(Wait {
+            numreplicas,
+            timeout,
+        })
+    }
+
+    /// Apply the `Wait` command to the specified `Db` instance.
+    ///
+    /// The response is written to `dst`. This is called by the server in order
+    /// to execute a received command.
+    #[instrument(skip(self, db, dst))]
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::Result<()> {
+        let response = if db.is_replica() {
+            Frame::Error("ERR WAIT cannot be used with replica instances".to_string())
+        } else {
+            Frame::Integer(self.wait(db).await as u64)
+        };
+
+        debug!(?response);
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Waits for the acknowledgements and returns the number of replicas that
+    /// acknowledged the offset.
+    async fn wait(&self, db: &Db) -> usize {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+
+        // The receiver is created before the first count, so an
+        // acknowledgement received in between still wakes the loop up.
+        let (offset, mut acked) = db.wait_offset();
+
+        let mut num_acked = db.num_acked(offset);
+
+        if num_acked as u64 >= self.numreplicas {
+            return num_acked;
+        }
+
+        // Ask the replicas for their offset instead of waiting for the next
+        // periodic acknowledgement.
+        db.request_acks();
+
+        loop {
+            let changed = acked.changed();
+
+            let res = match deadline {
+                Some(deadline) => tokio::select! {
+                    res = changed => res,
+                    _ = time::sleep_until(deadline) => return db.num_acked(offset),
+                },
+                None => changed.await,
+            };
+
+            // The replication backlog was dropped, no more acknowledgement is
+            // received.
+            if res.is_err() {
+                return db.num_acked(offset);
+            }
+
+            num_acked = db.num_acked(offset);
+
+            if num_acked as u64 >= self.numreplicas {
+                return num_acked;
+            }
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Wait` command to send to
+    /// the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("wait".as_bytes()));
+        frame.push_int(self.numreplicas);
+        frame.push_int(self.timeout.map(|t| t.as_millis() as u64).unwrap_or(0));
+        frame
+    }
+}
@@ -0,0 +1,91 @@
+//! 用于 `tokio_util::codec` 的 Redis 协议编解码器
+//!
+//! `RespCodec` 可以与 `Framed`、`FramedRead` 和 `FramedWrite` 一起使用，
+//! 在任意传输层上以 `Frame` 值的流和 sink 的形式读写 Redis 协议
+
+use crate::frame::{Error, Frame, Limits, Parser};
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// 将字节流解码为 `Frame` 值，并将 `Frame` 值编码为字节
+///
+/// 解码使用与 `Connection` 相同的增量解析器，不完整的帧在收到更多数据后从
+/// 中断的位置继续解析。编码使用 `Frame::encode`，支持嵌套数组
+///
+/// # Examples
+///
+/// ```
+/// use mini_redis::codec::RespCodec;
+/// use mini_redis::Frame;
+///
+/// use bytes::BytesMut;
+/// use tokio_util::codec::{Decoder, Encoder};
+///
+/// let mut codec = RespCodec::new();
+/// let mut buf = BytesMut::new();
+///
+/// codec.encode(Frame::Simple("OK".to_string()), &mut buf).unwrap();
+/// assert_eq!(&buf[..], b"+OK\r\n");
+///
+/// let frame = codec.decode(&mut buf).unwrap().unwrap();
+/// assert_eq!(frame, "OK");
+/// ```
+#[derive(Debug, Default)]
+pub struct RespCodec {
+    // 记录不完整帧的解析进度
+    parser: Parser,
+}
+
+impl RespCodec {
+    /// 创建一个使用默认 `Limits` 的编解码器
+    pub fn new() -> RespCodec {
+        RespCodec::default()
+    }
+
+    /// 创建一个解码时使用 `limits` 的编解码器
+    pub fn with_limits(limits: Limits) -> RespCodec {
+        RespCodec {
+            parser: Parser::with_limits(limits),
+        }
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = Frame;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, Error> {
+        self.parser.parse(src)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, Error> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            // 对等方在发送帧的过程中关闭了流，与 `Connection::read_frame`
+            // 相同。
+            None if !src.is_empty() || self.parser.is_partial() => {
+                Err("connection reset by peer".into())
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<Frame> for RespCodec {
+    type Error = Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), Error> {
+        frame.encode(dst);
+        Ok(())
+    }
+}
+
+impl Encoder<&Frame> for RespCodec {
+    type Error = Error;
+
+    fn encode(&mut self, frame: &Frame, dst: &mut BytesMut) -> Result<(), Error> {
+        frame.encode(dst);
+        Ok(())
+    }
+}
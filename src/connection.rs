@@ -1,42 +1,85 @@
-use crate::frame::{self, Frame};
+use crate::frame::{Frame, Limits, Parser};
 
-use bytes::{Buf, BytesMut};
-use std::io::{self, Cursor};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
-use tokio::net::TcpStream;
+use bytes::BytesMut;
+use std::fmt;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
+
+/// 可以承载 Redis 协议的双向字节流
+///
+/// 例如 `TcpStream`，或者测试中使用的 `tokio::io::duplex` 内存管道。
+/// 所有满足约束的类型都自动实现该 trait
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Transport for T {}
 
 /// 从远程对等方发送和接收 `Frame` 值
 ///
 /// 在实现网络协议时，该协议上的消息通常由几个较小的消息组成，称为帧。
-/// `Connection` 的目的是在底层 `TcpStream` 上读取和写入帧
+/// `Connection` 的目的是在底层流 `S` 上读取和写入帧。服务器和客户端使用
+/// 默认的 `Box<dyn Transport>`，因此可以使用任意传输层
 ///
 /// 要读取帧，`Connection` 使用内部缓冲区，该缓冲区会被填充，直到有足够的
 /// 字节来创建完整的帧。一旦这种情况发生，`Connection` 就会创建帧并将其返回
 /// 给调用者
 ///
 /// 发送帧时，帧首先被编码到写缓冲区中。然后写缓冲区的内容会被写入套接字
-#[derive(Debug)]
-pub struct Connection {
-    // `TcpStream`。它使用 `BufWriter` 装饰，提供写级别的缓冲
+///
+/// 启用批量写入后，只要读取缓冲区中还有对等方流水线发送的数据，写入的帧就
+/// 留在写缓冲区中，直到所有缓冲的请求都处理完，或者写缓冲区满了，再一次性
+/// 写入套接字
+pub struct Connection<S = Box<dyn Transport>> {
+    // 底层流。它使用 `BufWriter` 装饰，提供写级别的缓冲
     // Tokio 提供的 `BufWriter` 实现对于我们的需求来说已经足够了
-    stream: BufWriter<TcpStream>,
+    stream: BufWriter<S>,
 
     // 用于读取帧的缓冲区
     buffer: BytesMut,
+
+    // 编码写入的帧时使用的缓冲区，在多次写入之间重复使用。bulk 字符串的数据
+    // 不经过该缓冲区
+    encode_buf: BytesMut,
+
+    // 从 `buffer` 中解析帧，记录不完整帧的解析进度
+    parser: Parser,
+
+    // 为 true 时，读取缓冲区不为空时推迟刷新写缓冲区
+    batch_writes: bool,
 }
 
-impl Connection {
-    /// 创建一个新的 `Connection`，由 `socket` 支持。读取和写入缓冲区被初始化
-    pub fn new(socket: TcpStream) -> Connection {
+/// 写缓冲区的容量。批量写入时，缓冲的数据达到该大小后就会写入套接字，
+/// 而不必等到所有流水线请求都处理完
+const WRITE_BUFFER_CAPACITY: usize = 16 * 1024;
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// 创建一个新的 `Connection`，由 `stream` 支持。读取和写入缓冲区被初始化
+    pub fn new(stream: S) -> Connection<S> {
         Connection {
-            stream: BufWriter::new(socket),
+            stream: BufWriter::with_capacity(WRITE_BUFFER_CAPACITY, stream),
             // 默认使用 4KB 的读取缓冲区。对于 mini redis 的使用场景来说，
             // 这个大小是合适的。但是，实际应用会希望根据其特定使用场景调整此值。
             // 很有可能更大的读取缓冲区会工作得更好。
             buffer: BytesMut::with_capacity(4 * 1024),
+            encode_buf: BytesMut::new(),
+            parser: Parser::new(),
+            batch_writes: false,
         }
     }
 
+    /// 启用或禁用批量写入
+    ///
+    /// 启用后，`write_frame` 在读取缓冲区中还有数据时不刷新写缓冲区，对流水线
+    /// 发送的多个请求的响应会通过一次系统调用写入套接字。`read_frame` 在等待
+    /// 套接字之前总会先刷新写缓冲区，因此响应不会无限期地滞留
+    pub(crate) fn set_batch_writes(&mut self, enabled: bool) {
+        self.batch_writes = enabled;
+    }
+
+    /// 修改读取的帧的长度和嵌套深度限制，默认使用 `Limits::default()`
+    pub(crate) fn set_limits(&mut self, limits: Limits) {
+        self.parser.set_limits(limits);
+    }
+
     /// 从底层流中读取单个 `Frame` 值
     ///
     /// 该函数会等待直到它检索到足够的数据来解析帧。帧被解析后留在读取缓冲区中的
@@ -44,9 +87,16 @@ impl Connection {
     ///
     /// # 返回值
     ///
-    /// 成功时返回接收到的帧。如果 `TcpStream` 以不会将帧截断的方式关闭，
+    /// 成功时返回接收到的帧。如果底层流以不会将帧截断的方式关闭，
     /// 则返回 `None`。否则返回错误
     pub async fn read_frame(&mut self) -> crate::Result<Option<Frame>> {
+        Ok(self.read_frame_with_len().await?.map(|(frame, _)| frame))
+    }
+
+    /// 与 `read_frame` 相同，同时返回帧编码后的字节数
+    ///
+    /// 副本使用该长度跟踪复制流的偏移量
+    pub(crate) async fn read_frame_with_len(&mut self) -> crate::Result<Option<(Frame, usize)>> {
         loop {
             // 尝试从缓冲数据中解析帧。如果已经缓冲了足够的数据，
             // 则返回帧。
@@ -54,14 +104,26 @@ impl Connection {
                 return Ok(Some(frame));
             }
 
-            // 缓冲的数据不足以读取帧。尝试从套接字读取更多数据。
+            // 缓冲的数据不足以读取帧。在等待对等方之前，先把推迟的响应
+            // 写入套接字，对等方可能正在等待它们。
+            self.stream.flush().await?;
+
+            // 尝试从套接字读取更多数据。
             //
             // 成功时，返回读取的字节数。`0` 表示"流结束"。
-            if 0 == self.stream.read_buf(&mut self.buffer).await? {
+            let n = match self.stream.read_buf(&mut self.buffer).await {
+                Ok(n) => n,
+                // TLS 对等方可能不先发送 close_notify 就关闭连接，与关闭 TCP
+                // 连接同样处理。
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => 0,
+                Err(err) => return Err(err.into()),
+            };
+
+            if 0 == n {
                 // 远程对等方关闭了连接。要成为干净的关闭，
                 // 读取缓冲区中应该没有数据。如果有，这意味着对等方在发送帧时
                 // 关闭了套接字。
-                if self.buffer.is_empty() {
+                if self.buffer.is_empty() && !self.parser.is_partial() {
                     return Ok(None);
                 } else {
                     return Err("connection reset by peer".into());
@@ -73,76 +135,29 @@ impl Connection {
     /// 尝试从缓冲区解析帧。如果缓冲区包含足够的数据，则返回帧并从缓冲区中删除数据。
     /// 如果缓冲的数据还不够，则返回 `Ok(None)`。如果缓冲的数据不表示有效的帧，
     /// 则返回 `Err`
-    fn parse_frame(&mut self) -> crate::Result<Option<Frame>> {
-        use frame::Error::Incomplete;
-
-        // Cursor 用于跟踪缓冲区中的"当前"位置。Cursor 也实现了 `bytes` crate
-        // 的 `Buf` trait，它提供了许多处理字节的有用工具。
-        let mut buf = Cursor::new(&self.buffer[..]);
-
-        // 第一步是检查是否已经缓冲了足够的数据来解析单个帧。这一步通常比
-        // 完整解析帧快得多，并且允许我们跳过分配数据结构来保存帧数据，
-        // 除非我们知道已经收到了完整的帧。
-        match Frame::check(&mut buf) {
-            Ok(_) => {
-                // `check` 函数会将游标前进到帧的末尾。由于在调用 `Frame::check` 之前
-                // 游标的位置被设置为零，我们可以通过检查游标位置来获取帧的长度。
-                let len = buf.position() as usize;
-
-                // 在将游标传递给 `Frame::parse` 之前，将位置重置为零。
-                buf.set_position(0);
-
-                // 从缓冲区解析帧。这会分配必要的结构来表示帧并返回帧值。
-                //
-                // 如果编码的帧表示无效，则返回错误。这应该终止**当前**连接，
-                // 但不应影响任何其他已连接的客户端。
-                let frame = Frame::parse(&mut buf)?;
-
-                // 从读取缓冲区中丢弃已解析的数据。
-                //
-                // 当在读取缓冲区上调用 `advance` 时，直到 `len` 的所有数据都会被丢弃。
-                // 具体如何工作的细节留给 `BytesMut`。这通常通过移动内部游标来完成，
-                // 但也可能通过重新分配和复制数据来完成。
-                self.buffer.advance(len);
-
-                // 将解析的帧返回给调用者。
-                Ok(Some(frame))
-            }
-            // 读取缓冲区中没有足够的数据来解析单个帧。我们必须等待从套接字
-            // 接收更多数据。从套接字读取将在这个 `match` 之后的语句中完成。
-            //
-            // 我们不想从这里返回 `Err`，因为这种"错误"是预期的运行时条件。
-            Err(Incomplete) => Ok(None),
-            // 解析帧时遇到错误。连接现在处于无效状态。从这里返回 `Err` 
-            // 将导致连接被关闭。
-            Err(e) => Err(e.into()),
-        }
+    fn parse_frame(&mut self) -> crate::Result<Option<(Frame, usize)>> {
+        // 解析器会立即从缓冲区中移除已经解析的部分，并记住不完整帧的解析进度，
+        // 因此每个字节只会被扫描一次，即使一个很大的帧需要多次读取才能接收完。
+        //
+        // 如果编码的帧表示无效，则返回错误。这应该终止**当前**连接，
+        // 但不应影响任何其他已连接的客户端。
+        Ok(self.parser.parse_with_len(&mut self.buffer)?)
     }
 
     /// 将单个 `Frame` 值写入底层流
     ///
-    /// `Frame` 值使用 `AsyncWrite` 提供的各种 `write_*` 函数写入套接字
-    /// 直接在 `TcpStream` 上调用这些函数**不**建议，因为这会导致大量的系统调用
-    /// 但是，在*缓冲*写流上调用这些函数是可以的。数据会被写入缓冲区。一旦缓冲区
-    /// 满了，它就会被刷新到底层套接字
+    /// `Frame` 值先由 `Frame::encode` 编码，再写入*缓冲*写流。数据会被写入
+    /// 缓冲区。一旦缓冲区满了，它就会被刷新到底层套接字
+    ///
+    /// 启用批量写入并且读取缓冲区中还有未处理的数据时，帧留在写缓冲区中，
+    /// 由之后的写入或者 `read_frame` 刷新
     pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
-        // 数组通过编码每个条目来编码。所有其他帧类型被视为字面量。
-        // 目前，mini-redis 无法编码递归帧结构。详见下文。
-        match frame {
-            Frame::Array(val) => {
-                // 编码帧类型前缀。对于数组，它是 `*`。
-                self.stream.write_u8(b'*').await?;
-
-                // 编码数组的长度。
-                self.write_decimal(val.len() as u64).await?;
-
-                // 迭代并编码数组中的每个条目。
-                for entry in &**val {
-                    self.write_value(entry).await?;
-                }
-            }
-            // 帧类型是字面量。直接编码值。
-            _ => self.write_value(frame).await?,
+        self.write_value(frame).await?;
+
+        // 读取缓冲区中还有流水线请求，它们的响应会一起刷新。`BufWriter`
+        // 的缓冲区满了之后会自行写入套接字。
+        if self.batch_writes && !self.buffer.is_empty() {
+            return Ok(());
         }
 
         // 确保编码的帧被写入套接字。上面的调用是针对缓冲流和写入。
@@ -150,56 +165,84 @@ impl Connection {
         self.stream.flush().await
     }
 
+    /// 将写缓冲区中推迟的数据写入套接字
+    pub(crate) async fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush().await
+    }
+
+    /// 将已经编码的数据原样写入底层流
+    ///
+    /// 主节点使用它向副本发送复制流
+    pub(crate) async fn write_bytes(&mut self, src: &[u8]) -> io::Result<()> {
+        self.stream.write_all(src).await?;
+        self.stream.flush().await
+    }
+
     /// 将帧字面量写入流
+    ///
+    /// 除了 bulk 字符串的数据之外，帧被编码到 `encode_buf` 中。bulk 字符串的
+    /// 数据直接写入流，不会被复制到中间缓冲区
     async fn write_value(&mut self, frame: &Frame) -> io::Result<()> {
-        match frame {
-            Frame::Simple(val) => {
-                self.stream.write_u8(b'+').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Error(val) => {
-                self.stream.write_u8(b'-').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Integer(val) => {
-                self.stream.write_u8(b':').await?;
-                self.write_decimal(*val).await?;
-            }
-            Frame::Null => {
-                self.stream.write_all(b"$-1\r\n").await?;
-            }
-            Frame::Bulk(val) => {
-                let len = val.len();
+        use std::fmt::Write;
 
-                self.stream.write_u8(b'$').await?;
-                self.write_decimal(len as u64).await?;
-                self.stream.write_all(val).await?;
-                self.stream.write_all(b"\r\n").await?;
+        // Async functions cannot recurse, nested arrays are visited using a
+        // stack of the arrays being written.
+        let mut arrays: Vec<std::slice::Iter<'_, Frame>> = vec![];
+        let mut next = Some(frame);
+
+        loop {
+            let frame = match next.take() {
+                Some(frame) => frame,
+                None => match arrays.last_mut() {
+                    Some(array) => match array.next() {
+                        Some(frame) => frame,
+                        None => {
+                            arrays.pop();
+                            continue;
+                        }
+                    },
+                    None => break,
+                },
+            };
+
+            match frame {
+                Frame::Bulk(val) => {
+                    let _ = write!(self.encode_buf, "${}\r\n", val.len());
+                    self.stream.write_all(&self.encode_buf).await?;
+                    self.encode_buf.clear();
+
+                    self.stream.write_all(val).await?;
+                    self.encode_buf.extend_from_slice(b"\r\n");
+                }
+                Frame::Array(val) => {
+                    let _ = write!(self.encode_buf, "*{}\r\n", val.len());
+                    arrays.push(val.iter());
+                }
+                frame => frame.encode(&mut self.encode_buf),
             }
-            // 从值内部编码 `Array` 无法使用递归策略完成。
-            // 通常，异步函数不支持递归。Mini-redis 还不需要编码嵌套数组，
-            // 所以目前暂时跳过。
-            Frame::Array(_val) => unreachable!(),
         }
 
+        self.stream.write_all(&self.encode_buf).await?;
+        self.encode_buf.clear();
+
         Ok(())
     }
 
-    /// 将十进制帧写入流
-    async fn write_decimal(&mut self, val: u64) -> io::Result<()> {
-        use std::io::Write;
-
-        // 将值转换为字符串
-        let mut buf = [0u8; 20];
-        let mut buf = Cursor::new(&mut buf[..]);
-        write!(&mut buf, "{}", val)?;
-
-        let pos = buf.position() as usize;
-        self.stream.write_all(&buf.get_ref()[..pos]).await?;
-        self.stream.write_all(b"\r\n").await?;
+    /// 刷新写缓冲区并关闭底层流的写入端
+    ///
+    /// 对等方随后会读到"流结束"。读取端保持打开，调用者负责停止读取请求
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.shutdown().await
+    }
+}
 
-        Ok(())
+// 不包含底层流，大多数传输类型没有实现 `Debug`。
+impl<S> fmt::Debug for Connection<S> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Connection")
+            .field("buffer", &self.buffer)
+            .field("parser", &self.parser)
+            .field("batch_writes", &self.batch_writes)
+            .finish_non_exhaustive()
     }
 }
@@ -1,10 +1,32 @@
-use tokio::sync::{broadcast, Notify};
+use tokio::sync::{broadcast, watch, Notify};
 use tokio::time::{self, Duration, Instant};
 
+use crate::aof::{self, AppendFsync};
+use crate::cluster::ClusterState;
+use crate::cmd::ReplConf;
+use crate::eviction::{self, Access, MaxmemoryPolicy, MemoryStats, OutOfMemory};
+use crate::expiration::{self, ExpireStats};
+use crate::notify::{EventClass, KeyspaceEvents};
+use crate::replication::{self, Backlog, Link, Replica, Resync};
+use crate::sentinel::SentinelState;
+use crate::snapshot::{self, Record, SaveRule};
+use crate::Frame;
+
 use bytes::Bytes;
-use std::collections::{BTreeSet, HashMap};
-use std::sync::{Arc, Mutex};
-use tracing::debug;
+use indexmap::IndexMap;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+use tokio::task;
+use tracing::{debug, error, info};
+
+/// 未指定分片数量时，键空间被分成的分片数量
+const DEFAULT_SHARDS: usize = 16;
 
 /// `Db` 实例的包装器。它的存在是为了通过通知后台清理任务在
 /// 此结构体被删除时关闭，从而允许对 `Db` 进行有序清理
@@ -17,7 +39,7 @@ pub(crate) struct DbDropGuard {
 
 /// 在所有连接之间共享的服务器状态
 ///
-/// `Db` 包含一个存储键/值数据的 `HashMap` 以及用于活动发布/订阅通道的
+/// `Db` 包含存储键/值数据的分片以及用于活动发布/订阅通道的
 /// 所有 `broadcast::Sender` 值
 ///
 /// `Db` 实例是共享状态的句柄。克隆 `Db` 是浅拷贝，只会增加原子引用计数
@@ -31,46 +53,153 @@ pub(crate) struct Db {
     shared: Arc<Shared>,
 }
 
+/// 共享状态被拆分为多个独立加锁的部分，访问不同键的连接不会相互阻塞
+///
+/// 锁都是 `std::sync::Mutex` 而不是 Tokio 互斥锁。这是因为在持有互斥锁时
+/// 没有执行异步操作。此外，临界区非常小
+///
+/// Tokio 互斥锁主要用于需要在 `.await` yield 点之间持有锁的情况。
+/// 所有其他情况通常都最适合使用 std 互斥锁。如果临界区不包含任何
+/// 异步操作但是很长（CPU 密集型或执行阻塞操作），则整个操作，包括
+/// 等待互斥锁，都被视为"阻塞"操作，应该使用 `tokio::task::spawn_blocking`
+///
+/// 为了避免死锁，锁总是按照以下顺序获取：分片（按下标升序）、`state`、
+/// `pub_sub`。访问多个键的命令先按下标升序锁住涉及的所有分片
 #[derive(Debug)]
 struct Shared {
-    /// 共享状态由互斥锁保护。这是 `std::sync::Mutex` 而不是 Tokio 互斥锁。
-    /// 这是因为在持有互斥锁时没有执行异步操作。此外，临界区非常小
-    ///
-    /// Tokio 互斥锁主要用于需要在 `.await` yield 点之间持有锁的情况。
-    /// 所有其他情况通常都最适合使用 std 互斥锁。如果临界区不包含任何
-    /// 异步操作但是很长（CPU 密集型或执行阻塞操作），则整个操作，包括
-    /// 等待互斥锁，都被视为"阻塞"操作，应该使用 `tokio::task::spawn_blocking`
+    /// 键值数据，按键的哈希值分布在各个分片中
+    shards: Box<[Mutex<Shard>]>,
+
+    /// 用于选择键所在分片的哈希函数
+    hasher: RandomState,
+
+    /// 发布/订阅的通道，与键值数据分开加锁
+    pub_sub: Mutex<PubSub>,
+
+    /// 其余的服务器状态：配置、持久化、复制、集群以及哨兵
     state: Mutex<State>,
 
-    /// 通知处理条目过期的后台任务。后台任务等待被通知，然后检查过期的值
-    /// 或关闭信号
+    /// 数据集使用的内存估计值，即所有条目的 `eviction::entry_size` 之和
+    used_memory: AtomicUsize,
+
+    /// 内存上限，单位为字节。为 0 时没有上限
+    maxmemory: AtomicUsize,
+
+    /// 自启动以来因内存上限而被淘汰的键的数量
+    evicted_keys: AtomicU64,
+
+    /// 自启动以来过期并被删除的键的数量，包括访问时删除的和后台任务删除的
+    expired_keys: AtomicU64,
+
+    /// 自上次成功保存快照以来的修改次数
+    dirty: AtomicU64,
+
+    /// 启用的键空间通知，即 `KeyspaceEvents::bits`。当键被修改时，会在
+    /// `__keyspace@0__:<key>` 和 `__keyevent@0__:<event>` 通道上发布消息
+    notify_keyspace_events: AtomicU16,
+
+    /// 启用了 AOF 或复制积压缓冲区之后为 true。在此之前，写入不需要获取
+    /// `state` 的锁来传播修改
+    propagating: AtomicBool,
+
+    /// 该服务器是副本时为 true，与 `State::link` 一起修改。写命令检查它时
+    /// 不需要获取 `state` 的锁
+    replica: AtomicBool,
+
+    /// 启用集群模式时为 true。与 `replica` 一样，避免每个命令都获取 `state`
+    /// 的锁
+    cluster_enabled: AtomicBool,
+
+    /// 为 false 时后台任务不再主动删除过期键，只在访问时删除。用于测试
+    active_expire: AtomicBool,
+
+    /// 通知定期删除过期键的后台任务关闭
     background_task: Notify,
 }
 
+/// 键空间的一个分片
 #[derive(Debug)]
-struct State {
-    /// 键值数据。我们没有尝试做任何花哨的事情，所以
-    /// `std::collections::HashMap` 就可以正常工作
-    entries: HashMap<String, Entry>,
+struct Shard {
+    /// 键值数据。使用 `IndexMap` 而不是 `HashMap`，以便在淘汰键以及删除过期
+    /// 的键时通过下标随机采样
+    ///
+    /// 设置了过期时间的键总是排在前面，即下标 `0..volatile`，因此后台任务可以
+    /// 只对它们采样，而不需要单独保存一份过期键的索引
+    entries: IndexMap<String, Entry>,
+
+    /// 设置了过期时间的键的数量
+    volatile: usize,
+}
 
+/// 发布/订阅的通道
+#[derive(Debug, Default)]
+struct PubSub {
     /// 发布/订阅键空间。Redis 为键值和发布/订阅使用**单独**的键空间。
     /// `mini-redis` 通过使用单独的 `HashMap` 来处理这个问题
-    pub_sub: HashMap<String, broadcast::Sender<Bytes>>,
+    channels: HashMap<String, broadcast::Sender<Bytes>>,
 
-    /// 跟踪键的 TTL（生存时间）
-    ///
-    /// 使用 `BTreeSet` 来按过期时间维护排序的过期项。这允许后台任务遍历
-    /// 此映射以找到下一个过期的值
-    ///
-    /// 虽然极不可能，但有可能在同一时刻创建多个过期项。因此，`Instant`
-    /// 对于键来说是不够的。使用唯一键（`String`）来打破这些平局
-    expirations: BTreeSet<(Instant, String)>,
+    /// 模式订阅。键是 glob 风格的模式，发布的消息会连同实际的通道名一起发送给
+    /// 每个与通道名匹配的模式
+    patterns: HashMap<String, broadcast::Sender<(String, Bytes)>>,
+
+    /// 分片发布/订阅键空间。与 `channels` 分开，`SPUBLISH` 只会投递给
+    /// `SSUBSCRIBE` 的订阅者，并且不会与模式匹配
+    shard_channels: HashMap<String, broadcast::Sender<Bytes>>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// 达到内存上限时淘汰键的策略
+    maxmemory_policy: MaxmemoryPolicy,
+
+    /// 快照文件的路径。为 `None` 时禁用持久化
+    dbfilename: Option<PathBuf>,
+
+    /// 自动保存快照的规则。满足任意一条规则时在后台保存快照
+    save_rules: Vec<SaveRule>,
+
+    /// 上次成功保存快照的时刻。启动时初始化为当前时间
+    last_save: SystemTime,
+
+    /// 正在保存快照时为 true。同一时刻最多只有一个保存操作
+    save_in_progress: bool,
+
+    /// 仅追加文件。为 `None` 时禁用 AOF
+    aof: Option<aof::Log>,
+
+    /// 复制积压缓冲区。第一个副本连接时创建，之后的每次写入都会被追加到其中
+    backlog: Option<Backlog>,
+
+    /// 与主节点的复制连接。为 `Some` 时该服务器是副本，拒绝客户端的写入
+    link: Option<Link>,
+
+    /// 服务器接受客户端连接的地址，副本会把它告知主节点
+    addr: Option<(String, u16)>,
+
+    /// 集群拓扑。为 `None` 时禁用集群模式
+    cluster: Option<ClusterState>,
+
+    /// 哨兵状态。为 `Some` 时该服务器作为哨兵运行
+    sentinel: Option<SentinelState>,
+
+    /// 后台删除过期键的统计信息
+    expire_stats: ExpireStats,
 
     /// 当 Db 实例关闭时为 true。当所有 `Db` 值被删除时会发生这种情况。
     /// 将其设置为 `true` 会向后台任务发出退出信号
     shutdown: bool,
 }
 
+/// `Db` 拒绝写入的原因
+#[derive(Debug)]
+pub(crate) enum WriteError {
+    /// 无法腾出足够的内存，见 `OutOfMemory`
+    OutOfMemory,
+
+    /// 过期时间超出了时钟可以表示的范围
+    InvalidExpireTime,
+}
+
 /// 键值存储中的条目
 #[derive(Debug)]
 struct Entry {
@@ -79,13 +208,35 @@ struct Entry {
 
     /// 条目过期并应从数据库中删除的时刻
     expires_at: Option<Instant>,
+
+    /// 访问元数据，用于 LRU 和 LFU 淘汰策略
+    access: Access,
+}
+
+impl From<OutOfMemory> for WriteError {
+    fn from(_: OutOfMemory) -> WriteError {
+        WriteError::OutOfMemory
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WriteError::OutOfMemory => OutOfMemory.fmt(fmt),
+            WriteError::InvalidExpireTime => "ERR invalid expire time".fmt(fmt),
+        }
+    }
 }
 
+impl std::error::Error for WriteError {}
+
 impl DbDropGuard {
-    /// 创建一个新的 `DbDropGuard`，包装一个 `Db` 实例。当此对象被删除时，
-    /// `Db` 的清理任务将被关闭
-    pub(crate) fn new() -> DbDropGuard {
-        DbDropGuard { db: Db::new() }
+    /// 创建一个新的 `DbDropGuard`，包装一个键空间分为 `shards` 个分片的 `Db`
+    /// 实例。当此对象被删除时，`Db` 的清理任务将被关闭
+    pub(crate) fn new(shards: usize) -> DbDropGuard {
+        DbDropGuard {
+            db: Db::new(shards),
+        }
     }
 
     /// 获取共享数据库。内部这是一个 `Arc`，所以克隆只增加引用计数
@@ -102,24 +253,52 @@ impl Drop for DbDropGuard {
 }
 
 impl Db {
-    /// 创建一个新的空 `Db` 实例。分配共享状态并生成后台任务来管理键过期
-    ///
-    /// 键关联的值
-    ///
-    /// 如果没有值与键关联，则返回 `None`。这可能是由于从未为键分配过值，
-    /// 或者之前分配的值已过期
+    /// 创建一个新的空 `Db` 实例，键空间分为 `shards` 个分片，为 0 时使用
+    /// `DEFAULT_SHARDS`。分配共享状态并生成后台任务来管理键过期
+    pub(crate) fn new(shards: usize) -> Db {
+        let shards = if shards == 0 { DEFAULT_SHARDS } else { shards };
+
         let shared = Arc::new(Shared {
+            shards: (0..shards)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        entries: IndexMap::new(),
+                        volatile: 0,
+                    })
+                })
+                .collect(),
+            hasher: RandomState::new(),
+            pub_sub: Mutex::new(PubSub::default()),
             state: Mutex::new(State {
-                entries: HashMap::new(),
-                pub_sub: HashMap::new(),
-                expirations: BTreeSet::new(),
+                maxmemory_policy: MaxmemoryPolicy::default(),
+                dbfilename: None,
+                save_rules: vec![],
+                last_save: SystemTime::now(),
+                save_in_progress: false,
+                aof: None,
+                backlog: None,
+                link: None,
+                addr: None,
+                cluster: None,
+                sentinel: None,
+                expire_stats: ExpireStats::default(),
                 shutdown: false,
             }),
+            used_memory: AtomicUsize::new(0),
+            maxmemory: AtomicUsize::new(0),
+            evicted_keys: AtomicU64::new(0),
+            expired_keys: AtomicU64::new(0),
+            dirty: AtomicU64::new(0),
+            notify_keyspace_events: AtomicU16::new(KeyspaceEvents::default().bits()),
+            propagating: AtomicBool::new(false),
+            replica: AtomicBool::new(false),
+            cluster_enabled: AtomicBool::new(false),
+            active_expire: AtomicBool::new(true),
             background_task: Notify::new(),
         });
 
         // Start the background task.
-        tokio::spawn(purge_expired_tasks(shared.clone()));
+        tokio::spawn(active_expire_task(shared.clone()));
 
         Db { shared }
     }
@@ -127,80 +306,795 @@ impl Db {
     /// 获取与键关联的值
     ///
     /// 如果没有值与键关联，则返回 `None`。这可能是由于从未为键分配过值，
-    /// 或者之前分配的值已过期
+    /// 或者之前分配的值已过期。已经过期但尚未被后台任务删除的键在此时被删除
     pub(crate) fn get(&self, key: &str) -> Option<Bytes> {
-        // Acquire the lock, get the entry and clone the value.
+        // Acquire the lock of the key's shard, get the entry and clone the
+        // value.
         //
         // Because data is stored using `Bytes`, a clone here is a shallow
         // clone. Data is not copied.
-        let state = self.shared.state.lock().unwrap();
-        state.entries.get(key).map(|entry| entry.data.clone())
+        let mut shard = self.shared.lock_shard(key);
+        let now = Instant::now();
+        self.shared.expire_if_needed(&mut shard, key, now);
+
+        let entry = shard.entries.get_mut(key)?;
+        entry.access.touch(now);
+        Some(entry.data.clone())
+    }
+
+    /// 如果键存在，返回 `true`。与 `get` 不同，不会更新键的访问信息
+    pub(crate) fn exists(&self, key: &str) -> bool {
+        let mut shard = self.shared.lock_shard(key);
+        self.shared
+            .expire_if_needed(&mut shard, key, Instant::now());
+
+        shard.entries.contains_key(key)
+    }
+
+    /// 返回随机的一个键，数据集为空时返回 `None`
+    ///
+    /// 先随机选择一个分片，分片为空时依次尝试之后的分片。键在分片之间的分布
+    /// 并不完全均匀，因此结果也只是近似均匀。选中的键已经过期时删除它并重新选择
+    pub(crate) fn random_key(&self) -> Option<String> {
+        let shards = &self.shared.shards;
+        let start = eviction::random() as usize % shards.len();
+        let now = Instant::now();
+
+        (0..shards.len()).find_map(|i| {
+            let mut shard = shards[(start + i) % shards.len()].lock().unwrap();
+
+            // Every expired key picked is deleted, so the loop ends.
+            while !shard.entries.is_empty() {
+                let index = eviction::random() as usize % shard.entries.len();
+                let key = shard.entries.get_index(index).unwrap().0.clone();
+
+                if !self.shared.expire_if_needed(&mut shard, &key, now) {
+                    return Some(key);
+                }
+            }
+
+            None
+        })
+    }
+
+    /// 获取与键关联的值以及过期的时刻
+    ///
+    /// 键不存在或者已经过期时返回 `None`。与 `get` 一样，过期的键在此时被删除
+    pub(crate) fn get_with_expiration(&self, key: &str) -> Option<(Bytes, Option<Instant>)> {
+        let mut shard = self.shared.lock_shard(key);
+        let now = Instant::now();
+        self.shared.expire_if_needed(&mut shard, key, now);
+
+        let entry = shard.entries.get_mut(key)?;
+        entry.access.touch(now);
+        Some((entry.data.clone(), entry.expires_at))
     }
 
     /// 设置与键关联的值以及可选的过期持续时间
     ///
     /// 如果已经有一个值与键关联，它将被移除
-    pub(crate) fn set(&self, key: String, value: Bytes, expire: Option<Duration>) {
-        let mut state = self.shared.state.lock().unwrap();
+    ///
+    /// 如果写入会超过内存上限，先按照淘汰策略淘汰键。无法腾出足够的内存时
+    /// 返回 `WriteError::OutOfMemory`，过期时间无法表示时返回
+    /// `WriteError::InvalidExpireTime`，两种情况下都不做任何修改
+    pub(crate) fn set(
+        &self,
+        key: String,
+        value: Bytes,
+        expire: Option<Duration>,
+    ) -> Result<(), WriteError> {
+        let expires_at = expire.map(deadline).transpose()?;
 
-        // If this `set` becomes the key that expires **next**, the background
-        // task needs to be notified so it can update its state.
-        //
-        // Whether or not the task needs to be notified is computed during the
-        // `set` routine.
-        let mut notify = false;
-
-        let expires_at = expire.map(|duration| {
-            // `Instant` at which the key expires.
-            let when = Instant::now() + duration;
-
-            // Only notify the worker task if the newly inserted expiration is the
-            // **next** key to evict. In this case, the worker needs to be woken up
-            // to update its state.
-            notify = state
-                .next_expiration()
-                .map(|expiration| expiration > when)
-                .unwrap_or(true);
-
-            when
-        });
+        // Make room for the new entry before modifying anything, so that a
+        // rejected write has no effect.
+        self.shared.evict(
+            &key,
+            eviction::entry_size(&key, &value, expires_at.is_some()),
+        )?;
+
+        let shard = self.shared.lock_shard(&key);
+        self.set_locked(shard, key, value, expires_at);
+        Ok(())
+    }
+
+    /// 恢复由 `DUMP` 序列化的值。如果键已经存在并且没有设置 `replace`，则不做
+    /// 任何修改并返回 `false`
+    ///
+    /// 错误与 `set` 相同
+    pub(crate) fn restore(
+        &self,
+        key: String,
+        value: Bytes,
+        expire: Option<Duration>,
+        replace: bool,
+    ) -> Result<bool, WriteError> {
+        let expires_at = expire.map(deadline).transpose()?;
+
+        // Same as Redis, memory is freed before the command runs, even if the
+        // key turns out to exist.
+        self.shared.evict(
+            &key,
+            eviction::entry_size(&key, &value, expires_at.is_some()),
+        )?;
+
+        let mut shard = self.shared.lock_shard(&key);
+
+        // The check and the write happen under the same lock. An expired key
+        // does not prevent the restore.
+        self.shared
+            .expire_if_needed(&mut shard, &key, Instant::now());
+
+        if !replace && shard.entries.contains_key(&key) {
+            return Ok(false);
+        }
+
+        self.set_locked(shard, key, value, expires_at);
+        Ok(true)
+    }
+
+    /// `set` 的实现，在已经持有键所在分片的锁时调用。`expires_at` 由
+    /// `deadline` 在加锁之前计算，内存由 `Shared::evict` 在加锁之前腾出
+    fn set_locked(
+        &self,
+        mut shard: MutexGuard<'_, Shard>,
+        key: String,
+        value: Bytes,
+        expires_at: Option<(Instant, SystemTime)>,
+    ) {
+        let now = Instant::now();
 
-        // Insert the entry into the `HashMap`.
-        let prev = state.entries.insert(
+        // An expired value is deleted as such before being overwritten, so
+        // that its expiration is notified and counted.
+        self.shared.expire_if_needed(&mut shard, &key, now);
+
+        // `Bytes` clones are shallow, keeping a handle for the append-only
+        // file is cheap.
+        let value_for_aof = value.clone();
+
+        // Insert the entry into the shard. Keys with an expiration are found
+        // by the background task through sampling, it does not need to be
+        // notified.
+        self.shared.insert(
+            &mut shard,
             key.clone(),
             Entry {
                 data: value,
-                expires_at,
+                expires_at: expires_at.map(|(when, _)| when),
+                access: Access::new(now),
             },
         );
 
-        // If there was a value previously associated with the key **and** it
-        // had an expiration time. The associated entry in the `expirations` map
-        // must also be removed. This avoids leaking data.
-        if let Some(prev) = prev {
-            if let Some(when) = prev.expires_at {
-                // clear expiration
-                state.expirations.remove(&(when, key.clone()));
+        self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+        self.shared.notify(EventClass::String, "set", &key);
+
+        // The write is propagated while holding the shard lock, so the writes
+        // to a key reach the append-only file and the replicas in order.
+        self.shared
+            .propagate(|| aof::set_frame(&key, value_for_aof, expires_at.map(|(_, when)| when)));
+
+        if expires_at.is_some() {
+            self.shared.notify(EventClass::Generic, "expire", &key);
+        }
+    }
+
+    /// 删除键。如果键存在并且没有过期则返回 `true`
+    pub(crate) fn del(&self, key: &str) -> bool {
+        let mut shard = self.shared.lock_shard(key);
+        self.shared.del(&mut shard, key, Instant::now())
+    }
+
+    /// 如果键仍然与 `value` 关联并且在 `expires_at` 过期，删除键。键被修改过时
+    /// 保留它并返回 `false`，键已经不存在时返回 `true`
+    ///
+    /// 比较和删除在持有分片锁时进行，与之同时写入的值不会被删除
+    pub(crate) fn del_if_unchanged(
+        &self,
+        key: &str,
+        value: &Bytes,
+        expires_at: Option<Instant>,
+    ) -> bool {
+        let mut shard = self.shared.lock_shard(key);
+        let now = Instant::now();
+        self.shared.expire_if_needed(&mut shard, key, now);
+
+        let unchanged = match shard.entries.get(key) {
+            Some(entry) => entry.data == value && entry.expires_at == expires_at,
+            None => return true,
+        };
+
+        if unchanged {
+            self.shared.del(&mut shard, key, now);
+        }
+
+        unchanged
+    }
+
+    /// 删除多个键，返回存在的键的数量
+    ///
+    /// 先按下标升序锁住所有涉及的分片，因此删除是原子的，并且与其他多键命令
+    /// 同时执行时不会死锁
+    pub(crate) fn del_keys(&self, keys: &[String]) -> usize {
+        let mut shards = self.shared.lock_shards(keys.iter().map(String::as_str));
+        let now = Instant::now();
+
+        keys.iter()
+            .filter(|key| {
+                let shard = shards.get_mut(&self.shared.shard_index(key)).unwrap();
+                self.shared.del(shard, key, now)
+            })
+            .count()
+    }
+
+    /// 设置键在 `expire` 之后过期。如果键存在并且没有过期则返回 `true`
+    ///
+    /// 之前的过期时间（如果有）会被替换。过期时间无法表示时返回
+    /// `WriteError::InvalidExpireTime`，不做任何修改
+    pub(crate) fn expire(&self, key: &str, expire: Duration) -> Result<bool, WriteError> {
+        let (when, system_when) = deadline(expire)?;
+
+        let mut shard = self.shared.lock_shard(key);
+
+        // An expired key can not be given a new expiration.
+        self.shared
+            .expire_if_needed(&mut shard, key, Instant::now());
+
+        match shard.set_expires_at(key, when) {
+            Some(None) => {
+                self.shared
+                    .used_memory
+                    .fetch_add(eviction::expiration_size(), Ordering::Relaxed);
             }
+            Some(Some(_)) => {}
+            None => return Ok(false),
         }
 
-        // Track the expiration. If we insert before remove that will cause bug
-        // when current `(when, key)` equals prev `(when, key)`. Remove then insert
-        // can avoid this.
-        if let Some(when) = expires_at {
-            state.expirations.insert((when, key));
+        self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+        self.shared.notify(EventClass::Generic, "expire", key);
+        self.shared
+            .propagate(|| aof::pexpireat_frame(key, system_when));
+
+        Ok(true)
+    }
+
+    /// 返回当前启用的键空间通知
+    pub(crate) fn notify_keyspace_events(&self) -> KeyspaceEvents {
+        KeyspaceEvents::from_bits(self.shared.notify_keyspace_events.load(Ordering::Relaxed))
+    }
+
+    /// 设置启用的键空间通知
+    pub(crate) fn set_notify_keyspace_events(&self, events: KeyspaceEvents) {
+        self.shared
+            .notify_keyspace_events
+            .store(events.bits(), Ordering::Relaxed);
+    }
+
+    /// 返回快照文件的路径
+    pub(crate) fn dbfilename(&self) -> Option<PathBuf> {
+        self.shared.state.lock().unwrap().dbfilename.clone()
+    }
+
+    /// 设置快照文件的路径
+    pub(crate) fn set_dbfilename(&self, dbfilename: Option<PathBuf>) {
+        self.shared.state.lock().unwrap().dbfilename = dbfilename;
+    }
+
+    /// 返回自动保存快照的规则
+    pub(crate) fn save_rules(&self) -> Vec<SaveRule> {
+        self.shared.state.lock().unwrap().save_rules.clone()
+    }
+
+    /// 设置自动保存快照的规则
+    pub(crate) fn set_save_rules(&self, rules: Vec<SaveRule>) {
+        self.shared.state.lock().unwrap().save_rules = rules;
+    }
+
+    /// 返回内存上限，单位为字节。0 表示没有上限
+    pub(crate) fn maxmemory(&self) -> usize {
+        self.shared.maxmemory.load(Ordering::Relaxed)
+    }
+
+    /// 设置内存上限。降低上限不会立即淘汰键，而是在下一次写入之前淘汰
+    pub(crate) fn set_maxmemory(&self, maxmemory: usize) {
+        self.shared.maxmemory.store(maxmemory, Ordering::Relaxed);
+    }
+
+    /// 返回达到内存上限时的淘汰策略
+    pub(crate) fn maxmemory_policy(&self) -> MaxmemoryPolicy {
+        self.shared.state.lock().unwrap().maxmemory_policy
+    }
+
+    /// 设置达到内存上限时的淘汰策略
+    pub(crate) fn set_maxmemory_policy(&self, policy: MaxmemoryPolicy) {
+        self.shared.state.lock().unwrap().maxmemory_policy = policy;
+    }
+
+    /// 返回键占用的内存估计值，包括键、值以及过期时间的跟踪。键不存在时返回
+    /// `None`
+    pub(crate) fn memory_usage(&self, key: &str) -> Option<usize> {
+        let mut shard = self.shared.lock_shard(key);
+        self.shared
+            .expire_if_needed(&mut shard, key, Instant::now());
+
+        let entry = shard.entries.get(key)?;
+
+        Some(eviction::entry_size(
+            key,
+            &entry.data,
+            entry.expires_at.is_some(),
+        ))
+    }
+
+    /// 返回内存使用的统计信息
+    ///
+    /// 各个分片依次加锁，因此在有并发写入时统计信息只是近似值
+    pub(crate) fn memory_stats(&self) -> MemoryStats {
+        let (keys, expires) = self
+            .shared
+            .shards
+            .iter()
+            .map(|shard| {
+                let shard = shard.lock().unwrap();
+                (shard.entries.len(), shard.volatile)
+            })
+            .fold((0, 0), |(keys, expires), (len, volatile)| {
+                (keys + len, expires + volatile)
+            });
+
+        let state = self.shared.state.lock().unwrap();
+        let pub_sub = self.shared.pub_sub.lock().unwrap();
+
+        // Channels are counted once, whatever the number of subscribers.
+        let channels = pub_sub
+            .channels
+            .keys()
+            .chain(pub_sub.patterns.keys())
+            .chain(pub_sub.shard_channels.keys());
+
+        MemoryStats {
+            keys,
+            expires,
+            dataset: self.shared.used_memory.load(Ordering::Relaxed),
+            pubsub_channels: channels.clone().count(),
+            pubsub: channels.map(|name| eviction::channel_size(name)).sum(),
+            replication_backlog: state.backlog.as_ref().map_or(0, Backlog::len),
+            maxmemory: self.shared.maxmemory.load(Ordering::Relaxed),
+            maxmemory_policy: state.maxmemory_policy,
+            evicted_keys: self.shared.evicted_keys.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 返回上次成功保存快照的时刻
+    pub(crate) fn last_save(&self) -> SystemTime {
+        self.shared.state.lock().unwrap().last_save
+    }
+
+    /// 如果任意一条自动保存规则被满足并且当前没有正在进行的保存，返回 `true`
+    pub(crate) fn save_rules_triggered(&self) -> bool {
+        let state = self.shared.state.lock().unwrap();
+
+        if state.save_in_progress || state.dbfilename.is_none() {
+            return false;
+        }
+
+        let since_last_save = state.last_save.elapsed().unwrap_or_default();
+        let dirty = self.shared.dirty.load(Ordering::Relaxed);
+
+        state
+            .save_rules
+            .iter()
+            .any(|rule| rule.triggered(since_last_save, dirty))
+    }
+
+    /// 将数据集保存到快照文件，并在保存完成后返回
+    ///
+    /// 文件在阻塞线程池中写入，因此保存期间其他连接的请求仍然会被处理
+    pub(crate) async fn save(&self) -> crate::Result<()> {
+        let (path, records, dirty) = self.begin_save()?;
+        self.write_snapshot(path, records, dirty).await
+    }
+
+    /// 在后台保存快照。保存开始后立即返回
+    pub(crate) fn bgsave(&self) -> crate::Result<()> {
+        let (path, records, dirty) = self.begin_save()?;
+
+        let db = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = db.write_snapshot(path, records, dirty).await {
+                error!(cause = %err, "background save failed");
+            }
+        });
+
+        Ok(())
+    }
+
+    /// 加载快照中的键。已经过期的键会被跳过
+    pub(crate) fn load(&self, records: Vec<Record>) {
+        let mut shards = self.shared.lock_all();
+        self.shared.load(&mut shards, records);
+    }
+
+    /// 用主节点发送的快照替换整个数据集
+    ///
+    /// 之前的写入不再与数据集对应：仅追加文件会被重写，连接到该服务器的副本
+    /// 需要重新进行完整同步
+    pub(crate) fn replace(&self, records: Vec<Record>) {
+        let mut shards = self.shared.lock_all();
+
+        for shard in shards.iter_mut() {
+            shard.entries.clear();
+            shard.volatile = 0;
         }
 
-        // Release the mutex before notifying the background task. This helps
-        // reduce contention by avoiding the background task waking up only to
-        // be unable to acquire the mutex due to this function still holding it.
+        self.shared.used_memory.store(0, Ordering::Relaxed);
+        self.shared.load(&mut shards, records);
+        self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+
+        // Dropping the backlog closes the streams of the connected replicas.
+        let mut state = self.shared.state.lock().unwrap();
+        state.backlog = None;
+        let rewrite = state.aof.is_some();
         drop(state);
+        drop(shards);
+
+        if rewrite {
+            if let Err(err) = self.bgrewriteaof() {
+                error!(cause = %err, "append only file rewrite failed to start");
+            }
+        }
+    }
+
+    /// 开始保存快照。返回快照文件的路径、当前数据集的副本以及副本对应的修改次数
+    ///
+    /// 数据集由 `Shared::records` 逐个分片复制，不会阻塞其他连接的请求，之后
+    /// 写入文件时不再需要锁。值是 `Bytes`，复制只增加引用计数
+    fn begin_save(&self) -> crate::Result<(PathBuf, Vec<Record>, u64)> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        if state.save_in_progress {
+            return Err("Background save already in progress".into());
+        }
+
+        let path = match &state.dbfilename {
+            Some(path) => path.clone(),
+            None => return Err("snapshot persistence is disabled".into()),
+        };
+
+        state.save_in_progress = true;
+        drop(state);
+
+        // Changes made while the shards are copied may be missing from the
+        // snapshot, only the ones made before count as saved.
+        let dirty = self.shared.dirty.load(Ordering::Relaxed);
+
+        Ok((path, self.shared.records(), dirty))
+    }
+
+    /// 将 `begin_save` 返回的数据集写入文件。成功时更新上次保存的时刻，并从修改
+    /// 次数中减去快照包含的修改
+    async fn write_snapshot(
+        &self,
+        path: PathBuf,
+        records: Vec<Record>,
+        dirty: u64,
+    ) -> crate::Result<()> {
+        let res = task::spawn_blocking(move || snapshot::write(&path, &records)).await;
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.save_in_progress = false;
+
+        match res {
+            Ok(Ok(())) => {
+                // Changes made while the snapshot was being written are not
+                // part of it and still count as dirty.
+                self.shared.dirty.fetch_sub(dirty, Ordering::Relaxed);
+                state.last_save = SystemTime::now();
+                info!("DB saved on disk");
+                Ok(())
+            }
+            Ok(Err(err)) => Err(err.into()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// 打开仅追加文件。之后的每次写入都会被记录到该文件中
+    pub(crate) async fn open_aof(&self, path: PathBuf, fsync: AppendFsync) -> crate::Result<()> {
+        let log = aof::Log::open(path, fsync).await?;
+        self.shared.state.lock().unwrap().aof = Some(log);
+        self.shared.propagating.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// 将缓冲的写入写到仅追加文件中。策略为 `always` 时同时刷新到磁盘
+    ///
+    /// 写命令在回复客户端之前调用此函数
+    pub(crate) async fn flush_aof(&self) -> crate::Result<()> {
+        let (file, fsync) = match &self.shared.state.lock().unwrap().aof {
+            Some(log) => (log.file(), log.fsync()),
+            None => return Ok(()),
+        };
+
+        let mut file = file.lock().await;
+
+        // The buffer is taken while holding the file lock, so concurrent
+        // flushes write the buffers in order.
+        let buf = match &mut self.shared.state.lock().unwrap().aof {
+            Some(log) => log.take_buf(),
+            None => return Ok(()),
+        };
+
+        file.write(&buf, fsync).await?;
+        Ok(())
+    }
+
+    /// 将已写入仅追加文件的数据刷新到磁盘
+    pub(crate) async fn sync_aof(&self) -> crate::Result<()> {
+        let file = match &self.shared.state.lock().unwrap().aof {
+            Some(log) => log.file(),
+            None => return Ok(()),
+        };
+
+        file.lock().await.sync().await?;
+        Ok(())
+    }
+
+    /// 在后台重写仅追加文件。新文件只包含重建当前数据集所需的写入
+    ///
+    /// 数据集由 `Shared::records` 逐个分片复制，不会阻塞其他连接的请求
+    pub(crate) fn bgrewriteaof(&self) -> crate::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        let path = match &mut state.aof {
+            Some(log) => match log.start_rewrite() {
+                Some(path) => path,
+                None => {
+                    return Err("Background append only file rewriting already in progress".into())
+                }
+            },
+            None => return Err("append only file is disabled".into()),
+        };
+
+        // Writes made from now on are also recorded in the rewrite buffer and
+        // appended to the new file once the dataset has been written. A write
+        // made while the shards are copied may be in both, replaying it twice
+        // has the same result.
+        drop(state);
+        let records = self.shared.records();
+
+        let db = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = db.rewrite_aof(&path, records).await {
+                error!(cause = %err, "append only file rewrite failed");
+
+                if let Some(log) = &mut db.shared.state.lock().unwrap().aof {
+                    log.abort_rewrite();
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// 将 `records` 写入新的仅追加文件，然后用它替换 `path`
+    async fn rewrite_aof(&self, path: &Path, records: Vec<Record>) -> crate::Result<()> {
+        let tmp = aof::rewrite_path(path);
+        aof::write_rewrite(&tmp, records).await?;
+
+        let file = match &self.shared.state.lock().unwrap().aof {
+            Some(log) => log.file(),
+            None => return Ok(()),
+        };
+
+        // No flush can happen while the file lock is held. The writes made
+        // during the rewrite are taken and appended to the new file before
+        // any other write reaches the file.
+        let mut file = file.lock().await;
+
+        let buf = match &mut self.shared.state.lock().unwrap().aof {
+            Some(log) => log.finish_rewrite(),
+            None => return Ok(()),
+        };
+
+        file.replace(&tmp, path, &buf).await?;
+        info!("append only file rewritten");
+
+        Ok(())
+    }
+
+    /// 开始向副本发送复制流。副本已经收到了复制流 `replid` 中 `offset` 之前的
+    /// 数据
+    ///
+    /// 返回同步副本的方式以及之后写入的 `Receiver`。两者在同一个锁下获取，
+    /// 因此副本不会漏掉任何写入
+    ///
+    /// 完整同步时，数据集在 `Receiver` 创建之后由 `Shared::records` 逐个分片
+    /// 复制，不会阻塞其他连接的请求。复制期间的写入可能既在副本中又在复制流
+    /// 中，副本再次应用它们的结果相同
+    pub(crate) fn psync(
+        &self,
+        replid: &str,
+        offset: i64,
+    ) -> (Resync, broadcast::Receiver<Bytes>, Replica) {
+        let mut state = self.shared.state.lock().unwrap();
+        let backlog = state.backlog.get_or_insert_with(Backlog::new);
+        let replica = Replica::new(self.clone(), backlog.add_replica());
+
+        // The dataset is copied once the state is unlocked, the shards are
+        // locked before the state.
+        let sync = backlog.sync(replid, offset, Vec::new);
+        let stream = backlog.subscribe();
+
+        // Writes check the flag while holding their shard lock, so every write
+        // missing from the shards copied below is propagated.
+        self.shared.propagating.store(true, Ordering::Relaxed);
+        drop(state);
+
+        let sync = match sync {
+            Resync::Full { replid, offset, .. } => Resync::Full {
+                replid,
+                offset,
+                records: self.shared.records(),
+            },
+            sync => sync,
+        };
+
+        (sync, stream, replica)
+    }
+
+    /// 记录副本 `id` 已经应用了 `offset` 之前的复制流
+    pub(crate) fn ack_replica(&self, id: u64, offset: u64) {
+        if let Some(backlog) = &mut self.shared.state.lock().unwrap().backlog {
+            backlog.ack(id, offset);
+        }
+    }
+
+    /// 注销断开连接的副本
+    pub(crate) fn remove_replica(&self, id: u64) {
+        if let Some(backlog) = &mut self.shared.state.lock().unwrap().backlog {
+            backlog.remove_replica(id);
+        }
+    }
+
+    /// 返回当前的复制偏移量，以及副本确认偏移量时收到通知的 `Receiver`
+    pub(crate) fn wait_offset(&self) -> (u64, watch::Receiver<()>) {
+        let mut state = self.shared.state.lock().unwrap();
+        let backlog = state.backlog.get_or_insert_with(Backlog::new);
+        self.shared.propagating.store(true, Ordering::Relaxed);
+        (backlog.offset(), backlog.watch_acks())
+    }
+
+    /// 返回已经确认 `offset` 的副本数量
+    pub(crate) fn num_acked(&self, offset: u64) -> usize {
+        match &self.shared.state.lock().unwrap().backlog {
+            Some(backlog) => backlog.num_acked(offset),
+            None => 0,
+        }
+    }
+
+    /// 要求副本立即报告它们的偏移量。请求作为复制流的一部分发送，不会写入 AOF
+    pub(crate) fn request_acks(&self) {
+        if let Some(backlog) = &mut self.shared.state.lock().unwrap().backlog {
+            backlog.feed(&ReplConf::GetAck.into_frame());
+        }
+    }
+
+    /// 记录副本 `id` 接受客户端连接的地址
+    pub(crate) fn set_replica_addr(&self, id: u64, host: String, port: u16) {
+        if let Some(backlog) = &mut self.shared.state.lock().unwrap().backlog {
+            backlog.set_replica_addr(id, host, port);
+        }
+    }
+
+    /// 开始复制 `primary` 指定的主节点，或者在 `primary` 为 `None` 时停止复制
+    pub(crate) fn replicaof(&self, primary: Option<(String, u16)>) {
+        let mut state = self.shared.state.lock().unwrap();
+
+        // Dropping the link stops the replication task.
+        state.link = primary.map(|(host, port)| {
+            info!(%host, port, "replicating primary");
+            Link::new(self.clone(), host, port)
+        });
+
+        self.shared
+            .replica
+            .store(state.link.is_some(), Ordering::Relaxed);
+    }
+
+    /// 如果该服务器是副本，返回 `true`
+    pub(crate) fn is_replica(&self) -> bool {
+        self.shared.replica.load(Ordering::Relaxed)
+    }
+
+    /// 记录与主节点的连接状态以及已经应用的复制偏移量
+    pub(crate) fn set_link_status(&self, up: bool, offset: i64) {
+        if let Some(link) = &mut self.shared.state.lock().unwrap().link {
+            link.set_status(up, offset);
+        }
+    }
+
+    /// 返回 `INFO` 的 `stats` 部分
+    pub(crate) fn stats_info(&self) -> String {
+        let state = self.shared.state.lock().unwrap();
+
+        expiration::info(
+            &state.expire_stats,
+            self.shared.expired_keys.load(Ordering::Relaxed),
+            self.shared.evicted_keys.load(Ordering::Relaxed),
+        )
+    }
+
+    /// 返回 `INFO` 的 `replication` 部分
+    pub(crate) fn replication_info(&self) -> String {
+        let state = self.shared.state.lock().unwrap();
+        replication::info(state.backlog.as_ref(), state.link.as_ref())
+    }
+
+    /// 设置服务器接受客户端连接的地址
+    pub(crate) fn set_addr(&self, host: String, port: u16) {
+        self.shared.state.lock().unwrap().addr = Some((host, port));
+    }
+
+    /// 返回服务器接受客户端连接的地址
+    pub(crate) fn addr(&self) -> Option<(String, u16)> {
+        self.shared.state.lock().unwrap().addr.clone()
+    }
+
+    /// 启用集群模式
+    pub(crate) fn set_cluster(&self, cluster: ClusterState) {
+        self.shared.state.lock().unwrap().cluster = Some(cluster);
+        self.shared.cluster_enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// 在集群模式下，如果 `keys` 不由该节点提供服务，返回重定向错误
+    ///
+    /// `asking` 表示连接的上一个命令是 `ASKING`
+    pub(crate) fn cluster_redirect(&self, keys: &[&str], asking: bool) -> Option<Frame> {
+        if !self.cluster_enabled() {
+            return None;
+        }
+
+        // The shards are locked before the state, following the lock order.
+        let mut shards = self.shared.lock_shards(keys.iter().copied());
+        let now = Instant::now();
 
-        if notify {
-            // Finally, only notify the background task if it needs to update
-            // its state to reflect a new expiration.
-            self.shared.background_task.notify_one();
+        // Expired keys are missing, which matters for `ASK` redirections.
+        for key in keys {
+            let shard = shards.get_mut(&self.shared.shard_index(key)).unwrap();
+            self.shared.expire_if_needed(shard, key, now);
         }
+
+        let state = self.shared.state.lock().unwrap();
+        let cluster = state.cluster.as_ref()?;
+
+        cluster.redirect(keys, asking, |key| {
+            shards[&self.shared.shard_index(key)]
+                .entries
+                .contains_key(key)
+        })
+    }
+
+    /// 启用或禁用后台任务对过期键的主动删除。禁用时过期键只在被访问时删除
+    pub(crate) fn set_active_expire(&self, enabled: bool) {
+        self.shared.active_expire.store(enabled, Ordering::Relaxed);
+    }
+
+    /// 如果启用了集群模式，返回 `true`
+    pub(crate) fn cluster_enabled(&self) -> bool {
+        self.shared.cluster_enabled.load(Ordering::Relaxed)
+    }
+
+    /// 使用集群拓扑调用 `f`。未启用集群模式时返回 `None`
+    pub(crate) fn with_cluster<R>(&self, f: impl FnOnce(&mut ClusterState) -> R) -> Option<R> {
+        self.shared.state.lock().unwrap().cluster.as_mut().map(f)
+    }
+
+    /// 以哨兵模式运行
+    pub(crate) fn set_sentinel(&self, sentinel: SentinelState) {
+        self.shared.state.lock().unwrap().sentinel = Some(sentinel);
+    }
+
+    /// 使用哨兵状态调用 `f`。未以哨兵模式运行时返回 `None`
+    pub(crate) fn with_sentinel<R>(&self, f: impl FnOnce(&mut SentinelState) -> R) -> Option<R> {
+        self.shared.state.lock().unwrap().sentinel.as_mut().map(f)
     }
 
     /// 返回请求通道的 `Receiver`
@@ -210,12 +1104,12 @@ impl Db {
         use std::collections::hash_map::Entry;
 
         // Acquire the mutex
-        let mut state = self.shared.state.lock().unwrap();
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
 
         // If there is no entry for the requested channel, then create a new
         // broadcast channel and associate it with the key. If one already
         // exists, return an associated receiver.
-        match state.pub_sub.entry(key) {
+        match pub_sub.channels.entry(key) {
             Entry::Occupied(e) => e.get().subscribe(),
             Entry::Vacant(e) => {
                 // No broadcast channel exists yet, so create one.
@@ -235,20 +1129,55 @@ impl Db {
         }
     }
 
-    /// 向通道发布消息。返回监听该通道的订阅者数量
+    /// 返回请求模式的 `Receiver`
+    ///
+    /// 返回的 `Receiver` 接收发布到任何与 `pattern` 匹配的通道上的消息，
+    /// 每条消息都附带实际的通道名
+    pub(crate) fn psubscribe(&self, pattern: String) -> broadcast::Receiver<(String, Bytes)> {
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
+
+        // Same as `subscribe`, one broadcast channel is shared by all clients
+        // subscribed to the same pattern.
+        pub_sub
+            .patterns
+            .entry(pattern)
+            .or_insert_with(|| broadcast::channel(1024).0)
+            .subscribe()
+    }
+
+    /// 向通道发布消息。返回监听该通道的订阅者数量，包括通过模式订阅的订阅者
     pub(crate) fn publish(&self, key: &str, value: Bytes) -> usize {
-        let state = self.shared.state.lock().unwrap();
+        self.shared.pub_sub.lock().unwrap().publish(key, value)
+    }
 
-        state
-            .pub_sub
-            .get(key)
-            // On a successful message send on the broadcast channel, the number
-            // of subscribers is returned. An error indicates there are no
-            // receivers, in which case, `0` should be returned.
-            .map(|tx| tx.send(value).unwrap_or(0))
-            // If there is no entry for the channel key, then there are no
-            // subscribers. In this case, return `0`.
-            .unwrap_or(0)
+    /// 返回请求分片通道的 `Receiver`
+    ///
+    /// 返回的 `Receiver` 用于接收由 `SPUBLISH` 命令广播的值
+    pub(crate) fn ssubscribe(&self, key: String) -> broadcast::Receiver<Bytes> {
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
+
+        // Same as `subscribe`, but in the shard channel namespace.
+        pub_sub
+            .shard_channels
+            .entry(key)
+            .or_insert_with(|| broadcast::channel(1024).0)
+            .subscribe()
+    }
+
+    /// 向分片通道发布消息。返回监听该分片通道的订阅者数量
+    pub(crate) fn spublish(&self, key: &str, value: Bytes) -> usize {
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
+
+        // Same as `PubSub::publish`, a shard channel without subscribers is
+        // removed.
+        match pub_sub.shard_channels.get(key).map(|tx| tx.send(value)) {
+            Some(Ok(num_subscribers)) => num_subscribers,
+            Some(Err(_)) => {
+                pub_sub.shard_channels.remove(key);
+                0
+            }
+            None => 0,
+        }
     }
 
     /// 向清理后台任务发送关闭信号。这由 `DbShutdown` 的 `Drop` 实现调用
@@ -258,6 +1187,10 @@ impl Db {
         let mut state = self.shared.state.lock().unwrap();
         state.shutdown = true;
 
+        // The replication task holds a handle to the `Db` as well.
+        state.link = None;
+        self.shared.replica.store(false, Ordering::Relaxed);
+
         // Drop the lock before signalling the background task. This helps
         // reduce lock contention by ensuring the background task doesn't
         // wake up only to be unable to acquire the mutex.
@@ -267,40 +1200,173 @@ impl Db {
 }
 
 impl Shared {
-    /// 清理所有过期的键并返回**下一个**键将过期的时刻。后台任务将睡眠
-    /// 直到该时刻
-    fn purge_expired_keys(&self) -> Option<Instant> {
-        let mut state = self.state.lock().unwrap();
+    /// 返回键所在分片的下标
+    fn shard_index(&self, key: &str) -> usize {
+        self.hasher.hash_one(key) as usize % self.shards.len()
+    }
 
-        if state.shutdown {
-            // The database is shutting down. All handles to the shared state
-            // have dropped. The background task should exit.
-            return None;
+    /// 锁住键所在的分片
+    fn lock_shard(&self, key: &str) -> MutexGuard<'_, Shard> {
+        self.shards[self.shard_index(key)].lock().unwrap()
+    }
+
+    /// 按下标升序锁住 `keys` 所在的分片，返回以分片下标为键的锁
+    ///
+    /// 所有同时持有多个分片锁的操作都按照相同的顺序加锁，因此不会死锁
+    fn lock_shards<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a str>,
+    ) -> BTreeMap<usize, MutexGuard<'_, Shard>> {
+        let indexes: BTreeSet<usize> = keys.into_iter().map(|key| self.shard_index(key)).collect();
+
+        indexes
+            .into_iter()
+            .map(|index| (index, self.shards[index].lock().unwrap()))
+            .collect()
+    }
+
+    /// 按下标升序锁住所有分片，用于替换整个数据集的操作
+    fn lock_all(&self) -> Vec<MutexGuard<'_, Shard>> {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap())
+            .collect()
+    }
+
+    /// 复制数据集中没有过期的键，用于保存快照、重写仅追加文件以及完整同步
+    ///
+    /// 分片逐个加锁，同一时间只持有一个分片的锁，因此复制期间其他连接的请求
+    /// 仍然会被处理。每个分片的副本是一致的，但不同的分片在不同的时刻被复制
+    fn records(&self) -> Vec<Record> {
+        let mut records = vec![];
+
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+
+            // Expirations are stored as absolute wall clock times.
+            let now = Instant::now();
+            let system_now = SystemTime::now();
+
+            records.extend(
+                shard
+                    .entries
+                    .iter()
+                    .filter(|(_, entry)| entry.expires_at.is_none_or(|when| when > now))
+                    .map(|(key, entry)| Record {
+                        key: key.clone(),
+                        value: entry.data.clone(),
+                        expires_at: entry
+                            .expires_at
+                            .map(|when| system_now + when.saturating_duration_since(now)),
+                    }),
+            );
         }
 
-        // This is needed to make the borrow checker happy. In short, `lock()`
-        // returns a `MutexGuard` and not a `&mut State`. The borrow checker is
-        // not able to see "through" the mutex guard and determine that it is
-        // safe to access both `state.expirations` and `state.entries` mutably,
-        // so we get a "real" mutable reference to `State` outside of the loop.
-        let state = &mut *state;
+        records
+    }
 
-        // Find all keys scheduled to expire **before** now.
-        let now = Instant::now();
+    /// 执行一轮主动过期：从下标为 `start` 的分片开始，对设置了过期时间的键
+    /// 采样并删除其中过期的键。返回下一轮应该开始的分片
+    ///
+    /// 与 Redis 一样，只要采样到的键中过期的比例超过
+    /// `expiration::ACCEPTABLE_STALE`，就继续对同一个分片采样。每一轮花费的时间
+    /// 不超过 `expiration::CYCLE_BUDGET`，超时后下一轮从未处理完的分片继续
+    fn active_expire_cycle(&self, start: usize) -> usize {
+        let deadline = Instant::now() + expiration::CYCLE_BUDGET;
+        let len = self.shards.len();
+
+        let mut sampled = 0;
+        let mut expired = 0;
+        let mut resume = None;
 
-        while let Some(&(when, ref key)) = state.expirations.iter().next() {
-            if when > now {
-                // Done purging, `when` is the instant at which the next key
-                // expires. The worker task will wait until this instant.
-                return Some(when);
+        'shards: for i in 0..len {
+            let index = (start + i) % len;
+
+            loop {
+                if Instant::now() >= deadline {
+                    resume = Some(index);
+                    break 'shards;
+                }
+
+                // The shard is locked for one batch of samples at a time, so
+                // that connections are not blocked for the whole cycle.
+                let (batch_sampled, batch_expired) = self.expire_sample(index);
+                sampled += batch_sampled;
+                expired += batch_expired;
+
+                if batch_expired * 100 <= batch_sampled * expiration::ACCEPTABLE_STALE {
+                    break;
+                }
             }
+        }
 
-            // The key expired, remove it
-            state.entries.remove(key);
-            state.expirations.remove(&(when, key.clone()));
+        if expired > 0 {
+            debug!(sampled, expired, "active expiration cycle");
         }
 
-        None
+        let mut state = self.state.lock().unwrap();
+        state
+            .expire_stats
+            .record_cycle(sampled, expired, resume.is_some());
+
+        resume.unwrap_or(start)
+    }
+
+    /// 在分片 `index` 中对最多 `expiration::SAMPLES` 个设置了过期时间的键采样，
+    /// 并删除其中过期的键。返回采样和删除的键的数量
+    fn expire_sample(&self, index: usize) -> (usize, usize) {
+        let mut shard = self.shards[index].lock().unwrap();
+
+        if shard.volatile == 0 {
+            return (0, 0);
+        }
+
+        // Consecutive keys from a random position: unlike independent random
+        // indexes, no key is sampled twice.
+        let now = Instant::now();
+        let count = shard.volatile.min(expiration::SAMPLES);
+        let first = eviction::random() as usize % shard.volatile;
+
+        let expired: Vec<String> = (0..count)
+            .filter_map(|i| {
+                let (key, entry) = shard.entries.get_index((first + i) % shard.volatile)?;
+                entry
+                    .expires_at
+                    .filter(|when| *when <= now)
+                    .map(|_| key.clone())
+            })
+            .collect();
+
+        for key in &expired {
+            self.expire_key(&mut shard, key);
+        }
+
+        (count, expired.len())
+    }
+
+    /// 如果持有锁的分片 `shard` 中的键在 `now` 已经过期，删除它并返回 `true`
+    ///
+    /// 访问键之前调用，因此过期的键即使尚未被后台任务删除也不会被读到
+    fn expire_if_needed(&self, shard: &mut Shard, key: &str, now: Instant) -> bool {
+        let expired = shard
+            .entries
+            .get(key)
+            .and_then(|entry| entry.expires_at)
+            .is_some_and(|when| when <= now);
+
+        if expired {
+            self.expire_key(shard, key);
+        }
+
+        expired
+    }
+
+    /// 删除持有锁的分片 `shard` 中过期的键
+    fn expire_key(&self, shard: &mut Shard, key: &str) {
+        self.remove(shard, key);
+        self.expired_keys.fetch_add(1, Ordering::Relaxed);
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+        self.notify(EventClass::Expired, "expired", key);
     }
 
     /// 如果数据库正在关闭，返回 `true`
@@ -309,42 +1375,476 @@ impl Shared {
     fn is_shutdown(&self) -> bool {
         self.state.lock().unwrap().shutdown
     }
+
+    /// 将快照中的键插入持有锁的分片 `shards`。已经过期的键会被跳过
+    fn load(&self, shards: &mut [MutexGuard<'_, Shard>], records: Vec<Record>) {
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+
+        for record in records {
+            let expires_at = match record.expires_at {
+                // `duration_since` fails if the key expired in the past.
+                Some(when) => match when.duration_since(system_now) {
+                    Ok(ttl) => Some(now + ttl),
+                    Err(_) => continue,
+                },
+                None => None,
+            };
+
+            let shard = &mut shards[self.shard_index(&record.key)];
+
+            self.insert(
+                shard,
+                record.key,
+                Entry {
+                    data: record.value,
+                    expires_at,
+                    access: Access::new(now),
+                },
+            );
+        }
+    }
+
+    /// 插入条目并更新内存使用量
+    fn insert(&self, shard: &mut Shard, key: String, entry: Entry) {
+        let prev = shard
+            .entries
+            .get(&key)
+            .map(|prev| eviction::entry_size(&key, &prev.data, prev.expires_at.is_some()))
+            .unwrap_or(0);
+
+        // The new size is added before the previous one is subtracted, so that
+        // the estimate never underflows.
+        self.used_memory.fetch_add(
+            eviction::entry_size(&key, &entry.data, entry.expires_at.is_some()),
+            Ordering::Relaxed,
+        );
+        self.used_memory.fetch_sub(prev, Ordering::Relaxed);
+
+        shard.insert(key, entry);
+    }
+
+    /// 删除条目并更新内存使用量
+    fn remove(&self, shard: &mut Shard, key: &str) -> Option<Entry> {
+        let (key, entry) = shard.remove(key)?;
+
+        self.used_memory.fetch_sub(
+            eviction::entry_size(&key, &entry.data, entry.expires_at.is_some()),
+            Ordering::Relaxed,
+        );
+
+        Some(entry)
+    }
+
+    /// 删除持有锁的分片 `shard` 中的键。如果键存在并且在 `now` 没有过期则返回
+    /// `true`
+    fn del(&self, shard: &mut Shard, key: &str, now: Instant) -> bool {
+        if self.expire_if_needed(shard, key, now) || self.remove(shard, key).is_none() {
+            return false;
+        }
+
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+        self.notify(EventClass::Generic, "del", key);
+        self.propagate(|| aof::del_frame(key));
+
+        true
+    }
+
+    /// 在写入 `key` 之前淘汰键，直到大小为 `size` 的新条目不会使内存使用量超过
+    /// 上限。调用时不能持有任何分片的锁
+    ///
+    /// 淘汰策略为 `noeviction`，或者没有可以淘汰的键时返回 `OutOfMemory`。
+    /// 副本不淘汰键：主节点淘汰的键会以 `DEL` 的形式复制过来
+    ///
+    /// 与 Redis 相同，内存上限只是近似的：同时进行的写入在淘汰之后、写入之前
+    /// 可能让内存使用量短暂超过上限
+    fn evict(&self, key: &str, size: usize) -> Result<(), OutOfMemory> {
+        let maxmemory = self.maxmemory.load(Ordering::Relaxed);
+
+        if maxmemory == 0 || self.replica.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let policy = self.state.lock().unwrap().maxmemory_policy;
+
+        loop {
+            // The entry replaced by the write, if any, frees its memory.
+            let replaced = self
+                .lock_shard(key)
+                .entries
+                .get(key)
+                .map(|entry| eviction::entry_size(key, &entry.data, entry.expires_at.is_some()))
+                .unwrap_or(0);
+
+            let used_memory = self.used_memory.load(Ordering::Relaxed);
+
+            if used_memory.saturating_sub(replaced) + size <= maxmemory {
+                return Ok(());
+            }
+
+            if policy == MaxmemoryPolicy::NoEviction {
+                return Err(OutOfMemory);
+            }
+
+            let dirty = self.dirty.load(Ordering::Relaxed);
+
+            let (index, victim) = match self.eviction_candidate(policy) {
+                Some(candidate) => candidate,
+                // The shards are not locked together. When the dataset was
+                // modified during the scan, a key may have been missed.
+                None if self.dirty.load(Ordering::Relaxed) != dirty => continue,
+                None => return Err(OutOfMemory),
+            };
+            debug!(key = %victim, used_memory, "evicting key");
+
+            // The shard was unlocked in between, the key may be gone already.
+            let mut shard = self.shards[index].lock().unwrap();
+
+            if shard.entries.contains_key(&victim) {
+                self.evict_key(&mut shard, &victim);
+            }
+        }
+    }
+
+    /// 返回应该被淘汰的键以及它所在分片的下标
+    ///
+    /// 与 Redis 一样，每个分片从随机采样的 `eviction::SAMPLES` 个键中选出最佳的
+    /// 候选键，然后在所有分片之间比较。分片按下标升序逐个加锁，同一时间只持有
+    /// 一个分片的锁，因此所有分片都会被考虑，并且不会与其他操作死锁
+    fn eviction_candidate(&self, policy: MaxmemoryPolicy) -> Option<(usize, String)> {
+        let now = Instant::now();
+        let mut best: Option<(u64, usize, String)> = None;
+
+        for (index, shard) in self.shards.iter().enumerate() {
+            let candidate = shard.lock().unwrap().eviction_candidate(policy, now);
+
+            if let Some((score, key)) = candidate {
+                if best.as_ref().is_none_or(|(best, _, _)| score > *best) {
+                    best = Some((score, index, key));
+                }
+            }
+        }
+
+        best.map(|(_, index, key)| (index, key))
+    }
+
+    /// 淘汰持有锁的分片 `shard` 中的键
+    fn evict_key(&self, shard: &mut Shard, key: &str) {
+        self.remove(shard, key);
+        self.evicted_keys.fetch_add(1, Ordering::Relaxed);
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+        self.notify(EventClass::Evicted, "evicted", key);
+
+        // Replicas and the append-only file see the eviction as a `DEL`.
+        self.propagate(|| aof::del_frame(key));
+    }
+
+    /// 将 `frame` 返回的写入追加到 AOF 缓冲区以及复制积压缓冲区（如果已启用）
+    ///
+    /// 在持有被修改的键所在分片的锁时调用，因此对同一个键的写入在 AOF 和
+    /// 复制流中的顺序与数据集的修改顺序一致
+    fn propagate(&self, frame: impl FnOnce() -> Frame) {
+        if !self.propagating.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        if state.aof.is_none() && state.backlog.is_none() {
+            return;
+        }
+
+        let frame = frame();
+
+        if let Some(log) = &mut state.aof {
+            log.feed(&frame);
+        }
+
+        if let Some(backlog) = &mut state.backlog {
+            backlog.feed(&frame);
+        }
+    }
+
+    /// 如果启用了 `class` 类的事件，则发布键空间通知
+    ///
+    /// 通知使用普通的发布/订阅机制发送，因此客户端可以使用 `SUBSCRIBE` 或
+    /// `PSUBSCRIBE` 来接收它们
+    fn notify(&self, class: EventClass, event: &str, key: &str) {
+        let events = KeyspaceEvents::from_bits(self.notify_keyspace_events.load(Ordering::Relaxed));
+
+        if !events.enabled(class) {
+            return;
+        }
+
+        let mut pub_sub = self.pub_sub.lock().unwrap();
+
+        if events.keyspace() {
+            let channel = format!("__keyspace@0__:{}", key);
+            pub_sub.publish(&channel, Bytes::from(event.to_string()));
+        }
+
+        if events.keyevent() {
+            let channel = format!("__keyevent@0__:{}", event);
+            pub_sub.publish(&channel, Bytes::from(key.to_string()));
+        }
+    }
+}
+
+impl Shard {
+    /// 插入条目，返回之前的条目。设置了过期时间的键保持在前面
+    fn insert(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        let volatile = entry.expires_at.is_some();
+        let (index, prev) = self.entries.insert_full(key, entry);
+        let was_volatile = prev.as_ref().is_some_and(|prev| prev.expires_at.is_some());
+
+        self.moved(index, was_volatile, volatile);
+        prev
+    }
+
+    /// 删除条目，返回它的键和值
+    fn remove(&mut self, key: &str) -> Option<(String, Entry)> {
+        let mut index = self.entries.get_index_of(key)?;
+
+        // Move the entry out of the volatile keys first, `swap_remove` then
+        // replaces it with the last entry, which is not volatile.
+        if index < self.volatile {
+            self.moved(index, true, false);
+            index = self.volatile;
+        }
+
+        self.entries.swap_remove_index(index)
+    }
+
+    /// 设置键的过期时间。键不存在时返回 `None`，否则返回之前的过期时间
+    fn set_expires_at(&mut self, key: &str, when: Instant) -> Option<Option<Instant>> {
+        let (index, _, entry) = self.entries.get_full_mut(key)?;
+        let prev = entry.expires_at.replace(when);
+
+        self.moved(index, prev.is_some(), true);
+        Some(prev)
+    }
+
+    /// 下标为 `index` 的条目从 `was_volatile` 变为 `volatile` 之后，交换条目
+    /// 使设置了过期时间的键仍然排在前面
+    fn moved(&mut self, index: usize, was_volatile: bool, volatile: bool) {
+        match (was_volatile, volatile) {
+            (false, true) => {
+                self.entries.swap_indices(index, self.volatile);
+                self.volatile += 1;
+            }
+            (true, false) => {
+                self.volatile -= 1;
+                self.entries.swap_indices(index, self.volatile);
+            }
+            _ => {}
+        }
+    }
+
+    /// 返回该分片中最适合淘汰的键以及它的分数。与 Redis 一样，从随机采样的
+    /// `eviction::SAMPLES` 个键中选出最佳的候选键
+    ///
+    /// 带 `volatile` 的策略只从设置了过期时间的键，即下标 `0..volatile` 中采样
+    fn eviction_candidate(&self, policy: MaxmemoryPolicy, now: Instant) -> Option<(u64, String)> {
+        let len = if policy.is_volatile() {
+            self.volatile
+        } else {
+            self.entries.len()
+        };
+
+        let best = |indexes: &mut dyn Iterator<Item = usize>| {
+            indexes
+                .filter_map(|index| {
+                    let (key, entry) = self.entries.get_index(index).unwrap();
+                    let score = policy.score(&entry.access, entry.expires_at, now)?;
+                    Some((score, key))
+                })
+                .max_by_key(|(score, _)| *score)
+                .map(|(score, key)| (score, key.clone()))
+        };
+
+        // When there are few candidates, they are all considered.
+        if len <= eviction::SAMPLES {
+            return best(&mut (0..len));
+        }
+
+        best(&mut (0..eviction::SAMPLES).map(|_| eviction::random() as usize % len))
+    }
 }
 
-impl State {
-    fn next_expiration(&self) -> Option<Instant> {
-        self.expirations
+impl PubSub {
+    /// 向通道发布消息，返回接收到消息的订阅者数量，包括通过模式订阅的订阅者
+    ///
+    /// 订阅者断开连接时不会修改 `PubSub`，已经没有订阅者的通道和模式在这里移除
+    fn publish(&mut self, channel: &str, message: Bytes) -> usize {
+        let num_subscribers = match self
+            .channels
+            .get(channel)
+            .map(|tx| tx.send(message.clone()))
+        {
+            // On a successful message send on the broadcast channel, the
+            // number of subscribers is returned.
+            Some(Ok(num_subscribers)) => num_subscribers,
+            // An error indicates there are no receivers anymore.
+            Some(Err(_)) => {
+                self.channels.remove(channel);
+                0
+            }
+            // If there is no entry for the channel key, then there are no
+            // subscribers.
+            None => 0,
+        };
+
+        // Patterns without subscribers are removed before matching, so that
+        // they do not accumulate.
+        self.patterns.retain(|_, tx| tx.receiver_count() > 0);
+
+        // Every pattern matching the channel name receives the message as well.
+        // Patterns are matched on each publish, so the cost grows with the
+        // number of distinct patterns rather than with the number of channels.
+        let num_pattern_subscribers: usize = self
+            .patterns
             .iter()
-            .next()
-            .map(|expiration| expiration.0)
+            .filter(|(pattern, _)| glob_match(pattern.as_bytes(), channel.as_bytes()))
+            .map(|(_, tx)| tx.send((channel.to_string(), message.clone())).unwrap_or(0))
+            .sum();
+
+        num_subscribers + num_pattern_subscribers
+    }
+}
+
+/// 返回从现在起 `expire` 之后的时刻，以及对应的系统时间，用于写入 AOF 和
+/// 传播给副本
+///
+/// 时刻超出时钟可以表示的范围时返回 `WriteError::InvalidExpireTime`。在获取
+/// 分片的锁之前调用，溢出不会在持有锁时发生
+fn deadline(expire: Duration) -> Result<(Instant, SystemTime), WriteError> {
+    match (
+        Instant::now().checked_add(expire),
+        SystemTime::now().checked_add(expire),
+    ) {
+        (Some(when), Some(system_when)) => Ok((when, system_when)),
+        _ => Err(WriteError::InvalidExpireTime),
     }
 }
 
 /// 由后台任务执行的例程
 ///
-/// 等待被通知。收到通知时，从共享状态句柄中清除任何过期的键。
-/// 如果设置了 `shutdown`，则终止任务
-async fn purge_expired_tasks(shared: Arc<Shared>) {
-    // If the shutdown flag is set, then the task should exit.
-    while !shared.is_shutdown() {
-        // Purge all keys that are expired. The function returns the instant at
-        // which the **next** key will expire. The worker should wait until the
-        // instant has passed then purge again.
-        if let Some(when) = shared.purge_expired_keys() {
-            // Wait until the next key expires **or** until the background task
-            // is notified. If the task is notified, then it must reload its
-            // state as new keys have been set to expire early. This is done by
-            // looping.
-            tokio::select! {
-                _ = time::sleep_until(when) => {}
-                _ = shared.background_task.notified() => {}
+/// 每隔 `expiration::CYCLE_PERIOD` 执行一轮主动过期。收到通知并且设置了
+/// `shutdown` 时终止任务
+async fn active_expire_task(shared: Arc<Shared>) {
+    let mut start = 0;
+
+    loop {
+        tokio::select! {
+            _ = time::sleep(expiration::CYCLE_PERIOD) => {}
+            _ = shared.background_task.notified() => {}
+        }
+
+        // If the shutdown flag is set, then the task should exit.
+        if shared.is_shutdown() {
+            break;
+        }
+
+        if shared.active_expire.load(Ordering::Relaxed) {
+            start = shared.active_expire_cycle(start);
+        }
+    }
+
+    debug!("Active expiration background task shut down")
+}
+
+/// 判断 `string` 是否与 glob 风格的 `pattern` 匹配
+///
+/// 支持与 Redis `PSUBSCRIBE` 相同的语法：`*` 匹配任意字节序列，`?` 匹配单个字节，
+/// `[...]` 匹配字符集（支持 `^` 取反和 `a-z` 范围），`\` 转义下一个字节
+///
+/// 与 Redis 的 `stringmatchlen` 一样，不匹配时只回溯到最后一个 `*`，匹配时间与
+/// `pattern` 和 `string` 长度的乘积成正比
+pub(crate) fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let mut p = 0;
+    let mut s = 0;
+
+    // Position in `pattern` after the last `*`, and position in `string` of
+    // the end of the text it consumes.
+    let mut star = None;
+
+    loop {
+        if pattern.get(p) == Some(&b'*') {
+            // The `*` first consumes nothing.
+            p += 1;
+            star = Some((p, s));
+            continue;
+        }
+
+        let matched = match string.get(s) {
+            // Only trailing `*` match the end of the string, they were
+            // consumed above.
+            None => return p == pattern.len(),
+            Some(&c) => glob_match_one(&pattern[p..], c),
+        };
+
+        match (matched, star) {
+            (Some(len), _) => {
+                p += len;
+                s += 1;
             }
-        } else {
-            // There are no keys expiring in the future. Wait until the task is
-            // notified.
-            shared.background_task.notified().await;
+            (None, Some((star_p, star_s))) => {
+                // The last `*` consumes one more byte. Earlier stars never
+                // need to consume more: any text they would take can be taken
+                // by the last one instead.
+                p = star_p;
+                s = star_s + 1;
+                star = Some((star_p, s));
+            }
+            (None, None) => return false,
         }
     }
+}
+
+/// 如果 `pattern` 开头的元素（`*` 以外）与字节 `c` 匹配，返回该元素的长度
+fn glob_match_one(pattern: &[u8], c: u8) -> Option<usize> {
+    match pattern {
+        [] => None,
+        [b'?', ..] => Some(1),
+        [b'[', rest @ ..] => {
+            let (negate, mut class) = match rest.split_first() {
+                Some((b'^', class)) => (true, class),
+                _ => (false, rest),
+            };
 
-    debug!("Purge background task shut down")
+            let mut matched = false;
+
+            loop {
+                match class {
+                    // An unterminated class matches up to the end of the pattern.
+                    [] => break,
+                    [b']', tail @ ..] => {
+                        class = tail;
+                        break;
+                    }
+                    [b'\\', escaped, tail @ ..] => {
+                        matched |= *escaped == c;
+                        class = tail;
+                    }
+                    [start, b'-', end, tail @ ..] if *end != b']' => {
+                        let (lo, hi) = if start <= end {
+                            (*start, *end)
+                        } else {
+                            (*end, *start)
+                        };
+                        matched |= lo <= c && c <= hi;
+                        class = tail;
+                    }
+                    [other, tail @ ..] => {
+                        matched |= *other == c;
+                        class = tail;
+                    }
+                }
+            }
+
+            (matched != negate).then_some(pattern.len() - class.len())
+        }
+        [b'\\', escaped, ..] => (*escaped == c).then_some(2),
+        [p, ..] => (*p == c).then_some(1),
+    }
 }
@@ -0,0 +1,291 @@
+//! Memory limit and key eviction
+//!
+//! The memory used by the dataset is estimated from the size of each key and
+//! value, plus a fixed overhead per entry and per expiration. The same
+//! estimates are reported by `MEMORY USAGE` and `MEMORY STATS`. When `maxmemory` is set and a write
+//! would exceed it, keys are evicted according to the `maxmemory-policy`
+//! before the write is applied. See
+//! https://redis.io/docs/reference/eviction/ for details.
+//!
+//! Like Redis, the policies are approximated: instead of keeping the keys
+//! sorted by last access or frequency, a few random keys are sampled and the
+//! best candidate among them is evicted.
+
+use bytes::Bytes;
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use tokio::time::{Duration, Instant};
+
+/// Which keys are evicted when the memory limit is reached.
+///
+/// Parsed from, and formatted as, the values of the Redis `maxmemory-policy`
+/// configuration parameter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MaxmemoryPolicy {
+    /// Keys are never evicted, writes fail with an `OOM` error instead. This
+    /// is the default.
+    #[default]
+    NoEviction,
+
+    /// Evict the least recently used keys.
+    AllKeysLru,
+
+    /// Evict the least frequently used keys.
+    AllKeysLfu,
+
+    /// Evict random keys.
+    AllKeysRandom,
+
+    /// Evict the least recently used keys among the keys with an expiration.
+    VolatileLru,
+
+    /// Evict the keys with the shortest time to live.
+    VolatileTtl,
+}
+
+/// Policy names, as used by the `maxmemory-policy` parameter.
+const POLICIES: &[(&str, MaxmemoryPolicy)] = &[
+    ("noeviction", MaxmemoryPolicy::NoEviction),
+    ("allkeys-lru", MaxmemoryPolicy::AllKeysLru),
+    ("allkeys-lfu", MaxmemoryPolicy::AllKeysLfu),
+    ("allkeys-random", MaxmemoryPolicy::AllKeysRandom),
+    ("volatile-lru", MaxmemoryPolicy::VolatileLru),
+    ("volatile-ttl", MaxmemoryPolicy::VolatileTtl),
+];
+
+/// Number of keys sampled to pick the key to evict. Same default as the Redis
+/// `maxmemory-samples` parameter.
+pub(crate) const SAMPLES: usize = 5;
+
+/// Estimated memory used by an entry in addition to its key and value: the
+/// hash table slot, the `String` and `Bytes` headers and the metadata.
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Estimated memory used to track the expiration of a key: the deadline stored
+/// in the entry. Keys with an expiration are not indexed separately, see
+/// `expiration`.
+const EXPIRATION_OVERHEAD: usize = 16;
+
+/// Estimated memory used by a pub/sub channel in addition to its name: the
+/// broadcast channel allocates room for 1024 messages upfront.
+const CHANNEL_OVERHEAD: usize = 64 * 1024;
+
+/// Initial access frequency of new keys, so that they are not evicted before
+/// having a chance to be accessed.
+const LFU_INIT: u8 = 5;
+
+/// The higher the factor, the more accesses are needed to increment the
+/// logarithmic access frequency.
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// The access frequency is decremented once per period without access.
+const LFU_DECAY_PERIOD: Duration = Duration::from_secs(60);
+
+/// Error returned when a write is rejected because of the memory limit.
+#[derive(Debug)]
+pub(crate) struct OutOfMemory;
+
+/// Aggregated memory usage, reported by `MEMORY STATS` and `MEMORY DOCTOR`.
+#[derive(Debug)]
+pub(crate) struct MemoryStats {
+    /// Number of keys.
+    pub(crate) keys: usize,
+
+    /// Number of keys with an expiration.
+    pub(crate) expires: usize,
+
+    /// Memory used by the keys, values and expirations.
+    pub(crate) dataset: usize,
+
+    /// Number of pub/sub channels and patterns with subscribers.
+    pub(crate) pubsub_channels: usize,
+
+    /// Memory used by the pub/sub channels and patterns.
+    pub(crate) pubsub: usize,
+
+    /// Memory used by the replication backlog.
+    pub(crate) replication_backlog: usize,
+
+    /// Memory limit of the dataset, 0 if there is no limit.
+    pub(crate) maxmemory: usize,
+
+    /// Eviction policy applied when the limit is reached.
+    pub(crate) maxmemory_policy: MaxmemoryPolicy,
+
+    /// Number of keys evicted since the server started.
+    pub(crate) evicted_keys: u64,
+}
+
+/// Access metadata of an entry, used by the LRU and LFU policies.
+#[derive(Debug)]
+pub(crate) struct Access {
+    /// Last time the entry was read or written.
+    last: Instant,
+
+    /// Logarithmic access frequency, as the Redis LFU counter: the counter is
+    /// incremented with a probability decreasing as it grows, so that 255 is
+    /// only reached after about a million accesses.
+    counter: u8,
+}
+
+impl MaxmemoryPolicy {
+    /// Returns `true` if the policy only evicts keys with an expiration.
+    pub(crate) fn is_volatile(self) -> bool {
+        matches!(
+            self,
+            MaxmemoryPolicy::VolatileLru | MaxmemoryPolicy::VolatileTtl
+        )
+    }
+
+    /// Returns how good a candidate for eviction an entry is, the highest
+    /// score being evicted first. Returns `None` if the policy never evicts
+    /// the entry.
+    ///
+    /// With `allkeys-random` the score is random, so that comparing candidates
+    /// picked from different shards still evicts a random key.
+    pub(crate) fn score(
+        self,
+        access: &Access,
+        expires_at: Option<Instant>,
+        now: Instant,
+    ) -> Option<u64> {
+        let idle = || now.saturating_duration_since(access.last).as_millis() as u64;
+
+        match self {
+            MaxmemoryPolicy::NoEviction => None,
+            MaxmemoryPolicy::AllKeysLru => Some(idle()),
+            MaxmemoryPolicy::AllKeysLfu => Some(u64::from(u8::MAX - access.decayed(now))),
+            MaxmemoryPolicy::AllKeysRandom => Some(random()),
+            MaxmemoryPolicy::VolatileLru => expires_at.map(|_| idle()),
+            MaxmemoryPolicy::VolatileTtl => expires_at
+                .map(|when| u64::MAX - when.saturating_duration_since(now).as_millis() as u64),
+        }
+    }
+}
+
+impl FromStr for MaxmemoryPolicy {
+    type Err = crate::Error;
+
+    fn from_str(src: &str) -> crate::Result<MaxmemoryPolicy> {
+        POLICIES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(src))
+            .map(|(_, policy)| *policy)
+            .ok_or_else(|| format!("invalid maxmemory policy `{}`", src).into())
+    }
+}
+
+impl fmt::Display for MaxmemoryPolicy {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let (name, _) = POLICIES.iter().find(|(_, policy)| policy == self).unwrap();
+
+        name.fmt(fmt)
+    }
+}
+
+impl Access {
+    /// Metadata of an entry written at `now`.
+    pub(crate) fn new(now: Instant) -> Access {
+        Access {
+            last: now,
+            counter: LFU_INIT,
+        }
+    }
+
+    /// Records an access to the entry.
+    pub(crate) fn touch(&mut self, now: Instant) {
+        let counter = self.decayed(now);
+
+        let base = counter.saturating_sub(LFU_INIT);
+        let p = 1.0 / (f64::from(base) * LFU_LOG_FACTOR + 1.0);
+
+        self.counter = if counter < u8::MAX && random_f64() < p {
+            counter + 1
+        } else {
+            counter
+        };
+        self.last = now;
+    }
+
+    /// Returns the access frequency, decremented for every period elapsed
+    /// since the last access.
+    fn decayed(&self, now: Instant) -> u8 {
+        let periods =
+            now.saturating_duration_since(self.last).as_secs() / LFU_DECAY_PERIOD.as_secs();
+
+        self.counter
+            .saturating_sub(periods.min(u64::from(u8::MAX)) as u8)
+    }
+}
+
+impl fmt::Display for OutOfMemory {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        "OOM command not allowed when used memory > 'maxmemory'.".fmt(fmt)
+    }
+}
+
+impl Error for OutOfMemory {}
+
+/// Returns the estimated memory used by an entry, including the tracking of
+/// its expiration if `expires` is set.
+pub(crate) fn entry_size(key: &str, value: &Bytes, expires: bool) -> usize {
+    let size = key.len() + value.len() + ENTRY_OVERHEAD;
+
+    if expires {
+        size + expiration_size()
+    } else {
+        size
+    }
+}
+
+/// Returns the estimated memory used to track the expiration of a key.
+pub(crate) fn expiration_size() -> usize {
+    EXPIRATION_OVERHEAD
+}
+
+/// Returns the estimated memory used by a pub/sub channel or pattern.
+pub(crate) fn channel_size(name: &str) -> usize {
+    name.len() + CHANNEL_OVERHEAD
+}
+
+/// Parses a memory size, in bytes or with one of the units supported by
+/// Redis: `k`, `kb`, `m`, `mb`, `g` and `gb`. Like Redis, `k` is 1000 bytes
+/// while `kb` is 1024 bytes.
+pub(crate) fn parse_memory(src: &str) -> crate::Result<usize> {
+    let lower = src.to_ascii_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+
+    let unit: usize = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid memory size `{}`", src).into()),
+    };
+
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("invalid memory size `{}`", src).into())
+}
+
+/// Returns a random number, used to sample keys.
+pub(crate) fn random() -> u64 {
+    // `RandomState` is randomly seeded, which is enough to sample keys without
+    // depending on a random number generator.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    hasher.finish()
+}
+
+/// Returns a random number in `[0, 1)`.
+fn random_f64() -> f64 {
+    (random() >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,79 @@
+//! Key expiration
+//!
+//! Like Redis, expired keys are removed in two ways:
+//!
+//! * Lazily: a key accessed after its deadline is deleted on the spot and
+//!   treated as missing.
+//! * Actively: a background task periodically samples keys with an
+//!   expiration and deletes the expired ones. As long as a large share of the
+//!   sampled keys is expired, the same shard is sampled again. The time spent
+//!   per cycle is bounded, so that a burst of expirations does not stall the
+//!   server.
+//!
+//! Expiring keys are not indexed by deadline: each shard keeps its keys with
+//! an expiration at the front of its entries, where they can be sampled
+//! without keeping a second copy of the key. See
+//! https://redis.io/commands/expire/#how-redis-expires-keys for details.
+
+use std::fmt::Write;
+use tokio::time::Duration;
+
+/// Interval between two active expiration cycles. Same as the Redis default
+/// `hz` of 10.
+pub(crate) const CYCLE_PERIOD: Duration = Duration::from_millis(100);
+
+/// Maximum time spent in a single active expiration cycle, 25% of the period
+/// as in Redis.
+pub(crate) const CYCLE_BUDGET: Duration = Duration::from_millis(25);
+
+/// Number of keys with an expiration sampled at once in a shard.
+pub(crate) const SAMPLES: usize = 20;
+
+/// A shard is sampled again while more than this percentage of the sampled
+/// keys was expired.
+pub(crate) const ACCEPTABLE_STALE: usize = 10;
+
+/// Outcome of the active expiration cycles, reported by `INFO stats`.
+#[derive(Debug, Default)]
+pub(crate) struct ExpireStats {
+    /// Estimated percentage of the keys with an expiration that are expired
+    /// but not deleted yet: a running average of the ratio observed by the
+    /// active expiration cycles.
+    stale_perc: f64,
+
+    /// Number of active expiration cycles stopped early because they reached
+    /// `CYCLE_BUDGET`.
+    time_cap_reached: u64,
+}
+
+impl ExpireStats {
+    /// Records the outcome of an active expiration cycle, which found
+    /// `expired` expired keys among `sampled` keys.
+    pub(crate) fn record_cycle(&mut self, sampled: usize, expired: usize, time_cap_reached: bool) {
+        if sampled > 0 {
+            // Same smoothing as Redis, the average reflects the last few
+            // seconds of cycles.
+            let perc = expired as f64 * 100.0 / sampled as f64;
+            self.stale_perc = perc * 0.05 + self.stale_perc * 0.95;
+        }
+
+        if time_cap_reached {
+            self.time_cap_reached += 1;
+        }
+    }
+}
+
+/// Returns the `stats` section of `INFO`. `expired_keys` and `evicted_keys`
+/// are the number of keys expired and evicted since the server started.
+pub(crate) fn info(stats: &ExpireStats, expired_keys: u64, evicted_keys: u64) -> String {
+    let mut out = String::from("# Stats\r\n");
+
+    let _ = write!(
+        out,
+        "expired_keys:{}\r\nexpired_stale_perc:{:.2}\r\n\
+         expired_time_cap_reached_count:{}\r\nevicted_keys:{}\r\n",
+        expired_keys, stats.stale_perc, stats.time_cap_reached, evicted_keys
+    );
+
+    out
+}
@@ -1,9 +1,10 @@
 //! 提供表示 Redis 协议帧的类型以及从字节数组解析帧的工具
 
-use bytes::{Buf, Bytes};
-use std::convert::TryInto;
+use bytes::{Buf, Bytes, BytesMut};
+use std::convert::{TryFrom, TryInto};
 use std::fmt;
-use std::io::Cursor;
+use std::io::{self, Cursor};
+use std::mem;
 use std::num::TryFromIntError;
 use std::string::FromUtf8Error;
 
@@ -18,6 +19,70 @@ pub enum Frame {
     Array(Vec<Frame>),
 }
 
+/// 从读取缓冲区增量解析帧
+///
+/// 与 `Frame::check` 加 `Frame::parse` 不同，已经解析的部分会立即从缓冲区中
+/// 移除，并记录在解析器中。帧不完整时，下一次调用从中断的位置继续，而不是
+/// 从头重新扫描整个帧。较大的 bulk 字符串直接从缓冲区中切分出来，不复制数据
+#[derive(Debug, Default)]
+pub struct Parser {
+    /// 正在解析的数组，最内层的在最后
+    arrays: Vec<PartialArray>,
+
+    /// 头部已经解析、数据尚未完整接收的 bulk 字符串的长度
+    bulk: Option<usize>,
+
+    /// 当前帧已经从缓冲区中移除的字节数
+    consumed: usize,
+
+    /// 缓冲区开头的不完整的行中已经扫描过、不包含 `\r\n` 的字节数。收到更多
+    /// 数据后从这里继续查找行尾
+    scanned: usize,
+
+    /// 对等方声明的长度和嵌套深度的上限
+    limits: Limits,
+}
+
+/// 解析帧时对对等方声明的长度和嵌套深度的限制
+///
+/// 长度在数据到达之前就已经声明，不受限制时，一个声明了巨大长度的头部或者
+/// 嵌套很深的数组就可以耗尽内存或者栈。超过限制的帧是协议错误
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// bulk 字符串的最大长度，对应 Redis 的 `proto-max-bulk-len`。默认 512MB
+    pub max_bulk_len: usize,
+
+    /// 数组的最大元素个数。与 Redis 相同，默认为 `i32::MAX`
+    pub max_multibulk_len: usize,
+
+    /// 数组的最大嵌套深度，不嵌套的数组深度为 1。默认为 32
+    pub max_depth: usize,
+
+    /// 一行的最大长度，不包括结尾的 `\r\n`。适用于简单字符串、错误、整数以及
+    /// 数组和 bulk 字符串的头部。与 Redis 的 `PROTO_INLINE_MAX_SIZE` 相同，默认
+    /// 64KB
+    pub max_line_len: usize,
+}
+
+/// 正在解析的数组
+#[derive(Debug)]
+struct PartialArray {
+    /// 已经解析的元素
+    frames: Vec<Frame>,
+
+    /// 尚未解析的元素个数
+    remaining: usize,
+}
+
+/// 不小于该长度的 bulk 字符串直接从读取缓冲区中切分出来，不复制数据。与 Redis
+/// 相同，较短的字符串仍然被复制：切分出的 `Bytes` 会让整个读取缓冲区的内存
+/// 一直被占用，对于较短的值这个代价比复制更高
+const ZERO_COPY_MIN_LEN: usize = 32 * 1024;
+
+/// 收到较大的 bulk 字符串的头部时，最多为它的数据预先分配的字节数。长度来自
+/// 对等方，不能据此一次分配全部内存，缓冲区随着数据实际到达再增长
+const BULK_RESERVE_MAX: usize = 1024 * 1024;
+
 #[derive(Debug)]
 pub enum Error {
     /// 可用数据不足以解析消息
@@ -52,7 +117,7 @@ impl Frame {
     /// # Panic
     ///
     /// 如果 `self` 不是数组帧则 panic
-    pub(crate) fn push_integer(&mut self, value: u64) {
+    pub(crate) fn push_int(&mut self, value: u64) {
         match self {
             Frame::Array(vec) => {
                 vec.push(Frame::Integer(value));
@@ -61,17 +126,36 @@ impl Frame {
         }
     }
 
+    /// 将任意帧推入数组，例如嵌套的数组。`self` 必须是数组帧
+    ///
+    /// # Panic
+    ///
+    /// 如果 `self` 不是数组帧则 panic
+    pub(crate) fn push(&mut self, frame: Frame) {
+        match self {
+            Frame::Array(vec) => {
+                vec.push(frame);
+            }
+            _ => panic!("not an array frame"),
+        }
+    }
+
     /// 检查是否可以从 `src` 解析出完整的消息
+    ///
+    /// 使用默认的 `Limits`
     pub fn check(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
+        Frame::check_with_limits(src, &Limits::default())
+    }
+
+    /// 与 `check` 相同，超过 `limits` 的消息是协议错误
+    pub fn check_with_limits(src: &mut Cursor<&[u8]>, limits: &Limits) -> Result<(), Error> {
+        Frame::check_nested(src, limits, 0)
+    }
+
+    /// 检查嵌套在 `depth` 层数组中的消息
+    fn check_nested(src: &mut Cursor<&[u8]>, limits: &Limits, depth: usize) -> Result<(), Error> {
         match get_u8(src)? {
-            b'+' => {
-                get_line(src)?;
-                Ok(())
-            }
-            b'-' => {
-                get_line(src)?;
-                Ok(())
-            }
+            b'+' | b'-' => limits.line_len(get_line(src)?.len()),
             b':' => {
                 let _ = get_decimal(src)?;
                 Ok(())
@@ -82,17 +166,18 @@ impl Frame {
                     skip(src, 4)
                 } else {
                     // Read the bulk string
-                    let len: usize = get_decimal(src)?.try_into()?;
+                    let len = limits.bulk_len(get_decimal(src)?)?;
 
                     // skip that number of bytes + 2 (\r\n).
                     skip(src, len + 2)
                 }
             }
             b'*' => {
-                let len = get_decimal(src)?;
+                let len = limits.multibulk_len(get_decimal(src)?)?;
+                limits.nest(depth)?;
 
                 for _ in 0..len {
-                    Frame::check(src)?;
+                    Frame::check_nested(src, limits, depth + 1)?;
                 }
 
                 Ok(())
@@ -166,12 +251,275 @@ impl Frame {
         }
     }
 
+    /// 将帧按照 Redis 协议编码，追加到 `dst`
+    ///
+    /// 数组的条目递归编码，条目本身也可以是数组
+    pub fn encode(&self, dst: &mut BytesMut) {
+        use std::fmt::Write;
+
+        match self {
+            Frame::Simple(val) => {
+                let _ = write!(dst, "+{}\r\n", val);
+            }
+            Frame::Error(val) => {
+                let _ = write!(dst, "-{}\r\n", val);
+            }
+            Frame::Integer(val) => {
+                let _ = write!(dst, ":{}\r\n", val);
+            }
+            Frame::Null => dst.extend_from_slice(b"$-1\r\n"),
+            Frame::Bulk(val) => {
+                let _ = write!(dst, "${}\r\n", val.len());
+                dst.extend_from_slice(val);
+                dst.extend_from_slice(b"\r\n");
+            }
+            Frame::Array(val) => {
+                let _ = write!(dst, "*{}\r\n", val.len());
+
+                for entry in val {
+                    entry.encode(dst);
+                }
+            }
+        }
+    }
+
     /// 将帧转换为"意外帧"错误
     pub(crate) fn to_error(&self) -> crate::Error {
         format!("unexpected frame: {}", self).into()
     }
 }
 
+impl Parser {
+    /// 创建一个使用默认 `Limits` 的解析器
+    pub fn new() -> Parser {
+        Parser::default()
+    }
+
+    /// 创建一个使用 `limits` 的解析器
+    pub fn with_limits(limits: Limits) -> Parser {
+        Parser {
+            limits,
+            ..Parser::default()
+        }
+    }
+
+    /// 修改之后解析的帧的限制
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// 从 `buf` 中解析下一个帧，并移除它的字节
+    ///
+    /// 帧不完整时返回 `Ok(None)`。此时已经解析的部分同样会从 `buf` 中移除，
+    /// 调用者向 `buf` 追加更多数据后再次调用即可继续解析
+    pub fn parse(&mut self, buf: &mut BytesMut) -> Result<Option<Frame>, Error> {
+        Ok(self.parse_with_len(buf)?.map(|(frame, _)| frame))
+    }
+
+    /// 与 `parse` 相同，同时返回帧编码后的字节数
+    pub(crate) fn parse_with_len(
+        &mut self,
+        buf: &mut BytesMut,
+    ) -> Result<Option<(Frame, usize)>, Error> {
+        loop {
+            let consumed = self.consumed;
+
+            let mut frame = match self.bulk {
+                Some(len) => match self.parse_bulk_data(buf, len) {
+                    Some(frame) => frame,
+                    None => return Ok(None),
+                },
+                None => match self.parse_element(buf)? {
+                    Some(frame) => frame,
+                    // An array or bulk string header was consumed, its
+                    // content comes next.
+                    None if self.consumed > consumed => continue,
+                    None => return Ok(None),
+                },
+            };
+
+            // Add the complete frame to the innermost array, completing the
+            // arrays it was the last element of.
+            loop {
+                let array = match self.arrays.last_mut() {
+                    Some(array) => array,
+                    None => return Ok(Some((frame, mem::take(&mut self.consumed)))),
+                };
+
+                array.frames.push(frame);
+                array.remaining -= 1;
+
+                if array.remaining > 0 {
+                    break;
+                }
+
+                frame = Frame::Array(self.arrays.pop().unwrap().frames);
+            }
+        }
+    }
+
+    /// 如果一个帧解析到一半，返回 `true`
+    pub fn is_partial(&self) -> bool {
+        self.consumed > 0
+    }
+
+    /// 从 `buf` 的开头解析一行：简单字符串、错误、整数，或者数组和 bulk
+    /// 字符串的头部
+    ///
+    /// 头部被解析后，进度记录在 `self` 中并返回 `Ok(None)`，空数组除外
+    fn parse_element(&mut self, buf: &mut BytesMut) -> Result<Option<Frame>, Error> {
+        // The line starts after the type byte. The bytes scanned by a previous
+        // call do not contain the end of the line.
+        let start = self.scanned.max(1);
+
+        let end = match buf.get(start..).and_then(find_crlf) {
+            Some(end) => end + start,
+            None => {
+                // The last byte may be the `\r` of the line end, it is
+                // scanned again.
+                self.scanned = buf.len().saturating_sub(1);
+
+                let len = self.scanned.saturating_sub(buf.ends_with(b"\r") as usize);
+                self.limits.line_len(len)?;
+                return Ok(None);
+            }
+        };
+
+        self.scanned = 0;
+        self.limits.line_len(end - 1)?;
+
+        let line = &buf[1..end];
+
+        let frame = match buf[0] {
+            b'+' => Some(Frame::Simple(String::from_utf8(line.to_vec())?)),
+            b'-' => Some(Frame::Error(String::from_utf8(line.to_vec())?)),
+            b':' => Some(Frame::Integer(decimal(line)?)),
+            b'$' if line == b"-1" => Some(Frame::Null),
+            b'$' => {
+                let len = self.limits.bulk_len(decimal(line)?)?;
+
+                // The data of a large bulk string is kept in the allocation
+                // it is read into, make room for it ahead of time. The length
+                // comes from the peer, only part of it is trusted.
+                if len >= ZERO_COPY_MIN_LEN {
+                    let data = usize::min(len + 2, BULK_RESERVE_MAX);
+                    buf.reserve((end + 2 + data).saturating_sub(buf.len()));
+                }
+
+                self.bulk = Some(len);
+                None
+            }
+            b'*' => {
+                let len = self.limits.multibulk_len(decimal(line)?)?;
+                self.limits.nest(self.arrays.len())?;
+
+                if len == 0 {
+                    Some(Frame::Array(vec![]))
+                } else {
+                    self.arrays.push(PartialArray {
+                        // The length comes from the peer, do not trust it to
+                        // preallocate.
+                        frames: Vec::with_capacity(usize::min(len, 1024)),
+                        remaining: len,
+                    });
+                    None
+                }
+            }
+            actual => {
+                return Err(format!("protocol error; invalid frame type byte `{}`", actual).into())
+            }
+        };
+
+        buf.advance(end + 2);
+        self.consumed += end + 2;
+
+        Ok(frame)
+    }
+
+    /// 从 `buf` 的开头解析头部已经解析过的、长度为 `len` 的 bulk 字符串的数据。
+    /// 数据不完整时返回 `None`
+    fn parse_bulk_data(&mut self, buf: &mut BytesMut, len: usize) -> Option<Frame> {
+        if buf.len() < len + 2 {
+            return None;
+        }
+
+        let data = if len >= ZERO_COPY_MIN_LEN {
+            buf.split_to(len).freeze()
+        } else {
+            let data = Bytes::copy_from_slice(&buf[..len]);
+            buf.advance(len);
+            data
+        };
+
+        // Skip the trailing \r\n.
+        buf.advance(2);
+
+        self.bulk = None;
+        self.consumed += len + 2;
+
+        Some(Frame::Bulk(data))
+    }
+}
+
+impl Limits {
+    /// 不限制长度，只限制嵌套深度。用于可信的数据，例如 AOF 文件和主节点发送的
+    /// 复制流，其中的值可能是在更宽松的限制下写入的
+    pub(crate) fn trusted() -> Limits {
+        Limits {
+            max_bulk_len: usize::MAX,
+            max_multibulk_len: usize::MAX,
+            ..Limits::default()
+        }
+    }
+
+    /// 检查 bulk 字符串头部声明的长度
+    ///
+    /// 数据之后还有 `\r\n`，长度加 2 溢出时也返回错误
+    fn bulk_len(&self, len: u64) -> Result<usize, Error> {
+        match usize::try_from(len) {
+            Ok(len) if len <= self.max_bulk_len && len.checked_add(2).is_some() => Ok(len),
+            _ => Err("protocol error; invalid bulk length".into()),
+        }
+    }
+
+    /// 检查数组头部声明的元素个数
+    fn multibulk_len(&self, len: u64) -> Result<usize, Error> {
+        match len.try_into() {
+            Ok(len) if len <= self.max_multibulk_len => Ok(len),
+            _ => Err("protocol error; invalid multibulk length".into()),
+        }
+    }
+
+    /// 检查一行的长度
+    fn line_len(&self, len: usize) -> Result<(), Error> {
+        if len > self.max_line_len {
+            return Err("protocol error; line too long".into());
+        }
+
+        Ok(())
+    }
+
+    /// 检查是否可以在嵌套在 `depth` 层数组中的位置开始一个数组
+    fn nest(&self, depth: usize) -> Result<(), Error> {
+        if depth >= self.max_depth {
+            return Err("protocol error; arrays nested too deeply".into());
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: i32::MAX as usize,
+            max_depth: 32,
+            max_line_len: 64 * 1024,
+        }
+    }
+}
+
 impl PartialEq<&str> for Frame {
     fn eq(&self, other: &&str) -> bool {
         match self {
@@ -238,9 +586,18 @@ fn skip(src: &mut Cursor<&[u8]>, n: usize) -> Result<(), Error> {
 
 /// 读取以换行符结尾的十进制数
 fn get_decimal(src: &mut Cursor<&[u8]>) -> Result<u64, Error> {
-    use atoi::atoi;
+    decimal(get_line(src)?)
+}
 
-    let line = get_line(src)?;
+/// 返回 `src` 中第一个 `\r\n` 的位置
+fn find_crlf(src: &[u8]) -> Option<usize> {
+    // Same scan as `get_line`.
+    (0..src.len().saturating_sub(1)).find(|&i| src[i] == b'\r' && src[i + 1] == b'\n')
+}
+
+/// 将一行解析为十进制数
+fn decimal(line: &[u8]) -> Result<u64, Error> {
+    use atoi::atoi;
 
     atoi::<u64>(line).ok_or_else(|| "protocol error; invalid frame format".into())
 }
@@ -277,6 +634,12 @@ impl From<&str> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(src: io::Error) -> Error {
+        Error::Other(src.into())
+    }
+}
+
 impl From<FromUtf8Error> for Error {
     fn from(_src: FromUtf8Error) -> Error {
         "protocol error; invalid frame format".into()
@@ -11,35 +11,67 @@
 //! 主要组件包括：
 //!
 //! * `server`：Redis 服务器实现。包含一个单独的 `run` 函数，该函数接受一个
-//!   `TcpListener` 并开始接受 redis 客户端连接
+//!   `TcpListener` 或者 `UnixListener` 并开始接受 redis 客户端连接
 //!
 //! * `clients/client`：异步 Redis 客户端实现。演示如何使用 Tokio 构建客户端
 //!
+//! * `clients/cluster_client`：集群客户端。根据哈希槽把命令发送到对应的节点，
+//!   并跟随 `MOVED`/`ASK` 重定向
+//!
 //! * `cmd`：支持的 Redis 命令的实现
 //!
 //! * `frame`：表示单个 Redis 协议帧。帧被用作"命令"和字节表示之间的中间表示
+//!
+//! * `codec`：用于 `tokio_util::codec::Framed` 的帧编解码器
 
 pub mod clients;
-pub use clients::{BlockingClient, BufferedClient, Client};
+pub use clients::{BlockingClient, BufferedClient, Client, ClusterClient};
+
+pub mod cluster;
+
+pub mod sentinel;
 
 pub mod cmd;
 pub use cmd::Command;
 
+mod aof;
+pub use aof::AppendFsync;
+
 mod connection;
-pub use connection::Connection;
+pub use connection::{Connection, Transport};
 
 pub mod frame;
 pub use frame::Frame;
 
+pub mod codec;
+
 mod db;
 use db::Db;
 use db::DbDropGuard;
 
+mod eviction;
+pub use eviction::MaxmemoryPolicy;
+
+mod expiration;
+
+mod notify;
+pub use notify::KeyspaceEvents;
+
 mod parse;
 use parse::{Parse, ParseError};
 
+pub mod rdb;
+
+mod replication;
+
 pub mod server;
 
+#[cfg(feature = "tls")]
+pub mod tls;
+
+mod snapshot;
+pub use snapshot::SaveRule;
+
 mod shutdown;
 use shutdown::Shutdown;
 
@@ -0,0 +1,162 @@
+//! Keyspace notification settings
+//!
+//! Redis can publish a message on special pub/sub channels whenever a key is
+//! modified. Which events are published is controlled by the
+//! `notify-keyspace-events` setting, a string of single character flags. See
+//! https://redis.io/docs/manual/keyspace-notifications/ for details.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Set of keyspace events that are published.
+///
+/// Parsed from the same flag string as the Redis `notify-keyspace-events`
+/// configuration parameter:
+///
+/// * `K` -- Keyspace events, published on `__keyspace@0__:<key>`.
+/// * `E` -- Keyevent events, published on `__keyevent@0__:<event>`.
+/// * `g` -- Generic commands such as `del` and `expire`.
+/// * `$` -- String commands such as `set`.
+/// * `x` -- Expired events, generated when a key is removed by expiration.
+/// * `e` -- Evicted events, generated when a key is evicted because of the
+///   memory limit.
+/// * `A` -- Alias for all event classes.
+///
+/// The remaining Redis event classes (`l`, `s`, `h`, `z`, `t`, `m`, `d` and
+/// `n`) are accepted for compatibility, but mini-redis never generates the
+/// corresponding events.
+///
+/// At least one of `K` or `E` must be present for any event to be published.
+/// The empty string disables notifications, which is the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyspaceEvents(u16);
+
+/// Class of a keyspace event. Used to check if the event is enabled.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum EventClass {
+    /// Generic, type independent, commands.
+    Generic,
+
+    /// String commands.
+    String,
+
+    /// Key expired.
+    Expired,
+
+    /// Key evicted because of the memory limit.
+    Evicted,
+}
+
+const KEYSPACE: u16 = 1 << 0;
+const KEYEVENT: u16 = 1 << 1;
+const GENERIC: u16 = 1 << 2;
+const STRING: u16 = 1 << 3;
+const LIST: u16 = 1 << 4;
+const SET: u16 = 1 << 5;
+const HASH: u16 = 1 << 6;
+const ZSET: u16 = 1 << 7;
+const EXPIRED: u16 = 1 << 8;
+const EVICTED: u16 = 1 << 9;
+const STREAM: u16 = 1 << 10;
+const KEY_MISS: u16 = 1 << 11;
+const MODULE: u16 = 1 << 12;
+const NEW: u16 = 1 << 13;
+
+/// Event classes enabled by the `A` alias. As with Redis, `A` does not include
+/// key miss and new key events.
+const ALL: u16 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM | MODULE;
+
+/// Flag characters, in the order used when formatting.
+const FLAGS: &[(char, u16)] = &[
+    ('g', GENERIC),
+    ('$', STRING),
+    ('l', LIST),
+    ('s', SET),
+    ('h', HASH),
+    ('z', ZSET),
+    ('x', EXPIRED),
+    ('e', EVICTED),
+    ('t', STREAM),
+    ('m', KEY_MISS),
+    ('d', MODULE),
+    ('n', NEW),
+    ('K', KEYSPACE),
+    ('E', KEYEVENT),
+];
+
+impl KeyspaceEvents {
+    /// Returns the flags as a bit set, so that they can be stored atomically.
+    pub(crate) fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// Returns the flags stored by `bits`.
+    pub(crate) fn from_bits(bits: u16) -> KeyspaceEvents {
+        KeyspaceEvents(bits)
+    }
+
+    /// Returns `true` if keyspace events (`K`) are published.
+    pub(crate) fn keyspace(self) -> bool {
+        self.0 & KEYSPACE != 0
+    }
+
+    /// Returns `true` if keyevent events (`E`) are published.
+    pub(crate) fn keyevent(self) -> bool {
+        self.0 & KEYEVENT != 0
+    }
+
+    /// Returns `true` if events of the given class are published on at least
+    /// one of the keyspace or keyevent channels.
+    pub(crate) fn enabled(self, class: EventClass) -> bool {
+        let bit = match class {
+            EventClass::Generic => GENERIC,
+            EventClass::String => STRING,
+            EventClass::Expired => EXPIRED,
+            EventClass::Evicted => EVICTED,
+        };
+
+        self.0 & bit != 0 && (self.keyspace() || self.keyevent())
+    }
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = crate::Error;
+
+    fn from_str(src: &str) -> crate::Result<KeyspaceEvents> {
+        let mut flags = 0;
+
+        for c in src.chars() {
+            if c == 'A' {
+                flags |= ALL;
+                continue;
+            }
+
+            match FLAGS.iter().find(|(flag, _)| *flag == c) {
+                Some((_, bit)) => flags |= bit,
+                None => return Err(format!("invalid keyspace event flag `{}`", c).into()),
+            }
+        }
+
+        Ok(KeyspaceEvents(flags))
+    }
+}
+
+impl fmt::Display for KeyspaceEvents {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut flags = self.0;
+
+        // Like Redis, collapse the full set of classes into `A`.
+        if flags & ALL == ALL {
+            "A".fmt(fmt)?;
+            flags &= !ALL;
+        }
+
+        for (c, bit) in FLAGS {
+            if flags & bit != 0 {
+                write!(fmt, "{}", c)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -30,6 +30,7 @@ impl Parse {
     /// 创建一个新的 `Parse` 来解析 `frame` 的内容
     ///
     /// 如果 `frame` 不是数组帧，则返回 `Err`
+    pub(crate) fn new(frame: Frame) -> Result<Parse, ParseError> {
         let array = match frame {
             Frame::Array(array) => array,
             frame => return Err(format!("protocol error; expected array, got {:?}", frame).into()),
@@ -48,6 +49,7 @@ impl Parse {
     /// 以字符串形式返回下一个条目
     ///
     /// 如果下一个条目不能表示为字符串，则返回错误
+    pub(crate) fn next_string(&mut self) -> Result<String, ParseError> {
         match self.next()? {
             // Both `Simple` and `Bulk` representation may be strings. Strings
             // are parsed to UTF-8.
@@ -69,6 +71,7 @@ impl Parse {
     /// 以原始字节形式返回下一个条目
     ///
     /// 如果下一个条目不能表示为原始字节，则返回错误
+    pub(crate) fn next_bytes(&mut self) -> Result<Bytes, ParseError> {
         match self.next()? {
             // Both `Simple` and `Bulk` representation may be raw bytes.
             //
@@ -90,6 +93,7 @@ impl Parse {
     /// 帧类型会被解析
     ///
     /// 如果下一个条目不能表示为整数，则返回错误
+    pub(crate) fn next_int(&mut self) -> Result<u64, ParseError> {
         use atoi::atoi;
 
         const MSG: &str = "protocol error; invalid number";
@@ -0,0 +1,929 @@
+//! Import of Redis RDB files
+//!
+//! Parses the snapshot files written by Redis, so that data can be migrated
+//! from an existing Redis deployment into mini-redis. See
+//! https://rdb.fnordig.de/file_format.html for a description of the format.
+//!
+//! All the value types written by Redis are decoded, including the compact
+//! ziplist, listpack and intset encodings, but mini-redis only stores strings.
+//! Keys holding any other type are reported by `import` and skipped. Stream
+//! and module values are skipped over without being decoded.
+//!
+//! The same value encoding is used by the `DUMP` and `RESTORE` commands, see
+//! `dump`.
+
+use crate::db::WriteError;
+use crate::Db;
+
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// A key read from an RDB file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Database the key belongs to.
+    pub db: u64,
+
+    /// The key. Redis keys are binary safe.
+    pub key: Bytes,
+
+    /// The value stored at `key`.
+    pub value: Value,
+
+    /// When the key expires, if it has a time to live.
+    pub expires_at: Option<SystemTime>,
+}
+
+/// A value read from an RDB file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(Bytes),
+    List(Vec<Bytes>),
+    Set(Vec<Bytes>),
+    SortedSet(Vec<(Bytes, f64)>),
+    Hash(Vec<(Bytes, Bytes)>),
+
+    /// A value of a type that is skipped without being decoded, for example
+    /// `stream`.
+    Unsupported(&'static str),
+}
+
+/// Summary of an import.
+#[derive(Debug, Default)]
+pub(crate) struct ImportReport {
+    /// Number of keys stored in the database.
+    pub(crate) loaded: usize,
+
+    /// Number of keys skipped because they already expired.
+    pub(crate) expired: usize,
+
+    /// Number of keys that could not be imported, by reason. For example,
+    /// `"list"` counts the lists, as mini-redis only stores strings.
+    pub(crate) skipped: BTreeMap<String, usize>,
+}
+
+/// Latest RDB version supported, written by Redis 7.4.
+const MAX_VERSION: u32 = 12;
+
+// Opcodes
+const OPCODE_SLOT_INFO: u8 = 244;
+const OPCODE_FUNCTION2: u8 = 245;
+const OPCODE_FUNCTION_PRE_GA: u8 = 246;
+const OPCODE_MODULE_AUX: u8 = 247;
+const OPCODE_IDLE: u8 = 248;
+const OPCODE_FREQ: u8 = 249;
+const OPCODE_AUX: u8 = 250;
+const OPCODE_RESIZEDB: u8 = 251;
+const OPCODE_EXPIRETIME_MS: u8 = 252;
+const OPCODE_EXPIRETIME: u8 = 253;
+const OPCODE_SELECTDB: u8 = 254;
+const OPCODE_EOF: u8 = 255;
+
+// Value types
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_MODULE_2: u8 = 7;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+// Module value opcodes
+const MODULE_OPCODE_EOF: u64 = 0;
+const MODULE_OPCODE_SINT: u64 = 1;
+const MODULE_OPCODE_UINT: u64 = 2;
+const MODULE_OPCODE_FLOAT: u64 = 3;
+const MODULE_OPCODE_DOUBLE: u64 = 4;
+const MODULE_OPCODE_STRING: u64 = 5;
+
+/// Quicklist node containing a single element.
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+
+/// RDB version written in `DUMP` payloads. Strings are always written using
+/// the original encoding, so payloads can be restored by Redis 5.0 and later.
+const DUMP_VERSION: u16 = 9;
+
+/// Reads and parses the RDB file at `path`.
+pub fn read(path: impl AsRef<Path>) -> crate::Result<Vec<Entry>> {
+    parse(&std::fs::read(path)?)
+}
+
+/// Parses the content of an RDB file.
+///
+/// The checksum at the end of the file is verified, unless it is zero which
+/// means Redis was configured to not compute it.
+pub fn parse(src: &[u8]) -> crate::Result<Vec<Entry>> {
+    let mut reader = Reader::new(src);
+
+    if reader.bytes(5)? != b"REDIS" {
+        return Err("not an RDB file".into());
+    }
+
+    let version: u32 = std::str::from_utf8(reader.bytes(4)?)?.parse()?;
+    if version == 0 || version > MAX_VERSION {
+        return Err(format!("unsupported RDB version {}", version).into());
+    }
+
+    let mut entries = vec![];
+    let mut db = 0;
+    let mut expires_at = None;
+
+    loop {
+        match reader.u8()? {
+            OPCODE_EOF => break,
+            OPCODE_SELECTDB => db = reader.len()?,
+            OPCODE_EXPIRETIME => {
+                let secs = reader.u32_le()?;
+                expires_at = Some(UNIX_EPOCH + Duration::from_secs(secs as u64));
+            }
+            OPCODE_EXPIRETIME_MS => {
+                let ms = reader.u64_le()?;
+                expires_at = Some(UNIX_EPOCH + Duration::from_millis(ms));
+            }
+            OPCODE_RESIZEDB => {
+                reader.len()?;
+                reader.len()?;
+            }
+            OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            OPCODE_FREQ => {
+                reader.u8()?;
+            }
+            OPCODE_IDLE => {
+                reader.len()?;
+            }
+            OPCODE_SLOT_INFO => {
+                reader.len()?;
+                reader.len()?;
+                reader.len()?;
+            }
+            OPCODE_MODULE_AUX => {
+                reader.len()?;
+                reader.len()?;
+                reader.len()?;
+                reader.skip_module_value()?;
+            }
+            OPCODE_FUNCTION2 => {
+                reader.string()?;
+                warn!("skipping function library, functions are not supported");
+            }
+            OPCODE_FUNCTION_PRE_GA => {
+                return Err("functions saved by a pre-release Redis are not supported".into())
+            }
+            value_type => {
+                let key = reader.string()?;
+                let value = reader.value(value_type)?;
+
+                entries.push(Entry {
+                    db,
+                    key,
+                    value,
+                    expires_at: expires_at.take(),
+                });
+            }
+        }
+    }
+
+    // Since version 5, the file ends with a CRC64 of its content.
+    if version >= 5 {
+        let end = reader.pos;
+        let checksum = reader.u64_le()?;
+
+        if checksum != 0 && checksum != crc64(0, &src[..end]) {
+            return Err("RDB file checksum mismatch".into());
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Serializes `value` for the `DUMP` command.
+///
+/// The payload has the same format as Redis: the value type and the value,
+/// encoded as in an RDB file, followed by the RDB version as a 2 byte
+/// little-endian integer and a CRC64 of everything before it.
+pub(crate) fn dump(value: &[u8]) -> Bytes {
+    let mut payload = vec![TYPE_STRING];
+    write_string(&mut payload, value);
+    payload.extend_from_slice(&DUMP_VERSION.to_le_bytes());
+
+    let checksum = crc64(0, &payload);
+    payload.extend_from_slice(&checksum.to_le_bytes());
+
+    Bytes::from(payload)
+}
+
+/// Deserializes a payload created by `dump`. Payloads created by Redis are
+/// accepted as well, as long as they hold a string: other types are rejected
+/// before their content is decoded.
+///
+/// The error messages are the ones used by Redis in its replies.
+pub(crate) fn restore(payload: &[u8]) -> crate::Result<Bytes> {
+    if payload.len() < 10 {
+        return Err("DUMP payload version or checksum are wrong".into());
+    }
+
+    let (body, footer) = payload.split_at(payload.len() - 10);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let checksum = u64::from_le_bytes(footer[2..].try_into().unwrap());
+
+    if version as u32 > MAX_VERSION || checksum != crc64(0, &payload[..payload.len() - 8]) {
+        return Err("DUMP payload version or checksum are wrong".into());
+    }
+
+    let mut reader = Reader::new(body);
+    let value_type = reader.u8().map_err(|_| "Bad data format")?;
+
+    if value_type != TYPE_STRING {
+        return match type_name(value_type) {
+            Some(name) => Err(format!("unsupported value type `{}`", name).into()),
+            None => Err("Bad data format".into()),
+        };
+    }
+
+    let value = reader.string().map_err(|_| "Bad data format")?;
+
+    if reader.pos != body.len() {
+        return Err("Bad data format".into());
+    }
+
+    Ok(value)
+}
+
+/// Stores the string keys of `entries` in `db`. Keys that already expired are
+/// skipped, as well as keys that mini-redis is not able to store, which are
+/// counted in the returned report.
+pub(crate) fn import(db: &Db, entries: Vec<Entry>) -> ImportReport {
+    let mut report = ImportReport::default();
+    let now = SystemTime::now();
+
+    for entry in entries {
+        let mut skip = |reason: String| *report.skipped.entry(reason).or_default() += 1;
+
+        let value = match entry.value {
+            Value::String(value) => value,
+            value => {
+                skip(value.type_name().to_string());
+                continue;
+            }
+        };
+
+        // mini-redis has a single database.
+        if entry.db != 0 {
+            skip(format!("database {}", entry.db));
+            continue;
+        }
+
+        let key = match String::from_utf8(entry.key.to_vec()) {
+            Ok(key) => key,
+            Err(_) => {
+                skip("non UTF-8 key".to_string());
+                continue;
+            }
+        };
+
+        let expire = match entry.expires_at {
+            Some(when) => match when.duration_since(now) {
+                Ok(ttl) => Some(ttl),
+                Err(_) => {
+                    report.expired += 1;
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        match db.set(key, value, expire) {
+            Ok(()) => {}
+            Err(WriteError::OutOfMemory) => {
+                skip("out of memory".to_string());
+                continue;
+            }
+            Err(WriteError::InvalidExpireTime) => {
+                skip("invalid expire time".to_string());
+                continue;
+            }
+        }
+
+        report.loaded += 1;
+    }
+
+    report
+}
+
+impl Value {
+    /// Returns the name of the type, as returned by the Redis `TYPE` command.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+            Value::Hash(_) => "hash",
+            Value::Unsupported(name) => name,
+        }
+    }
+}
+
+/// Returns the name of the type of values encoded as `value_type`, as
+/// returned by the Redis `TYPE` command.
+fn type_name(value_type: u8) -> Option<&'static str> {
+    let name = match value_type {
+        TYPE_STRING => "string",
+        TYPE_LIST | TYPE_LIST_ZIPLIST | TYPE_LIST_QUICKLIST | TYPE_LIST_QUICKLIST_2 => "list",
+        TYPE_SET | TYPE_SET_INTSET | TYPE_SET_LISTPACK => "set",
+        TYPE_ZSET | TYPE_ZSET_2 | TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => "zset",
+        TYPE_HASH | TYPE_HASH_ZIPMAP | TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK => "hash",
+        TYPE_MODULE_2 => "module",
+        TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => "stream",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// Cursor over the content of an RDB file, or of an encoded value.
+struct Reader<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+/// A length, or the encoding of a string stored in a special format.
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+impl<'a> Reader<'a> {
+    fn new(src: &'a [u8]) -> Reader<'a> {
+        Reader { src, pos: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> crate::Result<&'a [u8]> {
+        if self.src.len() - self.pos < n {
+            return Err("RDB file is truncated".into());
+        }
+
+        let bytes = &self.src[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> crate::Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> crate::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32_le(&mut self) -> crate::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64_le(&mut self) -> crate::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn length(&mut self) -> crate::Result<Length> {
+        let first = self.u8()?;
+
+        let len = match first >> 6 {
+            0 => (first & 0x3f) as u64,
+            1 => (((first & 0x3f) as u64) << 8) | self.u8()? as u64,
+            2 if first == 0x80 => u32::from_be_bytes(self.array()?) as u64,
+            2 if first == 0x81 => u64::from_be_bytes(self.array()?),
+            2 => return Err(format!("invalid RDB length encoding {}", first).into()),
+            _ => return Ok(Length::Encoded(first & 0x3f)),
+        };
+
+        Ok(Length::Len(len))
+    }
+
+    fn len(&mut self) -> crate::Result<u64> {
+        match self.length()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err("invalid RDB length".into()),
+        }
+    }
+
+    fn string(&mut self) -> crate::Result<Bytes> {
+        let string = match self.length()? {
+            Length::Len(len) => Bytes::copy_from_slice(self.bytes(len.try_into()?)?),
+            Length::Encoded(0) => int_string(self.u8()? as i8 as i64),
+            Length::Encoded(1) => int_string(i16::from_le_bytes(self.array()?) as i64),
+            Length::Encoded(2) => int_string(i32::from_le_bytes(self.array()?) as i64),
+            Length::Encoded(3) => {
+                let compressed_len = self.len()?.try_into()?;
+                let len = self.len()?.try_into()?;
+                Bytes::from(lzf_decompress(self.bytes(compressed_len)?, len)?)
+            }
+            Length::Encoded(encoding) => {
+                return Err(format!("invalid RDB string encoding {}", encoding).into())
+            }
+        };
+
+        Ok(string)
+    }
+
+    /// Reads `len` strings.
+    fn strings(&mut self) -> crate::Result<Vec<Bytes>> {
+        let len = self.len()?;
+        (0..len).map(|_| self.string()).collect()
+    }
+
+    /// Reads a sorted set score stored as a string, as written by the
+    /// original `zset` type.
+    fn score_string(&mut self) -> crate::Result<f64> {
+        match self.u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => parse_score(self.bytes(len as usize)?),
+        }
+    }
+
+    fn value(&mut self, value_type: u8) -> crate::Result<Value> {
+        let value = match value_type {
+            TYPE_STRING => Value::String(self.string()?),
+            TYPE_LIST => Value::List(self.strings()?),
+            TYPE_SET => Value::Set(self.strings()?),
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let len = self.len()?;
+                let mut members = vec![];
+
+                for _ in 0..len {
+                    let member = self.string()?;
+                    let score = if value_type == TYPE_ZSET {
+                        self.score_string()?
+                    } else {
+                        f64::from_le_bytes(self.array()?)
+                    };
+                    members.push((member, score));
+                }
+
+                Value::SortedSet(members)
+            }
+            TYPE_HASH => {
+                let len = self.len()?;
+                let mut fields = vec![];
+
+                for _ in 0..len {
+                    fields.push((self.string()?, self.string()?));
+                }
+
+                Value::Hash(fields)
+            }
+            TYPE_MODULE_2 => {
+                self.len()?;
+                self.skip_module_value()?;
+                Value::Unsupported("module")
+            }
+            TYPE_HASH_ZIPMAP => Value::Hash(zipmap(&self.string()?)?),
+            TYPE_LIST_ZIPLIST => Value::List(ziplist(&self.string()?)?),
+            TYPE_SET_INTSET => Value::Set(intset(&self.string()?)?),
+            TYPE_ZSET_ZIPLIST => Value::SortedSet(scores(ziplist(&self.string()?)?)?),
+            TYPE_HASH_ZIPLIST => Value::Hash(pairs(ziplist(&self.string()?)?)?),
+            TYPE_LIST_QUICKLIST => {
+                let len = self.len()?;
+                let mut elements = vec![];
+
+                for _ in 0..len {
+                    elements.extend(ziplist(&self.string()?)?);
+                }
+
+                Value::List(elements)
+            }
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                self.skip_stream(value_type)?;
+                Value::Unsupported("stream")
+            }
+            TYPE_HASH_LISTPACK => Value::Hash(pairs(listpack(&self.string()?)?)?),
+            TYPE_ZSET_LISTPACK => Value::SortedSet(scores(listpack(&self.string()?)?)?),
+            TYPE_LIST_QUICKLIST_2 => {
+                let len = self.len()?;
+                let mut elements = vec![];
+
+                for _ in 0..len {
+                    let container = self.len()?;
+                    let node = self.string()?;
+
+                    if container == QUICKLIST_NODE_PLAIN {
+                        elements.push(node);
+                    } else {
+                        elements.extend(listpack(&node)?);
+                    }
+                }
+
+                Value::List(elements)
+            }
+            TYPE_SET_LISTPACK => Value::Set(listpack(&self.string()?)?),
+            value_type => {
+                return Err(format!("unsupported RDB value type {}", value_type).into());
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Skips the data serialized by a module, up to its EOF opcode.
+    fn skip_module_value(&mut self) -> crate::Result<()> {
+        loop {
+            match self.len()? {
+                MODULE_OPCODE_EOF => return Ok(()),
+                MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => {
+                    self.len()?;
+                }
+                MODULE_OPCODE_FLOAT => {
+                    self.bytes(4)?;
+                }
+                MODULE_OPCODE_DOUBLE => {
+                    self.bytes(8)?;
+                }
+                MODULE_OPCODE_STRING => {
+                    self.string()?;
+                }
+                opcode => return Err(format!("invalid module opcode {}", opcode).into()),
+            }
+        }
+    }
+
+    /// Skips a stream, including its consumer groups.
+    fn skip_stream(&mut self, value_type: u8) -> crate::Result<()> {
+        // Listpacks, keyed by their master entry ID
+        let listpacks = self.len()?;
+        for _ in 0..listpacks {
+            self.string()?;
+            self.string()?;
+        }
+
+        // Number of elements and last entry ID
+        self.len()?;
+        self.len()?;
+        self.len()?;
+
+        if value_type >= TYPE_STREAM_LISTPACKS_2 {
+            // First entry ID, max deleted entry ID and entries added
+            for _ in 0..5 {
+                self.len()?;
+            }
+        }
+
+        let groups = self.len()?;
+        for _ in 0..groups {
+            // Name and last delivered ID
+            self.string()?;
+            self.len()?;
+            self.len()?;
+
+            if value_type >= TYPE_STREAM_LISTPACKS_2 {
+                // Entries read
+                self.len()?;
+            }
+
+            // Pending entries: raw ID, delivery time and delivery count
+            let pending = self.len()?;
+            for _ in 0..pending {
+                self.bytes(16 + 8)?;
+                self.len()?;
+            }
+
+            let consumers = self.len()?;
+            for _ in 0..consumers {
+                // Name, seen time and, since version 3, active time
+                self.string()?;
+                self.bytes(8)?;
+
+                if value_type >= TYPE_STREAM_LISTPACKS_3 {
+                    self.bytes(8)?;
+                }
+
+                // Pending entries, as raw IDs
+                let pending = self.len()?;
+                for _ in 0..pending {
+                    self.bytes(16)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Appends `value` using the RDB string encoding.
+fn write_string(dst: &mut Vec<u8>, value: &[u8]) {
+    let len = value.len();
+
+    if len < 1 << 6 {
+        dst.push(len as u8);
+    } else if len < 1 << 14 {
+        dst.extend_from_slice(&[0x40 | (len >> 8) as u8, len as u8]);
+    } else if len <= u32::MAX as usize {
+        dst.push(0x80);
+        dst.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        dst.push(0x81);
+        dst.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+
+    dst.extend_from_slice(value);
+}
+
+/// Decodes the elements of a ziplist.
+fn ziplist(src: &[u8]) -> crate::Result<Vec<Bytes>> {
+    let mut reader = Reader::new(src);
+    let mut elements = vec![];
+
+    // Total bytes, offset of the last entry and number of entries
+    reader.bytes(10)?;
+
+    loop {
+        // Length of the previous entry
+        match reader.u8()? {
+            0xff => return Ok(elements),
+            0xfe => {
+                reader.bytes(4)?;
+            }
+            _ => {}
+        }
+
+        let encoding = reader.u8()?;
+
+        let element = match encoding >> 6 {
+            0 => str_element(&mut reader, (encoding & 0x3f) as usize)?,
+            1 => {
+                let len = (((encoding & 0x3f) as usize) << 8) | reader.u8()? as usize;
+                str_element(&mut reader, len)?
+            }
+            2 => {
+                let len = u32::from_be_bytes(reader.array()?) as usize;
+                str_element(&mut reader, len)?
+            }
+            _ => int_string(match encoding {
+                0xc0 => i16::from_le_bytes(reader.array()?) as i64,
+                0xd0 => i32::from_le_bytes(reader.array()?) as i64,
+                0xe0 => i64::from_le_bytes(reader.array()?),
+                0xf0 => i24(reader.array()?),
+                0xfe => reader.u8()? as i8 as i64,
+                0xf1..=0xfd => (encoding & 0x0f) as i64 - 1,
+                _ => return Err(format!("invalid ziplist encoding {}", encoding).into()),
+            }),
+        };
+
+        elements.push(element);
+    }
+}
+
+/// Decodes the elements of a listpack.
+fn listpack(src: &[u8]) -> crate::Result<Vec<Bytes>> {
+    let mut reader = Reader::new(src);
+    let mut elements = vec![];
+
+    // Total bytes and number of elements
+    reader.bytes(6)?;
+
+    loop {
+        let encoding = reader.u8()?;
+
+        // Size of the encoding and data, used to skip the entry back length.
+        let (element, len) = match encoding {
+            0xff => return Ok(elements),
+            _ if encoding & 0x80 == 0 => (int_string((encoding & 0x7f) as i64), 1),
+            _ if encoding & 0xc0 == 0x80 => {
+                let len = (encoding & 0x3f) as usize;
+                (str_element(&mut reader, len)?, 1 + len)
+            }
+            _ if encoding & 0xe0 == 0xc0 => {
+                let value = (((encoding & 0x1f) as i64) << 8) | reader.u8()? as i64;
+                // 13 bit two's complement
+                let value = if value >= 1 << 12 {
+                    value - (1 << 13)
+                } else {
+                    value
+                };
+                (int_string(value), 2)
+            }
+            _ if encoding & 0xf0 == 0xe0 => {
+                let len = (((encoding & 0x0f) as usize) << 8) | reader.u8()? as usize;
+                (str_element(&mut reader, len)?, 2 + len)
+            }
+            0xf0 => {
+                let len = reader.u32_le()? as usize;
+                (str_element(&mut reader, len)?, 5 + len)
+            }
+            0xf1 => (int_string(i16::from_le_bytes(reader.array()?) as i64), 3),
+            0xf2 => (int_string(i24(reader.array()?)), 4),
+            0xf3 => (int_string(i32::from_le_bytes(reader.array()?) as i64), 5),
+            0xf4 => (int_string(i64::from_le_bytes(reader.array()?)), 9),
+            _ => return Err(format!("invalid listpack encoding {}", encoding).into()),
+        };
+
+        let backlen = match len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        reader.bytes(backlen)?;
+
+        elements.push(element);
+    }
+}
+
+/// Decodes the members of an intset.
+fn intset(src: &[u8]) -> crate::Result<Vec<Bytes>> {
+    let mut reader = Reader::new(src);
+
+    let encoding = reader.u32_le()?;
+    let len = reader.u32_le()?;
+
+    (0..len)
+        .map(|_| {
+            let value = match encoding {
+                2 => i16::from_le_bytes(reader.array()?) as i64,
+                4 => i32::from_le_bytes(reader.array()?) as i64,
+                8 => i64::from_le_bytes(reader.array()?),
+                _ => return Err(format!("invalid intset encoding {}", encoding).into()),
+            };
+            Ok(int_string(value))
+        })
+        .collect()
+}
+
+/// Decodes the fields of a zipmap, the hash encoding used before Redis 2.6.
+fn zipmap(src: &[u8]) -> crate::Result<Vec<(Bytes, Bytes)>> {
+    let mut reader = Reader::new(src);
+    let mut fields = vec![];
+
+    // Number of fields, unreliable above 253
+    reader.u8()?;
+
+    let zipmap_len = |reader: &mut Reader| -> crate::Result<Option<usize>> {
+        match reader.u8()? {
+            0xff => Ok(None),
+            0xfe => Ok(Some(reader.u32_le()? as usize)),
+            len => Ok(Some(len as usize)),
+        }
+    };
+
+    while let Some(len) = zipmap_len(&mut reader)? {
+        let field = str_element(&mut reader, len)?;
+
+        let len = zipmap_len(&mut reader)?.ok_or("zipmap is truncated")?;
+        let free = reader.u8()? as usize;
+        let value = str_element(&mut reader, len)?;
+        reader.bytes(free)?;
+
+        fields.push((field, value));
+    }
+
+    Ok(fields)
+}
+
+/// Groups the elements of a ziplist or listpack encoded hash into pairs.
+fn pairs(elements: Vec<Bytes>) -> crate::Result<Vec<(Bytes, Bytes)>> {
+    let chunks = elements.chunks_exact(2);
+
+    if !chunks.remainder().is_empty() {
+        return Err("odd number of elements in hash".into());
+    }
+
+    Ok(chunks
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect())
+}
+
+/// Groups the elements of a ziplist or listpack encoded sorted set into
+/// member and score pairs.
+fn scores(elements: Vec<Bytes>) -> crate::Result<Vec<(Bytes, f64)>> {
+    pairs(elements)?
+        .into_iter()
+        .map(|(member, score)| Ok((member, parse_score(&score)?)))
+        .collect()
+}
+
+fn parse_score(src: &[u8]) -> crate::Result<f64> {
+    Ok(std::str::from_utf8(src)?.parse()?)
+}
+
+fn str_element(reader: &mut Reader, len: usize) -> crate::Result<Bytes> {
+    Ok(Bytes::copy_from_slice(reader.bytes(len)?))
+}
+
+fn int_string(value: i64) -> Bytes {
+    Bytes::from(value.to_string())
+}
+
+/// Decodes a 24 bit little-endian signed integer.
+fn i24(bytes: [u8; 3]) -> i64 {
+    (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as i64
+}
+
+/// Largest number of bytes a single byte of LZF data expands to: a 3 byte
+/// back reference copies at most 264 bytes.
+const LZF_MAX_EXPANSION: usize = 88;
+
+/// Decompresses a string compressed with LZF into `len` bytes.
+///
+/// `len` comes from the payload, so it is only trusted as far as `src` could
+/// actually expand to it.
+fn lzf_decompress(src: &[u8], len: usize) -> crate::Result<Vec<u8>> {
+    if len > src.len().saturating_mul(LZF_MAX_EXPANSION) {
+        return Err("invalid LZF compressed string length".into());
+    }
+
+    let mut out = Vec::with_capacity(len);
+    let mut reader = Reader::new(src);
+
+    while reader.pos < src.len() {
+        let ctrl = reader.u8()? as usize;
+
+        if ctrl < 32 {
+            // Literal run of `ctrl + 1` bytes
+            out.extend_from_slice(reader.bytes(ctrl + 1)?);
+        } else {
+            // Back reference
+            let mut n = ctrl >> 5;
+            if n == 7 {
+                n += reader.u8()? as usize;
+            }
+            n += 2;
+
+            let offset = ((ctrl & 0x1f) << 8) + reader.u8()? as usize + 1;
+            if offset > out.len() {
+                return Err("invalid LZF back reference".into());
+            }
+
+            // The referenced bytes may overlap with the bytes being copied,
+            // so they are copied one at a time.
+            let start = out.len() - offset;
+            for i in start..start + n {
+                out.push(out[i]);
+            }
+        }
+    }
+
+    if out.len() != len {
+        return Err("invalid LZF compressed string length".into());
+    }
+
+    Ok(out)
+}
+
+/// Lookup table for `crc64`.
+const CRC64_TABLE: [u64; 256] = {
+    // Reflected form of the Jones polynomial used by Redis.
+    const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+};
+
+/// Updates the CRC-64 checksum `crc` with `data`, using the same variant as
+/// Redis (Jones polynomial, reflected, no final xor).
+pub(crate) fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    for &byte in data {
+        crc = CRC64_TABLE[((crc ^ byte as u64) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    crc
+}
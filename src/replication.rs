@@ -0,0 +1,470 @@
+//! Leader/follower replication
+//!
+//! A replica connects to its primary with a regular `Client` and sends
+//! `PSYNC replid offset`. The primary either replies `+FULLRESYNC replid
+//! offset` followed by a snapshot of the dataset as a bulk string, or
+//! `+CONTINUE replid` when the replica can resume from the replication
+//! backlog. Afterwards, the primary streams every write to the replica as the
+//! equivalent Redis command, using the same frames as the append-only file.
+//!
+//! The replication stream is identified by a random replication ID. The
+//! offset is the number of bytes of the stream produced since the ID was
+//! created. The primary keeps the last bytes of the stream in a fixed size
+//! ring buffer, the backlog, so a replica that lost its connection only
+//! receives the writes it missed.
+//!
+//! Replicas report the offset they applied with `REPLCONF ACK offset` once per
+//! second, and when the primary sends `REPLCONF GETACK *` in the stream. The
+//! acknowledgements are used by `WAIT`. After the synchronization, replicas
+//! also announce the address they accept clients on with `REPLCONF
+//! listening-port port ip-address host`, which the primary lists in `INFO
+//! replication` so that sentinels can discover the replicas.
+
+use crate::clients::Client;
+use crate::cmd::ReplConf;
+use crate::frame::Limits;
+use crate::snapshot::{self, Record};
+use crate::{aof, Command, Db, Frame};
+
+use bytes::{Bytes, BytesMut};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tokio::{select, time};
+use tracing::{debug, info, warn};
+
+/// Size of the replication backlog, in bytes.
+const BACKLOG_SIZE: usize = 1024 * 1024;
+
+/// Number of writes buffered for a replica before it is disconnected.
+const STREAM_CAPACITY: usize = 1024;
+
+/// Delay before reconnecting to the primary after the link failed.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// How often replicas report their offset to the primary.
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Identifies the replicas connected to a primary.
+static NEXT_REPLICA_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Replication state of a primary, stored in the `Db` state.
+///
+/// The backlog is created when the first replica connects.
+#[derive(Debug)]
+pub(crate) struct Backlog {
+    /// Replication ID of the stream.
+    replid: String,
+
+    /// Offset of the end of the stream.
+    offset: u64,
+
+    /// Last `BACKLOG_SIZE` bytes of the stream.
+    buf: VecDeque<u8>,
+
+    /// Sends the stream to the connected replicas.
+    tx: broadcast::Sender<Bytes>,
+
+    /// Connected replicas, by ID.
+    replicas: HashMap<u64, ReplicaInfo>,
+
+    /// Notified when a replica acknowledges an offset.
+    acked: watch::Sender<()>,
+}
+
+/// A replica connected to the primary, as seen by the primary.
+#[derive(Debug, Default)]
+struct ReplicaInfo {
+    /// Offset acknowledged with `REPLCONF ACK`.
+    offset: u64,
+
+    /// Address announced with `REPLCONF listening-port`.
+    addr: Option<(String, u16)>,
+}
+
+/// Replication state of a replica, stored in the `Db` state.
+#[derive(Debug)]
+pub(crate) struct Link {
+    /// Address of the primary.
+    host: String,
+    port: u16,
+
+    /// Task replicating the primary, see `run_replica`.
+    task: JoinHandle<()>,
+
+    /// `true` while connected to the primary.
+    up: bool,
+
+    /// Offset of the replication stream applied to the dataset.
+    offset: i64,
+}
+
+/// A replica connected to the primary. The replica is unregistered when
+/// dropped.
+#[derive(Debug)]
+pub(crate) struct Replica {
+    db: Db,
+    id: u64,
+}
+
+/// How a replica is synchronized, returned by `Db::psync`.
+#[derive(Debug)]
+pub(crate) enum Resync {
+    /// The replica must load the dataset. The stream starts at `offset`.
+    Full {
+        replid: String,
+        offset: u64,
+        records: Vec<Record>,
+    },
+
+    /// The replica continues from its offset. `missed` are the writes it did
+    /// not receive yet.
+    Partial { replid: String, missed: Bytes },
+}
+
+impl Backlog {
+    pub(crate) fn new() -> Backlog {
+        Backlog {
+            replid: random_id(),
+            offset: 0,
+            buf: VecDeque::new(),
+            tx: broadcast::channel(STREAM_CAPACITY).0,
+            replicas: HashMap::new(),
+            acked: watch::channel(()).0,
+        }
+    }
+
+    /// Offset of the end of the stream.
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Appends a write to the stream and sends it to the connected replicas.
+    pub(crate) fn feed(&mut self, frame: &Frame) {
+        let mut data = BytesMut::new();
+        frame.encode(&mut data);
+
+        self.offset += data.len() as u64;
+        self.buf.extend(&data[..]);
+
+        if self.buf.len() > BACKLOG_SIZE {
+            self.buf.drain(..self.buf.len() - BACKLOG_SIZE);
+        }
+
+        // An error means no replica is connected.
+        let _ = self.tx.send(data.freeze());
+    }
+
+    /// Returns the number of bytes kept for partial resynchronizations.
+    pub(crate) fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns how to synchronize a replica that received the stream `replid`
+    /// up to `offset`. `records` returns the dataset for a full
+    /// synchronization.
+    pub(crate) fn sync(
+        &self,
+        replid: &str,
+        offset: i64,
+        records: impl FnOnce() -> Vec<Record>,
+    ) -> Resync {
+        let start = self.offset - self.buf.len() as u64;
+
+        if replid == self.replid && offset >= start as i64 && offset as u64 <= self.offset {
+            let skip = (offset as u64 - start) as usize;
+            let missed: Vec<u8> = self.buf.range(skip..).copied().collect();
+
+            return Resync::Partial {
+                replid: self.replid.clone(),
+                missed: Bytes::from(missed),
+            };
+        }
+
+        Resync::Full {
+            replid: self.replid.clone(),
+            offset: self.offset,
+            records: records(),
+        }
+    }
+
+    /// Returns a receiver for the writes fed after this call.
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Bytes> {
+        self.tx.subscribe()
+    }
+
+    /// Registers a connected replica, which did not acknowledge any offset
+    /// yet. Returns its ID.
+    pub(crate) fn add_replica(&mut self) -> u64 {
+        let id = NEXT_REPLICA_ID.fetch_add(1, Ordering::Relaxed);
+        self.replicas.insert(id, ReplicaInfo::default());
+        id
+    }
+
+    pub(crate) fn remove_replica(&mut self, id: u64) {
+        self.replicas.remove(&id);
+    }
+
+    /// Records that replica `id` applied the stream up to `offset`.
+    pub(crate) fn ack(&mut self, id: u64, offset: u64) {
+        if let Some(replica) = self.replicas.get_mut(&id) {
+            replica.offset = offset.max(replica.offset);
+            self.acked.send_replace(());
+        }
+    }
+
+    /// Records the address announced by replica `id`.
+    pub(crate) fn set_replica_addr(&mut self, id: u64, host: String, port: u16) {
+        if let Some(replica) = self.replicas.get_mut(&id) {
+            replica.addr = Some((host, port));
+        }
+    }
+
+    /// Returns the number of replicas that acknowledged `offset`.
+    pub(crate) fn num_acked(&self, offset: u64) -> usize {
+        self.replicas
+            .values()
+            .filter(|replica| replica.offset >= offset)
+            .count()
+    }
+
+    /// Returns a receiver notified when a replica acknowledges an offset.
+    pub(crate) fn watch_acks(&self) -> watch::Receiver<()> {
+        self.acked.subscribe()
+    }
+}
+
+impl Replica {
+    pub(crate) fn new(db: Db, id: u64) -> Replica {
+        Replica { db, id }
+    }
+
+    /// Records that the replica applied the stream up to `offset`.
+    pub(crate) fn ack(&self, offset: u64) {
+        self.db.ack_replica(self.id, offset);
+    }
+
+    /// Records the address the replica accepts clients on.
+    pub(crate) fn set_addr(&self, host: String, port: u16) {
+        self.db.set_replica_addr(self.id, host, port);
+    }
+}
+
+impl Link {
+    /// Starts replicating the primary at `host:port` into `db`.
+    pub(crate) fn new(db: Db, host: String, port: u16) -> Link {
+        let task = tokio::spawn(run_replica(db, host.clone(), port));
+
+        Link {
+            host,
+            port,
+            task,
+            up: false,
+            offset: -1,
+        }
+    }
+
+    /// Records the state of the connection to the primary.
+    pub(crate) fn set_status(&mut self, up: bool, offset: i64) {
+        self.up = up;
+        self.offset = offset;
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        // The task holds a handle to the `Db`.
+        self.task.abort();
+    }
+}
+
+/// Returns the `replication` section of `INFO`, in the same format as Redis.
+pub(crate) fn info(backlog: Option<&Backlog>, link: Option<&Link>) -> String {
+    let mut out = String::from("# Replication\r\n");
+
+    if let Some(link) = link {
+        let status = if link.up { "up" } else { "down" };
+
+        let _ = write!(
+            out,
+            "role:slave\r\nmaster_host:{}\r\nmaster_port:{}\r\n\
+             master_link_status:{}\r\nslave_repl_offset:{}\r\n",
+            link.host, link.port, status, link.offset
+        );
+
+        return out;
+    }
+
+    out.push_str("role:master\r\n");
+
+    let replicas: Vec<_> = backlog
+        .map(|backlog| backlog.replicas.values().collect())
+        .unwrap_or_default();
+    let _ = write!(out, "connected_slaves:{}\r\n", replicas.len());
+
+    // Replicas that did not announce their address yet are not listed.
+    let announced = replicas
+        .iter()
+        .filter_map(|replica| Some((replica.addr.as_ref()?, replica.offset)));
+
+    for (i, ((host, port), offset)) in announced.enumerate() {
+        let _ = write!(
+            out,
+            "slave{}:ip={},port={},state=online,offset={},lag=0\r\n",
+            i, host, port, offset
+        );
+    }
+
+    if let Some(backlog) = backlog {
+        let _ = write!(out, "master_replid:{}\r\n", backlog.replid);
+    }
+
+    let offset = backlog.map(Backlog::offset).unwrap_or(0);
+    let _ = write!(out, "master_repl_offset:{}\r\n", offset);
+
+    out
+}
+
+impl Drop for Replica {
+    fn drop(&mut self) {
+        self.db.remove_replica(self.id);
+    }
+}
+
+/// Progress of a replica, kept across reconnections to resume the stream.
+#[derive(Debug)]
+struct Progress {
+    /// Replication ID of the primary, `?` before the first synchronization.
+    replid: String,
+
+    /// Offset of the stream applied to the dataset, `-1` before the first
+    /// synchronization.
+    offset: i64,
+}
+
+/// Replicates the primary at `host:port` into `db`. Reconnects when the link
+/// fails, until the task is aborted by `REPLICAOF`.
+async fn run_replica(db: Db, host: String, port: u16) {
+    let mut progress = Progress {
+        replid: "?".to_string(),
+        offset: -1,
+    };
+
+    loop {
+        match replicate(&db, &host, port, &mut progress).await {
+            Ok(()) => info!(%host, port, "primary closed the replication link"),
+            Err(err) => warn!(%host, port, cause = %err, "replication link failed"),
+        }
+
+        db.set_link_status(false, progress.offset);
+
+        time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Synchronizes with the primary, then applies the stream of writes until the
+/// connection is closed.
+async fn replicate(db: &Db, host: &str, port: u16, progress: &mut Progress) -> crate::Result<()> {
+    let mut client = Client::connect((host, port)).await?;
+
+    let reply = client.psync(&progress.replid, progress.offset).await?;
+    let mut connection = client.into_connection();
+
+    // The primary is trusted. The snapshot in particular can be larger than
+    // any request.
+    connection.set_limits(Limits::trusted());
+
+    let args: Vec<&str> = reply.split(' ').collect();
+
+    match &args[..] {
+        ["FULLRESYNC", replid, offset] => {
+            let offset = offset.parse()?;
+
+            let records = match connection.read_frame().await? {
+                Some(Frame::Bulk(data)) => snapshot::decode(data)?,
+                frame => return Err(format!("expected a snapshot, got {:?}", frame).into()),
+            };
+
+            info!(%host, port, keys = records.len(), "full resynchronization with primary");
+            db.replace(records);
+
+            progress.replid = replid.to_string();
+            progress.offset = offset;
+        }
+        ["CONTINUE", replid] => {
+            info!(%host, port, offset = progress.offset, "partial resynchronization with primary");
+            progress.replid = replid.to_string();
+        }
+        _ => return Err(format!("unexpected PSYNC reply `{}`", reply).into()),
+    }
+
+    db.set_link_status(true, progress.offset);
+
+    // Announce the address clients can reach this replica on.
+    if let Some((host, port)) = db.addr() {
+        let announce = ReplConf::Options(vec![
+            ("listening-port".to_string(), port.to_string()),
+            ("ip-address".to_string(), host),
+        ]);
+        connection.write_frame(&announce.into_frame()).await?;
+    }
+
+    let mut ack_interval = time::interval(ACK_INTERVAL);
+
+    loop {
+        let (frame, len) = select! {
+            res = connection.read_frame_with_len() => match res? {
+                Some(frame) => frame,
+                None => return Ok(()),
+            },
+            _ = ack_interval.tick() => {
+                db.set_link_status(true, progress.offset);
+                connection.write_frame(&ack_frame(progress.offset)).await?;
+                continue;
+            }
+        };
+
+        debug!(?frame, "replicating");
+
+        match Command::from_frame(frame)? {
+            // The primary waits for the acknowledgement, see `WAIT`. The
+            // acknowledged offset includes the request itself.
+            Command::ReplConf(cmd) if cmd.is_getack() => {
+                progress.offset += len as i64;
+                db.set_link_status(true, progress.offset);
+                connection.write_frame(&ack_frame(progress.offset)).await?;
+            }
+            // The stream contains the same commands as the append-only file.
+            // The offset only moves past a command once it was applied, so
+            // that a failed write is neither acknowledged nor skipped when
+            // resuming the stream.
+            cmd => {
+                aof::apply(cmd, db)?;
+                progress.offset += len as i64;
+            }
+        }
+    }
+}
+
+/// Returns the `REPLCONF ACK` frame reporting `offset`.
+fn ack_frame(offset: i64) -> Frame {
+    ReplConf::Ack(offset as u64).into_frame()
+}
+
+/// Generates a random ID of 40 hexadecimal characters, the format used by
+/// Redis for replication IDs and cluster node IDs.
+pub(crate) fn random_id() -> String {
+    (0..3)
+        .map(|i| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(i);
+            format!("{:016x}", hasher.finish())
+        })
+        .collect::<String>()[..40]
+        .to_string()
+}
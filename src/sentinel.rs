@@ -0,0 +1,613 @@
+//! Automatic failover
+//!
+//! Same as Redis Sentinel, a sentinel is a server that monitors a primary and
+//! its replicas, and promotes a replica when the primary is down. Sentinels
+//! run the regular server, with the `SENTINEL` command enabled, see
+//! `server::Config::sentinel`.
+//!
+//! Every sentinel periodically sends `PING` and `INFO replication` to the
+//! primary, which lists the addresses of its replicas. When the primary did
+//! not reply for `down_after`, the sentinel considers it down and asks the
+//! other sentinels with `SENTINEL is-master-down-by-addr`. The same request
+//! asks them to vote for the sentinel as the leader of a new epoch. Each
+//! sentinel votes once per epoch, for the first sentinel asking.
+//!
+//! When at least `quorum` sentinels agree that the primary is down and a
+//! majority voted for it, the leader promotes the replica with the largest
+//! replication offset with `REPLICAOF NO ONE`, points the other replicas to
+//! it, and publishes `+switch-master <name> <old-ip> <old-port> <new-ip>
+//! <new-port>` on its own pub/sub. The other sentinels learn the new primary
+//! by polling each other with `SENTINEL master`: the configuration with the
+//! largest epoch wins, and they publish `+switch-master` as well.
+//!
+//! Replicas that do not replicate the current primary, such as the old
+//! primary when it comes back, are reconfigured with `REPLICAOF`.
+
+use crate::clients::Client;
+use crate::cmd::Sentinel;
+use crate::{Db, Frame, Shutdown};
+
+use bytes::Bytes;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::select;
+use tokio::time::{self, Instant};
+use tracing::{debug, info, warn};
+
+/// Default port that a sentinel listens on.
+pub const DEFAULT_PORT: u16 = 26379;
+
+/// Pub/sub channel on which primary changes are published.
+pub const SWITCH_MASTER: &str = "+switch-master";
+
+/// Maximum delay between two checks of the monitored servers.
+const MAX_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// The primary monitored by a sentinel.
+///
+/// Parsed from the same syntax as the Redis `sentinel monitor` configuration
+/// directive, `<name> <host> <port> <quorum>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Monitor {
+    /// Name of the primary, used by clients to look it up.
+    pub name: String,
+
+    /// Address of the primary when the sentinel starts.
+    pub host: String,
+    pub port: u16,
+
+    /// Number of sentinels that must agree that the primary is down.
+    pub quorum: usize,
+}
+
+/// Sentinel configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The monitored primary.
+    pub monitor: Monitor,
+
+    /// The primary is considered down when it did not reply for this long.
+    pub down_after: Duration,
+
+    /// Addresses of the other sentinels monitoring the primary, as
+    /// `host:port`.
+    pub sentinels: Vec<String>,
+}
+
+/// State of a sentinel, stored in the `Db` state.
+#[derive(Debug, Clone)]
+pub(crate) struct SentinelState {
+    /// ID of the sentinel, used for the leader election.
+    myid: String,
+
+    config: Config,
+
+    /// Current address of the primary.
+    primary: (String, u16),
+
+    /// Replicas of the primary, discovered with `INFO`. Replicas are never
+    /// removed, so that they are reconfigured when they come back.
+    replicas: Vec<(String, u16)>,
+
+    /// Last epoch seen by the sentinel.
+    current_epoch: u64,
+
+    /// Epoch of the failover that promoted the current primary.
+    config_epoch: u64,
+
+    /// The sentinel voted for in `vote_epoch`.
+    vote: Option<String>,
+    vote_epoch: u64,
+
+    /// `true` when the primary did not reply for `down_after`.
+    down: bool,
+}
+
+/// Checks the monitored servers and triggers the failovers. Runs in the
+/// background of a sentinel, see `run`.
+struct Watcher {
+    db: Db,
+
+    /// Last time the primary replied.
+    last_reply: Instant,
+
+    /// When the sentinel may start an election, set once the primary is down.
+    election_at: Option<Instant>,
+}
+
+impl SentinelState {
+    pub(crate) fn new(config: Config) -> SentinelState {
+        SentinelState {
+            myid: crate::replication::random_id(),
+            primary: (config.monitor.host.clone(), config.monitor.port),
+            config,
+            replicas: vec![],
+            current_epoch: 0,
+            config_epoch: 0,
+            vote: None,
+            vote_epoch: 0,
+            down: false,
+        }
+    }
+
+    /// Returns the address of the primary named `name`.
+    pub(crate) fn primary_addr(&self, name: &str) -> Option<&(String, u16)> {
+        (name == self.config.monitor.name).then_some(&self.primary)
+    }
+
+    /// Returns the `SENTINEL master` reply, a flat list of fields and values.
+    pub(crate) fn master_frame(&self, name: &str) -> Option<Frame> {
+        let (host, port) = self.primary_addr(name)?;
+
+        let flags = if self.down { "master,s_down" } else { "master" };
+
+        let fields = [
+            ("name", name.to_string()),
+            ("ip", host.clone()),
+            ("port", port.to_string()),
+            ("flags", flags.to_string()),
+            ("num-slaves", self.replicas.len().to_string()),
+            ("quorum", self.config.monitor.quorum.to_string()),
+            ("config-epoch", self.config_epoch.to_string()),
+        ];
+
+        let mut frame = Frame::array();
+        for (field, value) in fields {
+            frame.push_bulk(Bytes::from(field));
+            frame.push_bulk(Bytes::from(value));
+        }
+
+        Some(frame)
+    }
+
+    /// Returns the `SENTINEL is-master-down-by-addr` reply:
+    /// `[down, leader, leader-epoch]`.
+    ///
+    /// When `runid` is not `*`, the sentinel `runid` asks for a vote in
+    /// `epoch`. The vote is granted if the sentinel did not vote in this
+    /// epoch yet.
+    pub(crate) fn is_master_down(
+        &mut self,
+        host: &str,
+        port: u16,
+        epoch: u64,
+        runid: &str,
+    ) -> Frame {
+        let down = self.down && self.primary.0 == host && self.primary.1 == port;
+
+        if runid != "*" && epoch > self.vote_epoch {
+            debug!(%runid, epoch, "voting for leader");
+            self.vote = Some(runid.to_string());
+            self.vote_epoch = epoch;
+            self.current_epoch = self.current_epoch.max(epoch);
+        }
+
+        let leader = match &self.vote {
+            Some(leader) if runid != "*" => leader.clone(),
+            _ => "*".to_string(),
+        };
+
+        let mut frame = Frame::array();
+        frame.push_int(down as u64);
+        frame.push_bulk(Bytes::from(leader));
+        frame.push_int(self.vote_epoch);
+        frame
+    }
+
+    /// Starts an election for a new epoch, voting for this sentinel. Returns
+    /// the epoch.
+    fn start_election(&mut self) -> u64 {
+        self.current_epoch += 1;
+        self.vote = Some(self.myid.clone());
+        self.vote_epoch = self.current_epoch;
+        self.current_epoch
+    }
+
+    /// Adds replicas discovered with `INFO`.
+    fn add_replicas(&mut self, replicas: Vec<(String, u16)>) {
+        for replica in replicas {
+            if replica != self.primary && !self.replicas.contains(&replica) {
+                self.replicas.push(replica);
+            }
+        }
+    }
+
+    /// Switches to the primary `addr`, promoted in `epoch`. Returns the
+    /// previous primary if it changed.
+    fn switch(&mut self, addr: (String, u16), epoch: u64) -> Option<(String, u16)> {
+        self.config_epoch = epoch;
+        self.current_epoch = self.current_epoch.max(epoch);
+        self.down = false;
+
+        if addr == self.primary {
+            return None;
+        }
+
+        self.replicas.retain(|replica| *replica != addr);
+        let old = std::mem::replace(&mut self.primary, addr);
+        self.replicas.push(old.clone());
+
+        Some(old)
+    }
+}
+
+impl FromStr for Monitor {
+    type Err = crate::Error;
+
+    fn from_str(src: &str) -> crate::Result<Monitor> {
+        match src.split_whitespace().collect::<Vec<_>>()[..] {
+            [name, host, port, quorum] => Ok(Monitor {
+                name: name.to_string(),
+                host: host.to_string(),
+                port: port.parse()?,
+                quorum: quorum.parse()?,
+            }),
+            _ => Err(format!(
+                "invalid monitor `{}`, expected `name host port quorum`",
+                src
+            )
+            .into()),
+        }
+    }
+}
+
+impl fmt::Display for Monitor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.name, self.host, self.port, self.quorum
+        )
+    }
+}
+
+/// Monitors the primary configured in the sentinel state of `db` until the
+/// server shuts down.
+pub(crate) async fn run(db: Db, mut shutdown: Shutdown) {
+    let down_after = match db.with_sentinel(|sentinel| sentinel.config.down_after) {
+        Some(down_after) => down_after,
+        None => return,
+    };
+
+    let mut watcher = Watcher {
+        db,
+        last_reply: Instant::now(),
+        election_at: None,
+    };
+
+    let mut interval = time::interval((down_after / 4).min(MAX_CHECK_PERIOD));
+
+    while !shutdown.is_shutdown() {
+        select! {
+            _ = interval.tick() => {}
+            _ = shutdown.recv() => return,
+        }
+
+        watcher.check().await;
+    }
+}
+
+impl Watcher {
+    /// Checks the primary and the replicas, and starts a failover if needed.
+    async fn check(&mut self) {
+        self.sync_with_sentinels().await;
+
+        let state = self.state();
+        let down_after = state.config.down_after;
+
+        match request(down_after, check_primary(&state.primary)).await {
+            Ok(replicas) => {
+                self.last_reply = Instant::now();
+                self.election_at = None;
+                self.db.with_sentinel(|sentinel| {
+                    sentinel.down = false;
+                    sentinel.add_replicas(replicas);
+                });
+
+                self.reconfigure_replicas().await;
+                return;
+            }
+            Err(err) => debug!(cause = %err, "primary did not reply"),
+        }
+
+        if self.last_reply.elapsed() < down_after {
+            return;
+        }
+
+        self.db.with_sentinel(|sentinel| {
+            if !sentinel.down {
+                warn!(primary = ?sentinel.primary, "primary is down");
+                sentinel.down = true;
+            }
+        });
+
+        // The sentinels start their elections at random times, so that one
+        // of them is likely to get the majority of the votes.
+        let now = Instant::now();
+        let election_at = *self
+            .election_at
+            .get_or_insert_with(|| now + jitter(down_after));
+
+        if now < election_at {
+            return;
+        }
+
+        match self.elect().await {
+            Some(epoch) => {
+                if let Err(err) = self.failover(epoch).await {
+                    warn!(cause = %err, "failover failed");
+                }
+            }
+            None => debug!("not elected leader"),
+        }
+
+        self.election_at = Some(Instant::now() + down_after + jitter(down_after));
+    }
+
+    /// Asks the other sentinels whether the primary is down and to vote for
+    /// this sentinel. Returns the epoch if the sentinel is elected leader.
+    async fn elect(&mut self) -> Option<u64> {
+        let epoch = self.db.with_sentinel(SentinelState::start_election)?;
+        let state = self.state();
+        let (host, port) = &state.primary;
+
+        // This sentinel votes for itself.
+        let mut down = 1;
+        let mut votes = 1;
+
+        for addr in &state.config.sentinels {
+            let cmd = Sentinel::IsMasterDownByAddr {
+                host: host.clone(),
+                port: *port,
+                epoch,
+                runid: state.myid.clone(),
+            };
+
+            let reply = match request(state.config.down_after, sentinel(addr, cmd)).await {
+                Ok(Frame::Array(reply)) => reply,
+                Ok(frame) => {
+                    warn!(%addr, ?frame, "unexpected reply from sentinel");
+                    continue;
+                }
+                Err(err) => {
+                    debug!(%addr, cause = %err, "sentinel did not reply");
+                    continue;
+                }
+            };
+
+            if let [Frame::Integer(is_down), leader, Frame::Integer(leader_epoch)] = &reply[..] {
+                down += *is_down as usize;
+
+                if *leader == state.myid.as_str() && *leader_epoch == epoch {
+                    votes += 1;
+                }
+            }
+        }
+
+        // This sentinel and the other ones.
+        let sentinels = state.config.sentinels.len() + 1;
+        let majority = sentinels / 2 + 1;
+        debug!(epoch, down, votes, majority, "election");
+
+        (down >= state.config.monitor.quorum && votes >= majority.max(state.config.monitor.quorum))
+            .then_some(epoch)
+    }
+
+    /// Promotes the replica with the largest replication offset.
+    async fn failover(&mut self, epoch: u64) -> crate::Result<()> {
+        let state = self.state();
+        let down_after = state.config.down_after;
+
+        let mut promoted: Option<((String, u16), i64)> = None;
+
+        for replica in &state.replicas {
+            let info = match request(down_after, info(replica)).await {
+                Ok(info) => info,
+                Err(err) => {
+                    debug!(?replica, cause = %err, "replica did not reply");
+                    continue;
+                }
+            };
+
+            if info.get("role").map(String::as_str) != Some("slave") {
+                continue;
+            }
+
+            let offset = info
+                .get("slave_repl_offset")
+                .and_then(|offset| offset.parse().ok())
+                .unwrap_or(-1);
+
+            if promoted.as_ref().is_none_or(|(_, best)| offset > *best) {
+                promoted = Some((replica.clone(), offset));
+            }
+        }
+
+        let (addr, _) = promoted.ok_or("no replica can be promoted")?;
+
+        info!(replica = ?addr, epoch, "promoting replica");
+        request(down_after, async {
+            Client::connect((addr.0.as_str(), addr.1))
+                .await?
+                .replicaof_no_one()
+                .await
+        })
+        .await?;
+
+        self.switch(addr, epoch);
+
+        // Replicas that cannot be reached now are reconfigured when they
+        // come back.
+        self.reconfigure_replicas().await;
+
+        Ok(())
+    }
+
+    /// Adopts the configuration of the other sentinels if it is more recent.
+    async fn sync_with_sentinels(&mut self) {
+        let state = self.state();
+
+        for addr in &state.config.sentinels {
+            let cmd = Sentinel::Master(state.config.monitor.name.clone());
+
+            let fields = match request(state.config.down_after, sentinel(addr, cmd)).await {
+                Ok(Frame::Array(fields)) => fields,
+                _ => continue,
+            };
+
+            let fields: HashMap<String, String> = fields
+                .chunks_exact(2)
+                .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                .collect();
+
+            let primary = (|| {
+                let epoch: u64 = fields.get("config-epoch")?.parse().ok()?;
+                let host = fields.get("ip")?.clone();
+                let port: u16 = fields.get("port")?.parse().ok()?;
+                Some(((host, port), epoch))
+            })();
+
+            match primary {
+                Some((primary, epoch)) if epoch > self.state().config_epoch => {
+                    self.switch(primary, epoch);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Points the replicas that do not replicate the primary to it.
+    async fn reconfigure_replicas(&mut self) {
+        let state = self.state();
+        let (host, port) = &state.primary;
+
+        for replica in &state.replicas {
+            let reconfigured = request(state.config.down_after, async {
+                let info = info(replica).await?;
+
+                let replicates_primary = info.get("role").map(String::as_str) == Some("slave")
+                    && info.get("master_host") == Some(host)
+                    && info.get("master_port") == Some(&port.to_string());
+
+                if replicates_primary {
+                    return Ok(false);
+                }
+
+                let mut client = Client::connect((replica.0.as_str(), replica.1)).await?;
+                client.replicaof(host, *port).await?;
+                Ok(true)
+            })
+            .await;
+
+            match reconfigured {
+                Ok(true) => info!(?replica, primary = ?state.primary, "reconfigured replica"),
+                Ok(false) => {}
+                Err(err) => debug!(?replica, cause = %err, "replica did not reply"),
+            }
+        }
+    }
+
+    /// Switches to the primary `addr` and publishes the change.
+    fn switch(&mut self, addr: (String, u16), epoch: u64) {
+        let name = match self
+            .db
+            .with_sentinel(|sentinel| sentinel.config.monitor.name.clone())
+        {
+            Some(name) => name,
+            None => return,
+        };
+
+        let old = match self
+            .db
+            .with_sentinel(|sentinel| sentinel.switch(addr.clone(), epoch))
+        {
+            Some(Some(old)) => old,
+            _ => return,
+        };
+
+        info!(%name, ?old, new = ?addr, epoch, "switched primary");
+
+        self.last_reply = Instant::now();
+        self.election_at = None;
+
+        let message = format!("{} {} {} {} {}", name, old.0, old.1, addr.0, addr.1);
+        self.db.publish(SWITCH_MASTER, Bytes::from(message));
+    }
+
+    /// Returns a copy of the sentinel state.
+    fn state(&self) -> SentinelState {
+        self.db
+            .with_sentinel(|sentinel| sentinel.clone())
+            .expect("sentinel mode is enabled")
+    }
+}
+
+/// Pings the primary and returns the addresses of its replicas.
+async fn check_primary(addr: &(String, u16)) -> crate::Result<Vec<(String, u16)>> {
+    let mut client = Client::connect((addr.0.as_str(), addr.1)).await?;
+    client.ping(None).await?;
+
+    let info = parse_info(&client.info(Some("replication")).await?);
+
+    // Replicas are listed as `slave0:ip=...,port=...,state=online,...`.
+    let replicas = info
+        .iter()
+        .filter(|(field, _)| field.starts_with("slave") && field[5..].parse::<u64>().is_ok())
+        .filter_map(|(_, value)| {
+            let fields: HashMap<&str, &str> = value
+                .split(',')
+                .filter_map(|field| field.split_once('='))
+                .collect();
+
+            Some((
+                fields.get("ip")?.to_string(),
+                fields.get("port")?.parse().ok()?,
+            ))
+        })
+        .collect();
+
+    Ok(replicas)
+}
+
+/// Returns the `INFO replication` fields of the server at `addr`.
+async fn info(addr: &(String, u16)) -> crate::Result<HashMap<String, String>> {
+    let mut client = Client::connect((addr.0.as_str(), addr.1)).await?;
+    Ok(parse_info(&client.info(Some("replication")).await?))
+}
+
+/// Sends a `SENTINEL` command to the sentinel at `addr`.
+async fn sentinel(addr: &str, cmd: Sentinel) -> crate::Result<Frame> {
+    let mut client = Client::connect(addr).await?;
+    client.execute(&cmd.into_frame()).await
+}
+
+/// Parses the `field:value` lines of an `INFO` reply.
+fn parse_info(info: &str) -> HashMap<String, String> {
+    info.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(field, value)| (field.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Runs `request`, failing if it does not complete within `timeout`.
+async fn request<T>(
+    timeout: Duration,
+    request: impl Future<Output = crate::Result<T>>,
+) -> crate::Result<T> {
+    match time::timeout(timeout, request).await {
+        Ok(res) => res,
+        Err(_) => Err("request timed out".into()),
+    }
+}
+
+/// Returns a random duration lower than `max`.
+fn jitter(max: Duration) -> Duration {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    max.mul_f64((hasher.finish() % 1000) as f64 / 1000.0)
+}
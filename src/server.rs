@@ -2,14 +2,31 @@
 //!
 //! 提供一个异步的 `run` 函数，用于侦听传入连接，并为每个连接生成一个任务
 
-use crate::{Command, Connection, Db, DbDropGuard, Shutdown};
-
-use std::future::Future;
+use crate::cluster::ClusterState;
+use crate::cmd::Flow;
+use crate::frame::{self, Frame, Limits};
+use crate::sentinel::{self, SentinelState};
+#[cfg(feature = "tls")]
+use crate::tls::TlsListener;
+use crate::{
+    aof, rdb, snapshot, AppendFsync, Command, Connection, Db, DbDropGuard, KeyspaceEvents,
+    MaxmemoryPolicy, SaveRule, Shutdown, Transport,
+};
+
+use std::fmt;
+use std::future::{self, Future};
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::sync::{broadcast, mpsc, Semaphore};
 use tokio::time::{self, Duration};
-use tracing::{debug, error, info, instrument};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, error, info, instrument, warn};
 
 /// 服务器侦听器状态。在 `run` 调用中创建。它包括一个 `run` 方法，
 /// 该方法执行 TCP 侦听并初始化每连接状态
@@ -22,8 +39,9 @@ struct Listener {
     /// 这持有围绕 `Arc` 的包装器。可以检索内部 `Db` 并将其传递到每连接状态（`Handler`）
     db_holder: DbDropGuard,
 
-    /// 由 `run` 调用者提供的 TCP 侦听器
-    listener: TcpListener,
+    /// 由 `run` 调用者提供的侦听套接字，或者 `run_with_incoming` 调用者提供的
+    /// 连接
+    incoming: Incoming,
 
     /// 限制最大连接数
     ///
@@ -50,6 +68,21 @@ struct Listener {
     /// 一旦所有处理程序任务完成，`Sender` 的所有克隆也会被删除。这导致
     /// `shutdown_complete_rx.recv()` 以 `None` 完成。此时，退出服务器进程是安全的
     shutdown_complete_tx: mpsc::Sender<()>,
+
+    /// 客户端发送的帧的长度和嵌套深度限制
+    proto_limits: Limits,
+}
+
+/// 已经接受的连接。TLS 连接还需要完成握手，该 future 完成握手后返回连接
+pub(crate) type Accepted = Pin<Box<dyn Future<Output = io::Result<Box<dyn Transport>>> + Send>>;
+
+/// `Listener` 接受的连接的来源
+enum Incoming {
+    /// 由侦听套接字接受的连接
+    Sockets(Listeners),
+
+    /// 由 `run_with_incoming` 调用者建立的连接
+    Streams(Pin<Box<dyn Stream<Item = Box<dyn Transport>> + Send>>),
 }
 
 /// 每连接处理程序。从 `connection` 读取请求并将命令应用到 `db`
@@ -61,9 +94,9 @@ struct Handler {
     /// 每个命令都需要与 `db` 交互才能完成工作
     db: Db,
 
-    /// TCP 连接，使用使用缓冲 `TcpStream` 实现的 redis 协议编码器/解码器装饰
+    /// 客户端连接，使用使用缓冲流实现的 redis 协议编码器/解码器装饰
     ///
-    /// 当 `Listener` 收到传入连接时，`TcpStream` 被传递给 `Connection::new`，
+    /// 当 `Listener` 收到传入连接时，流被传递给 `Connection::new`，
     /// 它初始化关联的缓冲区。`Connection` 允许处理程序在"帧"级别操作，
     /// 并将字节级协议解析细节封装在 `Connection` 中
     connection: Connection,
@@ -76,6 +109,10 @@ struct Handler {
     /// 进行中的工作，直到它达到安全状态，此时终止连接
     shutdown: Shutdown,
 
+    /// 由 `ASKING` 设置，允许下一条命令访问该节点正在导入的槽。只在集群模式下
+    /// 使用
+    asking: bool,
+
     /// 不直接使用。相反，当 `Handler` 被删除时...？
     _shutdown_complete: mpsc::Sender<()>,
 }
@@ -94,16 +131,196 @@ struct Handler {
 /// well).
 const MAX_CONNECTIONS: usize = 250;
 
+/// Server configuration.
+///
+/// Settings that may also be changed at runtime using `CONFIG SET` only
+/// provide the initial value.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Keyspace events published when keys are modified. Disabled by default.
+    pub notify_keyspace_events: KeyspaceEvents,
+
+    /// Path of the snapshot file. The snapshot is loaded when the server
+    /// starts and written by `SAVE`, `BGSAVE` and the `save` rules. `None`
+    /// disables persistence, which is the default.
+    pub dbfilename: Option<PathBuf>,
+
+    /// Rules triggering a background save. When at least one rule is set, the
+    /// dataset is also saved when the server shuts down.
+    pub save: Vec<SaveRule>,
+
+    /// Path of the append-only file. When set, the file is replayed when the
+    /// server starts, instead of loading the snapshot, and every write is
+    /// logged to it. `None` disables the append-only file, which is the
+    /// default.
+    pub appendfilename: Option<PathBuf>,
+
+    /// When the append-only file is flushed to disk.
+    pub appendfsync: AppendFsync,
+
+    /// Redis RDB file imported when the server starts, after loading the
+    /// persisted data. Only string keys are imported, see `rdb`.
+    pub import_rdb: Option<PathBuf>,
+
+    /// Run in cluster mode: keys are distributed across the nodes of the
+    /// cluster by hash slot, see `cluster`.
+    pub cluster_enabled: bool,
+
+    /// Static cluster configuration, in the `CLUSTER NODES` format, read when
+    /// the server starts in cluster mode. Without it, the node starts alone
+    /// in its own cluster and serves no slots until `CLUSTER ADDSLOTS`.
+    pub cluster_config_file: Option<PathBuf>,
+
+    /// Memory limit of the dataset, in bytes. When a write would exceed it,
+    /// keys are evicted according to `maxmemory_policy`. 0, the default,
+    /// disables the limit.
+    pub maxmemory: usize,
+
+    /// Which keys are evicted when the memory limit is reached.
+    pub maxmemory_policy: MaxmemoryPolicy,
+
+    /// Run as a sentinel monitoring a primary, see `sentinel`. `None` by
+    /// default.
+    pub sentinel: Option<sentinel::Config>,
+
+    /// Number of independently locked shards the keyspace is split into.
+    /// Connections accessing keys of different shards do not wait for each
+    /// other. 0, the default, uses 16 shards.
+    pub shards: usize,
+
+    /// Limits on the length and nesting of the frames received from clients.
+    /// A client exceeding them gets a protocol error and is disconnected.
+    pub proto_limits: Limits,
+}
+
+/// Sockets the server accepts connections from: a TCP socket, a Unix domain
+/// socket and, with the `tls` feature, a TCP socket accepting TLS
+/// connections. At least one of them must be set.
+///
+/// A single `TcpListener`, `UnixListener` or `TlsListener` converts into
+/// `Listeners`, so any of them can be passed to `run` directly.
+#[derive(Debug, Default)]
+pub struct Listeners {
+    /// TCP socket. Other servers, such as replicas and cluster nodes, reach
+    /// this server at its address.
+    pub tcp: Option<TcpListener>,
+
+    /// Unix domain socket, for clients running on the same host.
+    #[cfg(unix)]
+    pub unix: Option<UnixListener>,
+
+    /// TCP socket accepting TLS connections.
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsListener>,
+}
+
 /// Run the mini-redis server.
 ///
-/// Accepts connections from the supplied listener. For each inbound connection,
+/// Accepts connections from the supplied listeners. For each inbound connection,
 /// a task is spawned to handle that connection. The server runs until the
 /// `shutdown` future completes, at which point the server shuts down
 /// gracefully.
 ///
 /// `tokio::signal::ctrl_c()` can be used as the `shutdown` argument. This will
 /// listen for a SIGINT signal.
-pub async fn run(listener: TcpListener, shutdown: impl Future) {
+///
+/// The server uses the default `Config`, see `run_with_config` to customize it.
+pub async fn run(listeners: impl Into<Listeners>, shutdown: impl Future) {
+    run_with_config(listeners, Config::default(), shutdown).await
+}
+
+/// Run the mini-redis server with the given `config`.
+///
+/// Same as `run`, but the initial server settings are taken from `config`.
+///
+/// If `config.dbfilename` points to an existing snapshot, it is loaded before
+/// accepting connections and keys that expired in the meantime are skipped.
+/// When `config.appendfilename` is set, the append-only file is replayed
+/// instead. If the data cannot be loaded, the error is logged and the server
+/// does not start, so that corrupted files are not overwritten.
+pub async fn run_with_config(
+    listeners: impl Into<Listeners>,
+    config: Config,
+    shutdown: impl Future,
+) {
+    let listeners = listeners.into();
+
+    if listeners.is_empty() {
+        error!("no socket to listen on");
+        return;
+    }
+
+    // Other servers reach this server at the TCP listening address: replicas
+    // announce it to their primary and cluster nodes to the other nodes.
+    let addr = match listeners.tcp.as_ref().map(TcpListener::local_addr) {
+        Some(Ok(addr)) if addr.ip().is_unspecified() => {
+            Some((Ipv4Addr::LOCALHOST.to_string(), addr.port()))
+        }
+        Some(Ok(addr)) => Some((addr.ip().to_string(), addr.port())),
+        Some(Err(err)) => {
+            error!(cause = %err, "failed to get the listening address");
+            return;
+        }
+        None => None,
+    };
+
+    serve(Incoming::Sockets(listeners), addr, config, shutdown).await
+}
+
+/// Run the mini-redis server over connections established by the caller.
+///
+/// Same as `run_with_config`, but instead of accepting connections from
+/// listening sockets, the server handles every stream yielded by `incoming`, such
+/// as `tokio::io::duplex` pipes in tests. Once `incoming` ends, the server
+/// stops accepting connections and keeps handling the existing ones until
+/// `shutdown` completes.
+///
+/// Without a listening address, the server cannot be reached by other
+/// servers. Cluster mode requires a `cluster_config_file` describing this
+/// node.
+///
+/// # Examples
+///
+/// ```
+/// use mini_redis::{clients::Client, server};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+///     let incoming = tokio_stream::once(server_stream);
+///     tokio::spawn(server::run_with_incoming(
+///         incoming,
+///         server::Config::default(),
+///         std::future::pending::<()>(),
+///     ));
+///
+///     let mut client = Client::from_stream(client_stream);
+///     assert_eq!(&client.ping(None).await.unwrap()[..], b"PONG");
+/// }
+/// ```
+pub async fn run_with_incoming<S: Transport>(
+    incoming: impl Stream<Item = S> + Send + 'static,
+    config: Config,
+    shutdown: impl Future,
+) {
+    let incoming = incoming.map(|stream| Box::new(stream) as Box<dyn Transport>);
+    serve(
+        Incoming::Streams(Box::pin(incoming)),
+        None,
+        config,
+        shutdown,
+    )
+    .await
+}
+
+/// Runs the server on the connections from `incoming`. `addr` is the host
+/// and port other servers can reach this server at, if any.
+async fn serve(
+    incoming: Incoming,
+    addr: Option<(String, u16)>,
+    config: Config,
+    shutdown: impl Future,
+) {
     // When the provided `shutdown` future completes, we must send a shutdown
     // message to all active connections. We use a broadcast channel for this
     // purpose. The call below ignores the receiver of the broadcast pair, and when
@@ -112,13 +329,125 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
     let (notify_shutdown, _) = broadcast::channel(1);
     let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
 
+    // Initialize the shared database with the configured settings.
+    let db_holder = DbDropGuard::new(config.shards);
+    let db = db_holder.db();
+    db.set_notify_keyspace_events(config.notify_keyspace_events);
+    db.set_dbfilename(config.dbfilename.clone());
+    db.set_save_rules(config.save);
+
+    if let Some(path) = config.appendfilename {
+        let res = match aof::replay(&path, &db).await {
+            Ok(()) => db.open_aof(path.clone(), config.appendfsync).await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = res {
+            error!(cause = %err, path = %path.display(), "failed to load append-only file");
+            return;
+        }
+
+        if config.appendfsync == AppendFsync::EverySec {
+            tokio::spawn(aof::run_fsync(
+                db.clone(),
+                Shutdown::new(notify_shutdown.subscribe()),
+            ));
+        }
+    } else if let Some(path) = &config.dbfilename {
+        match snapshot::read(path) {
+            Ok(Some(records)) => {
+                info!(keys = records.len(), "loaded snapshot");
+                db.load(records);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                error!(cause = %err, path = %path.display(), "failed to load snapshot");
+                return;
+            }
+        }
+    }
+
+    // The persisted data is loaded regardless of the memory limit, which only
+    // applies to the following writes, including the imported keys.
+    db.set_maxmemory(config.maxmemory);
+    db.set_maxmemory_policy(config.maxmemory_policy);
+
+    if let Some(path) = &config.import_rdb {
+        let entries = match rdb::read(path) {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!(cause = %err, path = %path.display(), "failed to read RDB file");
+                return;
+            }
+        };
+
+        let report = rdb::import(&db, entries);
+        info!(
+            loaded = report.loaded,
+            expired = report.expired,
+            "imported RDB file"
+        );
+
+        for (reason, count) in &report.skipped {
+            warn!(%reason, count, "skipped keys that cannot be imported");
+        }
+
+        // Imported keys are logged like any other write.
+        if let Err(err) = db.flush_aof().await {
+            error!(cause = %err, "failed to write the append-only file");
+            return;
+        }
+    }
+
+    if let Some((host, port)) = &addr {
+        db.set_addr(host.clone(), *port);
+    }
+
+    if config.cluster_enabled {
+        let cluster = match (&config.cluster_config_file, addr) {
+            (Some(path), _) => match ClusterState::load(path) {
+                Ok(cluster) => cluster,
+                Err(err) => {
+                    error!(cause = %err, path = %path.display(), "failed to load cluster configuration");
+                    return;
+                }
+            },
+            (None, Some((host, port))) => ClusterState::new(host, port),
+            (None, None) => {
+                error!("cluster mode without a listening address requires a cluster configuration file");
+                return;
+            }
+        };
+
+        info!(id = cluster.myid(), "cluster mode enabled");
+        db.set_cluster(cluster);
+    }
+
+    if let Some(config) = config.sentinel {
+        info!(monitor = %config.monitor, "sentinel mode enabled");
+        db.set_sentinel(SentinelState::new(config));
+
+        tokio::spawn(sentinel::run(
+            db.clone(),
+            Shutdown::new(notify_shutdown.subscribe()),
+        ));
+    }
+
+    // The save rules are checked in the background until the server shuts
+    // down. The rules may be changed at runtime with `CONFIG SET save`.
+    tokio::spawn(snapshot::run_save_rules(
+        db,
+        Shutdown::new(notify_shutdown.subscribe()),
+    ));
+
     // Initialize the listener state
     let mut server = Listener {
-        listener,
-        db_holder: DbDropGuard::new(),
+        incoming,
+        db_holder,
         limit_connections: Arc::new(Semaphore::new(MAX_CONNECTIONS)),
         notify_shutdown,
         shutdown_complete_tx,
+        proto_limits: config.proto_limits,
     };
 
     // Concurrently run the server and listen for the `shutdown` signal. The
@@ -162,6 +491,7 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
     // explicitly drop `shutdown_transmitter`. This is important, as the
     // `.await` below would otherwise never complete.
     let Listener {
+        db_holder,
         shutdown_complete_tx,
         notify_shutdown,
         ..
@@ -178,6 +508,19 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
     // `Sender` instances are held by connection handler tasks. When those drop,
     // the `mpsc` channel will close and `recv()` will return `None`.
     let _ = shutdown_complete_rx.recv().await;
+
+    // As with Redis, the dataset is saved one last time if save rules are
+    // configured.
+    let db = db_holder.db();
+    if !db.save_rules().is_empty() && db.dbfilename().is_some() {
+        if let Err(err) = db.save().await {
+            error!(cause = %err, "failed to save snapshot on shutdown");
+        }
+    }
+
+    if let Err(err) = db.sync_aof().await {
+        error!(cause = %err, "failed to flush the append-only file on shutdown");
+    }
 }
 
 impl Listener {
@@ -218,28 +561,53 @@ impl Listener {
             // Accept a new socket. This will attempt to perform error handling.
             // The `accept` method internally attempts to recover errors, so an
             // error here is non-recoverable.
-            let socket = self.accept().await?;
-
-            // Create the necessary per-connection handler state.
-            let mut handler = Handler {
-                // Get a handle to the shared database.
-                db: self.db_holder.db(),
+            let accepted = match self.accept().await? {
+                Some(accepted) => accepted,
+                // No more connections will come, the existing ones are handled
+                // until the server shuts down.
+                None => return future::pending().await,
+            };
 
-                // Initialize the connection state. This allocates read/write
-                // buffers to perform redis protocol frame parsing.
-                connection: Connection::new(socket),
+            // Get a handle to the shared database.
+            let db = self.db_holder.db();
 
-                // Receive shutdown notifications.
-                shutdown: Shutdown::new(self.notify_shutdown.subscribe()),
+            // Receive shutdown notifications.
+            let mut shutdown = Shutdown::new(self.notify_shutdown.subscribe());
 
-                // Notifies the receiver half once all clones are
-                // dropped.
-                _shutdown_complete: self.shutdown_complete_tx.clone(),
-            };
+            // Notifies the receiver half once all clones are dropped.
+            let shutdown_complete = self.shutdown_complete_tx.clone();
+            let proto_limits = self.proto_limits;
 
             // Spawn a new task to process the connections. Tokio tasks are like
             // asynchronous green threads and are executed concurrently.
             tokio::spawn(async move {
+                // TLS connections complete their handshake in their own task,
+                // a slow client does not delay the others.
+                let socket = tokio::select! {
+                    res = accepted => match res {
+                        Ok(socket) => socket,
+                        Err(err) => {
+                            debug!(cause = %err, "failed to establish the connection");
+                            return;
+                        }
+                    },
+                    _ = shutdown.recv() => return,
+                };
+
+                // Initialize the connection state. This allocates read/write
+                // buffers to perform redis protocol frame parsing.
+                let mut connection = Connection::new(socket);
+                connection.set_limits(proto_limits);
+
+                // Create the necessary per-connection handler state.
+                let mut handler = Handler {
+                    db,
+                    connection,
+                    shutdown,
+                    asking: false,
+                    _shutdown_complete: shutdown_complete,
+                };
+
                 // Process the connection. If an error is encountered, log it.
                 if let Err(err) = handler.run().await {
                     error!(cause = ?err, "connection error");
@@ -251,22 +619,31 @@ impl Listener {
         }
     }
 
-    /// Accept an inbound connection.
+    /// Accept an inbound connection. Returns `None` once the connections
+    /// given to `run_with_incoming` are exhausted.
     ///
     /// Errors are handled by backing off and retrying. An exponential backoff
     /// strategy is used. After the first failure, the task waits for 1 second.
     /// After the second failure, the task waits for 2 seconds. Each subsequent
     /// failure doubles the wait time. If accepting fails on the 6th try after
     /// waiting for 64 seconds, then this function returns with an error.
-    async fn accept(&mut self) -> crate::Result<TcpStream> {
+    async fn accept(&mut self) -> crate::Result<Option<Accepted>> {
+        let listeners = match &mut self.incoming {
+            Incoming::Sockets(listeners) => listeners,
+            Incoming::Streams(streams) => {
+                let stream = streams.next().await;
+                return Ok(stream.map(|stream| Box::pin(future::ready(Ok(stream))) as Accepted));
+            }
+        };
+
         let mut backoff = 1;
 
         // Try to accept a few times
         loop {
             // Perform the accept operation. If a socket is successfully
             // accepted, return it. Otherwise, save the error.
-            match self.listener.accept().await {
-                Ok((socket, _)) => return Ok(socket),
+            match listeners.accept().await {
+                Ok(socket) => return Ok(Some(socket)),
                 Err(err) => {
                     if backoff > 64 {
                         // Accept has failed too many times. Return the error.
@@ -284,28 +661,170 @@ impl Listener {
     }
 }
 
+impl Listeners {
+    /// Returns `true` if there is no socket to listen on.
+    fn is_empty(&self) -> bool {
+        #[cfg(unix)]
+        if self.unix.is_some() {
+            return false;
+        }
+
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() {
+            return false;
+        }
+
+        self.tcp.is_none()
+    }
+
+    /// Accepts a connection on whichever socket receives one first.
+    async fn accept(&self) -> io::Result<Accepted> {
+        let tcp = async {
+            match &self.tcp {
+                Some(listener) => {
+                    let (socket, _) = listener.accept().await?;
+
+                    // Responses are already batched by the handler, Nagle's
+                    // algorithm would only delay them. Same as Redis, a
+                    // failure only costs latency and is ignored.
+                    let _ = socket.set_nodelay(true);
+                    Ok(
+                        Box::pin(future::ready(Ok(Box::new(socket) as Box<dyn Transport>)))
+                            as Accepted,
+                    )
+                }
+                None => future::pending().await,
+            }
+        };
+
+        #[cfg(unix)]
+        let unix = async {
+            match &self.unix {
+                Some(listener) => {
+                    let (socket, _) = listener.accept().await?;
+                    Ok(
+                        Box::pin(future::ready(Ok(Box::new(socket) as Box<dyn Transport>)))
+                            as Accepted,
+                    )
+                }
+                None => future::pending().await,
+            }
+        };
+
+        #[cfg(not(unix))]
+        let unix = future::pending();
+
+        #[cfg(feature = "tls")]
+        let tls = async {
+            match &self.tls {
+                Some(listener) => listener.accept().await,
+                None => future::pending().await,
+            }
+        };
+
+        #[cfg(not(feature = "tls"))]
+        let tls = future::pending();
+
+        tokio::select! {
+            res = tcp => res,
+            res = unix => res,
+            res = tls => res,
+        }
+    }
+}
+
+impl From<TcpListener> for Listeners {
+    fn from(listener: TcpListener) -> Listeners {
+        Listeners {
+            tcp: Some(listener),
+            ..Listeners::default()
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixListener> for Listeners {
+    fn from(listener: UnixListener) -> Listeners {
+        Listeners {
+            unix: Some(listener),
+            ..Listeners::default()
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<TlsListener> for Listeners {
+    fn from(listener: TlsListener) -> Listeners {
+        Listeners {
+            tls: Some(listener),
+            ..Listeners::default()
+        }
+    }
+}
+
+impl fmt::Debug for Incoming {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Incoming::Sockets(listeners) => fmt.debug_tuple("Sockets").field(listeners).finish(),
+            Incoming::Streams(_) => fmt.write_str("Streams"),
+        }
+    }
+}
+
 impl Handler {
     /// Process a single connection.
     ///
     /// Request frames are read from the socket and processed. Responses are
     /// written back to the socket.
     ///
-    /// Currently, pipelining is not implemented. Pipelining is the ability to
-    /// process more than one request concurrently per connection without
-    /// interleaving frames. See for more details:
-    /// https://redis.io/topics/pipelining
+    /// Requests are processed one at a time, in order. When a client pipelines
+    /// requests, the frames already buffered are parsed and executed without
+    /// waiting, and their responses are written to the socket together once
+    /// the buffered requests are drained or the write buffer fills up. See
+    /// for more details: https://redis.io/topics/pipelining
     ///
     /// When the shutdown signal is received, the connection is processed until
     /// it reaches a safe state, at which point it is terminated.
     #[instrument(skip(self))]
     async fn run(&mut self) -> crate::Result<()> {
+        // Responses to pipelined requests are flushed in batches, see
+        // `Connection::set_batch_writes`.
+        self.connection.set_batch_writes(true);
+
+        let res = self.process().await;
+
+        // Responses to the requests executed before an invalid one are still
+        // delivered. The connection may already be broken, which `res`
+        // reports. Shutting the connection down notifies TLS peers that no
+        // data was truncated.
+        let _ = self.connection.flush().await;
+        let _ = self.connection.shutdown().await;
+
+        res
+    }
+
+    /// Reads and executes requests until the peer disconnects or the shutdown
+    /// signal is received.
+    async fn process(&mut self) -> crate::Result<()> {
         // As long as the shutdown signal has not been received, try to read a
         // new request frame.
         while !self.shutdown.is_shutdown() {
             // While reading a request frame, also listen for the shutdown
             // signal.
             let maybe_frame = tokio::select! {
-                res = self.connection.read_frame() => res?,
+                res = self.connection.read_frame() => match res {
+                    Ok(frame) => frame,
+                    Err(err) => {
+                        // Same as Redis, a client sending an invalid frame is
+                        // told why before being disconnected.
+                        if err.is::<frame::Error>() {
+                            let reply = Frame::Error(format!("ERR {}", err));
+                            let _ = self.connection.write_frame(&reply).await;
+                        }
+
+                        return Err(err);
+                    }
+                },
                 _ = self.shutdown.recv() => {
                     // If a shutdown signal is received, return from `run`.
                     // This will result in the task terminating.
@@ -337,6 +856,23 @@ impl Handler {
             // as key-value pairs.
             debug!(?cmd);
 
+            // In cluster mode, keys in slots served by other nodes are
+            // redirected. The `ASKING` flag only applies to the command that
+            // follows it.
+            let asking = std::mem::replace(&mut self.asking, matches!(cmd, Command::Asking(_)));
+
+            if let Some(redirect) = self.db.cluster_redirect(&cmd.keys(), asking) {
+                self.connection.write_frame(&redirect).await?;
+                continue;
+            }
+
+            // The responses to the requests pipelined before a command that
+            // may block are sent first, the client may need them before it
+            // stops waiting.
+            if cmd.blocks() {
+                self.connection.flush().await?;
+            }
+
             // Perform the work needed to apply the command. This may mutate the
             // database state as a result.
             //
@@ -344,8 +880,15 @@ impl Handler {
             // command to write response frames directly to the connection. In
             // the case of pub/sub, multiple frames may be send back to the
             // peer.
-            cmd.apply(&self.db, &mut self.connection, &mut self.shutdown)
+            let flow = cmd
+                .apply(&self.db, &mut self.connection, &mut self.shutdown)
                 .await?;
+
+            // `QUIT` closes the connection once its reply has been written,
+            // including when sent in the subscribed state.
+            if flow == Flow::Quit {
+                return Ok(());
+            }
         }
 
         Ok(())
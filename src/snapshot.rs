@@ -0,0 +1,272 @@
+//! Snapshot persistence
+//!
+//! The whole dataset can be written to a single binary file, similar to the
+//! Redis RDB file. The file is written by the `SAVE` and `BGSAVE` commands, as
+//! well as automatically when one of the configured `SaveRule`s triggers, and
+//! is loaded by the server at startup.
+//!
+//! # Format
+//!
+//! ```text
+//! "MINIREDIS" version:u8
+//! (opcode:u8 [expires_at:u64] key value)*
+//! EOF:u8
+//! ```
+//!
+//! `key` and `value` are encoded as a big-endian `u32` length followed by the
+//! bytes. Expirations are stored as absolute UNIX timestamps in milliseconds,
+//! so the remaining time to live is preserved across restarts.
+
+use crate::{Db, Shutdown};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time;
+use tracing::{debug, error};
+
+/// Magic string at the start of every snapshot file.
+const MAGIC: &[u8] = b"MINIREDIS";
+
+/// Version of the snapshot format.
+const VERSION: u8 = 1;
+
+/// A key without expiration.
+const OPCODE_ENTRY: u8 = 0x00;
+
+/// A key with an expiration.
+const OPCODE_ENTRY_EXPIRE: u8 = 0x01;
+
+/// End of the snapshot.
+const OPCODE_EOF: u8 = 0xFF;
+
+/// A single key stored in a snapshot.
+#[derive(Debug)]
+pub(crate) struct Record {
+    pub(crate) key: String,
+    pub(crate) value: Bytes,
+    pub(crate) expires_at: Option<SystemTime>,
+}
+
+/// Automatically save the dataset once at least `changes` writes happened
+/// in the last `seconds`.
+///
+/// Parsed from the same syntax as the Redis `save` configuration directive,
+/// for example `900 1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    /// Minimum time since the last successful save.
+    pub seconds: u64,
+
+    /// Minimum number of changes since the last successful save.
+    pub changes: u64,
+}
+
+impl SaveRule {
+    /// Returns `true` if the rule triggers a save.
+    pub(crate) fn triggered(&self, since_last_save: Duration, dirty: u64) -> bool {
+        dirty >= self.changes && since_last_save.as_secs() >= self.seconds
+    }
+
+    /// Parses a list of rules, `seconds changes [seconds changes ...]`. The
+    /// empty string is the empty list.
+    pub(crate) fn parse_list(src: &str) -> crate::Result<Vec<SaveRule>> {
+        let args: Vec<&str> = src.split_whitespace().collect();
+
+        let pairs = args.chunks_exact(2);
+
+        if !pairs.remainder().is_empty() {
+            return Err("save rules must be pairs of `seconds changes`".into());
+        }
+
+        pairs
+            .map(|pair| {
+                Ok(SaveRule {
+                    seconds: pair[0].parse()?,
+                    changes: pair[1].parse()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Formats a list of rules using the syntax accepted by `parse_list`.
+    pub(crate) fn format_list(rules: &[SaveRule]) -> String {
+        rules
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl FromStr for SaveRule {
+    type Err = crate::Error;
+
+    fn from_str(src: &str) -> crate::Result<SaveRule> {
+        match &SaveRule::parse_list(src)?[..] {
+            [rule] => Ok(*rule),
+            _ => Err(format!("invalid save rule `{}`", src).into()),
+        }
+    }
+}
+
+impl fmt::Display for SaveRule {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} {}", self.seconds, self.changes)
+    }
+}
+
+/// Encodes `records` into a snapshot.
+pub(crate) fn encode(records: &[Record]) -> Bytes {
+    let mut buf = BytesMut::new();
+
+    buf.put_slice(MAGIC);
+    buf.put_u8(VERSION);
+
+    for record in records {
+        match record.expires_at {
+            Some(when) => {
+                buf.put_u8(OPCODE_ENTRY_EXPIRE);
+                buf.put_u64(to_unix_millis(when));
+            }
+            None => buf.put_u8(OPCODE_ENTRY),
+        }
+
+        put_bytes(&mut buf, record.key.as_bytes());
+        put_bytes(&mut buf, &record.value);
+    }
+
+    buf.put_u8(OPCODE_EOF);
+    buf.freeze()
+}
+
+/// Decodes a snapshot produced by `encode`.
+pub(crate) fn decode(mut src: Bytes) -> crate::Result<Vec<Record>> {
+    if src.len() < MAGIC.len() + 1 || &src[..MAGIC.len()] != MAGIC {
+        return Err("not a mini-redis snapshot".into());
+    }
+    src.advance(MAGIC.len());
+
+    let version = src.get_u8();
+    if version != VERSION {
+        return Err(format!("unsupported snapshot version {}", version).into());
+    }
+
+    let mut records = vec![];
+
+    loop {
+        if !src.has_remaining() {
+            return Err("snapshot is truncated".into());
+        }
+
+        let expires_at = match src.get_u8() {
+            OPCODE_ENTRY => None,
+            OPCODE_ENTRY_EXPIRE => {
+                if src.remaining() < 8 {
+                    return Err("snapshot is truncated".into());
+                }
+                Some(UNIX_EPOCH + Duration::from_millis(src.get_u64()))
+            }
+            OPCODE_EOF => return Ok(records),
+            opcode => return Err(format!("invalid snapshot opcode {}", opcode).into()),
+        };
+
+        let key = String::from_utf8(get_bytes(&mut src)?.to_vec())?;
+        let value = get_bytes(&mut src)?;
+
+        records.push(Record {
+            key,
+            value,
+            expires_at,
+        });
+    }
+}
+
+/// Writes a snapshot of `records` to `path`.
+///
+/// The snapshot is first written to a temporary file which is then renamed,
+/// so `path` always contains a complete snapshot even if the process crashes
+/// while saving. The file is flushed to disk before the rename, and the
+/// directory after it, so the snapshot also survives a power loss.
+pub(crate) fn write(path: &Path, records: &[Record]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".tmp-{}", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(&encode(records))?;
+    file.sync_all()?;
+
+    std::fs::rename(&tmp, path)?;
+
+    // The rename is only durable once the directory entry is on disk.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+/// Reads the snapshot stored at `path`. Returns `None` if the file does not
+/// exist.
+pub(crate) fn read(path: &Path) -> crate::Result<Option<Vec<Record>>> {
+    match std::fs::read(path) {
+        Ok(src) => Ok(Some(decode(Bytes::from(src))?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Converts `when` to a UNIX timestamp in milliseconds. Times before the
+/// epoch are clamped to the epoch.
+pub(crate) fn to_unix_millis(when: SystemTime) -> u64 {
+    when.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Checks the save rules once per second and starts a background save when
+/// one of them triggers. Runs until `shutdown` is received.
+pub(crate) async fn run_save_rules(db: Db, mut shutdown: Shutdown) {
+    let mut interval = time::interval(Duration::from_secs(1));
+
+    while !shutdown.is_shutdown() {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.recv() => break,
+        }
+
+        if !db.save_rules_triggered() {
+            continue;
+        }
+
+        debug!("save rule triggered, saving");
+
+        if let Err(err) = db.bgsave() {
+            error!(cause = %err, "background save failed to start");
+        }
+    }
+}
+
+fn put_bytes(buf: &mut BytesMut, bytes: &[u8]) {
+    buf.put_u32(bytes.len() as u32);
+    buf.put_slice(bytes);
+}
+
+fn get_bytes(src: &mut Bytes) -> crate::Result<Bytes> {
+    if src.remaining() < 4 {
+        return Err("snapshot is truncated".into());
+    }
+
+    let len = src.get_u32() as usize;
+
+    if src.remaining() < len {
+        return Err("snapshot is truncated".into());
+    }
+
+    Ok(src.split_to(len))
+}
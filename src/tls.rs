@@ -0,0 +1,224 @@
+//! TLS for the server and the clients, available with the `tls` feature.
+//!
+//! Certificates and private keys are read from PEM files, as in Redis. The
+//! server listens for TLS connections on a dedicated port with a
+//! `TlsListener`, next to or instead of the plaintext port. Clients connect
+//! with `Client::connect_tls` or `BlockingClient::connect_tls`.
+//!
+//! Like Redis, the server requires clients to present a certificate signed by
+//! one of the trusted certificate authorities by default, see `AuthClients`.
+
+use crate::server::Accepted;
+use crate::Transport;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::RootCertStore;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+pub use tokio_rustls::rustls::{ClientConfig, ServerConfig};
+
+/// Whether the server requires clients to present a certificate, as the
+/// `tls-auth-clients` Redis setting.
+///
+/// Client certificates are verified against the certificate authorities of
+/// the CA certificate file, which is required unless authentication is
+/// disabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AuthClients {
+    /// Client certificates are not requested.
+    No,
+
+    /// Clients may present a certificate, which is then verified.
+    Optional,
+
+    /// Clients must present a valid certificate. This is the default.
+    #[default]
+    Yes,
+}
+
+/// A TCP listener accepting TLS connections.
+pub struct TlsListener {
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+}
+
+/// Builds the TLS configuration of the server.
+///
+/// The server presents the certificate chain of `cert_file`, with the private
+/// key of `key_file`. When `auth_clients` is not `AuthClients::No`, client
+/// certificates are verified against the certificates of `ca_cert_file`.
+pub fn server_config(
+    cert_file: &Path,
+    key_file: &Path,
+    ca_cert_file: Option<&Path>,
+    auth_clients: AuthClients,
+) -> crate::Result<Arc<ServerConfig>> {
+    let builder =
+        ServerConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+
+    let builder = match (auth_clients, ca_cert_file) {
+        (AuthClients::No, _) => builder.with_no_client_auth(),
+        (_, None) => return Err("authenticating TLS clients requires a CA certificate file".into()),
+        (auth_clients, Some(ca_cert_file)) => {
+            let roots = Arc::new(load_roots(ca_cert_file)?);
+            let mut verifier = WebPkiClientVerifier::builder_with_provider(roots, provider());
+
+            if auth_clients == AuthClients::Optional {
+                verifier = verifier.allow_unauthenticated();
+            }
+
+            builder.with_client_cert_verifier(verifier.build()?)
+        }
+    };
+
+    let config = builder.with_single_cert(load_certs(cert_file)?, load_key(key_file)?)?;
+
+    Ok(Arc::new(config))
+}
+
+/// Builds the TLS configuration of a client.
+///
+/// The server certificate is verified against the certificates of
+/// `ca_cert_file`. When the server authenticates clients, `identity` holds
+/// the paths of the certificate chain and private key the client presents.
+pub fn client_config(
+    ca_cert_file: &Path,
+    identity: Option<(&Path, &Path)>,
+) -> crate::Result<Arc<ClientConfig>> {
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(load_roots(ca_cert_file)?);
+
+    let config = match identity {
+        Some((cert_file, key_file)) => {
+            builder.with_client_auth_cert(load_certs(cert_file)?, load_key(key_file)?)?
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(Arc::new(config))
+}
+
+impl TlsListener {
+    /// Accepts TLS connections on `listener`, using `config`.
+    pub fn new(listener: TcpListener, config: Arc<ServerConfig>) -> TlsListener {
+        TlsListener {
+            listener,
+            acceptor: TlsAcceptor::from(config),
+        }
+    }
+
+    /// Returns the local address of the listener.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts a TCP connection. The returned future completes the TLS
+    /// handshake.
+    pub(crate) async fn accept(&self) -> io::Result<Accepted> {
+        let (socket, _) = self.listener.accept().await?;
+
+        // See `Listeners::accept`.
+        let _ = socket.set_nodelay(true);
+
+        let accept = self.acceptor.accept(socket);
+
+        Ok(Box::pin(async move {
+            let stream = accept.await?;
+            Ok(Box::new(stream) as Box<dyn Transport>)
+        }))
+    }
+}
+
+impl fmt::Debug for TlsListener {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("TlsListener")
+            .field("listener", &self.listener)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromStr for AuthClients {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<AuthClients> {
+        match &s.to_lowercase()[..] {
+            "no" => Ok(AuthClients::No),
+            "optional" => Ok(AuthClients::Optional),
+            "yes" => Ok(AuthClients::Yes),
+            _ => Err(format!("invalid TLS client authentication `{}`", s).into()),
+        }
+    }
+}
+
+impl fmt::Display for AuthClients {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthClients::No => "no".fmt(fmt),
+            AuthClients::Optional => "optional".fmt(fmt),
+            AuthClients::Yes => "yes".fmt(fmt),
+        }
+    }
+}
+
+/// Connects to `addr` and performs the TLS handshake, verifying that the
+/// server certificate is valid for `domain`.
+pub(crate) async fn connect<T: ToSocketAddrs>(
+    addr: T,
+    domain: &str,
+    config: Arc<ClientConfig>,
+) -> crate::Result<impl Transport> {
+    let domain = ServerName::try_from(domain.to_string())?;
+
+    let socket = TcpStream::connect(addr).await?;
+    socket.set_nodelay(true)?;
+
+    Ok(TlsConnector::from(config).connect(domain, socket).await?)
+}
+
+/// The crypto provider, chosen explicitly so that the configuration does not
+/// depend on the process-wide default.
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+/// Reads the certificates of a PEM file.
+fn load_certs(path: &Path) -> crate::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("failed to read `{}`: {}", path.display(), err))?;
+
+    if certs.is_empty() {
+        return Err(format!("no certificate in `{}`", path.display()).into());
+    }
+
+    Ok(certs)
+}
+
+/// Reads the private key of a PEM file.
+fn load_key(path: &Path) -> crate::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|err| format!("failed to read `{}`: {}", path.display(), err).into())
+}
+
+/// Reads the trusted certificate authorities of a PEM file.
+fn load_roots(path: &Path) -> crate::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+
+    Ok(roots)
+}
@@ -1,6 +1,11 @@
-use mini_redis::{clients::Client, server};
+use mini_redis::clients::{Client, Subscriber};
+use mini_redis::{server, MaxmemoryPolicy};
 use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{duplex, DuplexStream};
 use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::task::JoinHandle;
 
 /// A PING PONG test without message provided.
@@ -104,11 +109,563 @@ async fn unsubscribes_from_channels() {
     assert_eq!(subscriber.get_subscribed().len(), 0);
 }
 
+/// test that a subscriber can ping the server without losing messages
+/// published while waiting for the reply
+#[tokio::test]
+async fn ping_while_subscribed() {
+    let (addr, _) = start_server().await;
+
+    let client = Client::connect(addr).await.unwrap();
+    let mut subscriber = client.subscribe(vec!["hello".into()]).await.unwrap();
+
+    let pong = subscriber.ping(None).await.unwrap();
+    assert_eq!(b"PONG", &pong[..]);
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.publish("hello", "world".into()).await.unwrap();
+
+    let pong = subscriber.ping(Some("alive".into())).await.unwrap();
+    assert_eq!(b"alive", &pong[..]);
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!("hello", &message.channel);
+    assert_eq!(b"world", &message.content[..])
+}
+
+/// test that shard channel messages are only received by shard subscribers
+#[tokio::test]
+async fn sharded_publish_subscribe() {
+    let (addr, _) = start_server().await;
+
+    let client = Client::connect(addr).await.unwrap();
+    let mut subscriber = client.ssubscribe(vec!["hello".into()]).await.unwrap();
+    subscriber.ssubscribe(&["foo".into()]).await.unwrap();
+    assert_eq!(&["hello", "foo"][..], subscriber.get_subscribed_shard());
+
+    let mut client = Client::connect(addr).await.unwrap();
+    assert_eq!(0, client.publish("hello", "world".into()).await.unwrap());
+    assert_eq!(1, client.spublish("hello", "world".into()).await.unwrap());
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!("hello", &message.channel);
+    assert_eq!(b"world", &message.content[..]);
+
+    subscriber.sunsubscribe(&["hello".into()]).await.unwrap();
+    assert_eq!(&["foo"][..], subscriber.get_subscribed_shard());
+    assert_eq!(0, client.spublish("hello", "world".into()).await.unwrap());
+}
+
+/// test that keys are removed by `del` and that `expire` only applies to
+/// existing keys
+#[tokio::test]
+async fn del_and_expire() {
+    let (addr, _) = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    client.set("hello", "world".into()).await.unwrap();
+    client.set("foo", "bar".into()).await.unwrap();
+
+    assert!(client
+        .expire("hello", Duration::from_secs(10))
+        .await
+        .unwrap());
+    assert!(!client
+        .expire("missing", Duration::from_secs(10))
+        .await
+        .unwrap());
+
+    let removed = client
+        .del(&["hello".into(), "foo".into(), "missing".into()])
+        .await
+        .unwrap();
+    assert_eq!(removed, 2);
+
+    assert!(client.get("hello").await.unwrap().is_none());
+    assert!(client.get("foo").await.unwrap().is_none());
+}
+
+/// test that a dumped value can be restored to another key, and that existing
+/// keys are only overwritten with `replace`
+#[tokio::test]
+async fn dump_and_restore() {
+    let (addr, _) = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    client.set("hello", "world".into()).await.unwrap();
+    client.set("foo", "bar".into()).await.unwrap();
+
+    assert!(client.dump("missing").await.unwrap().is_none());
+    let payload = client.dump("hello").await.unwrap().unwrap();
+
+    client
+        .restore("copy", None, payload.clone(), false)
+        .await
+        .unwrap();
+    assert_eq!(b"world", &client.get("copy").await.unwrap().unwrap()[..]);
+
+    let err = client
+        .restore("foo", None, payload.clone(), false)
+        .await
+        .unwrap_err();
+    assert_eq!("BUSYKEY Target key name already exists.", err.to_string());
+    assert_eq!(b"bar", &client.get("foo").await.unwrap().unwrap()[..]);
+
+    client
+        .restore("foo", None, payload.clone(), true)
+        .await
+        .unwrap();
+    assert_eq!(b"world", &client.get("foo").await.unwrap().unwrap()[..]);
+
+    let mut corrupted = payload.to_vec();
+    corrupted[2] ^= 0xff;
+    let err = client
+        .restore("bad", None, corrupted.into(), false)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        "ERR DUMP payload version or checksum are wrong",
+        err.to_string()
+    );
+
+    client
+        .restore(
+            "ttl",
+            Some(Duration::from_millis(50)),
+            payload.clone(),
+            false,
+        )
+        .await
+        .unwrap();
+    assert!(client.get("ttl").await.unwrap().is_some());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(client.get("ttl").await.unwrap().is_none());
+
+    // A time to live under a millisecond still expires the key
+    client
+        .restore("short", Some(Duration::from_micros(100)), payload, false)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert!(client.get("short").await.unwrap().is_none());
+}
+
+/// test that payloads holding other types than strings are rejected before
+/// being decoded, and that declared lengths are not trusted
+#[tokio::test]
+async fn restore_rejects_invalid_payloads() {
+    let (addr, _) = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    // An LZF compressed string of 2 bytes declaring 2^62 decompressed bytes
+    let mut body = vec![0, 0xc3, 2, 0x81];
+    body.extend_from_slice(&(1u64 << 62).to_be_bytes());
+    body.extend_from_slice(&[0x00, b'a']);
+
+    let err = client
+        .restore("lzf", None, dump_payload(body).into(), false)
+        .await
+        .unwrap_err();
+    assert_eq!("ERR Bad data format", err.to_string());
+
+    // A list, which is never decoded, even with a huge declared length
+    let mut body = vec![1, 0x81];
+    body.extend_from_slice(&u64::MAX.to_be_bytes());
+
+    let err = client
+        .restore("list", None, dump_payload(body).into(), false)
+        .await
+        .unwrap_err();
+    assert_eq!("ERR unsupported value type `list`", err.to_string());
+
+    // The server is still up
+    client.set("hello", "world".into()).await.unwrap();
+    assert!(client.get("lzf").await.unwrap().is_none());
+}
+
+/// Appends the RDB version and the CRC64 checksum to a serialized value, as
+/// `DUMP` does.
+fn dump_payload(mut payload: Vec<u8>) -> Vec<u8> {
+    payload.extend_from_slice(&11u16.to_le_bytes());
+
+    // Reflected Jones polynomial, as in Redis
+    let mut crc = 0u64;
+    for byte in &payload {
+        crc ^= *byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x95ac_9329_ac4b_c9b5
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    payload.extend_from_slice(&crc.to_le_bytes());
+    payload
+}
+
+/// test that writes and expirations are published as keyspace and keyevent
+/// notifications when enabled in the server configuration
+#[tokio::test]
+async fn keyspace_notifications() {
+    tokio::time::pause();
+
+    let config = server::Config {
+        notify_keyspace_events: "KEA".parse().unwrap(),
+        ..Default::default()
+    };
+    let (addr, _) = start_server_with_config(config).await;
+
+    let client = Client::connect(addr).await.unwrap();
+    let mut subscriber = client
+        .subscribe(vec![
+            "__keyspace@0__:hello".into(),
+            "__keyevent@0__:set".into(),
+            "__keyevent@0__:del".into(),
+            "__keyevent@0__:expire".into(),
+            "__keyevent@0__:expired".into(),
+        ])
+        .await
+        .unwrap();
+
+    let mut client = Client::connect(addr).await.unwrap();
+
+    client.set("hello", "world".into()).await.unwrap();
+    assert_eq!(
+        next_messages(&mut subscriber, 2).await,
+        [
+            ("__keyevent@0__:set".to_string(), "hello".to_string()),
+            ("__keyspace@0__:hello".to_string(), "set".to_string()),
+        ]
+    );
+
+    client.del(&["hello".into()]).await.unwrap();
+    assert_eq!(
+        next_messages(&mut subscriber, 2).await,
+        [
+            ("__keyevent@0__:del".to_string(), "hello".to_string()),
+            ("__keyspace@0__:hello".to_string(), "del".to_string()),
+        ]
+    );
+
+    client
+        .set_expires("hello", "world".into(), Duration::from_millis(100))
+        .await
+        .unwrap();
+    assert_eq!(
+        next_messages(&mut subscriber, 4).await,
+        [
+            ("__keyevent@0__:expire".to_string(), "hello".to_string()),
+            ("__keyevent@0__:set".to_string(), "hello".to_string()),
+            ("__keyspace@0__:hello".to_string(), "expire".to_string()),
+            ("__keyspace@0__:hello".to_string(), "set".to_string()),
+        ]
+    );
+
+    // The expired event is published by the background purge task
+    tokio::time::advance(Duration::from_millis(100)).await;
+    assert_eq!(
+        next_messages(&mut subscriber, 2).await,
+        [
+            ("__keyevent@0__:expired".to_string(), "hello".to_string()),
+            ("__keyspace@0__:hello".to_string(), "expired".to_string()),
+        ]
+    );
+}
+
+/// Receives `n` messages and returns them as sorted `(channel, content)` pairs.
+///
+/// Messages on different channels may be received in any order, so the
+/// notifications generated by a command are compared as a sorted list.
+/// test that a replica loads the dataset of its primary, receives the
+/// following writes and rejects writes from clients
+#[tokio::test]
+async fn replication() {
+    let (primary_addr, _) = start_server().await;
+    let (replica_addr, _) = start_server().await;
+
+    let mut primary = Client::connect(primary_addr).await.unwrap();
+    let mut replica = Client::connect(replica_addr).await.unwrap();
+
+    primary.set("hello", "world".into()).await.unwrap();
+    replica.set("stale", "value".into()).await.unwrap();
+
+    replica
+        .replicaof("127.0.0.1", primary_addr.port())
+        .await
+        .unwrap();
+
+    wait_for(&mut replica, "hello", "world").await;
+    assert!(replica.get("stale").await.unwrap().is_none());
+
+    primary.set("foo", "bar".into()).await.unwrap();
+    wait_for(&mut replica, "foo", "bar").await;
+
+    let err = replica.set("foo", "baz".into()).await.unwrap_err();
+    assert_eq!(
+        "READONLY You can't write against a read only replica.",
+        err.to_string()
+    );
+
+    replica.replicaof_no_one().await.unwrap();
+    replica.set("foo", "baz".into()).await.unwrap();
+
+    primary.set("hello", "again".into()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(b"world", &replica.get("hello").await.unwrap().unwrap()[..]);
+}
+
+async fn next_messages(subscriber: &mut Subscriber, n: usize) -> Vec<(String, String)> {
+    let mut messages = vec![];
+
+    for _ in 0..n {
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        let content = String::from_utf8(message.content.to_vec()).unwrap();
+        messages.push((message.channel, content));
+    }
+
+    messages.sort();
+    messages
+}
+
+/// test that `wait` returns once the replicas applied the writes, or the
+/// number of replicas reached when the timeout elapses
+#[tokio::test]
+async fn wait_for_replicas() {
+    let (primary_addr, _) = start_server().await;
+    let mut primary = Client::connect(primary_addr).await.unwrap();
+
+    assert_eq!(0, primary.wait(0, None).await.unwrap());
+
+    let mut replicas = vec![];
+
+    for _ in 0..2 {
+        let (addr, _) = start_server().await;
+        let mut replica = Client::connect(addr).await.unwrap();
+        replica
+            .replicaof("127.0.0.1", primary_addr.port())
+            .await
+            .unwrap();
+        replicas.push(replica);
+    }
+
+    primary.set("hello", "world".into()).await.unwrap();
+
+    let timeout = Some(Duration::from_secs(5));
+    assert_eq!(2, primary.wait(2, timeout).await.unwrap());
+
+    for replica in &mut replicas {
+        let value = replica.get("hello").await.unwrap().unwrap();
+        assert_eq!(b"world", &value[..]);
+    }
+
+    let timeout = Some(Duration::from_millis(100));
+    assert_eq!(2, primary.wait(3, timeout).await.unwrap());
+
+    let err = replicas[0].wait(1, timeout).await.unwrap_err();
+    assert_eq!(
+        "ERR WAIT cannot be used with replica instances",
+        err.to_string()
+    );
+}
+
+/// `MEMORY USAGE` and `MEMORY STATS` report the estimated memory used by the
+/// keys, including the tracking of their expiration.
+#[tokio::test]
+async fn memory_usage_and_stats() {
+    let (addr, _) = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    client.set("foo", "bar".into()).await.unwrap();
+    client
+        .set_expires("baz", "qux".into(), Duration::from_secs(60))
+        .await
+        .unwrap();
+
+    let foo = client.memory_usage("foo").await.unwrap().unwrap();
+    let baz = client.memory_usage("baz").await.unwrap().unwrap();
+    assert!(baz > foo);
+    assert_eq!(None, client.memory_usage("missing").await.unwrap());
+
+    let stats = client.memory_stats().await.unwrap();
+    let stat = |name: &str| stats.iter().find(|(n, _)| n == name).unwrap().1;
+
+    assert_eq!(foo + baz, stat("dataset.bytes"));
+    assert_eq!(2, stat("keys.count"));
+    assert_eq!(1, stat("expires.count"));
+    assert_eq!(0, stat("pubsub.channels"));
+
+    // Keys sampled by `mini-redis-cli --bigkeys`.
+    let key = client.random_key().await.unwrap().unwrap();
+    assert!(key == "foo" || key == "baz");
+    assert_eq!("string", client.key_type(&key).await.unwrap());
+    assert_eq!(3, client.strlen(&key).await.unwrap());
+
+    assert_eq!("none", client.key_type("missing").await.unwrap());
+    assert_eq!(0, client.strlen("missing").await.unwrap());
+}
+
+/// `MEMORY DOCTOR` reports the missing memory limit and the evictions.
+#[tokio::test]
+async fn memory_doctor() {
+    let (addr, _) = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    let report = client.memory_doctor().await.unwrap();
+    assert!(report.contains("empty"), "{}", report);
+
+    client.set("foo", "bar".into()).await.unwrap();
+
+    let report = client.memory_doctor().await.unwrap();
+    assert!(report.contains("No memory limit"), "{}", report);
+
+    let (addr, _) = start_server_with_config(server::Config {
+        maxmemory: 200,
+        maxmemory_policy: MaxmemoryPolicy::AllKeysLru,
+        ..Default::default()
+    })
+    .await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    for key in ["a", "b", "c", "d"] {
+        client.set(key, "value".into()).await.unwrap();
+    }
+
+    let report = client.memory_doctor().await.unwrap();
+    assert!(report.contains("Evictions: 2 keys"), "{}", report);
+}
+
+/// Expired keys that are never accessed again are deleted by the active
+/// expiration cycles, and counted in `INFO stats`.
+#[tokio::test]
+async fn active_expiration() {
+    tokio::time::pause();
+
+    let (addr, _) = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    for i in 0..100 {
+        client
+            .set_expires(
+                &format!("key:{}", i),
+                "value".into(),
+                Duration::from_secs(3600),
+            )
+            .await
+            .unwrap();
+    }
+
+    client.set("persistent", "value".into()).await.unwrap();
+
+    let stats = client.info(Some("stats")).await.unwrap();
+    assert!(stats.contains("expired_keys:0\r\n"), "{}", stats);
+
+    // The paused clock may advance to the next cycle while waiting for the
+    // replies, the keys are set with a long time to live. Let a few cycles run
+    // once the keys expired.
+    tokio::time::advance(Duration::from_secs(3600)).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let stats = client.info(Some("stats")).await.unwrap();
+    assert!(stats.contains("expired_keys:100\r\n"), "{}", stats);
+    assert!(!stats.contains("expired_stale_perc:0.00\r\n"), "{}", stats);
+
+    let memory = client.memory_stats().await.unwrap();
+    let stat = |name: &str| memory.iter().find(|(n, _)| n == name).unwrap().1;
+
+    assert_eq!(1, stat("keys.count"));
+    assert_eq!(0, stat("expires.count"));
+}
+
+/// The protocol runs over any transport: clients connected to the server
+/// through in-memory pipes share the same keyspace and channels
+#[tokio::test]
+async fn duplex_transport() {
+    let mut streams = start_duplex_server(3);
+    let mut client = Client::from_stream(streams.pop().unwrap());
+
+    client.set("hello", "world".into()).await.unwrap();
+    let value = client.get("hello").await.unwrap().unwrap();
+    assert_eq!(b"world", &value[..]);
+
+    let subscriber = Client::from_stream(streams.pop().unwrap());
+    let mut subscriber = subscriber.subscribe(vec!["hello".into()]).await.unwrap();
+
+    let mut publisher = Client::from_stream(streams.pop().unwrap());
+    publisher.publish("hello", "world".into()).await.unwrap();
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!("hello", &message.channel);
+    assert_eq!(b"world", &message.content[..]);
+
+    // The connections keep being served once all the streams were accepted.
+    let value = publisher.get("hello").await.unwrap().unwrap();
+    assert_eq!(b"world", &value[..]);
+}
+
+/// A server listening on both TCP and a Unix domain socket serves the same
+/// keyspace on each
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket() {
+    let path = std::env::temp_dir().join(format!("mini-redis-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = tcp.local_addr().unwrap();
+    let mut listeners = server::Listeners::from(tcp);
+    listeners.unix = Some(UnixListener::bind(&path).unwrap());
+
+    tokio::spawn(server::run(listeners, std::future::pending::<()>()));
+
+    let mut unix_client = Client::connect_unix(&path).await.unwrap();
+    unix_client.set("hello", "world".into()).await.unwrap();
+
+    let mut tcp_client = Client::connect(addr).await.unwrap();
+    let value = tcp_client.get("hello").await.unwrap().unwrap();
+    assert_eq!(b"world", &value[..]);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Waits until `key` holds `value`.
+async fn wait_for(client: &mut Client, key: &str, value: &str) {
+    for _ in 0..100 {
+        if client.get(key).await.unwrap().as_deref() == Some(value.as_bytes()) {
+            return;
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("`{}` was never set to `{}`", key, value);
+}
+
 async fn start_server() -> (SocketAddr, JoinHandle<()>) {
+    start_server_with_config(server::Config::default()).await
+}
+
+async fn start_server_with_config(config: server::Config) -> (SocketAddr, JoinHandle<()>) {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
 
-    let handle = tokio::spawn(async move { server::run(listener, tokio::signal::ctrl_c()).await });
+    let handle = tokio::spawn(async move {
+        server::run_with_config(listener, config, tokio::signal::ctrl_c()).await
+    });
 
     (addr, handle)
 }
+
+/// Starts a server handling `n` in-memory connections and returns the client
+/// end of each of them.
+fn start_duplex_server(n: usize) -> Vec<DuplexStream> {
+    let (clients, servers): (Vec<_>, Vec<_>) = (0..n).map(|_| duplex(64 * 1024)).unzip();
+
+    tokio::spawn(server::run_with_incoming(
+        tokio_stream::iter(servers),
+        server::Config::default(),
+        std::future::pending::<()>(),
+    ));
+
+    clients
+}
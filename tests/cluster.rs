@@ -0,0 +1,433 @@
+use mini_redis::{server, ClusterClient};
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const NODE_A: &str = "e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca";
+const NODE_B: &str = "67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1";
+
+#[tokio::test]
+async fn cluster_support_disabled() {
+    let addr = start_server(server::Config::default()).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(&request(&["CLUSTER", "KEYSLOT", "foo"]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut stream,
+        b"-ERR This instance has cluster support disabled\r\n",
+    )
+    .await;
+
+    // Keys are not routed outside of cluster mode.
+    stream.write_all(&request(&["GET", "foo"])).await.unwrap();
+    read_reply(&mut stream, b"$-1\r\n").await;
+}
+
+#[tokio::test]
+async fn slot_assignment() {
+    let addr = start_server(cluster_config(None)).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(&request(&["CLUSTER", "KEYSLOT", "foo"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b":12182\r\n").await;
+
+    stream
+        .write_all(&request(&["CLUSTER", "KEYSLOT", "{foo}.bar"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b":12182\r\n").await;
+
+    // A new node does not serve any slot.
+    stream.write_all(&request(&["GET", "foo"])).await.unwrap();
+    read_reply(&mut stream, b"-CLUSTERDOWN Hash slot not served\r\n").await;
+
+    stream
+        .write_all(&request(&["CLUSTER", "ADDSLOTS", "12182", "5061"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    stream
+        .write_all(&request(&["CLUSTER", "ADDSLOTS", "12182"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"-ERR Slot 12182 is already busy\r\n").await;
+
+    stream
+        .write_all(&request(&["SET", "foo", "1"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    // Both keys are served by this node, but they are in different slots.
+    stream
+        .write_all(&request(&["DEL", "foo", "bar"]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut stream,
+        b"-CROSSSLOT Keys in request don't hash to the same slot\r\n",
+    )
+    .await;
+
+    stream
+        .write_all(&request(&["DEL", "foo", "{foo}.bar"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b":1\r\n").await;
+
+    stream
+        .write_all(&request(&["CLUSTER", "MYID"]))
+        .await
+        .unwrap();
+    let id = node_id(&mut stream).await;
+
+    stream
+        .write_all(&request(&["CLUSTER", "SLOTS"]))
+        .await
+        .unwrap();
+    let node = format!(
+        "*3\r\n$9\r\n127.0.0.1\r\n:{}\r\n$40\r\n{}\r\n",
+        addr.port(),
+        id
+    );
+    let expected = format!(
+        "*2\r\n*3\r\n:5061\r\n:5061\r\n{}*3\r\n:12182\r\n:12182\r\n{}",
+        node, node
+    );
+    read_reply(&mut stream, expected.as_bytes()).await;
+}
+
+#[tokio::test]
+async fn config_file() {
+    let path = temp_path("config_file");
+    std::fs::write(
+        &path,
+        format!(
+            "{} 127.0.0.1:30001@40001 myself,master - 0 0 0 connected 0-8191\n\
+             {} 127.0.0.1:30002@40002 master - 0 0 0 connected 8192-16383\n",
+            NODE_A, NODE_B
+        ),
+    )
+    .unwrap();
+
+    let addr = start_server(cluster_config(Some(path))).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(&request(&["SET", "bar", "1"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    stream.write_all(&request(&["GET", "foo"])).await.unwrap();
+    read_reply(&mut stream, b"-MOVED 12182 127.0.0.1:30002\r\n").await;
+
+    stream
+        .write_all(&request(&["CLUSTER", "MYID"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, format!("$40\r\n{}\r\n", NODE_A).as_bytes()).await;
+
+    let nodes = format!(
+        "{} 127.0.0.1:30001@40001 myself,master - 0 0 0 connected 0-8191\n\
+         {} 127.0.0.1:30002@40002 master - 0 0 0 connected 8192-16383\n",
+        NODE_A, NODE_B
+    );
+    stream
+        .write_all(&request(&["CLUSTER", "NODES"]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut stream,
+        format!("${}\r\n{}\r\n", nodes.len(), nodes).as_bytes(),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn ask_redirection() {
+    let addr_a = start_server(cluster_config(None)).await;
+    let addr_b = start_server(cluster_config(None)).await;
+
+    let mut a = TcpStream::connect(addr_a).await.unwrap();
+    let mut b = TcpStream::connect(addr_b).await.unwrap();
+
+    a.write_all(&request(&["CLUSTER", "ADDSLOTS", "12182"]))
+        .await
+        .unwrap();
+    read_reply(&mut a, b"+OK\r\n").await;
+
+    a.write_all(&request(&["SET", "foo", "1"])).await.unwrap();
+    read_reply(&mut a, b"+OK\r\n").await;
+
+    // The nodes learn about each other.
+    let port_a = addr_a.port().to_string();
+    let port_b = addr_b.port().to_string();
+
+    a.write_all(&request(&["CLUSTER", "MEET", "127.0.0.1", &port_b]))
+        .await
+        .unwrap();
+    read_reply(&mut a, b"+OK\r\n").await;
+
+    b.write_all(&request(&["CLUSTER", "MEET", "127.0.0.1", &port_a]))
+        .await
+        .unwrap();
+    read_reply(&mut b, b"+OK\r\n").await;
+
+    let moved_to_a = format!("-MOVED 12182 127.0.0.1:{}\r\n", port_a);
+    b.write_all(&request(&["GET", "foo"])).await.unwrap();
+    read_reply(&mut b, moved_to_a.as_bytes()).await;
+
+    // Migrate the slot from A to B.
+    a.write_all(&request(&["CLUSTER", "MYID"])).await.unwrap();
+    let id_a = node_id(&mut a).await;
+    b.write_all(&request(&["CLUSTER", "MYID"])).await.unwrap();
+    let id_b = node_id(&mut b).await;
+
+    a.write_all(&request(&[
+        "CLUSTER",
+        "SETSLOT",
+        "12182",
+        "MIGRATING",
+        &id_b,
+    ]))
+    .await
+    .unwrap();
+    read_reply(&mut a, b"+OK\r\n").await;
+
+    b.write_all(&request(&[
+        "CLUSTER",
+        "SETSLOT",
+        "12182",
+        "IMPORTING",
+        &id_a,
+    ]))
+    .await
+    .unwrap();
+    read_reply(&mut b, b"+OK\r\n").await;
+
+    // Keys that were not migrated yet are still served by A.
+    a.write_all(&request(&["GET", "foo"])).await.unwrap();
+    read_reply(&mut a, b"$1\r\n1\r\n").await;
+
+    let ask_b = format!("-ASK 12182 127.0.0.1:{}\r\n", port_b);
+    a.write_all(&request(&["GET", "{foo}.bar"])).await.unwrap();
+    read_reply(&mut a, ask_b.as_bytes()).await;
+
+    // B only serves the slot after `ASKING`, for a single command.
+    b.write_all(&request(&["GET", "{foo}.bar"])).await.unwrap();
+    read_reply(&mut b, moved_to_a.as_bytes()).await;
+
+    b.write_all(&request(&["ASKING"])).await.unwrap();
+    read_reply(&mut b, b"+OK\r\n").await;
+    b.write_all(&request(&["SET", "{foo}.bar", "2"]))
+        .await
+        .unwrap();
+    read_reply(&mut b, b"+OK\r\n").await;
+
+    b.write_all(&request(&["GET", "{foo}.bar"])).await.unwrap();
+    read_reply(&mut b, moved_to_a.as_bytes()).await;
+
+    // Assign the slot to B once the migration is done.
+    for stream in [&mut a, &mut b] {
+        stream
+            .write_all(&request(&["CLUSTER", "SETSLOT", "12182", "NODE", &id_b]))
+            .await
+            .unwrap();
+        read_reply(stream, b"+OK\r\n").await;
+    }
+
+    let moved_to_b = format!("-MOVED 12182 127.0.0.1:{}\r\n", port_b);
+    a.write_all(&request(&["GET", "{foo}.bar"])).await.unwrap();
+    read_reply(&mut a, moved_to_b.as_bytes()).await;
+
+    b.write_all(&request(&["GET", "{foo}.bar"])).await.unwrap();
+    read_reply(&mut b, b"$1\r\n2\r\n").await;
+}
+
+#[tokio::test]
+async fn cluster_client_routes_commands() {
+    let (addr_a, addr_b) = start_cluster().await;
+
+    let seed = format!("127.0.0.1:{}", addr_a.port());
+    let mut client = ClusterClient::connect(&seed).await.unwrap();
+
+    // `foo` is in slot 12182, served by B, and `bar` in slot 5061, served by A.
+    client.set("foo", "1".into()).await.unwrap();
+    client.set("bar", "2".into()).await.unwrap();
+
+    assert_eq!(Some("1".into()), client.get("foo").await.unwrap());
+    assert_eq!(Some("2".into()), client.get("bar").await.unwrap());
+
+    let mut b = TcpStream::connect(addr_b).await.unwrap();
+    b.write_all(&request(&["GET", "foo"])).await.unwrap();
+    read_reply(&mut b, b"$1\r\n1\r\n").await;
+
+    // The keys are removed by one command per slot.
+    let removed = client
+        .del(&["foo".into(), "bar".into(), "baz".into()])
+        .await
+        .unwrap();
+    assert_eq!(2, removed);
+}
+
+#[tokio::test]
+async fn cluster_client_follows_redirections() {
+    let (addr_a, addr_b) = start_cluster().await;
+
+    let seed = format!("127.0.0.1:{}", addr_a.port());
+    let mut client = ClusterClient::connect(&seed).await.unwrap();
+
+    client.set("foo", "1".into()).await.unwrap();
+
+    // Start migrating slot 12182 from B to A.
+    let id_a = myid(addr_a).await;
+    let id_b = myid(addr_b).await;
+    cluster(addr_b, &["SETSLOT", "12182", "MIGRATING", &id_a]).await;
+    cluster(addr_a, &["SETSLOT", "12182", "IMPORTING", &id_b]).await;
+
+    // New keys are created on A after an `-ASK` redirection.
+    client.set("{foo}.new", "2".into()).await.unwrap();
+
+    let mut a = TcpStream::connect(addr_a).await.unwrap();
+    a.write_all(&request(&["ASKING"])).await.unwrap();
+    read_reply(&mut a, b"+OK\r\n").await;
+    a.write_all(&request(&["GET", "{foo}.new"])).await.unwrap();
+    read_reply(&mut a, b"$1\r\n2\r\n").await;
+
+    assert_eq!(Some("1".into()), client.get("foo").await.unwrap());
+    assert_eq!(Some("2".into()), client.get("{foo}.new").await.unwrap());
+
+    // Move the remaining key and assign the slot to A.
+    let mut b = TcpStream::connect(addr_b).await.unwrap();
+    let port_a = addr_a.port().to_string();
+    b.write_all(&request(&[
+        "MIGRATE",
+        "127.0.0.1",
+        &port_a,
+        "foo",
+        "0",
+        "1000",
+    ]))
+    .await
+    .unwrap();
+    read_reply(&mut b, b"+OK\r\n").await;
+
+    cluster(addr_a, &["SETSLOT", "12182", "NODE", &id_a]).await;
+    cluster(addr_b, &["SETSLOT", "12182", "NODE", &id_a]).await;
+
+    // The client follows the `-MOVED` redirection and updates its slot table.
+    assert_eq!(Some("1".into()), client.get("foo").await.unwrap());
+    assert_eq!(1, client.del(&["{foo}.new".into()]).await.unwrap());
+}
+
+/// Starts a cluster of two nodes: A serves slots 0 to 8191 and B serves
+/// slots 8192 to 16383.
+async fn start_cluster() -> (SocketAddr, SocketAddr) {
+    let addr_a = start_server(cluster_config(None)).await;
+    let addr_b = start_server(cluster_config(None)).await;
+
+    let slots_a: Vec<String> = (0..8192).map(|slot| slot.to_string()).collect();
+    let slots_b: Vec<String> = (8192..16384).map(|slot| slot.to_string()).collect();
+
+    for (addr, slots) in [(addr_a, slots_a), (addr_b, slots_b)] {
+        let mut args = vec!["ADDSLOTS"];
+        args.extend(slots.iter().map(String::as_str));
+        cluster(addr, &args).await;
+    }
+
+    let port_a = addr_a.port().to_string();
+    let port_b = addr_b.port().to_string();
+    cluster(addr_a, &["MEET", "127.0.0.1", &port_b]).await;
+    cluster(addr_b, &["MEET", "127.0.0.1", &port_a]).await;
+
+    (addr_a, addr_b)
+}
+
+/// Sends a `CLUSTER` subcommand replying `OK` to the node at `addr`.
+async fn cluster(addr: SocketAddr, args: &[&str]) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    let mut request_args = vec!["CLUSTER"];
+    request_args.extend_from_slice(args);
+    stream.write_all(&request(&request_args)).await.unwrap();
+
+    read_reply(&mut stream, b"+OK\r\n").await;
+}
+
+/// Returns the ID of the node at `addr`.
+async fn myid(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(&request(&["CLUSTER", "MYID"]))
+        .await
+        .unwrap();
+    node_id(&mut stream).await
+}
+
+/// Returns a configuration enabling cluster mode.
+fn cluster_config(cluster_config_file: Option<PathBuf>) -> server::Config {
+    server::Config {
+        cluster_enabled: true,
+        cluster_config_file,
+        ..Default::default()
+    }
+}
+
+/// Encodes a command as an array of bulk strings.
+fn request(args: &[&str]) -> Vec<u8> {
+    let mut request = format!("*{}\r\n", args.len());
+
+    for arg in args {
+        request.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+
+    request.into_bytes()
+}
+
+/// Reads a reply of the same length as `expected` and compares them.
+async fn read_reply(stream: &mut TcpStream, expected: &[u8]) {
+    let mut response = vec![0; expected.len()];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(expected),
+        String::from_utf8_lossy(&response)
+    );
+}
+
+/// Reads the `CLUSTER MYID` reply.
+async fn node_id(stream: &mut TcpStream) -> String {
+    let mut response = [0; 47];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$40\r\n", &response[..5]);
+
+    String::from_utf8(response[5..45].to_vec()).unwrap()
+}
+
+/// Returns a path in the temporary directory, unique to the test.
+fn temp_path(test: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("mini-redis-{}-{}.conf", std::process::id(), test));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+async fn start_server(config: server::Config) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        server::run_with_config(listener, config, tokio::signal::ctrl_c()).await
+    });
+
+    addr
+}
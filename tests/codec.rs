@@ -0,0 +1,96 @@
+use mini_redis::codec::RespCodec;
+use mini_redis::{server, Frame};
+
+use bytes::{Bytes, BytesMut};
+use futures::SinkExt;
+use tokio::io::{duplex, AsyncWriteExt};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Decoder, Encoder, Framed, FramedRead};
+
+/// Frames are encoded and decoded back, including arrays nested in arrays
+#[test]
+fn encode_decode_nested_arrays() {
+    let frame = Frame::Array(vec![
+        Frame::Array(vec![Frame::Integer(1), Frame::Null]),
+        Frame::Array(vec![Frame::Array(vec![Frame::Simple("deep".into())])]),
+        Frame::Bulk(Bytes::from("value")),
+    ]);
+
+    let mut codec = RespCodec::new();
+    let mut buf = BytesMut::new();
+    codec.encode(&frame, &mut buf).unwrap();
+    assert_eq!(
+        &b"*3\r\n*2\r\n:1\r\n$-1\r\n*1\r\n*1\r\n+deep\r\n$5\r\nvalue\r\n"[..],
+        &buf[..]
+    );
+
+    // A partial frame is kept by the decoder until the rest is received.
+    let mut partial = buf.split_to(10);
+    assert!(codec.decode(&mut partial).unwrap().is_none());
+    partial.unsplit(buf);
+
+    let decoded = codec.decode(&mut partial).unwrap().unwrap();
+    assert_eq!(format!("{:?}", frame), format!("{:?}", decoded));
+    assert!(partial.is_empty());
+}
+
+/// Requests sent through a `Framed` transport are answered by the server
+#[tokio::test]
+async fn framed_requests() {
+    let (client, server) = duplex(64 * 1024);
+
+    tokio::spawn(server::run_with_incoming(
+        tokio_stream::once(server),
+        server::Config::default(),
+        std::future::pending::<()>(),
+    ));
+
+    let mut framed = Framed::new(client, RespCodec::new());
+
+    framed
+        .send(request(&["SET", "hello", "world"]))
+        .await
+        .unwrap();
+    framed.send(request(&["GET", "hello"])).await.unwrap();
+
+    let reply = framed.next().await.unwrap().unwrap();
+    assert_eq!(reply, "OK");
+
+    match framed.next().await.unwrap().unwrap() {
+        Frame::Bulk(value) => assert_eq!(b"world", &value[..]),
+        frame => panic!("unexpected frame {:?}", frame),
+    }
+}
+
+/// A stream closed in the middle of a frame is an error, a stream closed
+/// between frames ends cleanly
+#[tokio::test]
+async fn truncated_stream() {
+    let (mut tx, rx) = duplex(1024);
+    tx.write_all(b"+OK\r\n*2\r\n$3\r\nGET\r\n").await.unwrap();
+    drop(tx);
+
+    let mut frames = FramedRead::new(rx, RespCodec::new());
+
+    let frame = frames.next().await.unwrap().unwrap();
+    assert_eq!(frame, "OK");
+
+    let err = frames.next().await.unwrap().unwrap_err();
+    assert_eq!("connection reset by peer", err.to_string());
+
+    let (mut tx, rx) = duplex(1024);
+    tx.write_all(b":1\r\n").await.unwrap();
+    drop(tx);
+
+    let mut frames = FramedRead::new(rx, RespCodec::new());
+    assert!(matches!(frames.next().await, Some(Ok(Frame::Integer(1)))));
+    assert!(frames.next().await.is_none());
+}
+
+fn request(args: &[&str]) -> Frame {
+    Frame::Array(
+        args.iter()
+            .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+            .collect(),
+    )
+}
@@ -0,0 +1,304 @@
+use mini_redis::frame::{Frame, Limits, Parser};
+
+use std::io::Cursor;
+
+use bytes::BytesMut;
+
+/// A frame using every frame type, with nested arrays.
+const ENCODED: &[u8] = b"*6\r\n+OK\r\n-ERR oops\r\n:42\r\n$5\r\nhello\r\n$-1\r\n\
+                         *2\r\n*0\r\n$0\r\n\r\n";
+
+fn assert_encoded_frame(frame: &Frame) {
+    let parts = match frame {
+        Frame::Array(parts) => parts,
+        frame => panic!("unexpected frame {:?}", frame),
+    };
+
+    assert_eq!(6, parts.len());
+    assert!(matches!(&parts[0], Frame::Simple(s) if s == "OK"));
+    assert!(matches!(&parts[1], Frame::Error(s) if s == "ERR oops"));
+    assert!(matches!(parts[2], Frame::Integer(42)));
+    assert!(matches!(&parts[3], Frame::Bulk(b) if b == "hello"));
+    assert!(matches!(parts[4], Frame::Null));
+
+    match &parts[5] {
+        Frame::Array(nested) => {
+            assert!(matches!(&nested[0], Frame::Array(empty) if empty.is_empty()));
+            assert!(matches!(&nested[1], Frame::Bulk(b) if b.is_empty()));
+        }
+        frame => panic!("unexpected frame {:?}", frame),
+    }
+}
+
+#[test]
+fn parse_complete_frame() {
+    let mut parser = Parser::new();
+    let mut buf = BytesMut::from(ENCODED);
+
+    let frame = parser.parse(&mut buf).unwrap().unwrap();
+    assert_encoded_frame(&frame);
+    assert!(buf.is_empty());
+    assert!(!parser.is_partial());
+}
+
+// The frame is parsed the same way when it is received one byte at a time,
+// and nothing is returned before it is complete
+#[test]
+fn parse_frame_byte_by_byte() {
+    let mut parser = Parser::new();
+    let mut buf = BytesMut::new();
+
+    for (i, byte) in ENCODED.iter().enumerate() {
+        buf.extend_from_slice(&[*byte]);
+
+        match parser.parse(&mut buf).unwrap() {
+            Some(frame) => {
+                assert_eq!(ENCODED.len() - 1, i);
+                assert_encoded_frame(&frame);
+            }
+            None => assert!(i < ENCODED.len() - 1),
+        }
+    }
+
+    assert!(!parser.is_partial());
+}
+
+// A large bulk string is split off the buffer as it is received, only its
+// missing part is waited for
+#[test]
+fn parse_large_bulk_in_chunks() {
+    let value = vec![b'x'; 100 * 1024];
+
+    let mut encoded = format!("*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n${}\r\n", value.len()).into_bytes();
+    encoded.extend_from_slice(&value);
+    encoded.extend_from_slice(b"\r\n");
+
+    let mut parser = Parser::new();
+    let mut buf = BytesMut::new();
+    let mut chunks = encoded.chunks(4096);
+
+    let frame = loop {
+        buf.extend_from_slice(chunks.next().unwrap());
+
+        if let Some(frame) = parser.parse(&mut buf).unwrap() {
+            break frame;
+        }
+
+        assert!(parser.is_partial());
+    };
+
+    assert!(chunks.next().is_none());
+
+    match frame {
+        Frame::Array(parts) => {
+            assert!(matches!(&parts[0], Frame::Bulk(b) if b == "SET"));
+            assert!(matches!(&parts[1], Frame::Bulk(b) if b == "key"));
+            assert!(matches!(&parts[2], Frame::Bulk(b) if b[..] == value[..]));
+        }
+        frame => panic!("unexpected frame {:?}", frame),
+    }
+}
+
+// Pipelined frames are returned one at a time, leaving the next ones in the
+// buffer
+#[test]
+fn parse_pipelined_frames() {
+    let mut parser = Parser::new();
+    let mut buf = BytesMut::from(&b"+first\r\n:2\r\n$5\r\nthi"[..]);
+
+    let frame = parser.parse(&mut buf).unwrap().unwrap();
+    assert!(matches!(frame, Frame::Simple(s) if s == "first"));
+
+    let frame = parser.parse(&mut buf).unwrap().unwrap();
+    assert!(matches!(frame, Frame::Integer(2)));
+
+    assert!(parser.parse(&mut buf).unwrap().is_none());
+    assert!(parser.is_partial());
+
+    buf.extend_from_slice(b"rd\r\n");
+    let frame = parser.parse(&mut buf).unwrap().unwrap();
+    assert!(matches!(frame, Frame::Bulk(b) if b == "third"));
+}
+
+#[test]
+fn parse_invalid_frame() {
+    let mut parser = Parser::new();
+    let mut buf = BytesMut::from(&b"!oops\r\n"[..]);
+
+    let err = parser.parse(&mut buf).unwrap_err();
+    assert_eq!(
+        "protocol error; invalid frame type byte `33`",
+        err.to_string()
+    );
+
+    let mut parser = Parser::new();
+    let mut buf = BytesMut::from(&b"*1\r\n$abc\r\n"[..]);
+
+    let err = parser.parse(&mut buf).unwrap_err();
+    assert_eq!("protocol error; invalid frame format", err.to_string());
+}
+
+// Encoding a frame with nested arrays gives back the same bytes it was parsed
+// from
+#[test]
+fn encode_nested_frame() {
+    let frame = Parser::new()
+        .parse(&mut BytesMut::from(ENCODED))
+        .unwrap()
+        .unwrap();
+
+    let mut buf = BytesMut::new();
+    frame.encode(&mut buf);
+    assert_eq!(ENCODED, &buf[..]);
+}
+
+const LIMITS: Limits = Limits {
+    max_bulk_len: 5,
+    max_multibulk_len: 3,
+    max_depth: 2,
+    max_line_len: 8,
+};
+
+/// Parses `src` with `LIMITS`, using both `Parser` and `Frame::check`, and
+/// returns the error of each.
+fn limit_errors(src: &[u8]) -> (String, String) {
+    let mut parser = Parser::with_limits(LIMITS);
+    let err = parser.parse(&mut BytesMut::from(src)).unwrap_err();
+
+    let check_err = Frame::check_with_limits(&mut Cursor::new(src), &LIMITS).unwrap_err();
+
+    (err.to_string(), check_err.to_string())
+}
+
+// A bulk string longer than the limit is rejected as soon as its header is
+// received
+#[test]
+fn bulk_length_limit() {
+    let mut parser = Parser::with_limits(LIMITS);
+    let frame = parser
+        .parse(&mut BytesMut::from(&b"$5\r\nhello\r\n"[..]))
+        .unwrap();
+    assert!(matches!(frame, Some(Frame::Bulk(b)) if b == "hello"));
+
+    let err = "protocol error; invalid bulk length";
+    assert_eq!((err.into(), err.into()), limit_errors(b"$6\r\n"));
+}
+
+// Without a limit, a length that overflows once the trailing \r\n is added
+// is still rejected
+#[test]
+fn bulk_length_overflow() {
+    let limits = Limits {
+        max_bulk_len: usize::MAX,
+        max_multibulk_len: usize::MAX,
+        ..Limits::default()
+    };
+    let src = format!("${}\r\n", usize::MAX);
+    let err = "protocol error; invalid bulk length";
+
+    let mut parser = Parser::with_limits(limits);
+    let res = parser.parse(&mut BytesMut::from(src.as_bytes()));
+    assert_eq!(err, res.unwrap_err().to_string());
+
+    let res = Frame::check_with_limits(&mut Cursor::new(src.as_bytes()), &limits);
+    assert_eq!(err, res.unwrap_err().to_string());
+}
+
+// The length declared by a bulk string header is not preallocated in full
+// before the data arrives
+#[test]
+fn large_bulk_header_not_preallocated() {
+    let mut parser = Parser::new();
+    let mut buf = BytesMut::from(&b"$536870000\r\n"[..]);
+
+    assert!(parser.parse(&mut buf).unwrap().is_none());
+    assert!(buf.capacity() <= 2 * 1024 * 1024, "{}", buf.capacity());
+}
+
+#[test]
+fn multibulk_length_limit() {
+    let mut parser = Parser::with_limits(LIMITS);
+    let frame = parser
+        .parse(&mut BytesMut::from(&b"*3\r\n:1\r\n:2\r\n:3\r\n"[..]))
+        .unwrap();
+    assert!(matches!(frame, Some(Frame::Array(parts)) if parts.len() == 3));
+
+    let err = "protocol error; invalid multibulk length";
+    assert_eq!((err.into(), err.into()), limit_errors(b"*4\r\n"));
+
+    // With the default limits, the length must fit in an `i32`, as in Redis.
+    let mut parser = Parser::new();
+    let res = parser.parse(&mut BytesMut::from(&b"*4294967295\r\n"[..]));
+    assert_eq!(err, res.unwrap_err().to_string());
+}
+
+// A line longer than the limit is rejected, even before its end is received
+#[test]
+fn line_length_limit() {
+    let mut parser = Parser::with_limits(LIMITS);
+    let frame = parser
+        .parse(&mut BytesMut::from(&b"+12345678\r\n"[..]))
+        .unwrap();
+    assert!(matches!(frame, Some(Frame::Simple(s)) if s == "12345678"));
+
+    let err = "protocol error; line too long";
+    assert_eq!((err.into(), err.into()), limit_errors(b"-123456789\r\n"));
+
+    // The line is received a few bytes at a time, the limit applies to all
+    // of it.
+    let mut parser = Parser::with_limits(LIMITS);
+    let mut buf = BytesMut::new();
+
+    for chunk in [&b"+1234"[..], b"5678", b"\r"] {
+        buf.extend_from_slice(chunk);
+        assert!(parser.parse(&mut buf).unwrap().is_none());
+    }
+
+    buf.extend_from_slice(b"\n");
+    let frame = parser.parse(&mut buf).unwrap();
+    assert!(matches!(frame, Some(Frame::Simple(s)) if s == "12345678"));
+
+    let mut buf = BytesMut::from(&b"*12345"[..]);
+    assert!(parser.parse(&mut buf).unwrap().is_none());
+    buf.extend_from_slice(b"12345");
+    assert_eq!(err, parser.parse(&mut buf).unwrap_err().to_string());
+}
+
+#[test]
+fn nesting_depth_limit() {
+    let mut parser = Parser::with_limits(LIMITS);
+    let frame = parser
+        .parse(&mut BytesMut::from(&b"*1\r\n*1\r\n:1\r\n"[..]))
+        .unwrap();
+    assert!(matches!(frame, Some(Frame::Array(_))));
+
+    let err = "protocol error; arrays nested too deeply";
+    assert_eq!(
+        (err.into(), err.into()),
+        limit_errors(b"*1\r\n*1\r\n*1\r\n")
+    );
+
+    // Empty arrays count too.
+    assert_eq!(
+        (err.into(), err.into()),
+        limit_errors(b"*1\r\n*1\r\n*0\r\n")
+    );
+
+    // With the default limits, deep nesting is rejected before it can
+    // exhaust the stack.
+    let deep = b"*1\r\n".repeat(100_000);
+    let mut parser = Parser::new();
+    assert_eq!(
+        err,
+        parser
+            .parse(&mut BytesMut::from(&deep[..]))
+            .unwrap_err()
+            .to_string()
+    );
+    assert_eq!(
+        err,
+        Frame::check(&mut Cursor::new(&deep[..]))
+            .unwrap_err()
+            .to_string()
+    );
+}
@@ -0,0 +1,234 @@
+use mini_redis::rdb::{self, Value};
+use mini_redis::{server, Client};
+
+use bytes::Bytes;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::net::TcpListener;
+
+/// A file using the compact encodings written by recent Redis versions, with
+/// the checksum disabled.
+fn rdb_file() -> Vec<u8> {
+    let mut rdb = b"REDIS0011".to_vec();
+
+    // Auxiliary fields, database selection and size hints are skipped
+    rdb.push(0xfa);
+    string(&mut rdb, b"redis-ver");
+    string(&mut rdb, b"7.2.0");
+    rdb.extend_from_slice(&[0xfe, 0x00, 0xfb, 0x09, 0x01]);
+
+    entry(&mut rdb, 0, b"str", b"value");
+
+    // Expires in 2100
+    rdb.push(0xfc);
+    rdb.extend_from_slice(&4_102_444_800_000u64.to_le_bytes());
+    entry(&mut rdb, 0, b"ttl", b"soon");
+
+    // Already expired
+    rdb.push(0xfc);
+    rdb.extend_from_slice(&1000u64.to_le_bytes());
+    entry(&mut rdb, 0, b"old", b"gone");
+
+    // Integer encoded string
+    rdb.push(0);
+    string(&mut rdb, b"int");
+    rdb.extend_from_slice(&[0xc0, (-5i8) as u8]);
+
+    // LZF compressed string: the literal `a` followed by a back reference
+    // copying it 9 times.
+    rdb.push(0);
+    string(&mut rdb, b"lzf");
+    rdb.extend_from_slice(&[0xc3, 5, 10, 0x00, b'a', 0xe0, 0x00, 0x00]);
+
+    // Quicklist of a single listpack node
+    rdb.push(18);
+    string(&mut rdb, b"list");
+    rdb.extend_from_slice(&[1, 2]);
+    string(&mut rdb, &listpack(&[&[0x81, b'x', 0x02], &[0x07, 0x01]]));
+
+    // Intset
+    rdb.push(11);
+    string(&mut rdb, b"set");
+    let mut intset = vec![];
+    intset.extend_from_slice(&2u32.to_le_bytes());
+    intset.extend_from_slice(&2u32.to_le_bytes());
+    intset.extend_from_slice(&1i16.to_le_bytes());
+    intset.extend_from_slice(&300i16.to_le_bytes());
+    string(&mut rdb, &intset);
+
+    // Ziplist encoded hash
+    rdb.push(13);
+    string(&mut rdb, b"hash");
+    let mut ziplist = vec![17, 0, 0, 0, 13, 0, 0, 0, 2, 0];
+    ziplist.extend_from_slice(&[0x00, 0x01, b'f', 0x03, 0x01, b'v', 0xff]);
+    string(&mut rdb, &ziplist);
+
+    // Listpack encoded sorted set
+    rdb.push(17);
+    string(&mut rdb, b"zset");
+    string(
+        &mut rdb,
+        &listpack(&[&[0x81, b'm', 0x02], &[0x83, b'1', b'.', b'5', 0x04]]),
+    );
+
+    // Another database
+    rdb.extend_from_slice(&[0xfe, 0x01]);
+    entry(&mut rdb, 0, b"other", b"db1");
+
+    rdb.push(0xff);
+    rdb.extend_from_slice(&[0; 8]);
+    rdb
+}
+
+fn entry(rdb: &mut Vec<u8>, value_type: u8, key: &[u8], value: &[u8]) {
+    rdb.push(value_type);
+    string(rdb, key);
+    string(rdb, value);
+}
+
+/// Appends a string shorter than 64 bytes.
+fn string(rdb: &mut Vec<u8>, value: &[u8]) {
+    rdb.push(value.len() as u8);
+    rdb.extend_from_slice(value);
+}
+
+/// Builds a listpack from encoded entries, including their back length.
+fn listpack(entries: &[&[u8]]) -> Vec<u8> {
+    let len: usize = entries.iter().map(|entry| entry.len()).sum::<usize>() + 7;
+
+    let mut listpack = vec![];
+    listpack.extend_from_slice(&(len as u32).to_le_bytes());
+    listpack.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for entry in entries {
+        listpack.extend_from_slice(entry);
+    }
+    listpack.push(0xff);
+    listpack
+}
+
+#[test]
+fn parse_encodings() {
+    let entries = rdb::parse(&rdb_file()).unwrap();
+
+    let values: Vec<(&[u8], &Value)> = entries
+        .iter()
+        .map(|entry| (&entry.key[..], &entry.value))
+        .collect();
+
+    let b = |s: &'static str| Bytes::from(s);
+
+    assert_eq!(
+        vec![
+            (&b"str"[..], &Value::String(b("value"))),
+            (b"ttl", &Value::String(b("soon"))),
+            (b"old", &Value::String(b("gone"))),
+            (b"int", &Value::String(b("-5"))),
+            (b"lzf", &Value::String(b("aaaaaaaaaa"))),
+            (b"list", &Value::List(vec![b("x"), b("7")])),
+            (b"set", &Value::Set(vec![b("1"), b("300")])),
+            (b"hash", &Value::Hash(vec![(b("f"), b("v"))])),
+            (b"zset", &Value::SortedSet(vec![(b("m"), 1.5)])),
+            (b"other", &Value::String(b("db1"))),
+        ],
+        values
+    );
+
+    assert_eq!(
+        Some(UNIX_EPOCH + Duration::from_millis(4_102_444_800_000)),
+        entries[1].expires_at
+    );
+    assert_eq!(None, entries[0].expires_at);
+    assert_eq!(1, entries[9].db);
+}
+
+#[test]
+fn verify_checksum() {
+    let mut rdb = b"REDIS0011".to_vec();
+    rdb.extend_from_slice(&[0xfe, 0x00]);
+    entry(&mut rdb, 0, b"a", b"b");
+    rdb.push(0xff);
+
+    let checksum = [55, 133, 153, 224, 119, 242, 13, 183];
+
+    let mut valid = rdb.clone();
+    valid.extend_from_slice(&checksum);
+    assert_eq!(1, rdb::parse(&valid).unwrap().len());
+
+    let mut corrupted = rdb;
+    corrupted.extend_from_slice(&checksum);
+    corrupted[13] = b'c';
+    assert!(rdb::parse(&corrupted).is_err());
+}
+
+#[test]
+fn reject_unknown_type() {
+    let mut rdb = b"REDIS0011".to_vec();
+    entry(&mut rdb, 100, b"a", b"b");
+    rdb.push(0xff);
+    rdb.extend_from_slice(&[0; 8]);
+
+    let err = rdb::parse(&rdb).unwrap_err();
+    assert_eq!("unsupported RDB value type 100", err.to_string());
+}
+
+/// The decompressed length of an LZF string is not trusted beyond what the
+/// compressed data can expand to.
+#[test]
+fn reject_oversized_lzf_length() {
+    let mut rdb = b"REDIS0011".to_vec();
+    rdb.push(0);
+    string(&mut rdb, b"lzf");
+    // 2 compressed bytes declaring 2^62 decompressed bytes
+    rdb.extend_from_slice(&[0xc3, 2, 0x81]);
+    rdb.extend_from_slice(&(1u64 << 62).to_be_bytes());
+    rdb.extend_from_slice(&[0x00, b'a']);
+    rdb.push(0xff);
+    rdb.extend_from_slice(&[0; 8]);
+
+    let err = rdb::parse(&rdb).unwrap_err();
+    assert_eq!("invalid LZF compressed string length", err.to_string());
+}
+
+/// String keys of the first database are imported when the server starts,
+/// other keys are skipped.
+#[tokio::test]
+async fn import_on_startup() {
+    let path = std::env::temp_dir().join(format!("mini-redis-{}-import.rdb", std::process::id()));
+    std::fs::write(&path, rdb_file()).unwrap();
+
+    let addr = start_server(path.clone()).await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    for (key, value) in [
+        ("str", "value"),
+        ("ttl", "soon"),
+        ("int", "-5"),
+        ("lzf", "aaaaaaaaaa"),
+    ] {
+        let actual = client.get(key).await.unwrap().unwrap();
+        assert_eq!(value.as_bytes(), &actual[..]);
+    }
+
+    for key in ["old", "list", "set", "hash", "zset", "other"] {
+        assert!(client.get(key).await.unwrap().is_none());
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
+
+async fn start_server(import_rdb: PathBuf) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let config = server::Config {
+        import_rdb: Some(import_rdb),
+        ..Default::default()
+    };
+
+    tokio::spawn(async move {
+        server::run_with_config(listener, config, tokio::signal::ctrl_c()).await
+    });
+
+    addr
+}
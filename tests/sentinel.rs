@@ -0,0 +1,201 @@
+use mini_redis::clients::Client;
+use mini_redis::sentinel::{self, Monitor, SWITCH_MASTER};
+use mini_redis::server;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::time;
+
+/// A primary lists the replicas that announced their address.
+#[tokio::test]
+async fn info_replication() {
+    let (primary_addr, _stop) = start_server(server::Config::default()).await;
+    let (replica_addr, _stop_replica) = start_server(server::Config::default()).await;
+
+    let mut primary = Client::connect(primary_addr).await.unwrap();
+    let mut replica = Client::connect(replica_addr).await.unwrap();
+
+    let info = primary.info(Some("replication")).await.unwrap();
+    assert!(info.contains("role:master\r\nconnected_slaves:0\r\n"));
+
+    replica
+        .replicaof("127.0.0.1", primary_addr.port())
+        .await
+        .unwrap();
+
+    let listed = format!("slave0:ip=127.0.0.1,port={},", replica_addr.port());
+    wait_for_info(&mut primary, &listed).await;
+
+    let info = replica.info(None).await.unwrap();
+    assert!(info.contains("role:slave\r\n"));
+    assert!(info.contains(&format!("master_port:{}\r\n", primary_addr.port())));
+    wait_for_info(&mut replica, "master_link_status:up").await;
+
+    // Unknown sections are empty.
+    assert_eq!("", primary.info(Some("keyspace")).await.unwrap());
+}
+
+/// When the primary is down, the sentinels promote a replica and publish the
+/// new address. Clients resolve the new primary through the sentinels.
+#[tokio::test]
+async fn failover() {
+    let (primary_addr, stop_primary) = start_server(server::Config::default()).await;
+
+    let mut replicas = vec![];
+    let mut replica_addrs = vec![];
+
+    for _ in 0..2 {
+        let (addr, stop) = start_server(server::Config::default()).await;
+        let mut replica = Client::connect(addr).await.unwrap();
+        replica
+            .replicaof("127.0.0.1", primary_addr.port())
+            .await
+            .unwrap();
+
+        replicas.push((replica, stop));
+        replica_addrs.push(addr);
+    }
+
+    let mut primary = Client::connect(primary_addr).await.unwrap();
+    primary.set("hello", "world".into()).await.unwrap();
+    assert_eq!(
+        2,
+        primary.wait(2, Some(Duration::from_secs(5))).await.unwrap()
+    );
+    wait_for_info(&mut primary, "slave1:").await;
+
+    // Three sentinels, two of which must agree that the primary is down.
+    let mut listeners = vec![];
+    for _ in 0..3 {
+        listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+    }
+
+    let sentinel_addrs: Vec<SocketAddr> = listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap())
+        .collect();
+
+    for listener in listeners {
+        let me = listener.local_addr().unwrap();
+
+        let config = server::Config {
+            sentinel: Some(sentinel::Config {
+                monitor: Monitor {
+                    name: "mymaster".to_string(),
+                    host: "127.0.0.1".to_string(),
+                    port: primary_addr.port(),
+                    quorum: 2,
+                },
+                down_after: Duration::from_millis(200),
+                sentinels: sentinel_addrs
+                    .iter()
+                    .filter(|addr| **addr != me)
+                    .map(ToString::to_string)
+                    .collect(),
+            }),
+            ..Default::default()
+        };
+
+        tokio::spawn(server::run_with_config(
+            listener,
+            config,
+            std::future::pending::<()>(),
+        ));
+    }
+
+    let client = Client::connect_via_sentinel(&sentinel_addrs, "mymaster")
+        .await
+        .unwrap();
+    drop(client);
+
+    let mut subscriber = Client::connect(sentinel_addrs[0])
+        .await
+        .unwrap()
+        .subscribe(vec![SWITCH_MASTER.to_string()])
+        .await
+        .unwrap();
+
+    // Give the sentinels time to discover the replicas.
+    time::sleep(Duration::from_millis(300)).await;
+
+    stop_primary.send(()).unwrap();
+
+    let message = time::timeout(Duration::from_secs(10), subscriber.next_message())
+        .await
+        .expect("no failover")
+        .unwrap()
+        .unwrap();
+
+    let content = String::from_utf8(message.content.to_vec()).unwrap();
+    let args: Vec<&str> = content.split(' ').collect();
+    let old_port = primary_addr.port().to_string();
+    assert_eq!(
+        ["mymaster", "127.0.0.1", &old_port[..], "127.0.0.1"],
+        args[..4]
+    );
+
+    let promoted: u16 = args[4].parse().unwrap();
+    let other = replica_addrs
+        .iter()
+        .position(|addr| addr.port() != promoted)
+        .unwrap();
+    assert_ne!(promoted, replica_addrs[other].port());
+
+    // The promoted replica accepts writes, which are replicated to the other
+    // replica.
+    let mut client = connect_to_primary(&sentinel_addrs).await;
+    assert_eq!(b"world", &client.get("hello").await.unwrap().unwrap()[..]);
+    client.set("foo", "bar".into()).await.unwrap();
+
+    let other = &mut replicas[other].0;
+    for _ in 0..500 {
+        if other.get("foo").await.unwrap().as_deref() == Some(&b"bar"[..]) {
+            return;
+        }
+
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("the other replica does not replicate the new primary");
+}
+
+/// Connects to the primary through the sentinels, once they completed the
+/// failover.
+async fn connect_to_primary(sentinels: &[SocketAddr]) -> Client {
+    for _ in 0..500 {
+        if let Ok(client) = Client::connect_via_sentinel(sentinels, "mymaster").await {
+            return client;
+        }
+
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("the sentinels never resolved the new primary");
+}
+
+async fn wait_for_info(client: &mut Client, expected: &str) {
+    for _ in 0..300 {
+        if client.info(None).await.unwrap().contains(expected) {
+            return;
+        }
+
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("`INFO` never contained `{}`", expected);
+}
+
+/// Starts a server, which shuts down when the returned sender is used or
+/// dropped.
+async fn start_server(config: server::Config) -> (SocketAddr, oneshot::Sender<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = oneshot::channel();
+
+    tokio::spawn(async move { server::run_with_config(listener, config, rx).await });
+
+    (addr, tx)
+}
@@ -1,6 +1,8 @@
-use mini_redis::server;
+use mini_redis::frame::Limits;
+use mini_redis::{server, MaxmemoryPolicy};
 
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::time::{self, Duration};
@@ -119,6 +121,224 @@ async fn key_value_timeout() {
     assert_eq!(b"$-1\r\n", &response);
 }
 
+/// Expired keys that were not deleted yet by the background task are treated
+/// as missing by every command, and deleted on access.
+#[tokio::test]
+async fn expired_keys_are_absent() {
+    tokio::time::pause();
+
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    // Keep the expired keys around, as if the background task lagged behind
+    stream
+        .write_all(&request(&["DEBUG", "SET-ACTIVE-EXPIRE", "0"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    let keys = [
+        "get", "strlen", "type", "dump", "memory", "expire", "del", "set",
+    ];
+
+    for key in keys {
+        stream
+            .write_all(&request(&["SET", key, "value", "EX", "100"]))
+            .await
+            .unwrap();
+        read_reply(&mut stream, b"+OK\r\n").await;
+    }
+
+    // The paused clock also moves forward while the test waits for replies,
+    // keep a margin before the deadline.
+    time::advance(Duration::from_secs(99)).await;
+
+    stream.write_all(&request(&["GET", "get"])).await.unwrap();
+    read_reply(&mut stream, b"$5\r\nvalue\r\n").await;
+
+    // The keys expired, but were not deleted yet
+    time::advance(Duration::from_secs(1)).await;
+
+    stream.write_all(&request(&["GET", "get"])).await.unwrap();
+    read_reply(&mut stream, b"$-1\r\n").await;
+
+    stream
+        .write_all(&request(&["STRLEN", "strlen"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b":0\r\n").await;
+
+    stream.write_all(&request(&["TYPE", "type"])).await.unwrap();
+    read_reply(&mut stream, b"+none\r\n").await;
+
+    stream.write_all(&request(&["DUMP", "dump"])).await.unwrap();
+    read_reply(&mut stream, b"$-1\r\n").await;
+
+    stream
+        .write_all(&request(&["MEMORY", "USAGE", "memory"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"$-1\r\n").await;
+
+    // An expired key can not be given a new expiration
+    stream
+        .write_all(&request(&["EXPIRE", "expire", "100"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b":0\r\n").await;
+
+    stream
+        .write_all(&request(&["GET", "expire"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"$-1\r\n").await;
+
+    stream.write_all(&request(&["DEL", "del"])).await.unwrap();
+    read_reply(&mut stream, b":0\r\n").await;
+
+    // Overwriting an expired key creates a new key without expiration
+    stream
+        .write_all(&request(&["SET", "set", "other"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    stream.write_all(&request(&["GET", "set"])).await.unwrap();
+    read_reply(&mut stream, b"$5\r\nother\r\n").await;
+
+    // The only key left is the one written after the expiration
+    for _ in 0..10 {
+        stream.write_all(&request(&["RANDOMKEY"])).await.unwrap();
+        read_reply(&mut stream, b"$3\r\nset\r\n").await;
+    }
+
+    stream.write_all(&request(&["DEL", "set"])).await.unwrap();
+    read_reply(&mut stream, b":1\r\n").await;
+
+    stream.write_all(&request(&["RANDOMKEY"])).await.unwrap();
+    read_reply(&mut stream, b"$-1\r\n").await;
+
+    // Every key was deleted on access, not by the active expiration
+    stream
+        .write_all(&request(&["INFO", "stats"]))
+        .await
+        .unwrap();
+
+    let mut response = vec![0; 256];
+    let n = stream.read(&mut response).await.unwrap();
+    let stats = String::from_utf8_lossy(&response[..n]);
+    assert!(stats.contains("expired_keys:8\r\n"), "{}", stats);
+}
+
+/// Expire times beyond what the clock can represent are rejected, and leave
+/// the key and its shard usable.
+#[tokio::test]
+async fn invalid_expire_time() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(&request(&["SET", "key", "value"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    stream
+        .write_all(&request(&["EXPIRE", "key", "18446744073709551615"]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut stream,
+        b"-ERR invalid expire time in 'expire' command\r\n",
+    )
+    .await;
+
+    stream
+        .write_all(&request(&["EXPIREAT", "key", "18446744073709551615"]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut stream,
+        b"-ERR invalid expire time in 'expire' command\r\n",
+    )
+    .await;
+
+    stream
+        .write_all(&request(&[
+            "SET",
+            "key",
+            "other",
+            "EX",
+            "18446744073709551615",
+        ]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut stream,
+        b"-ERR invalid expire time in 'set' command\r\n",
+    )
+    .await;
+
+    // The key is unchanged and still has no expiration
+    stream.write_all(&request(&["GET", "key"])).await.unwrap();
+    read_reply(&mut stream, b"$5\r\nvalue\r\n").await;
+
+    stream
+        .write_all(&request(&["EXPIRE", "key", "100"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b":1\r\n").await;
+}
+
+/// Overwriting an expired key deletes it as expired first, publishing the
+/// `expired` event.
+#[tokio::test]
+async fn overwrite_expired_key_notifies() {
+    tokio::time::pause();
+
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(&request(&["CONFIG", "SET", "notify-keyspace-events", "Ex"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    let mut sub = TcpStream::connect(addr).await.unwrap();
+    sub.write_all(&request(&["SUBSCRIBE", "__keyevent@0__:expired"]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut sub,
+        b"*3\r\n$9\r\nsubscribe\r\n$22\r\n__keyevent@0__:expired\r\n:1\r\n",
+    )
+    .await;
+
+    stream
+        .write_all(&request(&["SET", "hello", "world", "EX", "100"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    time::advance(Duration::from_secs(100)).await;
+
+    stream
+        .write_all(&request(&["SET", "hello", "again"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    read_reply(
+        &mut sub,
+        b"*3\r\n$7\r\nmessage\r\n$22\r\n__keyevent@0__:expired\r\n$5\r\nhello\r\n",
+    )
+    .await;
+
+    stream.write_all(&request(&["GET", "hello"])).await.unwrap();
+    read_reply(&mut stream, b"$5\r\nagain\r\n").await;
+}
+
 #[tokio::test]
 async fn pub_sub() {
     let addr = start_server().await;
@@ -331,6 +551,164 @@ async fn manage_subscription() {
     );
 }
 
+// Pipelined requests sent in a single write are all answered, in order
+#[tokio::test]
+async fn pipelined_requests() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    let mut pipeline = vec![];
+    let mut expected = vec![];
+
+    for i in 0..1000 {
+        let key = format!("key:{}", i);
+        let value = format!("value:{}", i);
+
+        pipeline.extend(request(&["SET", &key, &value]));
+        pipeline.extend(request(&["GET", &key]));
+        expected.extend(b"+OK\r\n");
+        expected.extend(format!("${}\r\n{}\r\n", value.len(), value).as_bytes());
+    }
+
+    stream.write_all(&pipeline).await.unwrap();
+    read_reply(&mut stream, &expected).await;
+}
+
+// The responses to complete requests are written before the server waits for
+// the rest of a partially received request
+#[tokio::test]
+async fn pipelined_requests_partial_frame() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    let get = request(&["GET", "hello"]);
+    let (head, tail) = get.split_at(8);
+
+    let mut pipeline = request(&["SET", "hello", "world"]);
+    pipeline.extend(head);
+
+    stream.write_all(&pipeline).await.unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    stream.write_all(tail).await.unwrap();
+    read_reply(&mut stream, b"$5\r\nworld\r\n").await;
+}
+
+// Requests pipelined before an invalid one are still answered before the
+// connection is closed
+#[tokio::test]
+async fn pipelined_requests_before_invalid_request() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    let mut pipeline = request(&["PING"]);
+    pipeline.extend(request(&["GET"]));
+
+    stream.write_all(&pipeline).await.unwrap();
+    read_reply(&mut stream, b"+PONG\r\n").await;
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await.unwrap();
+    assert!(response.is_empty());
+}
+
+// A large value received over many reads is stored and returned intact
+#[tokio::test]
+async fn large_value_in_chunks() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    let value: String = (0..100 * 1024)
+        .map(|i| char::from(b'a' + (i % 26) as u8))
+        .collect();
+
+    for chunk in request(&["SET", "big", &value]).chunks(1000) {
+        stream.write_all(chunk).await.unwrap();
+        tokio::task::yield_now().await;
+    }
+
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    stream.write_all(&request(&["GET", "big"])).await.unwrap();
+    read_reply(
+        &mut stream,
+        format!("${}\r\n{}\r\n", value.len(), value).as_bytes(),
+    )
+    .await;
+}
+
+// Frames exceeding the protocol limits are answered with a protocol error and
+// the connection is closed, without waiting for the declared data
+#[tokio::test]
+async fn protocol_limits() {
+    let addr = start_server_with_config(server::Config {
+        proto_limits: Limits {
+            max_bulk_len: 16,
+            max_multibulk_len: 4,
+            max_depth: 2,
+            max_line_len: 16,
+        },
+        ..Default::default()
+    })
+    .await;
+
+    let cases: &[(&[u8], &[u8])] = &[
+        (
+            b"*2\r\n$3\r\nGET\r\n$17\r\n",
+            b"-ERR protocol error; invalid bulk length\r\n",
+        ),
+        (
+            b"*5\r\n",
+            b"-ERR protocol error; invalid multibulk length\r\n",
+        ),
+        (
+            b"*1\r\n*1\r\n*1\r\n",
+            b"-ERR protocol error; arrays nested too deeply\r\n",
+        ),
+        (
+            b"*2\r\n$3\r\nGET\r\n$0123456789abcdefg",
+            b"-ERR protocol error; line too long\r\n",
+        ),
+    ];
+
+    for (request, reply) in cases {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(reply),
+            String::from_utf8_lossy(&response)
+        );
+    }
+
+    // Frames within the limits are still accepted.
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(&request(&["SET", "hello", "sixteen bytes..."]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+}
+
+// The default limits reject an array declaring more elements than Redis
+// accepts
+#[tokio::test]
+async fn huge_multibulk_length() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream.write_all(b"*4294967295\r\n").await.unwrap();
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await.unwrap();
+    assert_eq!(
+        "-ERR protocol error; invalid multibulk length\r\n",
+        String::from_utf8_lossy(&response)
+    );
+}
+
 // In this case we test that server Responds with an Error message if a client
 // sends an unknown command
 #[tokio::test]
@@ -397,11 +775,1405 @@ async fn send_error_get_set_after_subscribe() {
     assert_eq!(b"-ERR unknown command \'get\'\r\n", &response);
 }
 
-async fn start_server() -> SocketAddr {
-    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let addr = listener.local_addr().unwrap();
+// PING, RESET and QUIT are accepted while in the subscribed state
+#[tokio::test]
+async fn ping_reset_quit_after_subscribe() {
+    let addr = start_server().await;
 
-    tokio::spawn(async move { server::run(listener, tokio::signal::ctrl_c()).await });
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(b"*2\r\n$9\r\nsubscribe\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 34];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*3\r\n$9\r\nsubscribe\r\n$5\r\nhello\r\n:1\r\n"[..],
+        &response[..]
+    );
+
+    // PING without a message replies with the `pong` array form
+    stream.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+
+    let mut response = [0; 20];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(&b"*2\r\n$4\r\npong\r\n$0\r\n\r\n"[..], &response[..]);
+
+    // PING with a message echoes it
+    stream
+        .write_all(b"*2\r\n$4\r\nPING\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 25];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(&b"*2\r\n$4\r\npong\r\n$5\r\nworld\r\n"[..], &response[..]);
+
+    // RESET leaves the subscribed state
+    stream.write_all(b"*1\r\n$5\r\nRESET\r\n").await.unwrap();
+
+    let mut response = [0; 8];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+RESET\r\n", &response);
+
+    // Regular commands are accepted again
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$-1\r\n", &response);
+
+    // Subscribe again and QUIT, the server closes the connection
+    stream
+        .write_all(b"*2\r\n$9\r\nsubscribe\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 34];
+    stream.read_exact(&mut response).await.unwrap();
+
+    // The request pipelined after QUIT is not executed
+    let mut requests = b"*1\r\n$4\r\nQUIT\r\n".to_vec();
+    requests.extend_from_slice(&request(&["SET", "hello", "world"]));
+    stream.write_all(&requests).await.unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    assert_eq!(0, stream.read(&mut response).await.unwrap());
+
+    let mut other = TcpStream::connect(addr).await.unwrap();
+    other.write_all(&request(&["GET", "hello"])).await.unwrap();
+    read_reply(&mut other, b"$-1\r\n").await;
+}
+
+// Messages published on channels matching a pattern are delivered as
+// `pmessage` frames
+#[tokio::test]
+async fn pattern_subscription() {
+    let addr = start_server().await;
+
+    let mut publisher = TcpStream::connect(addr).await.unwrap();
+
+    let mut sub = TcpStream::connect(addr).await.unwrap();
+    sub.write_all(b"*2\r\n$10\r\nPSUBSCRIBE\r\n$3\r\nh*o\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 34];
+    sub.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*3\r\n$10\r\npsubscribe\r\n$3\r\nh*o\r\n:1\r\n"[..],
+        &response[..]
+    );
+
+    // Channel subscriptions are counted together with pattern subscriptions
+    sub.write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 34];
+    sub.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*3\r\n$9\r\nsubscribe\r\n$5\r\nhello\r\n:2\r\n"[..],
+        &response[..]
+    );
+
+    // `hero` only matches the pattern
+    publisher
+        .write_all(b"*3\r\n$7\r\nPUBLISH\r\n$4\r\nhero\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 4];
+    publisher.read_exact(&mut response).await.unwrap();
+    assert_eq!(b":1\r\n", &response);
+
+    let mut response = [0; 48];
+    sub.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*4\r\n$8\r\npmessage\r\n$3\r\nh*o\r\n$4\r\nhero\r\n$5\r\nworld\r\n"[..],
+        &response[..]
+    );
+
+    // `help` matches nothing
+    publisher
+        .write_all(b"*3\r\n$7\r\nPUBLISH\r\n$4\r\nhelp\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 4];
+    publisher.read_exact(&mut response).await.unwrap();
+    assert_eq!(b":0\r\n", &response);
+
+    // Remove the pattern subscription
+    sub.write_all(b"*1\r\n$12\r\nPUNSUBSCRIBE\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 36];
+    sub.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*3\r\n$12\r\npunsubscribe\r\n$3\r\nh*o\r\n:1\r\n"[..],
+        &response[..]
+    );
+
+    // `hello` is only received through the channel subscription
+    publisher
+        .write_all(b"*3\r\n$7\r\nPUBLISH\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 4];
+    publisher.read_exact(&mut response).await.unwrap();
+    assert_eq!(b":1\r\n", &response);
+
+    let mut response = [0; 39];
+    sub.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*3\r\n$7\r\nmessage\r\n$5\r\nhello\r\n$5\r\nworld\r\n"[..],
+        &response[..]
+    );
+}
+
+/// Matching a pattern with many `*` against a long channel name does not
+/// take exponential time, publishing stays fast.
+#[tokio::test]
+async fn pathological_pattern() {
+    let addr = start_server().await;
+
+    let pattern = "*a".repeat(16) + "*b";
+
+    let mut sub = TcpStream::connect(addr).await.unwrap();
+    sub.write_all(&request(&["PSUBSCRIBE", &pattern]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut sub,
+        format!("*3\r\n$10\r\npsubscribe\r\n$34\r\n{}\r\n:1\r\n", pattern).as_bytes(),
+    )
+    .await;
+
+    let mut publisher = TcpStream::connect(addr).await.unwrap();
+
+    let channel = "a".repeat(64);
+    publisher
+        .write_all(&request(&["PUBLISH", &channel, "x"]))
+        .await
+        .unwrap();
+
+    time::timeout(
+        Duration::from_secs(5),
+        read_reply(&mut publisher, b":0\r\n"),
+    )
+    .await
+    .unwrap();
+
+    // The pattern still matches when it should
+    let channel = "a".repeat(64) + "b";
+    publisher
+        .write_all(&request(&["PUBLISH", &channel, "x"]))
+        .await
+        .unwrap();
+
+    time::timeout(
+        Duration::from_secs(5),
+        read_reply(&mut publisher, b":1\r\n"),
+    )
+    .await
+    .unwrap();
+}
+
+/// Patterns left by their subscribers are removed from the registry, they are
+/// not matched or counted anymore.
+#[tokio::test]
+async fn pattern_removed_after_disconnect() {
+    let addr = start_server().await;
+
+    let mut sub = TcpStream::connect(addr).await.unwrap();
+    sub.write_all(&request(&["PSUBSCRIBE", "h*o"]))
+        .await
+        .unwrap();
+    read_reply(&mut sub, b"*3\r\n$10\r\npsubscribe\r\n$3\r\nh*o\r\n:1\r\n").await;
+    drop(sub);
+
+    let mut publisher = TcpStream::connect(addr).await.unwrap();
+
+    // Wait for the server to notice the disconnection
+    loop {
+        publisher
+            .write_all(&request(&["PUBLISH", "hello", "world"]))
+            .await
+            .unwrap();
+
+        let mut response = [0; 4];
+        publisher.read_exact(&mut response).await.unwrap();
+        if &response == b":0\r\n" {
+            break;
+        }
+
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    publisher
+        .write_all(&request(&["MEMORY", "STATS"]))
+        .await
+        .unwrap();
+
+    let mut response = vec![0; 1024];
+    let n = publisher.read(&mut response).await.unwrap();
+    let stats = String::from_utf8_lossy(&response[..n]);
+    assert!(stats.contains("pubsub.channels\r\n:0\r\n"), "{}", stats);
+}
+
+// Shard channels are a namespace separate from regular channels
+#[tokio::test]
+async fn sharded_subscription() {
+    let addr = start_server().await;
+
+    let mut publisher = TcpStream::connect(addr).await.unwrap();
+
+    let mut sub = TcpStream::connect(addr).await.unwrap();
+    sub.write_all(b"*2\r\n$10\r\nSSUBSCRIBE\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 36];
+    sub.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*3\r\n$10\r\nssubscribe\r\n$5\r\nhello\r\n:1\r\n"[..],
+        &response[..]
+    );
+
+    // PUBLISH does not reach shard subscribers
+    publisher
+        .write_all(b"*3\r\n$7\r\nPUBLISH\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 4];
+    publisher.read_exact(&mut response).await.unwrap();
+    assert_eq!(b":0\r\n", &response);
+
+    publisher
+        .write_all(b"*3\r\n$8\r\nSPUBLISH\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 4];
+    publisher.read_exact(&mut response).await.unwrap();
+    assert_eq!(b":1\r\n", &response);
+
+    let mut response = [0; 40];
+    sub.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*3\r\n$8\r\nsmessage\r\n$5\r\nhello\r\n$5\r\nworld\r\n"[..],
+        &response[..]
+    );
+
+    sub.write_all(b"*1\r\n$12\r\nSUNSUBSCRIBE\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 38];
+    sub.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*3\r\n$12\r\nsunsubscribe\r\n$5\r\nhello\r\n:0\r\n"[..],
+        &response[..]
+    );
+
+    publisher
+        .write_all(b"*3\r\n$8\r\nSPUBLISH\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 4];
+    publisher.read_exact(&mut response).await.unwrap();
+    assert_eq!(b":0\r\n", &response);
+}
+
+// Keyspace notifications are configured at runtime using CONFIG SET
+#[tokio::test]
+async fn config_notify_keyspace_events() {
+    let addr = start_server().await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    // Disabled by default
+    stream
+        .write_all(b"*3\r\n$6\r\nCONFIG\r\n$3\r\nGET\r\n$22\r\nnotify-keyspace-events\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 39];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*2\r\n$22\r\nnotify-keyspace-events\r\n$0\r\n\r\n"[..],
+        &response[..]
+    );
+
+    stream
+        .write_all(
+            b"*4\r\n$6\r\nCONFIG\r\n$3\r\nSET\r\n$22\r\nnotify-keyspace-events\r\n$2\r\nE$\r\n",
+        )
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    // Subscribe to keyevent notifications for `set`
+    let mut sub = TcpStream::connect(addr).await.unwrap();
+    sub.write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$18\r\n__keyevent@0__:set\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 48];
+    sub.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*3\r\n$9\r\nsubscribe\r\n$18\r\n__keyevent@0__:set\r\n:1\r\n"[..],
+        &response[..]
+    );
+
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    let mut response = [0; 53];
+    sub.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"*3\r\n$7\r\nmessage\r\n$18\r\n__keyevent@0__:set\r\n$5\r\nhello\r\n"[..],
+        &response[..]
+    );
+
+    // Invalid flags are rejected
+    stream
+        .write_all(
+            b"*4\r\n$6\r\nCONFIG\r\n$3\r\nSET\r\n$22\r\nnotify-keyspace-events\r\n$1\r\nQ\r\n",
+        )
+        .await
+        .unwrap();
+
+    let mut response = [0; 67];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"-ERR Invalid argument 'Q' for CONFIG SET 'notify-keyspace-events'\r\n"[..],
+        &response[..]
+    );
+}
+
+// The dataset saved with SAVE is loaded when the server starts, skipping keys
+// that expired in the meantime
+#[tokio::test]
+async fn save_and_load_snapshot() {
+    let path = temp_path("save_and_load_snapshot");
+    let config = server::Config {
+        dbfilename: Some(path.clone()),
+        ..Default::default()
+    };
+
+    let addr = start_server_with_config(config.clone()).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    stream
+        .write_all(b"*5\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n$2\r\nPX\r\n$3\r\n100\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    stream.write_all(b"*1\r\n$4\r\nSAVE\r\n").await.unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    // Wait for `foo` to expire
+    time::sleep(Duration::from_millis(200)).await;
+
+    let addr = start_server_with_config(config).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 11];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$5\r\nworld\r\n", &response);
+
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$-1\r\n", &response);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+// BGSAVE replies immediately, LASTSAVE is updated once the save completed
+#[tokio::test]
+async fn bgsave_and_lastsave() {
+    let path = temp_path("bgsave_and_lastsave");
+    let config = server::Config {
+        dbfilename: Some(path.clone()),
+        ..Default::default()
+    };
+
+    let addr = start_server_with_config(config).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    let started = last_save(&mut stream).await;
+
+    // LASTSAVE has a resolution of one second
+    time::sleep(Duration::from_millis(1100)).await;
+
+    stream.write_all(b"*1\r\n$6\r\nBGSAVE\r\n").await.unwrap();
+
+    let mut response = [0; 28];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+Background saving started\r\n", &response);
+
+    let mut saved = started;
+    for _ in 0..100 {
+        saved = last_save(&mut stream).await;
+
+        if saved != started {
+            break;
+        }
+
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert!(saved > started);
+    assert!(path.exists());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+// A save rule triggers a background save once enough keys changed
+#[tokio::test]
+async fn save_rule_triggers_save() {
+    let path = temp_path("save_rule_triggers_save");
+    let config = server::Config {
+        dbfilename: Some(path.clone()),
+        save: vec!["0 2".parse().unwrap()],
+        ..Default::default()
+    };
+
+    let addr = start_server_with_config(config).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    // A single change does not trigger the rule
+    time::sleep(Duration::from_millis(1100)).await;
+    assert!(!path.exists());
+
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    for _ in 0..300 {
+        if path.exists() {
+            break;
+        }
+
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert!(path.exists());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+// SAVE fails when persistence is disabled
+#[tokio::test]
+async fn save_without_dbfilename() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream.write_all(b"*1\r\n$4\r\nSAVE\r\n").await.unwrap();
+
+    let mut response = [0; 39];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"-ERR snapshot persistence is disabled\r\n"[..],
+        &response[..]
+    );
+}
+
+// Writes logged to the append-only file are replayed when the server starts
+#[tokio::test]
+async fn append_only_file_replay() {
+    let path = temp_path("append_only_file_replay");
+    let config = server::Config {
+        appendfilename: Some(path.clone()),
+        appendfsync: "always".parse().unwrap(),
+        ..Default::default()
+    };
+
+    let addr = start_server_with_config(config.clone()).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    stream
+        .write_all(b"*2\r\n$3\r\nDEL\r\n$3\r\nfoo\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 4];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b":1\r\n", &response);
+
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nbaz\r\n$3\r\nqux\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    stream
+        .write_all(b"*3\r\n$7\r\nPEXPIRE\r\n$3\r\nbaz\r\n$3\r\n100\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 4];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b":1\r\n", &response);
+
+    // The relative expiration is logged as an absolute one, so `baz` is not
+    // brought back to life by the replay.
+    time::sleep(Duration::from_millis(200)).await;
+
+    let addr = start_server_with_config(config).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 11];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$5\r\nworld\r\n", &response);
+
+    for key in ["foo", "baz"] {
+        let request = format!("*2\r\n$3\r\nGET\r\n$3\r\n{}\r\n", key);
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"$-1\r\n", &response);
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
+
+// A partially written last record is discarded when replaying
+#[tokio::test]
+async fn append_only_file_truncated_record() {
+    let path = temp_path("append_only_file_truncated_record");
+
+    let complete = b"*3\r\n$3\r\nset\r\n$5\r\nhello\r\n$5\r\nworld\r\n";
+    let truncated = b"*3\r\n$3\r\nset\r\n$3\r\nfoo\r\n$3\r\nba";
+    std::fs::write(&path, [&complete[..], &truncated[..]].concat()).unwrap();
+
+    let config = server::Config {
+        appendfilename: Some(path.clone()),
+        ..Default::default()
+    };
+
+    let addr = start_server_with_config(config).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 11];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$5\r\nworld\r\n", &response);
+
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$-1\r\n", &response);
+
+    // The file is truncated to the last complete record
+    assert_eq!(&complete[..], &std::fs::read(&path).unwrap()[..]);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+// BGREWRITEAOF compacts the append-only file, writes made afterwards are
+// still logged
+#[tokio::test]
+async fn bgrewriteaof() {
+    let path = temp_path("bgrewriteaof");
+    let config = server::Config {
+        appendfilename: Some(path.clone()),
+        ..Default::default()
+    };
+
+    let addr = start_server_with_config(config.clone()).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    for value in ["one", "two", "six"] {
+        let request = format!("*3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$3\r\n{}\r\n", value);
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+OK\r\n", &response);
+    }
+
+    stream
+        .write_all(b"*1\r\n$12\r\nBGREWRITEAOF\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 48];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        &b"+Background append only file rewriting started\r\n"[..],
+        &response[..]
+    );
+
+    let rewritten = b"*3\r\n$3\r\nset\r\n$5\r\nhello\r\n$3\r\nsix\r\n";
+
+    for _ in 0..100 {
+        if std::fs::read(&path).unwrap() == rewritten {
+            break;
+        }
+
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(&rewritten[..], &std::fs::read(&path).unwrap()[..]);
+
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 5];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    let addr = start_server_with_config(config).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 9];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$3\r\nsix\r\n", &response);
+
+    stream
+        .write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n")
+        .await
+        .unwrap();
+
+    let mut response = [0; 9];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"$3\r\nbar\r\n", &response);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Keys are transferred to another server with `MIGRATE` and only deleted
+/// locally once the target accepted them.
+#[tokio::test]
+async fn migrate() {
+    let source = start_server().await;
+    let target = start_server().await;
+    let port = target.port().to_string();
+
+    let mut src = TcpStream::connect(source).await.unwrap();
+    let mut dst = TcpStream::connect(target).await.unwrap();
+
+    for (key, value) in [("a", "1"), ("b", "2"), ("c", "3")] {
+        src.write_all(&request(&["SET", key, value])).await.unwrap();
+        read_reply(&mut src, b"+OK\r\n").await;
+    }
+
+    // Move a single key
+    src.write_all(&request(&["MIGRATE", "127.0.0.1", &port, "a", "0", "1000"]))
+        .await
+        .unwrap();
+    read_reply(&mut src, b"+OK\r\n").await;
+
+    src.write_all(&request(&["GET", "a"])).await.unwrap();
+    read_reply(&mut src, b"$-1\r\n").await;
+    dst.write_all(&request(&["GET", "a"])).await.unwrap();
+    read_reply(&mut dst, b"$1\r\n1\r\n").await;
+
+    // Copy multiple keys, missing keys are ignored
+    src.write_all(&request(&[
+        "MIGRATE",
+        "127.0.0.1",
+        &port,
+        "",
+        "0",
+        "1000",
+        "COPY",
+        "KEYS",
+        "b",
+        "c",
+        "missing",
+    ]))
+    .await
+    .unwrap();
+    read_reply(&mut src, b"+OK\r\n").await;
+
+    for (stream, key, value) in [(&mut src, "b", "2"), (&mut dst, "c", "3")] {
+        stream.write_all(&request(&["GET", key])).await.unwrap();
+        read_reply(stream, format!("$1\r\n{}\r\n", value).as_bytes()).await;
+    }
+
+    // Existing keys on the target are only replaced with REPLACE. The local
+    // key is kept when the transfer fails.
+    src.write_all(&request(&["MIGRATE", "127.0.0.1", &port, "b", "0", "1000"]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut src,
+        b"-ERR Target instance replied with error: BUSYKEY Target key name already exists.\r\n",
+    )
+    .await;
+
+    src.write_all(&request(&["GET", "b"])).await.unwrap();
+    read_reply(&mut src, b"$1\r\n2\r\n").await;
+
+    src.write_all(&request(&[
+        "MIGRATE",
+        "127.0.0.1",
+        &port,
+        "b",
+        "0",
+        "1000",
+        "REPLACE",
+    ]))
+    .await
+    .unwrap();
+    read_reply(&mut src, b"+OK\r\n").await;
+
+    src.write_all(&request(&["GET", "b"])).await.unwrap();
+    read_reply(&mut src, b"$-1\r\n").await;
+
+    src.write_all(&request(&["MIGRATE", "127.0.0.1", &port, "a", "0", "1000"]))
+        .await
+        .unwrap();
+    read_reply(&mut src, b"+NOKEY\r\n").await;
+
+    // Nothing listens on the port of a dropped listener
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+        .to_string();
+
+    src.write_all(&request(&[
+        "MIGRATE",
+        "127.0.0.1",
+        &closed,
+        "c",
+        "0",
+        "1000",
+    ]))
+    .await
+    .unwrap();
+    let error = format!(
+        "-IOERR error or timeout migrating to target instance 127.0.0.1:{}\r\n",
+        closed
+    );
+    read_reply(&mut src, error.as_bytes()).await;
+
+    src.write_all(&request(&["GET", "c"])).await.unwrap();
+    read_reply(&mut src, b"$1\r\n3\r\n").await;
+}
+
+/// A key written while it is being migrated is not deleted, the write would
+/// otherwise be lost.
+#[tokio::test]
+async fn migrate_keeps_modified_key() {
+    let source = start_server().await;
+
+    // The target delays its reply to `RESTORE` until the key was modified.
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = target.local_addr().unwrap().port().to_string();
+
+    let mut src = TcpStream::connect(source).await.unwrap();
+
+    for key in ["a", "b"] {
+        src.write_all(&request(&["SET", key, "1"])).await.unwrap();
+        read_reply(&mut src, b"+OK\r\n").await;
+    }
+
+    src.write_all(&request(&[
+        "MIGRATE",
+        "127.0.0.1",
+        &port,
+        "",
+        "0",
+        "1000",
+        "KEYS",
+        "a",
+        "b",
+    ]))
+    .await
+    .unwrap();
+
+    let (mut dst, _) = target.accept().await.unwrap();
+    let mut buf = vec![0; 1024];
+
+    for key in ["a", "b"] {
+        let n = dst.read(&mut buf).await.unwrap();
+        let restore = String::from_utf8_lossy(&buf[..n]).into_owned();
+        assert!(restore.contains(&format!("restore\r\n$1\r\n{}", key)));
+
+        // `b` is modified before it is acknowledged by the target
+        if key == "b" {
+            let mut other = TcpStream::connect(source).await.unwrap();
+            other.write_all(&request(&["SET", "b", "2"])).await.unwrap();
+            read_reply(&mut other, b"+OK\r\n").await;
+        }
+
+        dst.write_all(b"+OK\r\n").await.unwrap();
+    }
+
+    read_reply(
+        &mut src,
+        b"-ERR keys modified during the migration were not deleted: b\r\n",
+    )
+    .await;
+
+    src.write_all(&request(&["GET", "a"])).await.unwrap();
+    read_reply(&mut src, b"$-1\r\n").await;
+    src.write_all(&request(&["GET", "b"])).await.unwrap();
+    read_reply(&mut src, b"$1\r\n2\r\n").await;
+}
+
+/// A replica that reconnects with the replication ID and offset it received
+/// only receives the writes it missed.
+#[tokio::test]
+async fn psync_partial_resynchronization() {
+    let addr = start_server().await;
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    let mut replica = TcpStream::connect(addr).await.unwrap();
+
+    replica
+        .write_all(&request(&["PSYNC", "?", "-1"]))
+        .await
+        .unwrap();
+
+    let mut response = [0; 56];
+    replica.read_exact(&mut response).await.unwrap();
+    assert_eq!(&b"+FULLRESYNC "[..], &response[..12]);
+    assert_eq!(&b" 0\r\n"[..], &response[52..]);
+    let replid = std::str::from_utf8(&response[12..52]).unwrap().to_string();
+
+    // Empty snapshot
+    read_reply(&mut replica, b"$11\r\nMINIREDIS\x01\xff\r\n").await;
+
+    client
+        .write_all(&request(&["SET", "hello", "world"]))
+        .await
+        .unwrap();
+    read_reply(&mut client, b"+OK\r\n").await;
+
+    let write = b"*3\r\n$3\r\nset\r\n$5\r\nhello\r\n$5\r\nworld\r\n";
+    read_reply(&mut replica, write).await;
+    drop(replica);
+
+    client
+        .write_all(&request(&["SET", "foo", "bar"]))
+        .await
+        .unwrap();
+    read_reply(&mut client, b"+OK\r\n").await;
+
+    let offset = write.len().to_string();
+    let mut replica = TcpStream::connect(addr).await.unwrap();
+    replica
+        .write_all(&request(&["PSYNC", &replid, &offset]))
+        .await
+        .unwrap();
+
+    read_reply(&mut replica, format!("+CONTINUE {}\r\n", replid).as_bytes()).await;
+    read_reply(
+        &mut replica,
+        b"*3\r\n$3\r\nset\r\n$3\r\nfoo\r\n$3\r\nbar\r\n",
+    )
+    .await;
+
+    // An unknown replication ID requires a full resynchronization
+    let mut replica = TcpStream::connect(addr).await.unwrap();
+    replica
+        .write_all(&request(&["PSYNC", "unknown", "0"]))
+        .await
+        .unwrap();
+
+    let mut response = [0; 57];
+    replica.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        format!("+FULLRESYNC {} 66\r\n", replid).as_bytes(),
+        &response[..]
+    );
+}
+
+/// `WAIT` asks the replicas for their offset and returns once enough of them
+/// acknowledged the writes.
+#[tokio::test]
+async fn wait_for_replica_acknowledgement() {
+    let addr = start_server().await;
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    let mut replica = TcpStream::connect(addr).await.unwrap();
+
+    replica
+        .write_all(&request(&["PSYNC", "?", "-1"]))
+        .await
+        .unwrap();
+
+    let mut response = [0; 56 + 18];
+    replica.read_exact(&mut response).await.unwrap();
+
+    client
+        .write_all(&request(&["SET", "hello", "world"]))
+        .await
+        .unwrap();
+    read_reply(&mut client, b"+OK\r\n").await;
+
+    let write = b"*3\r\n$3\r\nset\r\n$5\r\nhello\r\n$5\r\nworld\r\n";
+    read_reply(&mut replica, write).await;
+
+    // No replica acknowledged the write yet
+    client
+        .write_all(&request(&["WAIT", "1", "50"]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut replica,
+        b"*3\r\n$8\r\nreplconf\r\n$6\r\nGETACK\r\n$1\r\n*\r\n",
+    )
+    .await;
+    read_reply(&mut client, b":0\r\n").await;
+
+    client
+        .write_all(&request(&["WAIT", "1", "0"]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut replica,
+        b"*3\r\n$8\r\nreplconf\r\n$6\r\nGETACK\r\n$1\r\n*\r\n",
+    )
+    .await;
+
+    let offset = (write.len() + 2 * 37).to_string();
+    replica
+        .write_all(&request(&["REPLCONF", "ACK", &offset]))
+        .await
+        .unwrap();
+    read_reply(&mut client, b":1\r\n").await;
+
+    // Already acknowledged
+    client
+        .write_all(&request(&["WAIT", "1", "0"]))
+        .await
+        .unwrap();
+    read_reply(&mut client, b":1\r\n").await;
+}
+
+/// The responses to the requests pipelined before a blocking command are sent
+/// while it blocks.
+#[tokio::test]
+async fn blocking_command_flushes_pipelined_responses() {
+    let addr = start_server().await;
+
+    // The target of `MIGRATE` accepts the connection but never replies.
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = target.local_addr().unwrap().port().to_string();
+
+    let blocking = [
+        // There are no replicas, `WAIT` blocks forever.
+        request(&["WAIT", "1", "0"]),
+        request(&["MIGRATE", "127.0.0.1", &port, "hello", "0", "5000"]),
+    ];
+
+    for cmd in blocking {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let mut pipeline = request(&["SET", "hello", "world"]);
+        pipeline.extend(cmd);
+        stream.write_all(&pipeline).await.unwrap();
+
+        time::timeout(Duration::from_secs(1), read_reply(&mut stream, b"+OK\r\n"))
+            .await
+            .expect("response to SET not sent");
+    }
+}
+
+/// With the `noeviction` policy, writes exceeding `maxmemory` are rejected
+/// until memory is freed.
+#[tokio::test]
+async fn maxmemory_noeviction() {
+    // Each key uses 100 bytes, see `set_value`.
+    let addr = start_server_with_config(server::Config {
+        maxmemory: 250,
+        ..Default::default()
+    })
+    .await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    set_value(&mut stream, "a").await;
+    set_value(&mut stream, "b").await;
+
+    stream
+        .write_all(&request(&["SET", "c", VALUE]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut stream,
+        b"-OOM command not allowed when used memory > 'maxmemory'.\r\n",
+    )
+    .await;
+
+    // Reads and writes replacing a value of the same size are allowed.
+    assert_value(&mut stream, "a", true).await;
+    set_value(&mut stream, "a").await;
+
+    stream.write_all(&request(&["DEL", "b"])).await.unwrap();
+    read_reply(&mut stream, b":1\r\n").await;
+
+    set_value(&mut stream, "c").await;
+}
+
+/// The least recently used key is evicted.
+#[tokio::test]
+async fn maxmemory_allkeys_lru() {
+    let addr = start_server_with_config(server::Config {
+        maxmemory: 250,
+        maxmemory_policy: MaxmemoryPolicy::AllKeysLru,
+        ..Default::default()
+    })
+    .await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    set_value(&mut stream, "a").await;
+    set_value(&mut stream, "b").await;
+
+    time::sleep(Duration::from_millis(10)).await;
+    assert_value(&mut stream, "a", true).await;
+
+    set_value(&mut stream, "c").await;
+
+    assert_value(&mut stream, "b", false).await;
+    assert_value(&mut stream, "a", true).await;
+    assert_value(&mut stream, "c", true).await;
+}
+
+/// The least frequently used key is evicted. The policy can be changed at
+/// runtime.
+#[tokio::test]
+async fn maxmemory_allkeys_lfu() {
+    let addr = start_server_with_config(server::Config {
+        maxmemory: 250,
+        ..Default::default()
+    })
+    .await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(&request(&["DEBUG", "SET-ACTIVE-EXPIRE", "0"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    stream
+        .write_all(&request(&[
+            "CONFIG",
+            "SET",
+            "maxmemory-policy",
+            "allkeys-lfu",
+        ]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    set_value(&mut stream, "a").await;
+    set_value(&mut stream, "b").await;
+
+    // The first access always increments the frequency of a new key.
+    assert_value(&mut stream, "b", true).await;
+
+    set_value(&mut stream, "c").await;
+
+    assert_value(&mut stream, "a", false).await;
+    assert_value(&mut stream, "b", true).await;
+}
+
+/// Concurrent writes over the limit always find a key to evict, even when the
+/// other shards are in use at the same time.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn maxmemory_concurrent_writes() {
+    let addr = start_server_with_config(server::Config {
+        maxmemory: 250,
+        maxmemory_policy: MaxmemoryPolicy::AllKeysLru,
+        ..Default::default()
+    })
+    .await;
+
+    let clients = (0..16).map(|client| {
+        tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+
+            for i in 0..200 {
+                let key = char::from(b'a' + ((client + i) % 26) as u8);
+                set_value(&mut stream, &key.to_string()).await;
+            }
+        })
+    });
+
+    for client in futures::future::join_all(clients).await {
+        client.unwrap();
+    }
+}
+
+/// Only keys with an expiration are evicted, shortest time to live first.
+#[tokio::test]
+async fn maxmemory_volatile_ttl() {
+    let addr = start_server_with_config(server::Config {
+        // Room for three keys, two of which with an expiration.
+        maxmemory: 399,
+        maxmemory_policy: MaxmemoryPolicy::VolatileTtl,
+        ..Default::default()
+    })
+    .await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    set_value(&mut stream, "a").await;
+
+    for (key, ttl) in [("b", "100000"), ("c", "10000")] {
+        stream
+            .write_all(&request(&["SET", key, VALUE, "PX", ttl]))
+            .await
+            .unwrap();
+        read_reply(&mut stream, b"+OK\r\n").await;
+    }
+
+    set_value(&mut stream, "d").await;
+    assert_value(&mut stream, "c", false).await;
+
+    set_value(&mut stream, "e").await;
+    assert_value(&mut stream, "b", false).await;
+
+    // No key has an expiration anymore.
+    stream
+        .write_all(&request(&["SET", "f", VALUE]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut stream,
+        b"-OOM command not allowed when used memory > 'maxmemory'.\r\n",
+    )
+    .await;
+
+    assert_value(&mut stream, "a", true).await;
+}
+
+// The memory limit is configured at runtime using CONFIG SET
+#[tokio::test]
+async fn config_maxmemory() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+
+    stream
+        .write_all(&request(&["CONFIG", "GET", "maxmemory*"]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut stream,
+        b"*4\r\n$9\r\nmaxmemory\r\n$1\r\n0\r\n$16\r\nmaxmemory-policy\r\n$10\r\nnoeviction\r\n",
+    )
+    .await;
+
+    // Sizes may use a unit
+    stream
+        .write_all(&request(&["CONFIG", "SET", "maxmemory", "1kb"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"+OK\r\n").await;
+
+    stream
+        .write_all(&request(&["CONFIG", "GET", "maxmemory"]))
+        .await
+        .unwrap();
+    read_reply(&mut stream, b"*2\r\n$9\r\nmaxmemory\r\n$4\r\n1024\r\n").await;
+
+    stream
+        .write_all(&request(&["CONFIG", "SET", "maxmemory-policy", "lru"]))
+        .await
+        .unwrap();
+    read_reply(
+        &mut stream,
+        b"-ERR Invalid argument 'lru' for CONFIG SET 'maxmemory-policy'\r\n",
+    )
+    .await;
+}
+
+/// Value used by the `maxmemory` tests. With a single byte key, each entry
+/// without expiration uses 100 bytes.
+const VALUE: &str = "abcdefghijklmnopqrstuvwxyz012345678";
+
+async fn set_value(stream: &mut TcpStream, key: &str) {
+    stream
+        .write_all(&request(&["SET", key, VALUE]))
+        .await
+        .unwrap();
+    read_reply(stream, b"+OK\r\n").await;
+}
+
+/// Checks whether `key` holds `VALUE` or does not exist.
+async fn assert_value(stream: &mut TcpStream, key: &str, exists: bool) {
+    stream.write_all(&request(&["GET", key])).await.unwrap();
+
+    if exists {
+        read_reply(
+            stream,
+            format!("${}\r\n{}\r\n", VALUE.len(), VALUE).as_bytes(),
+        )
+        .await;
+    } else {
+        read_reply(stream, b"$-1\r\n").await;
+    }
+}
+
+async fn last_save(stream: &mut TcpStream) -> u64 {
+    stream.write_all(b"*1\r\n$8\r\nLASTSAVE\r\n").await.unwrap();
+
+    // A UNIX timestamp in seconds has 10 digits until the year 2286
+    let mut response = [0; 13];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(b':', response[0]);
+
+    std::str::from_utf8(&response[1..11])
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+/// Encodes a command as an array of bulk strings.
+fn request(args: &[&str]) -> Vec<u8> {
+    let mut request = format!("*{}\r\n", args.len());
+
+    for arg in args {
+        request.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+
+    request.into_bytes()
+}
+
+/// Reads a reply of the same length as `expected` and compares them.
+async fn read_reply(stream: &mut TcpStream, expected: &[u8]) {
+    let mut response = vec![0; expected.len()];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(expected),
+        String::from_utf8_lossy(&response)
+    );
+}
+
+/// Returns a path in the temporary directory, unique to the test.
+fn temp_path(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mini-redis-{}-{}.rdb", std::process::id(), test));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+async fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move { server::run(listener, tokio::signal::ctrl_c()).await });
+
+    addr
+}
+
+async fn start_server_with_config(config: server::Config) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        server::run_with_config(listener, config, tokio::signal::ctrl_c()).await
+    });
 
     addr
 }
@@ -0,0 +1,238 @@
+#![cfg(feature = "tls")]
+
+use mini_redis::clients::{BlockingClient, Client};
+use mini_redis::server;
+use mini_redis::tls::{self, AuthClients, TlsListener};
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::net::TcpListener;
+
+/// A client connected over TLS gets the same replies as over TCP
+#[tokio::test]
+async fn tls_get_set() {
+    let certs = Certs::generate("get-set");
+    let addr = start_server(&certs, AuthClients::No).await;
+
+    let config = tls::client_config(&certs.ca_cert, None).unwrap();
+    let mut client = Client::connect_tls(addr, "localhost", config)
+        .await
+        .unwrap();
+
+    client.set("hello", "world".into()).await.unwrap();
+    let value = client.get("hello").await.unwrap().unwrap();
+    assert_eq!(b"world", &value[..]);
+}
+
+/// A client does not connect to a server whose certificate is not signed by a
+/// trusted authority, or not issued for the requested name
+#[tokio::test]
+async fn untrusted_server_certificate() {
+    let certs = Certs::generate("untrusted-server");
+    let other = Certs::generate("untrusted-server-other");
+    let addr = start_server(&certs, AuthClients::No).await;
+
+    let config = tls::client_config(&other.ca_cert, None).unwrap();
+    assert!(Client::connect_tls(addr, "localhost", config)
+        .await
+        .is_err());
+
+    let config = tls::client_config(&certs.ca_cert, None).unwrap();
+    assert!(Client::connect_tls(addr, "example.com", config)
+        .await
+        .is_err());
+}
+
+/// By default, clients must present a certificate signed by the trusted
+/// authority
+#[tokio::test]
+async fn client_certificate_required() {
+    let certs = Certs::generate("client-required");
+    let other = Certs::generate("client-required-other");
+    let addr = start_server(&certs, AuthClients::Yes).await;
+
+    // With TLS 1.3, the server rejects the client certificate after the
+    // client considers the handshake complete: the first request fails.
+    let config = tls::client_config(&certs.ca_cert, None).unwrap();
+    let res = match Client::connect_tls(addr, "localhost", config).await {
+        Ok(mut client) => client.ping(None).await.map(|_| ()),
+        Err(err) => Err(err),
+    };
+    assert!(res.is_err());
+
+    let config = tls::client_config(
+        &certs.ca_cert,
+        Some((&other.client_cert, &other.client_key)),
+    )
+    .unwrap();
+    let res = match Client::connect_tls(addr, "localhost", config).await {
+        Ok(mut client) => client.ping(None).await.map(|_| ()),
+        Err(err) => Err(err),
+    };
+    assert!(res.is_err());
+
+    let config = tls::client_config(
+        &certs.ca_cert,
+        Some((&certs.client_cert, &certs.client_key)),
+    )
+    .unwrap();
+    let mut client = Client::connect_tls(addr, "localhost", config)
+        .await
+        .unwrap();
+
+    let pong = client.ping(None).await.unwrap();
+    assert_eq!(b"PONG", &pong[..]);
+}
+
+/// With optional authentication, clients may connect without a certificate,
+/// but a certificate they present is still verified
+#[tokio::test]
+async fn client_certificate_optional() {
+    let certs = Certs::generate("client-optional");
+    let other = Certs::generate("client-optional-other");
+    let addr = start_server(&certs, AuthClients::Optional).await;
+
+    let config = tls::client_config(&certs.ca_cert, None).unwrap();
+    let mut client = Client::connect_tls(addr, "localhost", config)
+        .await
+        .unwrap();
+
+    let pong = client.ping(None).await.unwrap();
+    assert_eq!(b"PONG", &pong[..]);
+
+    let config = tls::client_config(
+        &certs.ca_cert,
+        Some((&other.client_cert, &other.client_key)),
+    )
+    .unwrap();
+    let res = match Client::connect_tls(addr, "localhost", config).await {
+        Ok(mut client) => client.ping(None).await.map(|_| ()),
+        Err(err) => Err(err),
+    };
+    assert!(res.is_err());
+}
+
+/// Client authentication needs the authorities to verify certificates against
+#[test]
+fn client_authentication_requires_ca() {
+    let certs = Certs::generate("requires-ca");
+
+    let res = tls::server_config(
+        &certs.server_cert,
+        &certs.server_key,
+        None,
+        AuthClients::Yes,
+    );
+    assert!(res.is_err());
+
+    let res = tls::server_config(&certs.server_cert, &certs.server_key, None, AuthClients::No);
+    assert!(res.is_ok());
+}
+
+#[test]
+fn blocking_client() {
+    let certs = Certs::generate("blocking");
+
+    // The server runs on its own runtime, the blocking client creates another
+    // one.
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let addr = rt.block_on(start_server(&certs, AuthClients::Yes));
+
+    let config = tls::client_config(
+        &certs.ca_cert,
+        Some((&certs.client_cert, &certs.client_key)),
+    )
+    .unwrap();
+    let mut client = BlockingClient::connect_tls(addr, "localhost", config).unwrap();
+
+    client.set("hello", "world".into()).unwrap();
+    let value = client.get("hello").unwrap().unwrap();
+    assert_eq!(b"world", &value[..]);
+}
+
+/// PEM files of a certificate authority, a server certificate for `localhost`
+/// and a client certificate, both signed by the authority.
+struct Certs {
+    ca_cert: PathBuf,
+    server_cert: PathBuf,
+    server_key: PathBuf,
+    client_cert: PathBuf,
+    client_key: PathBuf,
+}
+
+impl Certs {
+    /// Generates the certificates in a temporary directory unique to the test.
+    fn generate(test: &str) -> Certs {
+        let dir =
+            std::env::temp_dir().join(format!("mini-redis-tls-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "mini-redis test CA");
+        let ca = params.self_signed(&ca_key).unwrap();
+
+        let ca_cert = write(&dir, "ca.crt", &ca.pem());
+        let (server_cert, server_key) = issue(&dir, "server", "localhost", &ca, &ca_key);
+        let (client_cert, client_key) = issue(&dir, "client", "client", &ca, &ca_key);
+
+        Certs {
+            ca_cert,
+            server_cert,
+            server_key,
+            client_cert,
+            client_key,
+        }
+    }
+}
+
+/// Issues a certificate for `name` signed by `ca` and writes it, with its
+/// private key, to `dir`.
+fn issue(
+    dir: &Path,
+    file: &str,
+    name: &str,
+    ca: &Certificate,
+    ca_key: &KeyPair,
+) -> (PathBuf, PathBuf) {
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec![name.to_string()])
+        .unwrap()
+        .signed_by(&key, ca, ca_key)
+        .unwrap();
+
+    (
+        write(dir, &format!("{}.crt", file), &cert.pem()),
+        write(dir, &format!("{}.key", file), &key.serialize_pem()),
+    )
+}
+
+fn write(dir: &Path, file: &str, contents: &str) -> PathBuf {
+    let path = dir.join(file);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+async fn start_server(certs: &Certs, auth_clients: AuthClients) -> SocketAddr {
+    let config = tls::server_config(
+        &certs.server_cert,
+        &certs.server_key,
+        Some(&certs.ca_cert),
+        auth_clients,
+    )
+    .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(server::run(
+        TlsListener::new(listener, config),
+        std::future::pending::<()>(),
+    ));
+
+    addr
+}